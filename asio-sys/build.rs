@@ -169,6 +169,7 @@ fn create_bindings(cpal_asio_dir: &PathBuf) {
         .whitelist_type("ASIOChannelInfo")
         .whitelist_type("AsioTimeInfoFlags")
         .whitelist_type("ASIOTimeCodeFlags")
+        .whitelist_type("ASIOInputMonitor")
         .whitelist_var("kAsioSelectorSupported")
         .whitelist_var("kAsioEngineVersion")
         .whitelist_var("kAsioResetRequest")
@@ -184,6 +185,8 @@ fn create_bindings(cpal_asio_dir: &PathBuf) {
         .whitelist_var("kAsioSupportsOutputGain")
         .whitelist_var("kAsioSupportsOutputMeter")
         .whitelist_var("kAsioOverload")
+        .whitelist_var("kAsioSetInputMonitor")
+        .whitelist_var("kAsioCanInputMonitor")
         .whitelist_function("ASIOGetChannels")
         .whitelist_function("ASIOGetChannelInfo")
         .whitelist_function("ASIOGetBufferSize")
@@ -196,6 +199,7 @@ fn create_bindings(cpal_asio_dir: &PathBuf) {
         .whitelist_function("ASIOStart")
         .whitelist_function("ASIOStop")
         .whitelist_function("ASIODisposeBuffers")
+        .whitelist_function("ASIOFuture")
         .whitelist_function("ASIOExit")
         .whitelist_function("load_asio_driver")
         .whitelist_function("remove_current_driver")
@@ -87,6 +87,39 @@ pub struct SampleRate {
 /// Holds the pointer to the callbacks that come from cpal
 struct BufferCallback(Box<dyn FnMut(i32) + Send>);
 
+/// Holds the pointer to the callbacks that are notified of driver messages.
+struct MessageCallback(Box<dyn FnMut(AsioMessage) + Send>);
+
+/// A notification from the driver, usually after the user changed its settings in the driver's
+/// control panel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AsioMessage {
+    /// The sample rate of the driver changed to `rate`.
+    SampleRateChanged { rate: c_double },
+    /// The driver needs to be reset, e.g. because its buffer size changed. The buffers must be
+    /// rebuilt with `Driver::rebuild_buffers`, which must not be called from the message callback.
+    ResetRequest,
+    /// The driver lost some data, but keeps running.
+    ResyncRequest,
+    /// The input or output latencies of the driver changed.
+    LatenciesChanged,
+}
+
+/// The direct monitoring of an input, routed to an output by the hardware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputMonitor {
+    /// The input channel, or `None` for all the inputs.
+    pub input: Option<usize>,
+    /// The output channel on which the input is monitored, the left one of a stereo pair.
+    pub output: usize,
+    /// Whether the input is monitored.
+    pub enabled: bool,
+    /// The gain, from 0 for -inf dB through `0x2000_0000` for 0 dB to `0x7fff_ffff` for +12 dB.
+    pub gain: c_long,
+    /// The pan, from 0 for left to `0x7fff_ffff` for right.
+    pub pan: c_long,
+}
+
 /// Input and Output streams.
 ///
 /// There is only ever max one input and one output.
@@ -107,6 +140,13 @@ pub struct AsioStream {
     pub buffer_size: i32,
 }
 
+impl AsioStream {
+    /// The channels of the driver that the stream uses, in the order of its buffers.
+    pub fn channels(&self) -> Vec<usize> {
+        self.buffer_infos.iter().map(|info| info.channel_num as usize).collect()
+    }
+}
+
 /// All the possible types from ASIO.
 /// This is a direct copy of the ASIOSampleType
 /// inside ASIO SDK.
@@ -238,6 +278,9 @@ struct BufferSizes {
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct CallbackId(usize);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageCallbackId(usize);
+
 lazy_static! {
     /// A global way to access all the callbacks.
     ///
@@ -248,6 +291,11 @@ lazy_static! {
     ///
     /// The indices are how we match a callback with a stream.
     static ref BUFFER_CALLBACK: Mutex<Vec<(CallbackId, BufferCallback)>> = Mutex::new(Vec::new());
+
+    /// The callbacks notified of the messages of the driver, which are also called without data
+    /// parameters.
+    static ref MESSAGE_CALLBACK: Mutex<Vec<(MessageCallbackId, MessageCallback)>> =
+        Mutex::new(Vec::new());
 }
 
 impl Asio {
@@ -352,6 +400,14 @@ impl BufferCallback {
     }
 }
 
+impl MessageCallback {
+    /// Calls the inner callback.
+    fn run(&mut self, message: AsioMessage) {
+        let cb = &mut self.0;
+        cb(message);
+    }
+}
+
 impl Driver {
     /// The name used to uniquely identify this driver.
     pub fn name(&self) -> &str {
@@ -397,6 +453,38 @@ impl Driver {
         Ok(())
     }
 
+    /// Whether the driver supports direct monitoring through `set_input_monitor`.
+    pub fn can_input_monitor(&self) -> bool {
+        unsafe {
+            let selector = ai::kAsioCanInputMonitor as c_long;
+            asio_result!(ai::ASIOFuture(selector, std::ptr::null_mut())).is_ok()
+        }
+    }
+
+    /// Routes an input to an output in the hardware, or stops doing so, as described by
+    /// `monitor`.
+    ///
+    /// The gain and pan are suggestions, which drivers may ignore.
+    pub fn set_input_monitor(&self, monitor: &InputMonitor) -> Result<(), AsioError> {
+        let mut params = ai::ASIOInputMonitor {
+            input: monitor.input.map_or(-1, |input| input as c_long),
+            output: monitor.output as c_long,
+            gain: monitor.gain,
+            state: if monitor.enabled { 1 } else { 0 },
+            pan: monitor.pan,
+        };
+        unsafe {
+            let selector = ai::kAsioSetInputMonitor as c_long;
+            asio_result!(ai::ASIOFuture(selector, &mut params as *mut _ as *mut _))?;
+        }
+        Ok(())
+    }
+
+    /// The largest buffer size, in frames, that the driver supports.
+    pub fn max_buffer_size(&self) -> Result<c_long, AsioError> {
+        Ok(asio_get_buffer_sizes()?.max)
+    }
+
     /// Get the current data type of the driver's input stream.
     ///
     /// This queries a single channel's type assuming all channels have the same sample type.
@@ -516,15 +604,16 @@ impl Driver {
     ///
     /// For this reason we take the output stream if it exists.
     ///
-    /// `num_channels` is the desired number of input channels.
+    /// `channels` are the indices of the input channels to create buffers for, in the order of
+    /// the buffers of the stream. Channels that are not listed are not processed by the driver.
     ///
     /// This returns a full AsioStreams with both input and output if output was active.
     pub fn prepare_input_stream(
         &self,
         output: Option<AsioStream>,
-        num_channels: usize,
+        channels: &[usize],
     ) -> Result<AsioStreams, AsioError> {
-        let input_buffer_infos = prepare_buffer_infos(true, num_channels);
+        let input_buffer_infos = prepare_buffer_infos(true, channels.iter().cloned());
         let output_buffer_infos = output
             .map(|output| output.buffer_infos)
             .unwrap_or_else(Vec::new);
@@ -538,21 +627,46 @@ impl Driver {
     ///
     /// For this reason we take the input stream if it exists.
     ///
-    /// `num_channels` is the desired number of output channels.
+    /// `channels` are the indices of the output channels to create buffers for, in the order of
+    /// the buffers of the stream. Channels that are not listed are not processed by the driver.
     ///
     /// This returns a full AsioStreams with both input and output if input was active.
     pub fn prepare_output_stream(
         &self,
         input: Option<AsioStream>,
-        num_channels: usize,
+        channels: &[usize],
     ) -> Result<AsioStreams, AsioError> {
         let input_buffer_infos = input
             .map(|input| input.buffer_infos)
             .unwrap_or_else(Vec::new);
-        let output_buffer_infos = prepare_buffer_infos(false, num_channels);
+        let output_buffer_infos = prepare_buffer_infos(false, channels.iter().cloned());
         self.create_streams(input_buffer_infos, output_buffer_infos)
     }
 
+    /// Rebuilds the buffers of `streams` with the current settings of the driver, e.g. after an
+    /// `AsioMessage::ResetRequest`. The streams keep their channels, but their buffer size may
+    /// change.
+    ///
+    /// This will `stop` the driver if it is `Running`, leaving it in the `Prepared` state. As
+    /// stopping waits for the buffer callbacks to return, call `stop` before locking anything the
+    /// buffer callbacks lock.
+    ///
+    /// This must not be called from a message callback, as the driver cannot be reset while it
+    /// is notifying the host.
+    pub fn rebuild_buffers(&self, streams: &mut AsioStreams) -> Result<(), AsioError> {
+        let channels = |stream: &Option<AsioStream>| {
+            stream.as_ref().map_or(Vec::new(), AsioStream::channels)
+        };
+        let input_buffer_infos = prepare_buffer_infos(true, channels(&streams.input));
+        let output_buffer_infos = prepare_buffer_infos(false, channels(&streams.output));
+        if input_buffer_infos.is_empty() && output_buffer_infos.is_empty() {
+            return Ok(());
+        }
+        // `create_buffers` stops the driver and disposes of the previous buffers first.
+        *streams = self.create_streams(input_buffer_infos, output_buffer_infos)?;
+        Ok(())
+    }
+
     /// Releases buffers allocations.
     ///
     /// This will `stop` the stream if the driver is `Running`.
@@ -612,10 +726,35 @@ impl Driver {
 
     /// Remove the callback with the given ID.
     pub fn remove_callback(&self, rem_id: CallbackId) {
-        let mut bc = BUFFER_CALLBACK.lock().unwrap();
+        // Called when a stream is dropped, so a callback that panicked must not make this panic.
+        let mut bc = BUFFER_CALLBACK.lock().unwrap_or_else(|err| err.into_inner());
         bc.retain(|&(id, _)| id != rem_id);
     }
 
+    /// Adds a callback to the list of callbacks notified of the messages of the driver.
+    ///
+    /// The callback is called from a thread of the driver, which waits for it to return.
+    ///
+    /// Returns an ID uniquely associated with the given callback so that it may be removed later.
+    pub fn add_message_callback<F>(&self, callback: F) -> MessageCallbackId
+    where
+        F: 'static + FnMut(AsioMessage) + Send,
+    {
+        let mut mc = MESSAGE_CALLBACK.lock().unwrap_or_else(|err| err.into_inner());
+        let id = mc
+            .last()
+            .map(|&(id, _)| MessageCallbackId(id.0.checked_add(1).expect("callback ID overflowed")))
+            .unwrap_or(MessageCallbackId(0));
+        mc.push((id, MessageCallback(Box::new(callback))));
+        id
+    }
+
+    /// Remove the message callback with the given ID.
+    pub fn remove_message_callback(&self, rem_id: MessageCallbackId) {
+        let mut mc = MESSAGE_CALLBACK.lock().unwrap_or_else(|err| err.into_inner());
+        mc.retain(|&(id, _)| id != rem_id);
+    }
+
     /// Consumes and destroys the `Driver`, stopping the streams if they are running and releasing
     /// any associated resources.
     ///
@@ -701,6 +840,9 @@ impl DriverInner {
             if let Ok(mut bcs) = BUFFER_CALLBACK.lock() {
                 bcs.clear();
             }
+            if let Ok(mut mcs) = MESSAGE_CALLBACK.lock() {
+                mcs.clear();
+            }
         }
 
         // Signal that the driver has been destroyed.
@@ -720,12 +862,18 @@ impl Drop for DriverInner {
     }
 }
 
+// The buffers are allocated by the driver and stay valid until they are disposed of, which only
+// happens through the `Driver`, from whichever thread owns the streams.
 unsafe impl Send for AsioStream {}
 
 /// Used by the input and output stream creation process.
-fn prepare_buffer_infos(is_input: bool, n_channels: usize) -> Vec<AsioBufferInfo> {
+fn prepare_buffer_infos<I>(is_input: bool, channels: I) -> Vec<AsioBufferInfo>
+where
+    I: IntoIterator<Item = usize>,
+{
     let is_input = if is_input { 1 } else { 0 };
-    (0..n_channels)
+    channels
+        .into_iter()
         .map(|ch| {
             let channel_num = ch as c_long;
             // To be filled by ASIOCreateBuffers.
@@ -803,11 +951,18 @@ fn _channel_name_to_utf8(bytes: &[c_char]) -> std::borrow::Cow<str> {
     }
 }
 
+/// Passes `message` to each message callback.
+fn notify(message: AsioMessage) {
+    // A callback that panicked must not stop the others from being notified.
+    let mut mcs = MESSAGE_CALLBACK.lock().unwrap_or_else(|err| err.into_inner());
+    for &mut (_, ref mut mc) in mcs.iter_mut() {
+        mc.run(message);
+    }
+}
+
 /// Indicates the stream sample rate has changed.
-///
-/// TODO: Provide some way of allowing CPAL to handle this.
 extern "C" fn sample_rate_did_change(s_rate: c_double) -> () {
-    eprintln!("unhandled sample rate change to {}", s_rate);
+    notify(AsioMessage::SampleRateChanged { rate: s_rate });
 }
 
 /// Message callback for ASIO to notify of certain events.
@@ -838,7 +993,7 @@ extern "C" fn asio_message(
             // You cannot reset the driver right now, as this code is called from the driver. Reset
             // the driver is done by completely destruct it. I.e. ASIOStop(), ASIODisposeBuffers(),
             // Destruction. Afterwards you initialize the driver again.
-            // TODO: Handle this.
+            notify(AsioMessage::ResetRequest);
             1
         }
 
@@ -848,7 +1003,7 @@ extern "C" fn asio_message(
             // around the Win16Mutex problems in Windows 95/98 with the Windows Multimedia system,
             // which could loose data because the Mutex was hold too long by another thread.
             // However a driver can issue it in other situations, too.
-            // TODO: Handle this.
+            notify(AsioMessage::ResyncRequest);
             1
         }
 
@@ -856,7 +1011,7 @@ extern "C" fn asio_message(
             // This will inform the host application that the drivers were latencies changed.
             // Beware, it this does not mean that the buffer sizes have changed! You might need to
             // update internal delay data.
-            // TODO: Handle this.
+            notify(AsioMessage::LatenciesChanged);
             1
         }
 
@@ -0,0 +1,554 @@
+//! The types that CPAL shares with the code processing its audio: sample and stream formats, and
+//! the buffers passed to data callbacks.
+//!
+//! This crate does not need `std`, so that firmware and DSP crates can use the same types as CPAL
+//! without pulling in its hosts: disable its default `std` feature to build it without. CPAL
+//! re-exports all of its items.
+
+#![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+#[cfg(not(feature = "std"))]
+extern crate libm;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+
+pub use samples_formats::{Sample, SampleFormat};
+
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::slice;
+
+mod samples_formats;
+
+/// Number of channels.
+pub type ChannelCount = u16;
+
+/// The number of samples processed per second for a single channel of audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SampleRate(pub u32);
+
+/// The format of an input or output audio stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Format {
+    pub channels: ChannelCount,
+    pub sample_rate: SampleRate,
+    pub data_type: SampleFormat,
+}
+
+/// Describes a range of supported stream formats.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SupportedFormat {
+    pub channels: ChannelCount,
+    /// Minimum value for the samples rate of the supported formats.
+    pub min_sample_rate: SampleRate,
+    /// Maximum value for the samples rate of the supported formats.
+    pub max_sample_rate: SampleRate,
+    /// Type of data expected by the device.
+    pub data_type: SampleFormat,
+}
+
+/// Represents a buffer containing audio data that may be read.
+///
+/// This struct implements the `Deref` trait targeting `[T]`. Therefore this buffer can be read the
+/// same way as reading from a `Vec` or any other kind of Rust array.
+///
+/// The samples are interleaved and the buffer always holds a whole number of frames, so it can be
+/// split with `chunks_exact(channels)`. A host that receives a partial frame from the device
+/// reports an error instead of calling the data callback.
+// TODO: explain audio stuff in general
+// TODO: remove the wrapper and just use slices in next major version
+#[derive(Debug)]
+pub struct InputBuffer<'a, T: 'a>
+where
+    T: Sample,
+{
+    buffer: &'a [T],
+}
+
+/// Represents a buffer that must be filled with audio data. The buffer in unfilled state may
+/// contain garbage values.
+///
+/// This struct implements the `Deref` and `DerefMut` traits to `[T]`. Therefore writing to this
+/// buffer is done in the same way as writing to a `Vec` or any other kind of Rust array.
+///
+/// As with `InputBuffer`, the buffer always holds a whole number of interleaved frames.
+// TODO: explain audio stuff in general
+// TODO: remove the wrapper and just use slices
+#[must_use]
+#[derive(Debug)]
+pub struct OutputBuffer<'a, T: 'a>
+where
+    T: Sample,
+{
+    buffer: &'a mut [T],
+}
+
+/// This is the struct that is provided to you by cpal when you want to read samples from a buffer.
+///
+/// Since the type of data is only known at runtime, you have to read the right buffer.
+#[derive(Debug)]
+pub enum UnknownTypeInputBuffer<'a> {
+    /// Samples whose format is `u16`.
+    U16(InputBuffer<'a, u16>),
+    /// Samples whose format is `i16`.
+    I16(InputBuffer<'a, i16>),
+    /// Samples whose format is `f32`.
+    F32(InputBuffer<'a, f32>),
+}
+
+/// This is the struct that is provided to you by cpal when you want to write samples to a buffer.
+///
+/// Since the type of data is only known at runtime, you have to fill the right buffer.
+#[derive(Debug)]
+pub enum UnknownTypeOutputBuffer<'a> {
+    /// Samples whose format is `u16`.
+    U16(OutputBuffer<'a, u16>),
+    /// Samples whose format is `i16`.
+    I16(OutputBuffer<'a, i16>),
+    /// Samples whose format is `f32`.
+    F32(OutputBuffer<'a, f32>),
+}
+
+/// A buffer of audio data that may be read, as raw bytes tagged with the format of the samples.
+///
+/// This is provided by the raw stream builders, for code that dispatches on the sample format at
+/// runtime itself. The bytes are interleaved samples in native byte order, and the buffer always
+/// holds a whole number of frames.
+#[derive(Debug)]
+pub struct RawInputBuffer<'a> {
+    bytes: &'a [u8],
+    sample_format: SampleFormat,
+}
+
+/// A buffer that must be filled with audio data, as raw bytes tagged with the format of the
+/// samples. The buffer in unfilled state may contain garbage values.
+///
+/// As with `RawInputBuffer`, the bytes are interleaved samples in native byte order.
+#[must_use]
+#[derive(Debug)]
+pub struct RawOutputBuffer<'a> {
+    bytes: &'a mut [u8],
+    sample_format: SampleFormat,
+}
+
+impl SupportedFormat {
+    /// Turns this `SupportedFormat` into a `Format` corresponding to the maximum samples rate.
+    #[inline]
+    pub fn with_max_sample_rate(self) -> Format {
+        Format {
+            channels: self.channels,
+            sample_rate: self.max_sample_rate,
+            data_type: self.data_type,
+        }
+    }
+
+    /// Turns this `SupportedFormat` into a `Format` with the given sample rate.
+    ///
+    /// Returns `None` if the sample rate is outside of the range of this `SupportedFormat`.
+    #[inline]
+    pub fn with_sample_rate(self, sample_rate: SampleRate) -> Option<Format> {
+        if sample_rate < self.min_sample_rate || sample_rate > self.max_sample_rate {
+            return None;
+        }
+        Some(Format {
+            channels: self.channels,
+            sample_rate,
+            data_type: self.data_type,
+        })
+    }
+
+    /// Whether `format` is one of the formats described by this range.
+    #[inline]
+    pub fn contains(&self, format: &Format) -> bool {
+        self.channels == format.channels
+            && self.data_type == format.data_type
+            && self.min_sample_rate <= format.sample_rate
+            && format.sample_rate <= self.max_sample_rate
+    }
+
+    /// The formats described by both this range and `other`.
+    ///
+    /// Returns `None` if the ranges have a different number of channels or sample format, or if
+    /// their sample rates do not overlap. This can be used to find the formats supported by both
+    /// an input and an output device, e.g. for a duplex application.
+    pub fn intersect(&self, other: &Self) -> Option<SupportedFormat> {
+        if self.channels != other.channels || self.data_type != other.data_type {
+            return None;
+        }
+        let min_sample_rate = core::cmp::max(self.min_sample_rate, other.min_sample_rate);
+        let max_sample_rate = core::cmp::min(self.max_sample_rate, other.max_sample_rate);
+        if min_sample_rate > max_sample_rate {
+            return None;
+        }
+        Some(SupportedFormat {
+            channels: self.channels,
+            min_sample_rate,
+            max_sample_rate,
+            data_type: self.data_type,
+        })
+    }
+
+    /// A comparison function which compares two `SupportedFormat`s in terms of the quality of the
+    /// audio they can carry.
+    ///
+    /// Unlike `cmp_default_heuristics`, which prefers common formats, this prefers the most
+    /// precise sample format (f32, then i16, then u16), then the highest maximum sample rate, then
+    /// the most channels. The best format of a device can be selected with
+    /// `formats.max_by(|a, b| a.cmp_quality(b))`.
+    pub fn cmp_quality(&self, other: &Self) -> core::cmp::Ordering {
+        fn precision(data_type: SampleFormat) -> u8 {
+            match data_type {
+                SampleFormat::U16 => 0,
+                SampleFormat::I16 => 1,
+                SampleFormat::F32 => 2,
+            }
+        }
+
+        precision(self.data_type)
+            .cmp(&precision(other.data_type))
+            .then(self.max_sample_rate.cmp(&other.max_sample_rate))
+            .then(self.channels.cmp(&other.channels))
+    }
+
+    /// A comparison function which compares two `SupportedFormat`s in terms of their priority of
+    /// use as a default stream format.
+    ///
+    /// Some backends do not provide a default stream format for their audio devices. In these
+    /// cases, CPAL attempts to decide on a reasonable default format for the user. To do this we
+    /// use the "greatest" of all supported stream formats when compared with this method.
+    ///
+    /// Formats are prioritised by the following heuristics:
+    ///
+    /// **Channels**:
+    ///
+    /// - Stereo
+    /// - Mono
+    /// - Max available channels
+    ///
+    /// **Sample format**:
+    /// - f32
+    /// - i16
+    /// - u16
+    ///
+    /// **Sample rate**:
+    ///
+    /// - 44100 (cd quality)
+    /// - Max sample rate
+    pub fn cmp_default_heuristics(&self, other: &Self) -> core::cmp::Ordering {
+        use core::cmp::Ordering::Equal;
+        use SampleFormat::{F32, I16, U16};
+
+        let cmp_stereo = (self.channels == 2).cmp(&(other.channels == 2));
+        if cmp_stereo != Equal {
+            return cmp_stereo;
+        }
+
+        let cmp_mono = (self.channels == 1).cmp(&(other.channels == 1));
+        if cmp_mono != Equal {
+            return cmp_mono;
+        }
+
+        let cmp_channels = self.channels.cmp(&other.channels);
+        if cmp_channels != Equal {
+            return cmp_channels;
+        }
+
+        let cmp_f32 = (self.data_type == F32).cmp(&(other.data_type == F32));
+        if cmp_f32 != Equal {
+            return cmp_f32;
+        }
+
+        let cmp_i16 = (self.data_type == I16).cmp(&(other.data_type == I16));
+        if cmp_i16 != Equal {
+            return cmp_i16;
+        }
+
+        let cmp_u16 = (self.data_type == U16).cmp(&(other.data_type == U16));
+        if cmp_u16 != Equal {
+            return cmp_u16;
+        }
+
+        const HZ_44100: SampleRate = SampleRate(44_100);
+        let r44100_in_self = self.min_sample_rate <= HZ_44100
+            && HZ_44100 <= self.max_sample_rate;
+        let r44100_in_other = other.min_sample_rate <= HZ_44100
+            && HZ_44100 <= other.max_sample_rate;
+        let cmp_r44100 = r44100_in_self.cmp(&r44100_in_other);
+        if cmp_r44100 != Equal {
+            return cmp_r44100;
+        }
+
+        self.max_sample_rate.cmp(&other.max_sample_rate)
+    }
+}
+
+impl<'a, T> InputBuffer<'a, T>
+    where T: Sample
+{
+    /// Wraps interleaved samples, which must hold a whole number of frames.
+    #[inline]
+    pub fn new(buffer: &'a [T]) -> Self {
+        InputBuffer { buffer }
+    }
+}
+
+impl<'a, T> OutputBuffer<'a, T>
+    where T: Sample
+{
+    /// Wraps room for interleaved samples, which must hold a whole number of frames.
+    #[inline]
+    pub fn new(buffer: &'a mut [T]) -> Self {
+        OutputBuffer { buffer }
+    }
+}
+
+impl<'a, T> Deref for InputBuffer<'a, T>
+    where T: Sample
+{
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        self.buffer
+    }
+}
+
+impl<'a, T> Deref for OutputBuffer<'a, T>
+    where T: Sample
+{
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        self.buffer
+    }
+}
+
+impl<'a, T> DerefMut for OutputBuffer<'a, T>
+    where T: Sample
+{
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.buffer
+    }
+}
+
+impl<'a> UnknownTypeInputBuffer<'a> {
+    /// Returns the length of the buffer in number of samples.
+    #[inline]
+    pub fn len(&self) -> usize {
+        match self {
+            &UnknownTypeInputBuffer::U16(ref buf) => buf.len(),
+            &UnknownTypeInputBuffer::I16(ref buf) => buf.len(),
+            &UnknownTypeInputBuffer::F32(ref buf) => buf.len(),
+        }
+    }
+}
+
+impl<'a> UnknownTypeOutputBuffer<'a> {
+    /// Returns the length of the buffer in number of samples.
+    #[inline]
+    pub fn len(&self) -> usize {
+        match self {
+            &UnknownTypeOutputBuffer::U16(ref buf) => buf.len(),
+            &UnknownTypeOutputBuffer::I16(ref buf) => buf.len(),
+            &UnknownTypeOutputBuffer::F32(ref buf) => buf.len(),
+        }
+    }
+
+    /// Fills the buffer with the equilibrium value of its sample format.
+    pub fn fill_silence(&mut self) {
+        match self {
+            &mut UnknownTypeOutputBuffer::U16(ref mut buf) => {
+                for s in buf.iter_mut() {
+                    *s = 32768;
+                }
+            },
+            &mut UnknownTypeOutputBuffer::I16(ref mut buf) => {
+                for s in buf.iter_mut() {
+                    *s = 0;
+                }
+            },
+            &mut UnknownTypeOutputBuffer::F32(ref mut buf) => {
+                for s in buf.iter_mut() {
+                    *s = 0.0;
+                }
+            },
+        }
+    }
+}
+
+impl<'a> RawInputBuffer<'a> {
+    /// The format of the samples in the buffer.
+    #[inline]
+    pub fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
+    /// The samples, or `None` if they are not of type `T`.
+    pub fn as_slice<T: Sample>(&self) -> Option<&[T]> {
+        if T::get_format() != self.sample_format {
+            return None;
+        }
+        // The bytes were cast from a slice of samples of this format, so they are aligned.
+        let len = self.bytes.len() / mem::size_of::<T>();
+        Some(unsafe { slice::from_raw_parts(self.bytes.as_ptr() as *const T, len) })
+    }
+}
+
+impl<'a> RawOutputBuffer<'a> {
+    /// The format of the samples in the buffer.
+    #[inline]
+    pub fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
+    /// The samples, or `None` if they are not of type `T`.
+    pub fn as_slice_mut<T: Sample>(&mut self) -> Option<&mut [T]> {
+        if T::get_format() != self.sample_format {
+            return None;
+        }
+        // The bytes were cast from a slice of samples of this format, so they are aligned.
+        let len = self.bytes.len() / mem::size_of::<T>();
+        Some(unsafe { slice::from_raw_parts_mut(self.bytes.as_mut_ptr() as *mut T, len) })
+    }
+
+    /// Fills the buffer with the equilibrium value of its sample format.
+    pub fn fill_silence(&mut self) {
+        match self.sample_format {
+            SampleFormat::U16 => {
+                for s in self.bytes.chunks_exact_mut(2) {
+                    s.copy_from_slice(&32768u16.to_ne_bytes());
+                }
+            },
+            // Zero is the equilibrium of `i16` and `f32` alike.
+            SampleFormat::I16 | SampleFormat::F32 => {
+                for b in self.bytes.iter_mut() {
+                    *b = 0;
+                }
+            },
+        }
+    }
+}
+
+impl<'a> Deref for RawInputBuffer<'a> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+impl<'a> Deref for RawOutputBuffer<'a> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+impl<'a> DerefMut for RawOutputBuffer<'a> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.bytes
+    }
+}
+
+impl<'a> From<UnknownTypeInputBuffer<'a>> for RawInputBuffer<'a> {
+    fn from(buffer: UnknownTypeInputBuffer<'a>) -> Self {
+        let (bytes, sample_format) = match buffer {
+            UnknownTypeInputBuffer::U16(buf) => (as_bytes(buf.buffer), SampleFormat::U16),
+            UnknownTypeInputBuffer::I16(buf) => (as_bytes(buf.buffer), SampleFormat::I16),
+            UnknownTypeInputBuffer::F32(buf) => (as_bytes(buf.buffer), SampleFormat::F32),
+        };
+        RawInputBuffer { bytes, sample_format }
+    }
+}
+
+impl<'a> From<UnknownTypeOutputBuffer<'a>> for RawOutputBuffer<'a> {
+    fn from(buffer: UnknownTypeOutputBuffer<'a>) -> Self {
+        let (bytes, sample_format) = match buffer {
+            UnknownTypeOutputBuffer::U16(buf) => (as_bytes_mut(buf.buffer), SampleFormat::U16),
+            UnknownTypeOutputBuffer::I16(buf) => (as_bytes_mut(buf.buffer), SampleFormat::I16),
+            UnknownTypeOutputBuffer::F32(buf) => (as_bytes_mut(buf.buffer), SampleFormat::F32),
+        };
+        RawOutputBuffer { bytes, sample_format }
+    }
+}
+
+// Samples are plain numbers, for which any bytes are a valid value.
+fn as_bytes<T: Sample>(samples: &[T]) -> &[u8] {
+    unsafe { slice::from_raw_parts(samples.as_ptr() as *const u8, mem::size_of_val(samples)) }
+}
+
+fn as_bytes_mut<T: Sample>(samples: &mut [T]) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut(samples.as_mut_ptr() as *mut u8, mem::size_of_val(samples)) }
+}
+
+impl From<Format> for SupportedFormat {
+    #[inline]
+    fn from(format: Format) -> SupportedFormat {
+        SupportedFormat {
+            channels: format.channels,
+            min_sample_rate: format.sample_rate,
+            max_sample_rate: format.sample_rate,
+            data_type: format.data_type,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Format, OutputBuffer, RawOutputBuffer, SampleFormat, SampleRate, SupportedFormat};
+    use UnknownTypeOutputBuffer;
+
+    fn range(min: u32, max: u32, data_type: SampleFormat) -> SupportedFormat {
+        SupportedFormat {
+            channels: 2,
+            min_sample_rate: SampleRate(min),
+            max_sample_rate: SampleRate(max),
+            data_type,
+        }
+    }
+
+    #[test]
+    fn supported_format_ranges() {
+        let input = range(8_000, 48_000, SampleFormat::I16);
+        let output = range(44_100, 96_000, SampleFormat::I16);
+        let both = input.intersect(&output).unwrap();
+        assert_eq!(both, range(44_100, 48_000, SampleFormat::I16));
+        assert!(input.intersect(&range(96_000, 192_000, SampleFormat::I16)).is_none());
+        assert!(input.intersect(&range(8_000, 48_000, SampleFormat::F32)).is_none());
+
+        let format = both.clone().with_sample_rate(SampleRate(48_000)).unwrap();
+        assert!(input.contains(&format) && output.contains(&format));
+        assert!(!output.contains(&Format { sample_rate: SampleRate(8_000), ..format }));
+        assert!(both.with_sample_rate(SampleRate(96_000)).is_none());
+
+        let formats = [output.clone(), range(8_000, 192_000, SampleFormat::U16), input];
+        assert_eq!(formats.iter().max_by(|a, b| a.cmp_quality(b)), Some(&output));
+    }
+
+    #[test]
+    fn raw_output_buffer_fills_silence() {
+        let mut samples = [0u16; 4];
+        {
+            let buffer = UnknownTypeOutputBuffer::U16(OutputBuffer::new(&mut samples));
+            let mut raw = RawOutputBuffer::from(buffer);
+            assert_eq!(raw.sample_format(), SampleFormat::U16);
+            assert_eq!(raw.len(), 8);
+            raw.fill_silence();
+            assert!(raw.as_slice_mut::<i16>().is_none());
+            assert_eq!(raw.as_slice_mut::<u16>().unwrap(), &[32768; 4]);
+        }
+        assert_eq!(samples, [32768; 4]);
+    }
+}
@@ -1,7 +1,8 @@
-use std::mem;
+use core::mem;
 
 /// Format that each sample has.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum SampleFormat {
     /// The value 0 corresponds to 0.
     I16,
@@ -90,9 +91,9 @@ unsafe impl Sample for i16 {
     #[inline]
     fn to_f32(&self) -> f32 {
         if *self < 0 {
-            *self as f32 / -(::std::i16::MIN as f32)
+            *self as f32 / -(::core::i16::MIN as f32)
         } else {
-            *self as f32 / ::std::i16::MAX as f32
+            *self as f32 / ::core::i16::MAX as f32
         }
     }
 
@@ -104,7 +105,7 @@ unsafe impl Sample for i16 {
     #[inline]
     fn to_u16(&self) -> u16 {
         if *self < 0 {
-            (*self - ::std::i16::MIN) as u16
+            (*self - ::core::i16::MIN) as u16
         } else {
             (*self as u16) + 32768
         }
@@ -132,15 +133,15 @@ unsafe impl Sample for f32 {
     #[inline]
     fn to_i16(&self) -> i16 {
         if *self >= 0.0 {
-            (*self * ::std::i16::MAX as f32) as i16
+            (*self * ::core::i16::MAX as f32) as i16
         } else {
-            (-*self * ::std::i16::MIN as f32) as i16
+            (-*self * ::core::i16::MIN as f32) as i16
         }
     }
 
     #[inline]
     fn to_u16(&self) -> u16 {
-        (((*self + 1.0) * 0.5) * ::std::u16::MAX as f32).round() as u16
+        round(((*self + 1.0) * 0.5) * ::core::u16::MAX as f32) as u16
     }
 
     #[inline]
@@ -151,6 +152,19 @@ unsafe impl Sample for f32 {
     }
 }
 
+// `f32::round` is only available with `std`.
+#[cfg(feature = "std")]
+#[inline]
+fn round(x: f32) -> f32 {
+    x.round()
+}
+
+#[cfg(not(feature = "std"))]
+#[inline]
+fn round(x: f32) -> f32 {
+    ::libm::roundf(x)
+}
+
 #[cfg(test)]
 mod test {
     use super::Sample;
@@ -205,9 +219,9 @@ mod test {
     #[test]
     fn f32_to_i16() {
         assert_eq!(0.0f32.to_i16(), 0);
-        assert_eq!((-0.5f32).to_i16(), ::std::i16::MIN / 2);
-        assert_eq!(1.0f32.to_i16(), ::std::i16::MAX);
-        assert_eq!((-1.0f32).to_i16(), ::std::i16::MIN);
+        assert_eq!((-0.5f32).to_i16(), ::core::i16::MIN / 2);
+        assert_eq!(1.0f32.to_i16(), ::core::i16::MAX);
+        assert_eq!((-1.0f32).to_i16(), ::core::i16::MIN);
     }
 
     #[test]
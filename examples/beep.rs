@@ -7,8 +7,20 @@ fn main() -> Result<(), anyhow::Error> {
     let host = cpal::default_host();
     let device = host.default_output_device().expect("failed to find a default output device");
     let format = device.default_output_format()?;
+
+    match format.data_type {
+        cpal::SampleFormat::F32 => run::<f32>(&device, &format),
+        cpal::SampleFormat::I16 => run::<i16>(&device, &format),
+        cpal::SampleFormat::U16 => run::<u16>(&device, &format),
+    }
+}
+
+fn run<T>(device: &cpal::Device, format: &cpal::Format) -> Result<(), anyhow::Error>
+where
+    T: cpal::Sample + 'static,
+{
     let sample_rate = format.sample_rate.0 as f32;
-    let channels = format.channels;
+    let channels = format.channels as usize;
     let mut sample_clock = 0f32;
 
     // Produce a sinusoid of maximum amplitude.
@@ -17,33 +29,12 @@ fn main() -> Result<(), anyhow::Error> {
         (sample_clock * 440.0 * 2.0 * 3.141592 / sample_rate).sin()
     };
 
-    let stream = device.build_output_stream(&format, move |data| {
-        match data {
-            cpal::StreamData::Output { buffer: cpal::UnknownTypeOutputBuffer::U16(mut buffer) } => {
-                for sample in buffer.chunks_mut(channels as usize) {
-                    let value = ((next_value() * 0.5 + 0.5) * std::u16::MAX as f32) as u16;
-                    for out in sample.iter_mut() {
-                        *out = value;
-                    }
-                }
-            },
-            cpal::StreamData::Output { buffer: cpal::UnknownTypeOutputBuffer::I16(mut buffer) } => {
-                for sample in buffer.chunks_mut(channels as usize) {
-                    let value = (next_value() * std::i16::MAX as f32) as i16;
-                    for out in sample.iter_mut() {
-                        *out = value;
-                    }
-                }
-            },
-            cpal::StreamData::Output { buffer: cpal::UnknownTypeOutputBuffer::F32(mut buffer) } => {
-                for sample in buffer.chunks_mut(channels as usize) {
-                    let value = next_value();
-                    for out in sample.iter_mut() {
-                        *out = value;
-                    }
-                }
-            },
-            _ => (),
+    let stream = device.build_output_stream(format, move |data: &mut [T], _: &_| {
+        for frame in data.chunks_mut(channels) {
+            let value: T = cpal::Sample::from::<f32>(&next_value());
+            for sample in frame.iter_mut() {
+                *sample = value;
+            }
         }
     }, move |err| {
         eprintln!("an error occurred on stream: {}", err);
@@ -7,6 +7,11 @@ fn main() -> Result<(), anyhow::Error> {
     println!("Supported hosts:\n  {:?}", cpal::ALL_HOSTS);
     let available_hosts = cpal::available_hosts();
     println!("Available hosts:\n  {:?}", available_hosts);
+    for &host_id in cpal::ALL_HOSTS {
+        if let Err(err) = cpal::host_availability(host_id) {
+            println!("{} is unavailable: {}", host_id.name(), err);
+        }
+    }
 
     for host_id in available_hosts {
         println!("{}", host_id.name());
@@ -8,10 +8,9 @@
 
 extern crate anyhow;
 extern crate cpal;
-extern crate ringbuf;
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use ringbuf::RingBuffer;
+use cpal::ring_buffer::ring_buffer;
 
 const LATENCY_MS: f32 = 150.0;
 
@@ -37,8 +36,7 @@ fn main() -> Result<(), anyhow::Error> {
     let latency_samples = latency_frames as usize * format.channels as usize;
 
     // The buffer to share samples
-    let ring = RingBuffer::new(latency_samples * 2);
-    let (mut producer, mut consumer) = ring.split();
+    let (producer, consumer) = ring_buffer(latency_samples * 2);
 
     // Fill the samples with 0.0 equal to the length of the delay.
     for _ in 0..latency_samples {
@@ -49,46 +47,20 @@ fn main() -> Result<(), anyhow::Error> {
 
     // Build streams.
     println!("Attempting to build both streams with `{:?}`.", format);
-    let input_stream = input_device.build_input_stream(&format, move |data| {
-        match data {
-            cpal::StreamData::Input {
-                buffer: cpal::UnknownTypeInputBuffer::F32(buffer),
-            } => {
-                let mut output_fell_behind = false;
-                for &sample in buffer.iter() {
-                    if producer.push(sample).is_err() {
-                        output_fell_behind = true;
-                    }
-                }
-                if output_fell_behind {
-                    eprintln!("output stream fell behind: try increasing latency");
-                }
-            },
-            _ => panic!("Expected input with f32 data"),
+    let input_stream = input_device.build_input_stream(&format, move |data: &[f32], _: &_| {
+        if producer.push_slice(data) < data.len() {
+            eprintln!("output stream fell behind: try increasing latency");
         }
     }, move |err| {
         eprintln!("an error occurred on input stream: {}", err);
     })?;
-    let output_stream = output_device.build_output_stream(&format, move |data| {
-        match data {
-            cpal::StreamData::Output {
-                buffer: cpal::UnknownTypeOutputBuffer::F32(mut buffer),
-            } => {
-                let mut input_fell_behind = None;
-                for sample in buffer.iter_mut() {
-                    *sample = match consumer.pop() {
-                        Ok(s) => s,
-                        Err(err) => {
-                            input_fell_behind = Some(err);
-                            0.0
-                        },
-                    };
-                }
-                if let Some(err) = input_fell_behind {
-                    eprintln!("input stream fell behind: {:?}: try increasing latency", err);
-                }
-            },
-            _ => panic!("Expected output with f32 data"),
+    let output_stream = output_device.build_output_stream(&format, move |data: &mut [f32], _: &_| {
+        let popped = consumer.pop_slice(data);
+        if popped < data.len() {
+            for sample in data[popped..].iter_mut() {
+                *sample = 0.0;
+            }
+            eprintln!("input stream fell behind: try increasing latency");
         }
     }, move |err| {
         eprintln!("an error occurred on output stream: {}", err);
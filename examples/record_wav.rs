@@ -32,48 +32,26 @@ fn main() -> Result<(), anyhow::Error> {
 
     // Run the input stream on a separate thread.
     let writer_2 = writer.clone();
-    let stream = device.build_input_stream(&format, move |data| {
-        // Otherwise write to the wav writer.
-        match data {
-            cpal::StreamData::Input {
-                buffer: cpal::UnknownTypeInputBuffer::U16(buffer),
-            } => {
-                if let Ok(mut guard) = writer_2.try_lock() {
-                    if let Some(writer) = guard.as_mut() {
-                        for sample in buffer.iter() {
-                            let sample = cpal::Sample::to_i16(sample);
-                            writer.write_sample(sample).ok();
-                        }
-                    }
-                }
-            },
-            cpal::StreamData::Input {
-                buffer: cpal::UnknownTypeInputBuffer::I16(buffer),
-            } => {
-                if let Ok(mut guard) = writer_2.try_lock() {
-                    if let Some(writer) = guard.as_mut() {
-                        for &sample in buffer.iter() {
-                            writer.write_sample(sample).ok();
-                        }
-                    }
-                }
-            },
-            cpal::StreamData::Input {
-                buffer: cpal::UnknownTypeInputBuffer::F32(buffer),
-            } => {
-                if let Ok(mut guard) = writer_2.try_lock() {
-                    if let Some(writer) = guard.as_mut() {
-                        for &sample in buffer.iter() {
-                            writer.write_sample(sample).ok();
-                        }
-                    }
-                }
-            },
-            _ => (),
-        }
-    }, move |err| {
+    let err_fn = move |err| {
         eprintln!("an error occurred on stream: {}", err);
-    })?;
+    };
+    let stream = match format.data_type {
+        cpal::SampleFormat::F32 => device.build_input_stream(
+            &format,
+            move |data: &[f32], _: &_| write_input_data::<f32, f32>(data, &writer_2),
+            err_fn,
+        )?,
+        cpal::SampleFormat::I16 => device.build_input_stream(
+            &format,
+            move |data: &[i16], _: &_| write_input_data::<i16, i16>(data, &writer_2),
+            err_fn,
+        )?,
+        cpal::SampleFormat::U16 => device.build_input_stream(
+            &format,
+            move |data: &[u16], _: &_| write_input_data::<u16, i16>(data, &writer_2),
+            err_fn,
+        )?,
+    };
     stream.play()?;
 
     // Let recording go for roughly three seconds.
@@ -92,6 +70,24 @@ fn sample_format(format: cpal::SampleFormat) -> hound::SampleFormat {
     }
 }
 
+type WavWriterHandle =
+    std::sync::Arc<std::sync::Mutex<Option<hound::WavWriter<std::io::BufWriter<std::fs::File>>>>>;
+
+fn write_input_data<T, U>(input: &[T], writer: &WavWriterHandle)
+where
+    T: cpal::Sample,
+    U: cpal::Sample + hound::Sample,
+{
+    if let Ok(mut guard) = writer.try_lock() {
+        if let Some(writer) = guard.as_mut() {
+            for sample in input.iter() {
+                let sample: U = cpal::Sample::from(sample);
+                writer.write_sample(sample).ok();
+            }
+        }
+    }
+}
+
 fn wav_spec_from_format(format: &cpal::Format) -> hound::WavSpec {
     hound::WavSpec {
         channels: format.channels as _,
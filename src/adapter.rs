@@ -0,0 +1,135 @@
+//! The state shared by the adapters that run a stream on behalf of the user, such as the
+//! `stream_io` readers and writers or the `Mixer`, and the helpers of the wrappers of data
+//! callbacks, such as the limiter or the meter.
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "async")]
+use futures::task::AtomicWaker;
+
+use traits::StreamTrait;
+use BuildStreamError;
+use Format;
+use InputBuffer;
+use OutputBuffer;
+use PlayStreamError;
+use StreamData;
+use StreamError;
+use UnknownTypeInputBuffer;
+use UnknownTypeOutputBuffer;
+
+// The state shared between an adapter and the callbacks of its stream.
+pub(crate) struct Shared {
+    // Woken by the data callback after each buffer, and when the stream stops.
+    #[cfg(feature = "async")]
+    pub waker: AtomicWaker,
+    // The error after which the stream no longer calls the data callback.
+    stopped: Mutex<Option<StreamError>>,
+}
+
+impl Shared {
+    pub fn new() -> Self {
+        Shared {
+            #[cfg(feature = "async")]
+            waker: AtomicWaker::new(),
+            stopped: Mutex::new(None),
+        }
+    }
+
+    pub fn stopped(&self) -> Option<StreamError> {
+        self.stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    // Fails with the error that stopped the stream, for the adapters implementing `std::io`
+    // traits.
+    pub fn check_stopped(&self) -> io::Result<()> {
+        match self.stopped() {
+            Some(err) => Err(io::Error::other(err)),
+            None => Ok(()),
+        }
+    }
+
+    // Wakes the task waiting for the stream, if any.
+    pub fn wake(&self) {
+        #[cfg(feature = "async")]
+        self.waker.wake();
+    }
+
+    // Records the errors after which the stream no longer calls the data callback, so that the
+    // adapter fails or ends instead of waiting forever.
+    pub fn error_callback(self: &Arc<Self>) -> impl FnMut(StreamError) + Send + 'static {
+        let shared = self.clone();
+        move |err| match err {
+            StreamError::DeviceNotAvailable { .. } | StreamError::CallbackPanicked { .. } => {
+                {
+                    let mut stopped =
+                        shared.stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    if stopped.is_none() {
+                        *stopped = Some(err);
+                    }
+                }
+                shared.wake();
+            },
+            _ => (),
+        }
+    }
+}
+
+// The number of bytes of a frame in the raw buffers of a stream of `format`.
+pub(crate) fn frame_bytes(format: &Format) -> usize {
+    format.channels as usize * format.data_type.sample_size()
+}
+
+// Starts a stream that was just built, reporting failures as build errors.
+pub(crate) fn play<S: StreamTrait>(stream: &S) -> Result<(), BuildStreamError> {
+    stream.play().map_err(|err| match err {
+        PlayStreamError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+        PlayStreamError::BackendSpecific { err } => BuildStreamError::BackendSpecific { err },
+    })
+}
+
+// Passes `data` to `data_callback`, then to `inspect` once the callback has returned, so that a
+// wrapper of the callback can read or modify the samples that the callback read or wrote.
+pub(crate) fn call_then<D, F>(mut data: StreamData, data_callback: &mut D, inspect: F)
+where
+    D: FnMut(StreamData),
+    F: FnOnce(StreamData),
+{
+    data_callback(reborrow(&mut data));
+    inspect(data);
+}
+
+// The buffer of `data`, borrowed for the duration of a call to the data callback.
+fn reborrow<'a>(data: &'a mut StreamData) -> StreamData<'a> {
+    match *data {
+        StreamData::Input { ref buffer, info } => {
+            let buffer = match *buffer {
+                UnknownTypeInputBuffer::U16(ref b) => {
+                    UnknownTypeInputBuffer::U16(InputBuffer::new(b))
+                },
+                UnknownTypeInputBuffer::I16(ref b) => {
+                    UnknownTypeInputBuffer::I16(InputBuffer::new(b))
+                },
+                UnknownTypeInputBuffer::F32(ref b) => {
+                    UnknownTypeInputBuffer::F32(InputBuffer::new(b))
+                },
+            };
+            StreamData::Input { buffer, info }
+        },
+        StreamData::Output { ref mut buffer, info } => {
+            let buffer = match *buffer {
+                UnknownTypeOutputBuffer::U16(ref mut b) => {
+                    UnknownTypeOutputBuffer::U16(OutputBuffer::new(b))
+                },
+                UnknownTypeOutputBuffer::I16(ref mut b) => {
+                    UnknownTypeOutputBuffer::I16(OutputBuffer::new(b))
+                },
+                UnknownTypeOutputBuffer::F32(ref mut b) => {
+                    UnknownTypeOutputBuffer::F32(OutputBuffer::new(b))
+                },
+            };
+            StreamData::Output { buffer, info }
+        },
+    }
+}
@@ -0,0 +1,740 @@
+//! A C API over hosts, devices and streams.
+//!
+//! This module is only available with the `capi` feature. It exposes the default host of the
+//! platform to C, C++ and other languages through `extern "C"` functions operating on opaque
+//! handles. A C header can be generated with `cbindgen --lang c`, with `prefix_with_name = true`
+//! in the `[enum]` section of its configuration so that the enum constants do not clash. To link
+//! the functions, build a `staticlib` or `cdylib` crate that depends on CPAL with this feature.
+//!
+//! Conventions:
+//!
+//! - Handles are created by the functions of this module and released with the matching `_free`
+//!   function. A handle must not be used after it was released.
+//! - Functions that can fail return a `CpalError`, which is `CpalError::Ok` on success, and write
+//!   their result through an out pointer. Functions returning a size return a negated `CpalError`
+//!   on failure.
+//! - Hosts, devices and device iterators may be used from any thread. A stream must be played,
+//!   paused and released on the thread that built it.
+//! - Callbacks are called on the audio thread with the `user_data` given when building the stream.
+//!   They must not block, and `user_data` must be safe to use from that thread.
+//! - Panics never cross the API: they are reported as `CpalError::Panicked`.
+//!
+//! # Safety
+//!
+//! Every function dereferences the pointers it is given. Handles must have been created by this
+//! module and not released yet, out pointers must be valid for writes and buffers must be valid
+//! for their stated length.
+
+use std::ffi::c_void;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use traits::{DeviceTrait, HostTrait, StreamTrait};
+use BuildStreamError;
+use DefaultFormatError;
+use Device;
+use DeviceNameError;
+use Devices;
+use Format;
+use Host;
+use PauseStreamError;
+use PlayStreamError;
+use SampleFormat;
+use SampleRate;
+use Stream;
+use StreamData;
+use StreamError;
+use StreamOptions;
+use SupportedFormat;
+use SupportedFormatsError;
+use UnknownTypeInputBuffer;
+use UnknownTypeOutputBuffer;
+
+/// A host, released with `cpal_host_free`.
+pub struct CpalHost(Host);
+
+/// A device, released with `cpal_device_free`.
+pub struct CpalDevice(Device);
+
+/// An iterator over the devices of a host, released with `cpal_devices_free`.
+pub struct CpalDevices(Devices);
+
+/// A stream, released with `cpal_stream_free`.
+pub struct CpalStream(Stream);
+
+/// The outcome of a function of the C API, or an error reported by a stream.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpalError {
+    Ok = 0,
+    /// A pointer was null or an argument was out of range.
+    InvalidArgument = 1,
+    DeviceNotAvailable = 2,
+    StreamTypeNotSupported = 3,
+    FormatNotSupported = 4,
+    DeviceInUse = 5,
+    AccessDenied = 6,
+    /// The device did not answer in time.
+    Timeout = 7,
+    /// An output stream ran out of data.
+    Underrun = 8,
+    /// An input stream discarded captured data.
+    Overrun = 9,
+    /// The device was reconfigured and the stream adapted to it.
+    DeviceReconfigured = 10,
+    /// An error of the host that has no other code.
+    BackendSpecific = 11,
+    /// CPAL panicked. The stream stopped if the panic happened on its audio thread.
+    Panicked = 12,
+}
+
+/// The format of the samples of a stream, passed as a `u32` in the format structures.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpalSampleFormat {
+    I16 = 0,
+    U16 = 1,
+    F32 = 2,
+}
+
+/// The format of a stream.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpalFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// A `CpalSampleFormat`. Other values are rejected as `CpalError::InvalidArgument`.
+    pub sample_format: u32,
+}
+
+/// A range of formats supported by a device.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpalSupportedFormat {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    /// A `CpalSampleFormat`.
+    pub sample_format: u32,
+}
+
+/// Receives the `len` interleaved samples captured by an input stream.
+pub type CpalInputCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, samples: *const c_void, len: usize)>;
+
+/// Fills the `len` interleaved samples to be played by an output stream.
+pub type CpalOutputCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, samples: *mut c_void, len: usize)>;
+
+/// Receives the errors reported by a stream.
+pub type CpalErrorCallback = Option<unsafe extern "C" fn(user_data: *mut c_void, error: CpalError)>;
+
+// The user data passed to the callbacks of a stream, which the caller vouches for.
+#[derive(Clone, Copy)]
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+/// Creates the default host of the platform.
+///
+/// Returns null if CPAL panicked.
+#[no_mangle]
+pub extern "C" fn cpal_default_host() -> *mut CpalHost {
+    catch(ptr::null_mut(), || Box::into_raw(Box::new(CpalHost(::default_host()))))
+}
+
+/// Releases a host. Devices and streams of the host remain valid.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_host_free(host: *mut CpalHost) {
+    free(host)
+}
+
+/// Copies the name of a host into `buf`, see `cpal_device_name`.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_host_name(
+    host: *const CpalHost,
+    buf: *mut c_char,
+    len: usize,
+) -> isize {
+    let host = match host.as_ref() {
+        Some(host) => host,
+        None => return -(CpalError::InvalidArgument as isize),
+    };
+    copy_str(host.0.id().name(), buf, len)
+}
+
+/// Enumerates the devices of a host.
+///
+/// Returns null if the devices cannot be enumerated.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_host_devices(host: *const CpalHost) -> *mut CpalDevices {
+    let host = match host.as_ref() {
+        Some(host) => host,
+        None => return ptr::null_mut(),
+    };
+    catch(ptr::null_mut(), || match host.0.devices() {
+        Ok(devices) => Box::into_raw(Box::new(CpalDevices(devices))),
+        Err(_) => ptr::null_mut(),
+    })
+}
+
+/// The next device of an enumeration, or null once all devices were returned.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_devices_next(devices: *mut CpalDevices) -> *mut CpalDevice {
+    let devices = match devices.as_mut() {
+        Some(devices) => devices,
+        None => return ptr::null_mut(),
+    };
+    catch(ptr::null_mut(), || match devices.0.next() {
+        Some(device) => Box::into_raw(Box::new(CpalDevice(device))),
+        None => ptr::null_mut(),
+    })
+}
+
+/// Releases an enumeration of devices. The devices it returned remain valid.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_devices_free(devices: *mut CpalDevices) {
+    free(devices)
+}
+
+/// The default input device of a host, or null if there is none.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_host_default_input_device(host: *const CpalHost) -> *mut CpalDevice {
+    match host.as_ref() {
+        Some(host) => catch(ptr::null_mut(), || new_device(host.0.default_input_device())),
+        None => ptr::null_mut(),
+    }
+}
+
+/// The default output device of a host, or null if there is none.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_host_default_output_device(host: *const CpalHost) -> *mut CpalDevice {
+    match host.as_ref() {
+        Some(host) => catch(ptr::null_mut(), || new_device(host.0.default_output_device())),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Releases a device. Streams built on the device remain valid.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_free(device: *mut CpalDevice) {
+    free(device)
+}
+
+/// Copies the name of a device into `buf` as a NUL-terminated UTF-8 string, truncated to `len`
+/// bytes if needed.
+///
+/// Returns the size of the whole name including the NUL terminator, like `snprintf`, or a negated
+/// `CpalError`.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_name(
+    device: *const CpalDevice,
+    buf: *mut c_char,
+    len: usize,
+) -> isize {
+    let device = match device.as_ref() {
+        Some(device) => device,
+        None => return -(CpalError::InvalidArgument as isize),
+    };
+    let name = catch(Err(CpalError::Panicked), || {
+        device.0.name().map_err(CpalError::from)
+    });
+    match name {
+        Ok(name) => copy_str(&name, buf, len),
+        Err(err) => -(err as isize),
+    }
+}
+
+/// Writes the default input format of a device to `format`.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_default_input_format(
+    device: *const CpalDevice,
+    format: *mut CpalFormat,
+) -> CpalError {
+    match device.as_ref() {
+        Some(device) => write_format(|| device.0.default_input_format(), format),
+        None => CpalError::InvalidArgument,
+    }
+}
+
+/// Writes the default output format of a device to `format`.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_default_output_format(
+    device: *const CpalDevice,
+    format: *mut CpalFormat,
+) -> CpalError {
+    match device.as_ref() {
+        Some(device) => write_format(|| device.0.default_output_format(), format),
+        None => CpalError::InvalidArgument,
+    }
+}
+
+/// Writes up to `capacity` input formats supported by a device to `formats`.
+///
+/// Returns the number of supported formats, which may exceed `capacity`, or a negated
+/// `CpalError`.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_supported_input_formats(
+    device: *const CpalDevice,
+    formats: *mut CpalSupportedFormat,
+    capacity: usize,
+) -> isize {
+    match device.as_ref() {
+        Some(device) => {
+            let query = || device.0.supported_input_formats().map(|f| f.collect());
+            write_supported_formats(query, formats, capacity)
+        },
+        None => -(CpalError::InvalidArgument as isize),
+    }
+}
+
+/// Writes up to `capacity` output formats supported by a device to `formats`, see
+/// `cpal_device_supported_input_formats`.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_supported_output_formats(
+    device: *const CpalDevice,
+    formats: *mut CpalSupportedFormat,
+    capacity: usize,
+) -> isize {
+    match device.as_ref() {
+        Some(device) => {
+            let query = || device.0.supported_output_formats().map(|f| f.collect());
+            write_supported_formats(query, formats, capacity)
+        },
+        None => -(CpalError::InvalidArgument as isize),
+    }
+}
+
+/// Builds an input stream on a device and writes it to `stream`.
+///
+/// `data_callback` is called with the samples captured in `format.sample_format`.
+/// `error_callback` may be null.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_build_input_stream(
+    device: *const CpalDevice,
+    format: *const CpalFormat,
+    data_callback: CpalInputCallback,
+    error_callback: CpalErrorCallback,
+    user_data: *mut c_void,
+    stream: *mut *mut CpalStream,
+) -> CpalError {
+    let (device, format, data_callback) = match (device.as_ref(), format.as_ref(), data_callback) {
+        (Some(device), Some(format), Some(data_callback)) if !stream.is_null() => {
+            (device, format, data_callback)
+        },
+        _ => return CpalError::InvalidArgument,
+    };
+    let format = match format_from_c(format) {
+        Some(format) => format,
+        None => return CpalError::InvalidArgument,
+    };
+    let user_data = UserData(user_data);
+    let result = catch(Err(CpalError::Panicked), || {
+        device.0.build_input_stream_dyn(
+            &format,
+            &StreamOptions::default(),
+            move |data| {
+                let (samples, len) = match data {
+                    StreamData::Input { buffer, .. } => input_samples(&buffer),
+                    StreamData::Output { .. } => return,
+                };
+                unsafe { data_callback(user_data.0, samples, len) };
+            },
+            move |err| report(error_callback, user_data, err),
+        )
+        .map_err(CpalError::from)
+    });
+    write_stream(result, stream)
+}
+
+/// Builds an output stream on a device and writes it to `stream`.
+///
+/// `data_callback` must fill the samples to play in `format.sample_format`. `error_callback` may
+/// be null.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_device_build_output_stream(
+    device: *const CpalDevice,
+    format: *const CpalFormat,
+    data_callback: CpalOutputCallback,
+    error_callback: CpalErrorCallback,
+    user_data: *mut c_void,
+    stream: *mut *mut CpalStream,
+) -> CpalError {
+    let (device, format, data_callback) = match (device.as_ref(), format.as_ref(), data_callback) {
+        (Some(device), Some(format), Some(data_callback)) if !stream.is_null() => {
+            (device, format, data_callback)
+        },
+        _ => return CpalError::InvalidArgument,
+    };
+    let format = match format_from_c(format) {
+        Some(format) => format,
+        None => return CpalError::InvalidArgument,
+    };
+    let user_data = UserData(user_data);
+    let result = catch(Err(CpalError::Panicked), || {
+        device.0.build_output_stream_dyn(
+            &format,
+            &StreamOptions::default(),
+            move |data| {
+                let (samples, len) = match data {
+                    StreamData::Output { mut buffer, .. } => output_samples(&mut buffer),
+                    StreamData::Input { .. } => return,
+                };
+                unsafe { data_callback(user_data.0, samples, len) };
+            },
+            move |err| report(error_callback, user_data, err),
+        )
+        .map_err(CpalError::from)
+    });
+    write_stream(result, stream)
+}
+
+/// Starts or resumes a stream.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_stream_play(stream: *const CpalStream) -> CpalError {
+    match stream.as_ref() {
+        Some(stream) => catch(CpalError::Panicked, || to_code(stream.0.play())),
+        None => CpalError::InvalidArgument,
+    }
+}
+
+/// Pauses a stream.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_stream_pause(stream: *const CpalStream) -> CpalError {
+    match stream.as_ref() {
+        Some(stream) => catch(CpalError::Panicked, || to_code(stream.0.pause())),
+        None => CpalError::InvalidArgument,
+    }
+}
+
+/// Stops and releases a stream. Its callbacks are not called anymore once this returns.
+///
+/// # Safety
+///
+/// See the module documentation.
+#[no_mangle]
+pub unsafe extern "C" fn cpal_stream_free(stream: *mut CpalStream) {
+    free(stream)
+}
+
+// Runs `f`, returning `default` if it panics.
+fn catch<T, F: FnOnce() -> T>(default: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(default)
+}
+
+unsafe fn free<T>(handle: *mut T) {
+    if !handle.is_null() {
+        catch((), || drop(Box::from_raw(handle)));
+    }
+}
+
+fn new_device(device: Option<Device>) -> *mut CpalDevice {
+    match device {
+        Some(device) => Box::into_raw(Box::new(CpalDevice(device))),
+        None => ptr::null_mut(),
+    }
+}
+
+// Copies `s` into `buf` like `snprintf` does.
+unsafe fn copy_str(s: &str, buf: *mut c_char, len: usize) -> isize {
+    if !buf.is_null() && len > 0 {
+        let copied = s.len().min(len - 1);
+        ptr::copy_nonoverlapping(s.as_ptr() as *const c_char, buf, copied);
+        *buf.add(copied) = 0;
+    }
+    s.len() as isize + 1
+}
+
+unsafe fn write_format<F>(query: F, format: *mut CpalFormat) -> CpalError
+where
+    F: FnOnce() -> Result<Format, DefaultFormatError>,
+{
+    if format.is_null() {
+        return CpalError::InvalidArgument;
+    }
+    match catch(Err(CpalError::Panicked), || query().map_err(CpalError::from)) {
+        Ok(f) => {
+            *format = CpalFormat::from(&f);
+            CpalError::Ok
+        },
+        Err(err) => err,
+    }
+}
+
+unsafe fn write_supported_formats<F>(
+    query: F,
+    formats: *mut CpalSupportedFormat,
+    capacity: usize,
+) -> isize
+where
+    F: FnOnce() -> Result<Vec<SupportedFormat>, SupportedFormatsError>,
+{
+    if formats.is_null() && capacity > 0 {
+        return -(CpalError::InvalidArgument as isize);
+    }
+    match catch(Err(CpalError::Panicked), || query().map_err(CpalError::from)) {
+        Ok(supported) => {
+            for (i, f) in supported.iter().take(capacity).enumerate() {
+                *formats.add(i) = CpalSupportedFormat::from(f);
+            }
+            supported.len() as isize
+        },
+        Err(err) => -(err as isize),
+    }
+}
+
+unsafe fn write_stream(
+    result: Result<Stream, CpalError>,
+    stream: *mut *mut CpalStream,
+) -> CpalError {
+    match result {
+        Ok(s) => {
+            *stream = Box::into_raw(Box::new(CpalStream(s)));
+            CpalError::Ok
+        },
+        Err(err) => err,
+    }
+}
+
+fn report(error_callback: CpalErrorCallback, user_data: UserData, err: StreamError) {
+    if let Some(error_callback) = error_callback {
+        unsafe { error_callback(user_data.0, CpalError::from(err)) }
+    }
+}
+
+fn input_samples(buffer: &UnknownTypeInputBuffer) -> (*const c_void, usize) {
+    match *buffer {
+        UnknownTypeInputBuffer::I16(ref b) => (b.as_ptr() as *const c_void, b.len()),
+        UnknownTypeInputBuffer::U16(ref b) => (b.as_ptr() as *const c_void, b.len()),
+        UnknownTypeInputBuffer::F32(ref b) => (b.as_ptr() as *const c_void, b.len()),
+    }
+}
+
+fn output_samples(buffer: &mut UnknownTypeOutputBuffer) -> (*mut c_void, usize) {
+    match *buffer {
+        UnknownTypeOutputBuffer::I16(ref mut b) => (b.as_mut_ptr() as *mut c_void, b.len()),
+        UnknownTypeOutputBuffer::U16(ref mut b) => (b.as_mut_ptr() as *mut c_void, b.len()),
+        UnknownTypeOutputBuffer::F32(ref mut b) => (b.as_mut_ptr() as *mut c_void, b.len()),
+    }
+}
+
+fn to_code<E: Into<CpalError>>(result: Result<(), E>) -> CpalError {
+    match result {
+        Ok(()) => CpalError::Ok,
+        Err(err) => err.into(),
+    }
+}
+
+// The format of a stream requested by C, or `None` if its sample format is not a
+// `CpalSampleFormat`.
+fn format_from_c(format: &CpalFormat) -> Option<Format> {
+    let data_type = match format.sample_format {
+        x if x == CpalSampleFormat::I16 as u32 => SampleFormat::I16,
+        x if x == CpalSampleFormat::U16 as u32 => SampleFormat::U16,
+        x if x == CpalSampleFormat::F32 as u32 => SampleFormat::F32,
+        _ => return None,
+    };
+    Some(Format {
+        channels: format.channels,
+        sample_rate: SampleRate(format.sample_rate),
+        data_type,
+    })
+}
+
+impl From<SampleFormat> for CpalSampleFormat {
+    fn from(sample_format: SampleFormat) -> Self {
+        match sample_format {
+            SampleFormat::I16 => CpalSampleFormat::I16,
+            SampleFormat::U16 => CpalSampleFormat::U16,
+            SampleFormat::F32 => CpalSampleFormat::F32,
+        }
+    }
+}
+
+impl<'a> From<&'a Format> for CpalFormat {
+    fn from(format: &'a Format) -> Self {
+        CpalFormat {
+            channels: format.channels,
+            sample_rate: format.sample_rate.0,
+            sample_format: CpalSampleFormat::from(format.data_type) as u32,
+        }
+    }
+}
+
+impl<'a> From<&'a SupportedFormat> for CpalSupportedFormat {
+    fn from(format: &'a SupportedFormat) -> Self {
+        CpalSupportedFormat {
+            channels: format.channels,
+            min_sample_rate: format.min_sample_rate.0,
+            max_sample_rate: format.max_sample_rate.0,
+            sample_format: CpalSampleFormat::from(format.data_type) as u32,
+        }
+    }
+}
+
+impl From<DeviceNameError> for CpalError {
+    fn from(err: DeviceNameError) -> Self {
+        match err {
+            DeviceNameError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<SupportedFormatsError> for CpalError {
+    fn from(err: SupportedFormatsError) -> Self {
+        match err {
+            SupportedFormatsError::DeviceNotAvailable => CpalError::DeviceNotAvailable,
+            SupportedFormatsError::InvalidArgument => CpalError::InvalidArgument,
+            SupportedFormatsError::Timeout => CpalError::Timeout,
+            SupportedFormatsError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<DefaultFormatError> for CpalError {
+    fn from(err: DefaultFormatError) -> Self {
+        match err {
+            DefaultFormatError::DeviceNotAvailable => CpalError::DeviceNotAvailable,
+            DefaultFormatError::StreamTypeNotSupported => {
+                CpalError::StreamTypeNotSupported
+            },
+            DefaultFormatError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<BuildStreamError> for CpalError {
+    fn from(err: BuildStreamError) -> Self {
+        match err {
+            BuildStreamError::DeviceNotAvailable => CpalError::DeviceNotAvailable,
+            BuildStreamError::FormatNotSupported
+            | BuildStreamError::InvalidChannelCount
+            | BuildStreamError::SampleRateNotSupported
+            | BuildStreamError::SampleFormatNotSupported => {
+                CpalError::FormatNotSupported
+            },
+            BuildStreamError::DeviceInUse => CpalError::DeviceInUse,
+            BuildStreamError::AccessDenied | BuildStreamError::SandboxAccessDenied => {
+                CpalError::AccessDenied
+            },
+            BuildStreamError::InvalidArgument => CpalError::InvalidArgument,
+            BuildStreamError::StreamIdOverflow | BuildStreamError::BackendSpecific { .. } => {
+                CpalError::BackendSpecific
+            },
+        }
+    }
+}
+
+impl From<PlayStreamError> for CpalError {
+    fn from(err: PlayStreamError) -> Self {
+        match err {
+            PlayStreamError::DeviceNotAvailable => CpalError::DeviceNotAvailable,
+            PlayStreamError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<PauseStreamError> for CpalError {
+    fn from(err: PauseStreamError) -> Self {
+        match err {
+            PauseStreamError::DeviceNotAvailable => CpalError::DeviceNotAvailable,
+            PauseStreamError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+impl From<StreamError> for CpalError {
+    fn from(err: StreamError) -> Self {
+        match err {
+            StreamError::DeviceNotAvailable { .. } => CpalError::DeviceNotAvailable,
+            StreamError::Underrun { .. } => CpalError::Underrun,
+            StreamError::Overrun { .. } => CpalError::Overrun,
+            StreamError::CallbackPanicked { .. } => CpalError::Panicked,
+            StreamError::DeviceReconfigured { .. } => CpalError::DeviceReconfigured,
+            StreamError::BackendSpecific { .. } => CpalError::BackendSpecific,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::copy_str;
+    use std::os::raw::c_char;
+
+    #[test]
+    fn names_are_truncated_like_snprintf() {
+        let mut buf = [1 as c_char; 4];
+        assert_eq!(unsafe { copy_str("device", buf.as_mut_ptr(), buf.len()) }, 7);
+        assert_eq!(buf, [b'd' as c_char, b'e' as c_char, b'v' as c_char, 0]);
+        assert_eq!(unsafe { copy_str("device", ::std::ptr::null_mut(), 0) }, 7);
+    }
+}
@@ -0,0 +1,128 @@
+//! Capturing an input stream into a ring buffer, see `DeviceTrait::capture_to_ring`.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use adapter::{play, Shared};
+use ring_buffer::{ring_buffer, Consumer};
+use traits::{DeviceTrait, StreamTrait};
+use BuildStreamError;
+use Format;
+use Sample;
+use StreamError;
+
+/// An input stream pushing the samples it captures to a ring buffer.
+///
+/// The samples are interleaved and the ring buffer only receives whole frames. The frames that do
+/// not fit in the ring buffer are dropped. Dropping the `CaptureRing` stops the stream.
+pub struct CaptureRing<S, T> {
+    stream: S,
+    consumer: Consumer<T>,
+    dropped_frames: Arc<AtomicUsize>,
+    shared: Arc<Shared>,
+}
+
+impl<S, T> CaptureRing<S, T>
+where
+    S: StreamTrait,
+    T: Sample + Send + 'static,
+{
+    pub(crate) fn new<D>(
+        device: &D,
+        format: &Format,
+        capacity: usize,
+    ) -> Result<Self, BuildStreamError>
+    where
+        D: DeviceTrait<Stream = S> + ?Sized,
+    {
+        let frame_len = (format.channels as usize).max(1);
+        let (producer, consumer) = ring_buffer(capacity);
+        let dropped_frames = Arc::new(AtomicUsize::new(0));
+        let shared = Arc::new(Shared::new());
+        let callback_dropped_frames = dropped_frames.clone();
+        let stream = device.build_input_stream(
+            format,
+            move |data: &[T], _: &_| {
+                let pushed = producer.push_frames(data, frame_len);
+                if pushed < data.len() {
+                    let dropped = (data.len() - pushed) / frame_len;
+                    callback_dropped_frames.fetch_add(dropped, Ordering::Relaxed);
+                }
+            },
+            shared.error_callback(),
+        )?;
+        play(&stream)?;
+        Ok(CaptureRing {
+            stream,
+            consumer,
+            dropped_frames,
+            shared,
+        })
+    }
+}
+
+impl<S, T: Copy> CaptureRing<S, T> {
+    /// Removes the oldest samples from the ring buffer into the beginning of `values` and returns
+    /// how many were popped.
+    pub fn pop_slice(&self, values: &mut [T]) -> usize {
+        self.consumer.pop_slice(values)
+    }
+}
+
+impl<S, T> CaptureRing<S, T> {
+    /// The number of samples in the ring buffer.
+    pub fn len(&self) -> usize {
+        self.consumer.len()
+    }
+
+    /// Whether the ring buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.consumer.is_empty()
+    }
+
+    /// The maximum number of samples in the ring buffer.
+    pub fn capacity(&self) -> usize {
+        self.consumer.capacity()
+    }
+
+    /// The number of captured frames that were dropped because the ring buffer was full.
+    pub fn dropped_frames(&self) -> usize {
+        self.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    /// The error after which the stream stopped capturing, if any.
+    pub fn error(&self) -> Option<StreamError> {
+        self.shared.stopped()
+    }
+
+    /// The input stream.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+#[cfg(all(test, feature = "virtual-device"))]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+    use traits::DeviceTrait;
+    use virtual_device::VirtualDevice;
+    use {Format, SampleFormat, SampleRate};
+
+    #[test]
+    fn full_ring_drops_whole_frames() {
+        let format = Format {
+            channels: 2,
+            sample_rate: SampleRate(48_000),
+            data_type: SampleFormat::F32,
+        };
+        let mut device = VirtualDevice::new("virtual", format.clone());
+        device.real_time = false;
+        let capture = device.capture_to_ring::<f32>(&format, 1_001).unwrap();
+        while capture.dropped_frames() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(capture.len(), 1_000);
+        assert!(capture.error().is_none());
+    }
+}
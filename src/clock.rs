@@ -0,0 +1,180 @@
+//! Conversions between `Instant`s and the monotonic clock of the system.
+//!
+//! The times reported by streams, such as `StreamPosition::time`, are `Instant`s. Media APIs and
+//! input events are timestamped with the raw ticks of the system clock instead: `CLOCK_MONOTONIC`
+//! on Linux, DragonFly and FreeBSD, `QueryPerformanceCounter` on Windows and `mach_absolute_time`
+//! on macOS and iOS. These functions convert between the two, so that audio can be correlated
+//! with input events and video frames.
+//!
+//! The conversions read both clocks one after the other, so they are accurate to a few
+//! microseconds. They return `None` on platforms without a known system clock.
+//!
+//! ```
+//! use std::time::Instant;
+//!
+//! let time = Instant::now();
+//! if let Some(ticks) = cpal::clock::to_ticks(time) {
+//!     let ticks_per_second = cpal::clock::ticks_per_second().unwrap();
+//!     println!("{} ticks at {} ticks per second", ticks, ticks_per_second);
+//! }
+//! ```
+
+use std::time::{Duration, Instant};
+
+/// The current reading of the system clock, in ticks.
+pub fn now() -> Option<u64> {
+    sys::now()
+}
+
+/// The number of ticks of the system clock per second.
+pub fn ticks_per_second() -> Option<u64> {
+    let (numer, denom) = sys::timebase()?;
+    Some((1_000_000_000 * denom as u128 / numer as u128) as u64)
+}
+
+/// The reading of the system clock at `time`, in ticks.
+pub fn to_ticks(time: Instant) -> Option<u64> {
+    let (numer, denom) = sys::timebase()?;
+    let instant_now = Instant::now();
+    let ticks_now = sys::now()?;
+    let ticks = |d: Duration| (d.as_nanos() * denom as u128 / numer as u128) as u64;
+    if time >= instant_now {
+        ticks_now.checked_add(ticks(time - instant_now))
+    } else {
+        ticks_now.checked_sub(ticks(instant_now - time))
+    }
+}
+
+/// The `Instant` at which the system clock read `ticks`.
+///
+/// Returns `None` if the `Instant` cannot be represented, e.g. because it is before the boot of
+/// the system on some platforms.
+pub fn from_ticks(ticks: u64) -> Option<Instant> {
+    let (numer, denom) = sys::timebase()?;
+    let instant_now = Instant::now();
+    let ticks_now = sys::now()?;
+    let nanos = |t: u64| Duration::from_nanos((t as u128 * numer as u128 / denom as u128) as u64);
+    if ticks >= ticks_now {
+        instant_now.checked_add(nanos(ticks - ticks_now))
+    } else {
+        instant_now.checked_sub(nanos(ticks_now - ticks))
+    }
+}
+
+// Each platform provides the ticks of its clock, and the `(numer, denom)` ratio converting ticks to
+// nanoseconds.
+#[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"))]
+mod sys {
+    use std::os::raw::{c_int, c_long};
+
+    #[cfg(target_os = "linux")]
+    const CLOCK_MONOTONIC: c_int = 1;
+    #[cfg(any(target_os = "dragonfly", target_os = "freebsd"))]
+    const CLOCK_MONOTONIC: c_int = 4;
+
+    #[repr(C)]
+    struct Timespec {
+        tv_sec: c_long,
+        tv_nsec: c_long,
+    }
+
+    extern "C" {
+        fn clock_gettime(clock_id: c_int, tp: *mut Timespec) -> c_int;
+    }
+
+    pub fn now() -> Option<u64> {
+        let mut tp = Timespec { tv_sec: 0, tv_nsec: 0 };
+        if unsafe { clock_gettime(CLOCK_MONOTONIC, &mut tp) } != 0 {
+            return None;
+        }
+        Some(tp.tv_sec as u64 * 1_000_000_000 + tp.tv_nsec as u64)
+    }
+
+    pub fn timebase() -> Option<(u64, u64)> {
+        Some((1, 1))
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod sys {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn QueryPerformanceCounter(count: *mut i64) -> i32;
+        fn QueryPerformanceFrequency(frequency: *mut i64) -> i32;
+    }
+
+    pub fn now() -> Option<u64> {
+        let mut count = 0;
+        if unsafe { QueryPerformanceCounter(&mut count) } == 0 {
+            return None;
+        }
+        Some(count as u64)
+    }
+
+    pub fn timebase() -> Option<(u64, u64)> {
+        let mut frequency = 0;
+        if unsafe { QueryPerformanceFrequency(&mut frequency) } == 0 || frequency <= 0 {
+            return None;
+        }
+        Some((1_000_000_000, frequency as u64))
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+mod sys {
+    #[repr(C)]
+    struct MachTimebaseInfo {
+        numer: u32,
+        denom: u32,
+    }
+
+    extern "C" {
+        fn mach_absolute_time() -> u64;
+        fn mach_timebase_info(info: *mut MachTimebaseInfo) -> i32;
+    }
+
+    pub fn now() -> Option<u64> {
+        Some(unsafe { mach_absolute_time() })
+    }
+
+    pub fn timebase() -> Option<(u64, u64)> {
+        let mut timebase = MachTimebaseInfo { numer: 0, denom: 0 };
+        if unsafe { mach_timebase_info(&mut timebase) } != 0 || timebase.denom == 0 {
+            return None;
+        }
+        Some((timebase.numer as u64, timebase.denom as u64))
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "windows",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+mod sys {
+    pub fn now() -> Option<u64> {
+        None
+    }
+
+    pub fn timebase() -> Option<(u64, u64)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{from_ticks, to_ticks};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn ticks_round_trip() {
+        let time = Instant::now() - Duration::from_millis(20);
+        let ticks = to_ticks(time).unwrap();
+        let back = from_ticks(ticks).unwrap();
+        let error = if back > time { back - time } else { time - back };
+        assert!(error < Duration::from_millis(1), "{:?}", error);
+    }
+}
@@ -0,0 +1,425 @@
+//! Conversion of whole buffers between sample formats.
+//!
+//! Converting a buffer with `convert_samples` gives the same result as converting each sample with
+//! `Sample::from`, but the conversions between `i16`, `u16` and `f32` use SSE2 on x86 and NEON on
+//! AArch64 when available.
+//!
+//! With the `fixed-point` feature, the conversions from `i16` and `u16` to `f32` and from `f32` to
+//! `i16` use integer arithmetic only for the samples that are not handled by SIMD. This is much
+//! faster on targets without a floating-point unit, e.g. soft-float ARM boards, where it applies to
+//! the whole buffer. The results are identical. The ALSA host converts the samples of its streams
+//! with `convert_samples`, and then prefers integer sample formats of the device.
+
+use std::slice;
+
+use Sample;
+use SampleFormat;
+
+/// Converts each sample of `src` to the format of `dst`.
+///
+/// # Panics
+///
+/// Panics if `src` and `dst` do not have the same length.
+pub fn convert_samples<S, D>(src: &[S], dst: &mut [D])
+where
+    S: Sample,
+    D: Sample,
+{
+    assert_eq!(src.len(), dst.len(), "buffers of different lengths");
+    // `Sample` is an unsafe trait whose format identifies the type implementing it.
+    unsafe {
+        match (S::get_format(), D::get_format()) {
+            (SampleFormat::I16, SampleFormat::F32) => i16_to_f32(cast(src), cast_mut(dst)),
+            (SampleFormat::U16, SampleFormat::F32) => u16_to_f32(cast(src), cast_mut(dst)),
+            (SampleFormat::F32, SampleFormat::I16) => f32_to_i16(cast(src), cast_mut(dst)),
+            _ => convert_scalar(src, dst),
+        }
+    }
+}
+
+#[inline]
+unsafe fn cast<S, T>(samples: &[S]) -> &[T] {
+    slice::from_raw_parts(samples.as_ptr() as *const T, samples.len())
+}
+
+#[inline]
+unsafe fn cast_mut<S, T>(samples: &mut [S]) -> &mut [T] {
+    slice::from_raw_parts_mut(samples.as_mut_ptr() as *mut T, samples.len())
+}
+
+fn convert_scalar<S, D>(src: &[S], dst: &mut [D])
+where
+    S: Sample,
+    D: Sample,
+{
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = D::from(s);
+    }
+}
+
+fn i16_to_f32(src: &[i16], dst: &mut [f32]) {
+    let done = simd::i16_to_f32(src, dst);
+    if cfg!(feature = "fixed-point") {
+        fixed::i16_to_f32(&src[done..], &mut dst[done..]);
+    } else {
+        convert_scalar(&src[done..], &mut dst[done..]);
+    }
+}
+
+fn u16_to_f32(src: &[u16], dst: &mut [f32]) {
+    let done = simd::u16_to_f32(src, dst);
+    if cfg!(feature = "fixed-point") {
+        fixed::u16_to_f32(&src[done..], &mut dst[done..]);
+    } else {
+        convert_scalar(&src[done..], &mut dst[done..]);
+    }
+}
+
+fn f32_to_i16(src: &[f32], dst: &mut [i16]) {
+    let done = simd::f32_to_i16(src, dst);
+    if cfg!(feature = "fixed-point") {
+        fixed::f32_to_i16(&src[done..], &mut dst[done..]);
+    } else {
+        convert_scalar(&src[done..], &mut dst[done..]);
+    }
+}
+
+// Conversions using integer arithmetic only, which operate on the bits of the `f32` samples. They
+// reproduce the rounding and saturation of the `Sample` implementations exactly.
+mod fixed {
+    const SIGN: u32 = 0x8000_0000;
+    const MANTISSA_BITS: u32 = 23;
+    const MANTISSA: u32 = (1 << MANTISSA_BITS) - 1;
+    const EXPONENT_BIAS: i32 = 127;
+
+    pub fn i16_to_f32(src: &[i16], dst: &mut [f32]) {
+        for (d, &s) in dst.iter_mut().zip(src) {
+            *d = f32::from_bits(i16_bits(s));
+        }
+    }
+
+    pub fn u16_to_f32(src: &[u16], dst: &mut [f32]) {
+        for (d, &s) in dst.iter_mut().zip(src) {
+            // Flipping the sign bit converts `u16` samples to `i16`.
+            *d = f32::from_bits(i16_bits((s ^ 0x8000) as i16));
+        }
+    }
+
+    pub fn f32_to_i16(src: &[f32], dst: &mut [i16]) {
+        for (d, &s) in dst.iter_mut().zip(src) {
+            *d = to_i16(s.to_bits());
+        }
+    }
+
+    // The bits of `s / 32768.0` for negative samples and of `s / 32767.0` otherwise.
+    #[inline]
+    fn i16_bits(s: i16) -> u32 {
+        if s < 0 {
+            // Dividing by a power of two is exact.
+            let n = -(s as i32) as u32;
+            let msb = 31 - n.leading_zeros();
+            let mantissa = (n << (MANTISSA_BITS - msb)) & MANTISSA;
+            let exponent = (msb as i32 - 15 + EXPONENT_BIAS) as u32;
+            SIGN | exponent << MANTISSA_BITS | mantissa
+        } else if s == 0 {
+            0
+        } else if s == i16::MAX {
+            1.0f32.to_bits()
+        } else {
+            // Scale the sample so that the quotient has 24 significant bits, then round it to the
+            // nearest. The divisor is odd, so there are no ties.
+            let n = s as u64;
+            let mut shift = 38 - (63 - n.leading_zeros());
+            let mut quotient = (n << shift) / 32767;
+            if quotient >> (MANTISSA_BITS + 1) != 0 {
+                shift -= 1;
+                quotient = (n << shift) / 32767;
+            }
+            let remainder = (n << shift) - quotient * 32767;
+            if 2 * remainder > 32767 {
+                quotient += 1;
+                if quotient >> (MANTISSA_BITS + 1) != 0 {
+                    quotient >>= 1;
+                    shift -= 1;
+                }
+            }
+            let exponent = (MANTISSA_BITS as i32 - shift as i32 + EXPONENT_BIAS) as u32;
+            exponent << MANTISSA_BITS | (quotient as u32 & MANTISSA)
+        }
+    }
+
+    // `(v * 32767.0) as i16` for `v >= 0.0` and `(v * 32768.0) as i16` otherwise.
+    #[inline]
+    fn to_i16(bits: u32) -> i16 {
+        let biased = (bits >> MANTISSA_BITS) & 0xff;
+        let fraction = bits & MANTISSA;
+        if biased == 0xff {
+            return match (fraction, bits & SIGN) {
+                (0, 0) => i16::MAX,
+                (0, _) => i16::MIN,
+                // NaN
+                _ => 0,
+            };
+        }
+        // The value is `mantissa * 2^exponent`.
+        let (mantissa, exponent) = if biased == 0 {
+            (fraction as u64, 1 - EXPONENT_BIAS - MANTISSA_BITS as i32)
+        } else {
+            (
+                (fraction | 1 << MANTISSA_BITS) as u64,
+                biased as i32 - EXPONENT_BIAS - MANTISSA_BITS as i32,
+            )
+        };
+        if bits & SIGN != 0 {
+            // Multiplying by a power of two is exact.
+            let magnitude = truncate(mantissa, exponent + 15);
+            -(magnitude.min(32768) as i32) as i16
+        } else {
+            // The product is rounded to 24 significant bits, to the nearest or even.
+            let mut product = mantissa * 32767;
+            let mut exponent = exponent;
+            let bits = 64 - product.leading_zeros();
+            if bits > MANTISSA_BITS + 1 {
+                let shift = bits - (MANTISSA_BITS + 1);
+                let rest = product & ((1 << shift) - 1);
+                let half = 1 << (shift - 1);
+                product >>= shift;
+                if rest > half || (rest == half && product & 1 == 1) {
+                    product += 1;
+                }
+                exponent += shift as i32;
+            }
+            truncate(product, exponent).min(i16::MAX as u64) as i16
+        }
+    }
+
+    // `value * 2^exponent` rounded towards zero, saturated to a value larger than any `i16`.
+    #[inline]
+    fn truncate(value: u64, exponent: i32) -> u64 {
+        if exponent >= 0 {
+            if exponent > 32 {
+                u64::MAX
+            } else {
+                value << exponent
+            }
+        } else if exponent <= -64 {
+            0
+        } else {
+            value >> -exponent
+        }
+    }
+}
+
+// Each function converts the longest prefix of `src` made of whole vectors and returns its length.
+// The kernels reproduce the rounding and saturation of the `Sample` implementations exactly.
+#[cfg(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse2"),
+))]
+mod simd {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    pub fn i16_to_f32(src: &[i16], dst: &mut [f32]) -> usize {
+        unsafe { to_f32(src.as_ptr(), dst, 0) }
+    }
+
+    pub fn u16_to_f32(src: &[u16], dst: &mut [f32]) -> usize {
+        // Flipping the sign bit converts `u16` samples to `i16`.
+        unsafe { to_f32(src.as_ptr() as *const i16, dst, -0x8000) }
+    }
+
+    unsafe fn to_f32(src: *const i16, dst: &mut [f32], flip: i16) -> usize {
+        let len = dst.len() - dst.len() % 8;
+        let flip = _mm_set1_epi16(flip);
+        let zero = _mm_setzero_ps();
+        let positive_scale = _mm_set1_ps(i16::MAX as f32);
+        let negative_scale = _mm_set1_ps(-(i16::MIN as f32));
+        for i in (0..len).step_by(8) {
+            let v = _mm_xor_si128(_mm_loadu_si128(src.add(i) as *const __m128i), flip);
+            // Sign-extend to 32 bits by unpacking into the high halves and shifting back.
+            let lo = _mm_srai_epi32(_mm_unpacklo_epi16(v, v), 16);
+            let hi = _mm_srai_epi32(_mm_unpackhi_epi16(v, v), 16);
+            for (j, v) in [lo, hi].iter().enumerate() {
+                let v = _mm_cvtepi32_ps(*v);
+                let negative = _mm_cmplt_ps(v, zero);
+                let scale = _mm_or_ps(
+                    _mm_and_ps(negative, negative_scale),
+                    _mm_andnot_ps(negative, positive_scale),
+                );
+                _mm_storeu_ps(dst.as_mut_ptr().add(i + j * 4), _mm_div_ps(v, scale));
+            }
+        }
+        len
+    }
+
+    pub fn f32_to_i16(src: &[f32], dst: &mut [i16]) -> usize {
+        let len = src.len() - src.len() % 8;
+        unsafe {
+            let zero = _mm_setzero_ps();
+            let positive_scale = _mm_set1_ps(i16::MAX as f32);
+            let negative_scale = _mm_set1_ps(-(i16::MIN as f32));
+            let min = _mm_set1_ps(i16::MIN as f32);
+            let max = _mm_set1_ps(i16::MAX as f32);
+            let convert = |v: __m128| {
+                let positive = _mm_cmpge_ps(v, zero);
+                let scale = _mm_or_ps(
+                    _mm_and_ps(positive, positive_scale),
+                    _mm_andnot_ps(positive, negative_scale),
+                );
+                // `as` converts NaN to zero and saturates out of range values.
+                let v = _mm_and_ps(_mm_mul_ps(v, scale), _mm_cmpord_ps(v, v));
+                _mm_cvttps_epi32(_mm_min_ps(_mm_max_ps(v, min), max))
+            };
+            for i in (0..len).step_by(8) {
+                let lo = convert(_mm_loadu_ps(src.as_ptr().add(i)));
+                let hi = convert(_mm_loadu_ps(src.as_ptr().add(i + 4)));
+                let v = _mm_packs_epi32(lo, hi);
+                _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, v);
+            }
+        }
+        len
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod simd {
+    use std::arch::aarch64::*;
+
+    pub fn i16_to_f32(src: &[i16], dst: &mut [f32]) -> usize {
+        unsafe { to_f32(src.as_ptr(), dst, 0) }
+    }
+
+    pub fn u16_to_f32(src: &[u16], dst: &mut [f32]) -> usize {
+        // Flipping the sign bit converts `u16` samples to `i16`.
+        unsafe { to_f32(src.as_ptr() as *const i16, dst, -0x8000) }
+    }
+
+    unsafe fn to_f32(src: *const i16, dst: &mut [f32], flip: i16) -> usize {
+        let len = dst.len() - dst.len() % 8;
+        let flip = vdupq_n_s16(flip);
+        let zero = vdupq_n_f32(0.0);
+        let positive_scale = vdupq_n_f32(i16::MAX as f32);
+        let negative_scale = vdupq_n_f32(-(i16::MIN as f32));
+        for i in (0..len).step_by(8) {
+            let v = veorq_s16(vld1q_s16(src.add(i)), flip);
+            let lo = vmovl_s16(vget_low_s16(v));
+            let hi = vmovl_s16(vget_high_s16(v));
+            for (j, v) in [lo, hi].iter().enumerate() {
+                let v = vcvtq_f32_s32(*v);
+                let scale = vbslq_f32(vcltq_f32(v, zero), negative_scale, positive_scale);
+                vst1q_f32(dst.as_mut_ptr().add(i + j * 4), vdivq_f32(v, scale));
+            }
+        }
+        len
+    }
+
+    pub fn f32_to_i16(src: &[f32], dst: &mut [i16]) -> usize {
+        let len = src.len() - src.len() % 8;
+        unsafe {
+            let zero = vdupq_n_f32(0.0);
+            let positive_scale = vdupq_n_f32(i16::MAX as f32);
+            let negative_scale = vdupq_n_f32(-(i16::MIN as f32));
+            let convert = |v: float32x4_t| {
+                let scale = vbslq_f32(vcgeq_f32(v, zero), positive_scale, negative_scale);
+                // Truncates like `as`, converting NaN to zero and saturating to the `i32` range.
+                vcvtq_s32_f32(vmulq_f32(v, scale))
+            };
+            for i in (0..len).step_by(8) {
+                let lo = convert(vld1q_f32(src.as_ptr().add(i)));
+                let hi = convert(vld1q_f32(src.as_ptr().add(i + 4)));
+                let v = vcombine_s16(vqmovn_s32(lo), vqmovn_s32(hi));
+                vst1q_s16(dst.as_mut_ptr().add(i), v);
+            }
+        }
+        len
+    }
+}
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    all(target_arch = "x86", target_feature = "sse2"),
+    target_arch = "aarch64",
+)))]
+mod simd {
+    pub fn i16_to_f32(_: &[i16], _: &mut [f32]) -> usize {
+        0
+    }
+
+    pub fn u16_to_f32(_: &[u16], _: &mut [f32]) -> usize {
+        0
+    }
+
+    pub fn f32_to_i16(_: &[f32], _: &mut [i16]) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{convert_samples, convert_scalar, fixed};
+    use Sample;
+
+    fn assert_same_as_scalar<S, D>(src: &[S])
+    where
+        S: Sample,
+        D: Sample + Default + PartialEq + ::std::fmt::Debug,
+    {
+        let mut expected = vec![D::default(); src.len()];
+        let mut converted = vec![D::default(); src.len()];
+        convert_scalar(src, &mut expected);
+        convert_samples(src, &mut converted);
+        assert_eq!(converted, expected);
+    }
+
+    #[test]
+    fn integer_conversions_match_scalar() {
+        // An odd length exercises both the vectorized part and the remainder.
+        let src: Vec<i16> = (i16::MIN..=i16::MAX).step_by(7).collect();
+        assert_same_as_scalar::<i16, f32>(&src);
+        assert_same_as_scalar::<i16, u16>(&src);
+        let src: Vec<u16> = src.iter().map(|s| s.to_u16()).collect();
+        assert_same_as_scalar::<u16, f32>(&src);
+        assert_same_as_scalar::<u16, i16>(&src);
+    }
+
+    #[test]
+    fn float_conversions_match_scalar() {
+        let mut src: Vec<f32> = (-1100..=1100).map(|i| i as f32 / 1000.0).collect();
+        src.extend_from_slice(&[
+            f32::NAN, f32::INFINITY, f32::NEG_INFINITY, 1e10, -1e10, -0.0, 1.0, -1.0,
+        ]);
+        assert_same_as_scalar::<f32, i16>(&src);
+        assert_same_as_scalar::<f32, u16>(&src);
+    }
+
+    #[test]
+    fn fixed_point_integer_conversions_match_scalar() {
+        let src: Vec<i16> = (i16::MIN..=i16::MAX).collect();
+        let mut expected = vec![0.0f32; src.len()];
+        let mut converted = vec![0.0f32; src.len()];
+        convert_scalar(&src, &mut expected);
+        fixed::i16_to_f32(&src, &mut converted);
+        assert!(converted.iter().zip(&expected).all(|(c, e)| c.to_bits() == e.to_bits()));
+        let src: Vec<u16> = (0..=u16::MAX).collect();
+        convert_scalar(&src, &mut expected);
+        fixed::u16_to_f32(&src, &mut converted);
+        assert!(converted.iter().zip(&expected).all(|(c, e)| c.to_bits() == e.to_bits()));
+    }
+
+    #[test]
+    fn fixed_point_float_conversions_match_scalar() {
+        // Every 251st bit pattern covers all exponents, both signs, subnormals and NaNs.
+        let mut src: Vec<f32> = (0..=u32::MAX).step_by(251).map(f32::from_bits).collect();
+        src.extend_from_slice(&[
+            f32::INFINITY, f32::NEG_INFINITY, 0.0, -0.0, 1.0, -1.0, 0.5, -0.5, 1.0 / 32767.0,
+        ]);
+        let mut expected = vec![0i16; src.len()];
+        let mut converted = vec![0i16; src.len()];
+        convert_scalar(&src, &mut expected);
+        fixed::f32_to_i16(&src, &mut converted);
+        assert_eq!(converted, expected);
+    }
+}
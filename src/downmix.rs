@@ -0,0 +1,315 @@
+//! Opening stereo streams on surround devices, see `StreamOptions::downmix_surround`.
+//!
+//! The stream is opened with the 6 (5.1) or 8 (7.1) channels of the device, in the order of the
+//! host, see `SurroundLayout`. The data callback still receives stereo buffers. Captured audio is
+//! downmixed with the ITU-R BS.775 coefficients, scaled so that it does not clip, and stereo
+//! output is played on the front channels.
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use playback::supported_formats_error;
+use traits::DeviceTrait;
+use BuildStreamError;
+use ChannelCount;
+use Format;
+use InputBuffer;
+use OutputBuffer;
+use Sample;
+use SampleFormat;
+use StreamData;
+use SupportedFormat;
+use UnknownTypeInputBuffer;
+use UnknownTypeOutputBuffer;
+
+/// The speaker that a channel of a surround frame feeds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Speaker {
+    FrontLeft,
+    FrontRight,
+    Center,
+    LowFrequency,
+    BackLeft,
+    BackRight,
+    SideLeft,
+    SideRight,
+    /// A speaker that the downmix leaves out.
+    Other,
+}
+
+/// The order of the channels of the surround streams of a device.
+pub(crate) trait SurroundLayout {
+    /// The speaker of each channel of a frame of `channels` channels, in order.
+    ///
+    /// Defaults to the order of WAVE files: front left, front right, center, LFE, back left, back
+    /// right, then side left and side right.
+    fn surround_layout(&self, channels: ChannelCount) -> Vec<Speaker> {
+        let mut layout = vec![
+            Speaker::FrontLeft,
+            Speaker::FrontRight,
+            Speaker::Center,
+            Speaker::LowFrequency,
+            Speaker::BackLeft,
+            Speaker::BackRight,
+            Speaker::SideLeft,
+            Speaker::SideRight,
+        ];
+        layout.resize(channels as usize, Speaker::Other);
+        layout
+    }
+}
+
+/// The format and layout with which a stereo stream is opened on a surround device.
+pub(crate) struct Surround {
+    pub format: Format,
+    layout: Vec<Speaker>,
+}
+
+// No host passes buffers longer than a second of audio to the data callback, so a scratch buffer
+// of this duration never grows.
+const MAX_BUFFER_SECS: usize = 1;
+
+// The stereo samples passed to the data callback of an input stream.
+enum Scratch {
+    U16(Vec<u16>),
+    I16(Vec<i16>),
+    F32(Vec<f32>),
+}
+
+/// The format with which a stream of `format` is opened on `device` to convert from or to stereo,
+/// or `None` if the device supports `format` itself or no surround format.
+pub(crate) fn surround_format<D: DeviceTrait + SurroundLayout>(
+    device: &D,
+    format: &Format,
+    input: bool,
+) -> Result<Option<Surround>, BuildStreamError> {
+    if format.channels != 2 {
+        return Ok(None);
+    }
+    let formats: Vec<SupportedFormat> = if input {
+        device.supported_input_formats().map(Iterator::collect)
+    } else {
+        device.supported_output_formats().map(Iterator::collect)
+    }
+    .map_err(supported_formats_error)?;
+    if formats.iter().any(|supported| supported.contains(format)) {
+        return Ok(None);
+    }
+    let mut surround = [6, 8].iter().map(|&channels| Format {
+        channels,
+        ..format.clone()
+    });
+    let surround = surround.find(|surround| {
+        formats.iter().any(|supported| supported.contains(surround))
+    });
+    Ok(surround.map(|format| Surround {
+        layout: device.surround_layout(format.channels),
+        format,
+    }))
+}
+
+/// Wraps `data_callback`, which handles stereo buffers, for a stream opened with `surround`. Does
+/// nothing if `surround` is `None`.
+pub(crate) fn adapt<D>(
+    surround: Option<&Surround>,
+    mut data_callback: D,
+) -> impl FnMut(StreamData) + Send + 'static
+where
+    D: FnMut(StreamData) + Send + 'static,
+{
+    let mut state = surround.map(|surround| {
+        let format = &surround.format;
+        let len = 2 * MAX_BUFFER_SECS * format.sample_rate.0 as usize;
+        let scratch = match format.data_type {
+            SampleFormat::U16 => Scratch::U16(Vec::with_capacity(len)),
+            SampleFormat::I16 => Scratch::I16(Vec::with_capacity(len)),
+            SampleFormat::F32 => Scratch::F32(Vec::with_capacity(len)),
+        };
+        (Mix::new(&surround.layout), scratch)
+    });
+    move |data: StreamData| {
+        let (mix, scratch) = match state {
+            Some((ref mix, ref mut scratch)) => (mix, scratch),
+            None => return data_callback(data),
+        };
+        match data {
+            StreamData::Input { buffer, info } => match (buffer, scratch) {
+                (UnknownTypeInputBuffer::U16(buffer), &mut Scratch::U16(ref mut stereo)) => {
+                    let stereo = mix.downmix(&buffer, stereo);
+                    let buffer = UnknownTypeInputBuffer::U16(InputBuffer::new(stereo));
+                    data_callback(StreamData::Input { buffer, info })
+                },
+                (UnknownTypeInputBuffer::I16(buffer), &mut Scratch::I16(ref mut stereo)) => {
+                    let stereo = mix.downmix(&buffer, stereo);
+                    let buffer = UnknownTypeInputBuffer::I16(InputBuffer::new(stereo));
+                    data_callback(StreamData::Input { buffer, info })
+                },
+                (UnknownTypeInputBuffer::F32(buffer), &mut Scratch::F32(ref mut stereo)) => {
+                    let stereo = mix.downmix(&buffer, stereo);
+                    let buffer = UnknownTypeInputBuffer::F32(InputBuffer::new(stereo));
+                    data_callback(StreamData::Input { buffer, info })
+                },
+                // The host passes buffers of the format of the stream.
+                _ => (),
+            },
+            // The data callback writes stereo frames to the beginning of the buffer, which are
+            // then spread over the surround frames in place.
+            StreamData::Output { buffer, info } => match buffer {
+                UnknownTypeOutputBuffer::U16(mut buffer) => {
+                    let stereo_len = 2 * (buffer.len() / mix.channels());
+                    data_callback(StreamData::Output {
+                        buffer: UnknownTypeOutputBuffer::U16(OutputBuffer::new(
+                            &mut buffer[..stereo_len],
+                        )),
+                        info,
+                    });
+                    mix.upmix(&mut buffer);
+                },
+                UnknownTypeOutputBuffer::I16(mut buffer) => {
+                    let stereo_len = 2 * (buffer.len() / mix.channels());
+                    data_callback(StreamData::Output {
+                        buffer: UnknownTypeOutputBuffer::I16(OutputBuffer::new(
+                            &mut buffer[..stereo_len],
+                        )),
+                        info,
+                    });
+                    mix.upmix(&mut buffer);
+                },
+                UnknownTypeOutputBuffer::F32(mut buffer) => {
+                    let stereo_len = 2 * (buffer.len() / mix.channels());
+                    data_callback(StreamData::Output {
+                        buffer: UnknownTypeOutputBuffer::F32(OutputBuffer::new(
+                            &mut buffer[..stereo_len],
+                        )),
+                        info,
+                    });
+                    mix.upmix(&mut buffer);
+                },
+            },
+        }
+    }
+}
+
+// The weights of the channels of a surround layout in the stereo downmix, and the front channels
+// that stereo output is played on.
+struct Mix {
+    // The weights of each channel in the left and right channels, scaled so that they do not clip.
+    weights: Vec<(f32, f32)>,
+    front_left: usize,
+    front_right: usize,
+}
+
+impl Mix {
+    fn new(layout: &[Speaker]) -> Self {
+        // The LFE channel is left out.
+        let weight = |speaker| match speaker {
+            Speaker::FrontLeft => (1.0, 0.0),
+            Speaker::FrontRight => (0.0, 1.0),
+            Speaker::Center => (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            Speaker::BackLeft | Speaker::SideLeft => (FRAC_1_SQRT_2, 0.0),
+            Speaker::BackRight | Speaker::SideRight => (0.0, FRAC_1_SQRT_2),
+            Speaker::LowFrequency | Speaker::Other => (0.0, 0.0),
+        };
+        let mut weights: Vec<(f32, f32)> = layout.iter().map(|&speaker| weight(speaker)).collect();
+        let left: f32 = weights.iter().map(|w| w.0).sum();
+        let right: f32 = weights.iter().map(|w| w.1).sum();
+        let scale = 1.0 / left.max(right).max(1.0);
+        for w in &mut weights {
+            *w = (scale * w.0, scale * w.1);
+        }
+        let position = |speaker, default| {
+            layout.iter().position(|&s| s == speaker).unwrap_or(default)
+        };
+        Mix {
+            weights,
+            front_left: position(Speaker::FrontLeft, 0),
+            front_right: position(Speaker::FrontRight, 1),
+        }
+    }
+
+    fn channels(&self) -> usize {
+        self.weights.len()
+    }
+
+    // Downmixes the surround frames of `samples` to the beginning of `scratch`.
+    fn downmix<'a, T: Sample>(&self, samples: &[T], scratch: &'a mut Vec<T>) -> &'a [T] {
+        let frames = samples.len() / self.channels();
+        // Only grows for buffers longer than `MAX_BUFFER_SECS`.
+        if scratch.len() < 2 * frames {
+            scratch.resize(2 * frames, T::from(&0.0f32));
+        }
+        let stereo = &mut scratch[..2 * frames];
+        for (frame, out) in samples.chunks_exact(self.channels()).zip(stereo.chunks_exact_mut(2)) {
+            let (mut left, mut right) = (0.0, 0.0);
+            for (sample, &(l, r)) in frame.iter().zip(&self.weights) {
+                left += l * sample.to_f32();
+                right += r * sample.to_f32();
+            }
+            out[0] = T::from(&left);
+            out[1] = T::from(&right);
+        }
+        stereo
+    }
+
+    // Spreads the stereo frames at the beginning of `buffer` over its surround frames, on the
+    // front channels, and silences the other channels.
+    fn upmix<T: Sample>(&self, buffer: &mut [T]) {
+        let channels = self.channels();
+        let silence = T::from(&0.0f32);
+        // From the last frame, as each surround frame starts at or after its stereo frame.
+        for i in (0..buffer.len() / channels).rev() {
+            let (left, right) = (buffer[2 * i], buffer[2 * i + 1]);
+            let frame = &mut buffer[i * channels..(i + 1) * channels];
+            for sample in frame.iter_mut() {
+                *sample = silence;
+            }
+            frame[self.front_left] = left;
+            frame[self.front_right] = right;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mix, Speaker, SurroundLayout};
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    struct Wave;
+
+    impl SurroundLayout for Wave {}
+
+    #[test]
+    fn surround_is_converted_from_and_to_stereo() {
+        // Front left, center, LFE and back right.
+        let surround = [1.0, 0.0, 1.0, 1.0, 0.0, 1.0];
+        let mut scratch = Vec::new();
+        let stereo = Mix::new(&Wave.surround_layout(6)).downmix(&surround, &mut scratch);
+        let scale = 1.0 / (1.0 + 2.0 * FRAC_1_SQRT_2);
+        assert!((stereo[0] - scale * (1.0 + FRAC_1_SQRT_2)).abs() < 1e-6);
+        assert!((stereo[1] - scale * 2.0 * FRAC_1_SQRT_2).abs() < 1e-6);
+
+        let mut buffer = [1.0; 16];
+        buffer[..4].copy_from_slice(&[0.5, -0.5, 0.25, -0.25]);
+        Mix::new(&Wave.surround_layout(8)).upmix(&mut buffer);
+        assert_eq!(&buffer[..8], &[0.5, -0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(&buffer[8..], &[0.25, -0.25, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn surround_follows_the_layout_of_the_host() {
+        // The default 5.1 order of ALSA, with the center and LFE channels last.
+        let layout = [
+            Speaker::FrontLeft,
+            Speaker::FrontRight,
+            Speaker::BackLeft,
+            Speaker::BackRight,
+            Speaker::Center,
+            Speaker::LowFrequency,
+        ];
+        let mix = Mix::new(&layout);
+        let mut scratch = Vec::new();
+        let stereo = mix.downmix(&[0.0, 0.0, 0.0, 0.0, 1.0, 1.0], &mut scratch);
+        let center = FRAC_1_SQRT_2 / (1.0 + 2.0 * FRAC_1_SQRT_2);
+        assert!((stereo[0] - center).abs() < 1e-6);
+        assert!((stereo[1] - center).abs() < 1e-6);
+    }
+}
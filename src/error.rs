@@ -1,9 +1,37 @@
 use thiserror::Error;
+use SampleRate;
 
 /// The requested host, although supported on this platform, is unavailable.
 #[derive(Clone, Debug, Error)]
-#[error("the requested host is unavailable")]
-pub struct HostUnavailable;
+pub enum HostUnavailable {
+    /// A library or driver that the host requires is not installed on the system.
+    #[error("the host is unavailable: {name} was not found")]
+    LibraryNotFound { name: String },
+    /// The sound server that the host connects to is not running.
+    #[error("the host is unavailable: the sound server is not running")]
+    ServerNotRunning,
+    /// The process is not allowed to use the host.
+    #[error("the host is unavailable: permission denied")]
+    PermissionDenied,
+    /// The host cannot be used on this system, with no more specific reason.
+    #[error("the host is unavailable on this system")]
+    NotSupported,
+    /// See the `BackendSpecificError` docs for more information about this error variant.
+    #[error("{err}")]
+    BackendSpecific {
+        #[from]
+        err: BackendSpecificError,
+    },
+}
+
+/// The string does not name a host supported on this platform.
+///
+/// Hosts of other platforms are unknown as well, for example `wasapi` on Linux.
+#[derive(Clone, Debug, Error)]
+#[error("unknown host: {name}")]
+pub struct UnknownHostId {
+    pub name: String,
+}
 
 /// Some error has occurred that is specific to the backend from which it was produced.
 ///
@@ -21,6 +49,9 @@ pub struct HostUnavailable;
 #[error("A backend-specific error has occurred: {description}")]
 pub struct BackendSpecificError {
     pub description: String,
+    /// The raw error code reported by the system, if any. This is an `HRESULT` on Windows, an
+    /// `errno` value on Linux, an `OSStatus` on macOS and an `ASIOError` with ASIO.
+    pub code: Option<i32>,
 }
 
 /// An error that might occur while attempting to enumerate the available devices on a system.
@@ -57,6 +88,10 @@ pub enum SupportedFormatsError {
         "Invalid argument passed to the backend. For example, this happens when trying to read capture capabilities when the device does not support it."
     )]
     InvalidArgument,
+    /// The device did not report its formats in time, which happens with some broken drivers.
+    /// The device keeps being reported as such until it responds.
+    #[error("The device did not report its supported formats in time.")]
+    Timeout,
     /// See the `BackendSpecificError` docs for more information about this error variant.
     #[error("{err}")]
     BackendSpecific {
@@ -93,6 +128,27 @@ pub enum BuildStreamError {
     /// The required format is not supported.
     #[error("The requested stream format is not supported by the device.")]
     FormatNotSupported,
+    /// The device does not support the requested number of channels.
+    #[error("The requested number of channels is not supported by the device.")]
+    InvalidChannelCount,
+    /// The device does not support the requested sample rate.
+    #[error("The requested sample rate is not supported by the device.")]
+    SampleRateNotSupported,
+    /// The device does not support the requested sample format.
+    #[error("The requested sample format is not supported by the device.")]
+    SampleFormatNotSupported,
+    /// The device is in use by another application, which may have exclusive access to it.
+    #[error("The requested device is in use by another application.")]
+    DeviceInUse,
+    /// The application is not allowed to use the device. For example, access to the microphone
+    /// may be disabled in the privacy settings of the operating system.
+    #[error("Access to the requested device was denied. Check the privacy settings of the system.")]
+    AccessDenied,
+    /// The application runs in a Flatpak or Snap sandbox that does not give it access to the
+    /// device. A Flatpak needs the permission of the desktop to use the microphone, and a Snap
+    /// needs its `audio-record` or `alsa` interface to be connected.
+    #[error("Access to the requested device was denied by the Flatpak or Snap sandbox.")]
+    SandboxAccessDenied,
     /// We called something the C-Layer did not understand
     ///
     /// On ALSA device functions called with a feature they do not support will yield this. E.g.
@@ -114,7 +170,7 @@ pub enum BuildStreamError {
 ///
 /// As of writing this, only macOS may immediately return an error while calling this method. This
 /// is because both the alsa and wasapi backends only enqueue these commands and do not process
-/// them immediately.
+/// them immediately. Use `StreamTrait::play_blocking` to wait for the outcome of the command.
 #[derive(Debug, Error)]
 pub enum PlayStreamError {
     /// The device associated with the stream is no longer available.
@@ -132,7 +188,7 @@ pub enum PlayStreamError {
 ///
 /// As of writing this, only macOS may immediately return an error while calling this method. This
 /// is because both the alsa and wasapi backends only enqueue these commands and do not process
-/// them immediately.
+/// them immediately. Use `StreamTrait::pause_blocking` to wait for the outcome of the command.
 #[derive(Debug, Error)]
 pub enum PauseStreamError {
     /// The device associated with the stream is no longer available.
@@ -146,13 +202,79 @@ pub enum PauseStreamError {
     },
 }
 
+/// Why the device of a stream is no longer available.
+///
+/// This tells whether it is worth waiting for the device to come back or building a stream on it
+/// again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceLossReason {
+    /// The device was unplugged. It becomes available again when it is plugged back in.
+    Unplugged,
+    /// The device was disabled, e.g. by the user in the settings of the system.
+    Disabled,
+    /// The device was removed from the system, e.g. because its driver was uninstalled.
+    Removed,
+    /// The device is still present, but it was reconfigured, e.g. its format was changed. A new
+    /// stream can usually be built on the device right away.
+    Reconfigured,
+    /// Another application took exclusive control of the device. The device becomes available
+    /// again once that application releases it.
+    Preempted,
+    /// The audio service of the system stopped. Streams can be built again once it restarts.
+    ServiceStopped,
+    /// The user session that the stream belongs to ended, e.g. because the user logged off or the
+    /// remote desktop connection was closed.
+    SessionEnded,
+    /// The host does not tell why.
+    Unknown,
+}
+
 /// Errors that might occur while a stream is running.
-#[derive(Debug, Error)]
+#[derive(Clone, Debug, Error)]
 pub enum StreamError {
     /// The device no longer exists. This can happen if the device is disconnected while the
     /// program is running.
     #[error("The requested device is no longer available. For example, it has been unplugged.")]
-    DeviceNotAvailable,
+    DeviceNotAvailable {
+        /// Why the device is no longer available.
+        reason: DeviceLossReason,
+    },
+    /// The device ran out of data to play because the data callback did not provide it in time.
+    ///
+    /// Reported on every underrun by ALSA, and only with `SlowCallbackPolicy::Error` by the other
+    /// hosts.
+    #[error("output underrun: the data callback did not provide data in time")]
+    Underrun {
+        /// The size of the device buffer that ran empty, in frames. Increasing it makes underruns
+        /// less likely.
+        buffer_frames: usize,
+    },
+    /// The device discarded captured data because the stream did not read it in time.
+    ///
+    /// Reported by ALSA and WASAPI. The next buffer is flagged as a discontinuity.
+    #[error("input overrun: captured data was discarded because it was not read in time")]
+    Overrun {
+        /// The size of the device buffer that overflowed, in frames.
+        buffer_frames: usize,
+    },
+    /// The data callback panicked. The stream stopped and no longer calls the data callback.
+    ///
+    /// Reported by the hosts on which CPAL runs the audio thread itself, ALSA and WASAPI.
+    #[error("the data callback panicked: {message}")]
+    CallbackPanicked {
+        /// The message of the panic, if it was a string.
+        message: String,
+    },
+    /// The device was reconfigured while the stream was running, e.g. from the control panel of
+    /// its driver. The stream keeps running, but the size of its buffers may have changed.
+    ///
+    /// Reported by ASIO, and by CoreAudio when the sample rate of the device changes.
+    #[error("the device was reconfigured while the stream was running")]
+    DeviceReconfigured {
+        /// The new sample rate of the device, if it changed. The stream no longer plays at the
+        /// rate of its format and has to be built again to play at the right speed.
+        sample_rate: Option<SampleRate>,
+    },
     /// See the `BackendSpecificError` docs for more information about this error variant.
     #[error("{err}")]
     BackendSpecific {
@@ -160,3 +282,28 @@ pub enum StreamError {
         err: BackendSpecificError,
     },
 }
+
+/// Errors that might occur when measuring the round-trip latency of a pair of devices with
+/// `latency::measure_round_trip`.
+#[derive(Debug, Error)]
+pub enum MeasureLatencyError {
+    /// One of the streams could not be built or started.
+    #[error("{err}")]
+    BuildStream {
+        #[from]
+        err: BuildStreamError,
+    },
+    /// One of the streams stopped during the measurement.
+    #[error("{err}")]
+    Stream {
+        #[from]
+        err: StreamError,
+    },
+    /// One of the streams does not report its position, which the measurement relies on.
+    #[error("The devices do not report the positions of their streams.")]
+    PositionNotSupported,
+    /// The chirp played on the output device was not captured by the input device, e.g. because
+    /// the devices are not connected or the input is muted.
+    #[error("The chirp played on the output device was not detected on the input device.")]
+    ChirpNotDetected,
+}
@@ -0,0 +1,455 @@
+//! A cache of the formats supported by devices.
+//!
+//! Querying the formats supported by a device is slow on some hosts, as it opens the device and
+//! tries each combination of parameters. The results are cached per device and invalidated when
+//! the devices are enumerated again, or when a device is reported as not available.
+//!
+//! Some broken drivers even hang for seconds when they are opened. Queries run on their own
+//! thread and are given up after `QUERY_TIMEOUT`, so that listing the input or output devices
+//! cannot freeze an application.
+//!
+//! Listing the input or output devices prefetches their formats in the background, querying a
+//! few devices at once, so that the formats of all the devices are ready soon even when there are
+//! many of them.
+//!
+//! The cached formats are also used to check the format of a stream before opening the device, so
+//! that an unsupported format is reported with the field at fault.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use BackendSpecificError;
+use BuildStreamError;
+use Format;
+use SupportedFormat;
+use SupportedFormatsError;
+
+/// How long to wait for a device to report its formats.
+pub(crate) const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How many devices `FormatCache::prefetch` queries at once.
+const MAX_CONCURRENT_QUERIES: usize = 4;
+
+/// A query of the formats of a device, queued by `FormatCache::prefetch`.
+type Query = Box<dyn FnOnce() -> Result<Vec<SupportedFormat>, SupportedFormatsError> + Send>;
+
+/// The formats supported by devices for one direction, input or output, keyed by device id.
+pub(crate) struct FormatCache {
+    shared: Arc<Shared>,
+    timeout: Duration,
+}
+
+struct Shared {
+    entries: Mutex<Entries>,
+    // Notified whenever a query starts or completes.
+    queried: Condvar,
+}
+
+struct Entries {
+    formats: HashMap<String, Vec<SupportedFormat>>,
+    // The devices being queried by `get_or_query`, including those whose query timed out.
+    pending: HashSet<String>,
+    // The devices queued by `prefetch`, in order.
+    queue: VecDeque<(String, Query)>,
+    // The devices being queried by `prefetch`.
+    prefetching: HashSet<String>,
+    // The number of threads querying the queued devices.
+    workers: usize,
+}
+
+impl FormatCache {
+    pub fn new() -> Self {
+        let entries = Entries {
+            formats: HashMap::new(),
+            pending: HashSet::new(),
+            queue: VecDeque::new(),
+            prefetching: HashSet::new(),
+            workers: 0,
+        };
+        let shared = Shared {
+            entries: Mutex::new(entries),
+            queried: Condvar::new(),
+        };
+        FormatCache {
+            shared: Arc::new(shared),
+            timeout: QUERY_TIMEOUT,
+        }
+    }
+
+    /// Returns the cached formats of device `id`, or queries them with `query` and caches them.
+    ///
+    /// The query runs on its own thread. If it does not complete within the timeout, the device is
+    /// reported as `SupportedFormatsError::Timeout` until the query completes and caches its
+    /// result. The device is not queried again in the meantime: concurrent callers wait for the
+    /// running query instead, within the timeout. Errors are not cached.
+    ///
+    /// If the device is being prefetched, this waits for the prefetch instead, within the timeout.
+    /// If the device is still queued for a prefetch, this waits for the query to start, and runs
+    /// the queued query itself if no prefetched query completes within the timeout.
+    pub fn get_or_query<F>(
+        &self,
+        id: &str,
+        query: F,
+    ) -> Result<Vec<SupportedFormat>, SupportedFormatsError>
+    where
+        F: FnOnce() -> Result<Vec<SupportedFormat>, SupportedFormatsError> + Send + 'static,
+    {
+        let mut query: Query = Box::new(query);
+        {
+            let mut entries = self.entries();
+            if entries.is_queued(id) {
+                let (guard, _) = self
+                    .shared
+                    .queried
+                    .wait_timeout_while(entries, self.timeout, |e| e.is_queued(id))
+                    .unwrap_or_else(PoisonError::into_inner);
+                entries = guard;
+                // The devices ahead are slow to query, so this one is queried on demand instead.
+                if let Some(i) = entries.queue.iter().position(|(queued, _)| queued == id) {
+                    query = entries.queue.remove(i).expect("queued device").1;
+                }
+            }
+            if entries.prefetching.contains(id) {
+                let (guard, wait) = self
+                    .shared
+                    .queried
+                    .wait_timeout_while(entries, self.timeout, |e| e.prefetching.contains(id))
+                    .unwrap_or_else(PoisonError::into_inner);
+                if wait.timed_out() {
+                    trace_event!(warn, device = id, "format prefetch timed out");
+                    return Err(SupportedFormatsError::Timeout);
+                }
+                // A failed prefetch is not cached, so the device is queried again below.
+                entries = guard;
+            }
+            if entries.pending.contains(id) {
+                let (guard, wait) = self
+                    .shared
+                    .queried
+                    .wait_timeout_while(entries, self.timeout, |e| e.pending.contains(id))
+                    .unwrap_or_else(PoisonError::into_inner);
+                if wait.timed_out() {
+                    trace_event!(warn, device = id, "format query timed out");
+                    return Err(SupportedFormatsError::Timeout);
+                }
+                // A failed query is not cached, so the device is queried again below.
+                entries = guard;
+            }
+            if let Some(formats) = entries.formats.get(id) {
+                return Ok(formats.clone());
+            }
+            entries.pending.insert(id.to_owned());
+        }
+        let (tx, rx) = mpsc::channel();
+        let shared = self.shared.clone();
+        let thread_id = id.to_owned();
+        // Don't hold the lock while querying the device, which may take a while.
+        let spawned = thread::Builder::new()
+            .name("cpal_format_query".to_owned())
+            .spawn(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(query))
+                    .unwrap_or_else(|_| Err(query_panicked()));
+                let mut entries = shared.entries.lock().unwrap_or_else(PoisonError::into_inner);
+                if let Ok(ref formats) = result {
+                    entries.formats.insert(thread_id.clone(), formats.clone());
+                }
+                entries.pending.remove(&thread_id);
+                shared.queried.notify_all();
+                // The caller is gone if the query timed out.
+                let _ = tx.send(result);
+            });
+        if let Err(err) = spawned {
+            self.entries().pending.remove(id);
+            self.shared.queried.notify_all();
+            let description = format!("failed to spawn the format query thread: {}", err);
+            let code = err.raw_os_error();
+            return Err(BackendSpecificError { description, code }.into());
+        }
+        match rx.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                trace_event!(warn, device = id, timeout = ?self.timeout, "format query timed out");
+                Err(SupportedFormatsError::Timeout)
+            },
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(query_panicked()),
+        }
+    }
+
+    /// Queries the formats of the devices in `queries` in the background, and caches them.
+    ///
+    /// At most `MAX_CONCURRENT_QUERIES` devices are queried at once. The devices whose formats are
+    /// cached or being queried already are skipped.
+    pub fn prefetch<F>(&self, queries: Vec<(String, F)>)
+    where
+        F: FnOnce() -> Result<Vec<SupportedFormat>, SupportedFormatsError> + Send + 'static,
+    {
+        let mut entries = self.entries();
+        for (id, query) in queries {
+            let known = entries.formats.contains_key(&id)
+                || entries.pending.contains(&id)
+                || entries.prefetching.contains(&id)
+                || entries.is_queued(&id);
+            if !known {
+                entries.queue.push_back((id, Box::new(query)));
+            }
+        }
+        let workers = cmp::min(entries.queue.len(), MAX_CONCURRENT_QUERIES);
+        for _ in entries.workers..workers {
+            let shared = self.shared.clone();
+            let spawned = thread::Builder::new()
+                .name("cpal_format_prefetch".to_owned())
+                .spawn(move || prefetch_queued(&shared));
+            if spawned.is_ok() {
+                entries.workers += 1;
+            }
+        }
+        if entries.workers == 0 {
+            // Nothing will query the queued devices, so they are queried on demand instead.
+            entries.queue.clear();
+        }
+    }
+
+    /// Forgets the formats of device `id`.
+    pub fn invalidate(&self, id: &str) {
+        self.entries().formats.remove(id);
+    }
+
+    /// Forgets the formats of all devices.
+    ///
+    /// Queries that timed out keep running, so that a hanging device is not queried again.
+    pub fn clear(&self) {
+        self.entries().formats.clear();
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.shared.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Entries {
+    fn is_queued(&self, id: &str) -> bool {
+        self.queue.iter().any(|(queued, _)| queued == id)
+    }
+}
+
+// Queries the devices queued by `FormatCache::prefetch` until the queue is empty.
+fn prefetch_queued(shared: &Shared) {
+    let mut entries = shared.entries.lock().unwrap_or_else(PoisonError::into_inner);
+    while let Some((id, query)) = entries.queue.pop_front() {
+        entries.prefetching.insert(id.clone());
+        shared.queried.notify_all();
+        drop(entries);
+        let result = panic::catch_unwind(AssertUnwindSafe(query));
+        entries = shared.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Ok(Ok(formats)) = result {
+            entries.formats.insert(id.clone(), formats);
+        }
+        entries.prefetching.remove(&id);
+        shared.queried.notify_all();
+    }
+    entries.workers -= 1;
+}
+
+fn query_panicked() -> SupportedFormatsError {
+    let description = String::from("the query of the supported formats panicked");
+    BackendSpecificError { description, code: None }.into()
+}
+
+/// Checks `format` against the `supported` formats of a device.
+///
+/// If no supported format matches, the error names the first field that rules them all out, in
+/// the order channels, sample format, sample rate. An empty list is not checked, as it only means
+/// that the formats could not be determined.
+pub(crate) fn check_format(
+    format: &Format,
+    supported: &[SupportedFormat],
+) -> Result<(), BuildStreamError>
+{
+    if supported.is_empty() {
+        return Ok(());
+    }
+    let rate_matches = |f: &SupportedFormat| {
+        f.min_sample_rate <= format.sample_rate && format.sample_rate <= f.max_sample_rate
+    };
+    let mut channels = supported.iter().filter(|f| f.channels == format.channels).peekable();
+    if channels.peek().is_none() {
+        return Err(BuildStreamError::InvalidChannelCount);
+    }
+    let mut data_types = channels.filter(|f| f.data_type == format.data_type).peekable();
+    if data_types.peek().is_none() {
+        return Err(BuildStreamError::SampleFormatNotSupported);
+    }
+    if !data_types.any(rate_matches) {
+        return Err(BuildStreamError::SampleRateNotSupported);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_format, FormatCache, Query, MAX_CONCURRENT_QUERIES};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use {BuildStreamError, Format, SampleFormat, SampleRate, SupportedFormat, SupportedFormatsError};
+
+    fn formats() -> Vec<SupportedFormat> {
+        vec![SupportedFormat {
+            channels: 2,
+            min_sample_rate: SampleRate(44_100),
+            max_sample_rate: SampleRate(48_000),
+            data_type: SampleFormat::F32,
+        }]
+    }
+
+    #[test]
+    fn queries_are_cached_until_invalidated() {
+        let cache = FormatCache::new();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let query = || {
+            let queries = queries.clone();
+            move || {
+                queries.fetch_add(1, Ordering::SeqCst);
+                Ok(formats())
+            }
+        };
+        let count = || queries.load(Ordering::SeqCst);
+        assert_eq!(cache.get_or_query("a", query()).unwrap(), formats());
+        assert_eq!(cache.get_or_query("a", query()).unwrap(), formats());
+        assert_eq!(count(), 1);
+        cache.get_or_query("b", query()).unwrap();
+        assert_eq!(count(), 2);
+        cache.invalidate("a");
+        cache.get_or_query("a", query()).unwrap();
+        cache.get_or_query("b", query()).unwrap();
+        assert_eq!(count(), 3);
+        cache.clear();
+        cache.get_or_query("b", query()).unwrap();
+        assert_eq!(count(), 4);
+    }
+
+    #[test]
+    fn hanging_queries_time_out() {
+        let mut cache = FormatCache::new();
+        cache.timeout = Duration::from_millis(10);
+        let result = cache.get_or_query("a", || {
+            thread::sleep(Duration::from_millis(200));
+            Ok(formats())
+        });
+        assert!(matches!(result, Err(SupportedFormatsError::Timeout)));
+        // The device is not queried again while the first query is running.
+        let result = cache.get_or_query("a", || panic!("queried twice"));
+        assert!(matches!(result, Err(SupportedFormatsError::Timeout)));
+        // The result of the first query is cached once it completes.
+        let mut result = Err(SupportedFormatsError::Timeout);
+        while let Err(SupportedFormatsError::Timeout) = result {
+            thread::sleep(Duration::from_millis(10));
+            result = cache.get_or_query("a", || panic!("queried twice"));
+        }
+        assert_eq!(result.unwrap(), formats());
+    }
+
+    #[test]
+    fn concurrent_queries_wait_for_the_running_one() {
+        let cache = Arc::new(FormatCache::new());
+        let queried = Arc::new(AtomicUsize::new(0));
+        let first_queried = queried.clone();
+        let first_cache = cache.clone();
+        let first = thread::spawn(move || {
+            first_cache.get_or_query("a", move || {
+                first_queried.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(100));
+                Ok(formats())
+            })
+        });
+        while queried.load(Ordering::SeqCst) == 0 {
+            thread::yield_now();
+        }
+        let result = cache.get_or_query("a", || panic!("queried twice"));
+        assert_eq!(result.unwrap(), formats());
+        assert_eq!(first.join().unwrap().unwrap(), formats());
+    }
+
+    #[test]
+    fn errors_are_not_cached() {
+        let cache = FormatCache::new();
+        let result = cache.get_or_query("a", || Err(SupportedFormatsError::DeviceNotAvailable));
+        assert!(result.is_err());
+        assert_eq!(cache.get_or_query("a", || Ok(formats())).unwrap(), formats());
+    }
+
+    #[test]
+    fn prefetched_devices_are_not_queried_again() {
+        let cache = FormatCache::new();
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let queries = (0..10)
+            .map(|i| {
+                let running = running.clone();
+                let max_running = max_running.clone();
+                let query = move || {
+                    let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now_running, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(formats())
+                };
+                (i.to_string(), query)
+            })
+            .collect();
+        cache.prefetch(queries);
+        for i in 0..10 {
+            let result = cache.get_or_query(&i.to_string(), || panic!("queried twice"));
+            assert_eq!(result.unwrap(), formats());
+        }
+        assert!(max_running.load(Ordering::SeqCst) <= MAX_CONCURRENT_QUERIES);
+    }
+
+    #[test]
+    fn queued_devices_do_not_time_out_behind_slow_ones() {
+        let mut cache = FormatCache::new();
+        cache.timeout = Duration::from_millis(50);
+        let mut queries: Vec<(String, Query)> = (0..MAX_CONCURRENT_QUERIES)
+            .map(|i| {
+                let query: Query = Box::new(|| {
+                    thread::sleep(Duration::from_millis(500));
+                    Ok(formats())
+                });
+                (i.to_string(), query)
+            })
+            .collect();
+        queries.push(("fast".to_owned(), Box::new(|| Ok(formats()))));
+        cache.prefetch(queries);
+        let result = cache.get_or_query("fast", || panic!("queried twice"));
+        assert_eq!(result.unwrap(), formats());
+    }
+
+    #[test]
+    fn check_format_names_the_unsupported_field() {
+        let format = |channels, sample_rate, data_type| Format {
+            channels,
+            sample_rate: SampleRate(sample_rate),
+            data_type,
+        };
+        let check = |f| check_format(&f, &formats());
+        assert!(check(format(2, 48_000, SampleFormat::F32)).is_ok());
+        assert!(matches!(
+            check(format(6, 48_000, SampleFormat::F32)),
+            Err(BuildStreamError::InvalidChannelCount)
+        ));
+        assert!(matches!(
+            check(format(2, 48_000, SampleFormat::I16)),
+            Err(BuildStreamError::SampleFormatNotSupported)
+        ));
+        assert!(matches!(
+            check(format(2, 96_000, SampleFormat::F32)),
+            Err(BuildStreamError::SampleRateNotSupported)
+        ));
+        assert!(check_format(&format(6, 96_000, SampleFormat::I16), &[]).is_ok());
+    }
+}
@@ -0,0 +1,221 @@
+//! Switching an output device to a stream with another format without a gap.
+//!
+//! The format of a stream cannot be changed once it has been built. A `GaplessOutput` builds the
+//! stream with the new format while the previous stream keeps playing, then crossfades from the
+//! previous stream to the new one. This allows gapless playback to survive a change of sample rate
+//! or sample format, e.g. between the tracks of an album.
+//!
+//! Both streams play at the same time during the crossfade, which relies on the host mixing the
+//! streams of a device. With a crossfade of zero, the previous stream is silenced at its next
+//! buffer boundary while the new stream starts playing with its first buffer.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+use adapter::{self, play};
+use traits::DeviceTrait;
+use BuildStreamError;
+use Format;
+use Sample;
+use StreamData;
+use StreamError;
+use StreamOptions;
+use UnknownTypeOutputBuffer;
+
+/// An output stream whose format can be changed without interrupting playback.
+pub struct GaplessOutput<D: DeviceTrait> {
+    stream: D::Stream,
+    gain: Arc<Gain>,
+    // The stream being faded out after the last reconfiguration.
+    previous: Option<(D::Stream, Arc<Gain>)>,
+}
+
+// The gain applied to the output of a stream, ramped towards `target` by the data callback.
+struct Gain {
+    // The target gain, stored as the bits of an `f32`.
+    target: AtomicU32,
+    // The duration of a ramp from `0.0` to `1.0` in seconds, stored as the bits of an `f32`.
+    ramp: AtomicU32,
+    // Set by the data callback once the gain reached a target of `0.0`.
+    silent: AtomicBool,
+}
+
+impl<D: DeviceTrait> GaplessOutput<D> {
+    /// Builds an output stream on `device` and starts playing it.
+    pub fn new<C, E>(
+        device: &D,
+        format: &Format,
+        data_callback: C,
+        error_callback: E,
+    ) -> Result<Self, BuildStreamError>
+    where
+        C: FnMut(StreamData) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let gain = Arc::new(Gain::new(1.0, Duration::from_secs(0)));
+        let stream = build_stream(device, format, &gain, 1.0, data_callback, error_callback)?;
+        Ok(GaplessOutput {
+            stream,
+            gain,
+            previous: None,
+        })
+    }
+
+    /// Replaces the stream with a new stream using `format`, crossfading between the two streams
+    /// over `crossfade`.
+    ///
+    /// The current stream keeps playing until the new stream has been built and started. If a
+    /// previous stream is still fading out, it is dropped.
+    pub fn reconfigure<C, E>(
+        &mut self,
+        device: &D,
+        format: &Format,
+        data_callback: C,
+        error_callback: E,
+        crossfade: Duration,
+    ) -> Result<(), BuildStreamError>
+    where
+        C: FnMut(StreamData) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let gain = Arc::new(Gain::new(1.0, crossfade));
+        let stream = build_stream(device, format, &gain, 0.0, data_callback, error_callback)?;
+        self.gain.fade_to(0.0, crossfade);
+        let previous_stream = std::mem::replace(&mut self.stream, stream);
+        let previous_gain = std::mem::replace(&mut self.gain, gain);
+        self.previous = Some((previous_stream, previous_gain));
+        Ok(())
+    }
+
+    /// The stream that is currently playing.
+    pub fn stream(&self) -> &D::Stream {
+        &self.stream
+    }
+
+    /// Drops the previous stream once it has been faded out.
+    ///
+    /// Returns `true` if no previous stream remains. Streams that are faded out only output
+    /// silence, but they keep using the device until they are dropped.
+    pub fn release_previous(&mut self) -> bool {
+        let faded_out = match self.previous {
+            Some((_, ref gain)) => gain.silent.load(Ordering::Acquire),
+            None => return true,
+        };
+        if faded_out {
+            self.previous = None;
+        }
+        faded_out
+    }
+}
+
+impl Gain {
+    fn new(target: f32, ramp: Duration) -> Self {
+        Gain {
+            target: AtomicU32::new(target.to_bits()),
+            ramp: AtomicU32::new(ramp.as_secs_f32().to_bits()),
+            silent: AtomicBool::new(false),
+        }
+    }
+
+    fn fade_to(&self, target: f32, ramp: Duration) {
+        self.ramp.store(ramp.as_secs_f32().to_bits(), Ordering::Relaxed);
+        self.target.store(target.to_bits(), Ordering::Release);
+    }
+}
+
+// Builds and plays an output stream whose output is multiplied by `gain`, starting at `initial`.
+fn build_stream<D, C, E>(
+    device: &D,
+    format: &Format,
+    gain: &Arc<Gain>,
+    initial: f32,
+    mut data_callback: C,
+    error_callback: E,
+) -> Result<D::Stream, BuildStreamError>
+where
+    D: DeviceTrait,
+    C: FnMut(StreamData) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let channels = format.channels as usize;
+    let sample_rate = format.sample_rate.0 as f32;
+    let gain = gain.clone();
+    let mut current = initial;
+    let stream = device.build_output_stream_dyn(
+        format,
+        &StreamOptions::default(),
+        move |data| {
+            adapter::call_then(data, &mut data_callback, |data| {
+                let buffer = match data {
+                    StreamData::Output { buffer, .. } => buffer,
+                    StreamData::Input { .. } => return,
+                };
+                let target = f32::from_bits(gain.target.load(Ordering::Acquire));
+                let ramp = f32::from_bits(gain.ramp.load(Ordering::Relaxed));
+                let step = if ramp > 0.0 { 1.0 / (ramp * sample_rate) } else { 1.0 };
+                match buffer {
+                    UnknownTypeOutputBuffer::U16(mut samples) => {
+                        apply_gain(&mut samples, channels, &mut current, target, step)
+                    },
+                    UnknownTypeOutputBuffer::I16(mut samples) => {
+                        apply_gain(&mut samples, channels, &mut current, target, step)
+                    },
+                    UnknownTypeOutputBuffer::F32(mut samples) => {
+                        apply_gain(&mut samples, channels, &mut current, target, step)
+                    },
+                }
+                if current == 0.0 && target == 0.0 {
+                    gain.silent.store(true, Ordering::Release);
+                }
+            });
+        },
+        error_callback,
+    )?;
+    play(&stream)?;
+    Ok(stream)
+}
+
+// Multiplies each frame of `samples` by `current`, which moves towards `target` by `step` per
+// frame.
+fn apply_gain<T>(samples: &mut [T], channels: usize, current: &mut f32, target: f32, step: f32)
+where
+    T: Sample,
+{
+    if *current == 1.0 && target == 1.0 {
+        return;
+    }
+    for frame in samples.chunks_mut(channels) {
+        if *current < target {
+            *current = (*current + step).min(target);
+        } else if *current > target {
+            *current = (*current - step).max(target);
+        }
+        for sample in frame.iter_mut() {
+            *sample = T::from(&(sample.to_f32() * *current));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::apply_gain;
+
+    #[test]
+    fn gain_ramps_per_frame() {
+        let mut samples = [1.0f32; 8];
+        let mut current = 0.0;
+        apply_gain(&mut samples, 2, &mut current, 1.0, 0.25);
+        assert_eq!(samples, [0.25, 0.25, 0.5, 0.5, 0.75, 0.75, 1.0, 1.0]);
+        assert_eq!(current, 1.0);
+    }
+
+    #[test]
+    fn gain_fades_out_to_silence() {
+        let mut samples = [16_384i16; 6];
+        let mut current = 1.0;
+        apply_gain(&mut samples, 2, &mut current, 0.0, 0.5);
+        assert_eq!(samples, [8_192, 8_192, 0, 0, 0, 0]);
+        assert_eq!(current, 0.0);
+    }
+}
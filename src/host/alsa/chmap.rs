@@ -0,0 +1,139 @@
+use BackendSpecificError;
+use super::alsa;
+use super::check_errors;
+use super::libc;
+use std::slice;
+
+/// The position of a channel of an ALSA device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChannelPosition {
+    /// The driver does not know the position of the channel.
+    Unknown,
+    /// The channel is not connected.
+    NotAvailable,
+    Mono,
+    FrontLeft,
+    FrontRight,
+    RearLeft,
+    RearRight,
+    FrontCenter,
+    LowFrequency,
+    SideLeft,
+    SideRight,
+    RearCenter,
+    /// Another position, by its `SND_CHMAP_*` value, e.g. one of the height channels.
+    Other(u32),
+}
+
+/// Whether the channels of a channel map can be reordered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChannelMapType {
+    /// The channels are in this order only.
+    Fixed,
+    /// Any order of these positions can be selected.
+    Variable,
+    /// Any order of pairs of these positions can be selected.
+    Paired,
+}
+
+/// A channel map supported by an ALSA device, as reported by `snd_pcm_query_chmaps`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChannelMap {
+    pub map_type: ChannelMapType,
+    /// The position of each channel of a frame, in order.
+    pub positions: Vec<ChannelPosition>,
+}
+
+impl ChannelPosition {
+    fn from_alsa(position: alsa::snd_pcm_chmap_position) -> Self {
+        match position {
+            alsa::SND_CHMAP_UNKNOWN => ChannelPosition::Unknown,
+            alsa::SND_CHMAP_NA => ChannelPosition::NotAvailable,
+            alsa::SND_CHMAP_MONO => ChannelPosition::Mono,
+            alsa::SND_CHMAP_FL => ChannelPosition::FrontLeft,
+            alsa::SND_CHMAP_FR => ChannelPosition::FrontRight,
+            alsa::SND_CHMAP_RL => ChannelPosition::RearLeft,
+            alsa::SND_CHMAP_RR => ChannelPosition::RearRight,
+            alsa::SND_CHMAP_FC => ChannelPosition::FrontCenter,
+            alsa::SND_CHMAP_LFE => ChannelPosition::LowFrequency,
+            alsa::SND_CHMAP_SL => ChannelPosition::SideLeft,
+            alsa::SND_CHMAP_SR => ChannelPosition::SideRight,
+            alsa::SND_CHMAP_RC => ChannelPosition::RearCenter,
+            other => ChannelPosition::Other(other),
+        }
+    }
+
+    fn to_alsa(self) -> alsa::snd_pcm_chmap_position {
+        match self {
+            ChannelPosition::Unknown => alsa::SND_CHMAP_UNKNOWN,
+            ChannelPosition::NotAvailable => alsa::SND_CHMAP_NA,
+            ChannelPosition::Mono => alsa::SND_CHMAP_MONO,
+            ChannelPosition::FrontLeft => alsa::SND_CHMAP_FL,
+            ChannelPosition::FrontRight => alsa::SND_CHMAP_FR,
+            ChannelPosition::RearLeft => alsa::SND_CHMAP_RL,
+            ChannelPosition::RearRight => alsa::SND_CHMAP_RR,
+            ChannelPosition::FrontCenter => alsa::SND_CHMAP_FC,
+            ChannelPosition::LowFrequency => alsa::SND_CHMAP_LFE,
+            ChannelPosition::SideLeft => alsa::SND_CHMAP_SL,
+            ChannelPosition::SideRight => alsa::SND_CHMAP_SR,
+            ChannelPosition::RearCenter => alsa::SND_CHMAP_RC,
+            ChannelPosition::Other(position) => position,
+        }
+    }
+}
+
+unsafe fn positions(map: *const alsa::snd_pcm_chmap_t) -> Vec<ChannelPosition> {
+    let channels = (*map).channels as usize;
+    slice::from_raw_parts((*map).pos.as_ptr(), channels)
+        .iter()
+        .map(|&position| ChannelPosition::from_alsa(position))
+        .collect()
+}
+
+// The channel maps supported by `handle`, or none if the driver does not report them.
+pub unsafe fn query(handle: *mut alsa::snd_pcm_t) -> Vec<ChannelMap> {
+    let maps = alsa::snd_pcm_query_chmaps(handle);
+    if maps.is_null() {
+        return Vec::new();
+    }
+    let mut result = Vec::new();
+    let mut next = maps;
+    while !(*next).is_null() {
+        let query = *next;
+        let map_type = match (*query)._type {
+            alsa::SND_CHMAP_TYPE_VAR => ChannelMapType::Variable,
+            alsa::SND_CHMAP_TYPE_PAIRED => ChannelMapType::Paired,
+            _ => ChannelMapType::Fixed,
+        };
+        result.push(ChannelMap {
+            map_type,
+            positions: positions(&(*query).map),
+        });
+        next = next.offset(1);
+    }
+    alsa::snd_pcm_free_chmaps(maps);
+    result
+}
+
+// The channel map of `handle`, or `None` if the driver does not report it.
+pub unsafe fn get(handle: *mut alsa::snd_pcm_t) -> Option<Vec<ChannelPosition>> {
+    let map = alsa::snd_pcm_get_chmap(handle);
+    if map.is_null() {
+        return None;
+    }
+    let positions = positions(map);
+    // ALSA allocates the map with `malloc`.
+    libc::free(map as *mut _);
+    Some(positions)
+}
+
+pub unsafe fn set(
+    handle: *mut alsa::snd_pcm_t,
+    positions: &[ChannelPosition],
+) -> Result<(), BackendSpecificError> {
+    // The layout of `snd_pcm_chmap_t`: the number of channels followed by their positions.
+    let mut map = Vec::with_capacity(positions.len() + 1);
+    map.push(positions.len() as libc::c_uint);
+    map.extend(positions.iter().map(|position| position.to_alsa()));
+    check_errors(alsa::snd_pcm_set_chmap(handle, map.as_ptr() as *const _))
+}
@@ -1,4 +1,4 @@
-use {BackendSpecificError, DevicesError};
+use DevicesError;
 use super::Device;
 use super::alsa;
 use super::check_errors;
@@ -6,6 +6,10 @@ use std::ffi::CString;
 use std::ptr;
 
 /// ALSA implementation for `Devices`.
+///
+/// Devices are listed from the name hints of ALSA without being opened, so enumerating them is
+/// cheap even when there are many of them. `Host::devices` then probes their formats in the
+/// background.
 pub struct Devices {
     // we keep the original list so that we can pass it to the free function
     global_list: *const *const u8,
@@ -22,8 +26,7 @@ impl Devices {
             let iface = b"pcm\0"; // Interface identification.
             let mut hints = ptr::null_mut(); // Array of device name hints.
             let res = alsa::snd_device_name_hint(card, iface.as_ptr() as *const _, &mut hints);
-            if let Err(description) = check_errors(res) {
-                let err = BackendSpecificError { description };
+            if let Err(err) = check_errors(res) {
                 return Err(err.into());
             }
             let hints = hints as *const *const u8;
@@ -36,6 +39,8 @@ impl Devices {
     }
 }
 
+// The hint list is owned by `Devices`, which only reads it through `&mut self` and frees it once
+// when dropped.
 unsafe impl Send for Devices {
 }
 unsafe impl Sync for Devices {
@@ -92,46 +97,13 @@ impl Iterator for Devices {
                     }
                 }
 
-                let name = match name {
-                    Some(name) => {
-                        // Ignoring the `null` device.
-                        if name == "null" {
-                            continue;
-                        }
-                        name
-                    },
-                    _ => continue,
-                };
-
-                // trying to open the PCM device to see if it can be opened
-                let name_zeroed = CString::new(&name[..]).unwrap();
-
-                // See if the device has an available output stream.
-                let mut playback_handle = ptr::null_mut();
-                let has_available_output = alsa::snd_pcm_open(
-                    &mut playback_handle,
-                    name_zeroed.as_ptr() as *const _,
-                    alsa::SND_PCM_STREAM_PLAYBACK,
-                    alsa::SND_PCM_NONBLOCK,
-                ) == 0;
-                if has_available_output {
-                    alsa::snd_pcm_close(playback_handle);
-                }
-
-                // See if the device has an available input stream.
-                let mut capture_handle = ptr::null_mut();
-                let has_available_input = alsa::snd_pcm_open(
-                    &mut capture_handle,
-                    name_zeroed.as_ptr() as *const _,
-                    alsa::SND_PCM_STREAM_CAPTURE,
-                    alsa::SND_PCM_NONBLOCK,
-                ) == 0;
-                if has_available_input {
-                    alsa::snd_pcm_close(capture_handle);
-                }
-
-                if has_available_output || has_available_input {
-                    return Some(Device(name));
+                match name {
+                    // Ignoring the `null` device.
+                    Some(ref name) if name == "null" => continue,
+                    // The device is only opened once its formats are queried, which fails if it
+                    // is not available.
+                    Some(name) => return Some(Device(name, None)),
+                    None => continue,
                 }
             }
         }
@@ -140,10 +112,10 @@ impl Iterator for Devices {
 
 #[inline]
 pub fn default_input_device() -> Option<Device> {
-    Some(Device("default".to_owned()))
+    Some(Device("default".to_owned(), None))
 }
 
 #[inline]
 pub fn default_output_device() -> Option<Device> {
-    Some(Device("default".to_owned()))
+    Some(Device("default".to_owned(), None))
 }
@@ -1,37 +1,85 @@
 extern crate alsa_sys as alsa;
 extern crate libc;
 
-use std::{cmp, ffi, io, mem, ptr};
+use std::{cmp, env, ffi, io, mem, ptr};
+use std::path::Path;
 use std::sync::Arc;
-use std::thread::{self, JoinHandle};
+use std::hint;
+use std::time::{Duration, Instant};
 use std::vec::IntoIter as VecIntoIter;
 
 use BackendSpecificError;
+use BufferFlags;
 use BuildStreamError;
+use CallbackInfo;
 use ChannelCount;
 use DefaultFormatError;
+use DeviceLossReason;
 use DeviceNameError;
 use DevicesError;
 use Format;
 use PauseStreamError;
 use PlayStreamError;
+use RawDeviceHandle;
+use RawStreamHandle;
 use SampleFormat;
 use SampleRate;
+use SlowCallbackPolicy;
 use StreamData;
 use StreamError;
+use StreamOptions;
+use StreamPosition;
+use StreamStats;
 use SupportedFormat;
 use SupportedFormatsError;
-use traits::{DeviceTrait, HostTrait, StreamTrait};
+use convert::convert_samples;
+use downmix::{Speaker, SurroundLayout};
+use format_cache::{self, FormatCache};
+use InputDevices;
+use OutputDevices;
+use stats::StreamMeter;
+use trace;
+use traits::{self, DeviceTrait, HostTrait, StreamTrait};
 use UnknownTypeInputBuffer;
 use UnknownTypeOutputBuffer;
+use worker::{self, Worker};
 
+pub use self::chmap::{ChannelMap, ChannelMapType, ChannelPosition};
 pub use self::enumerate::{default_input_device, default_output_device, Devices};
 
 pub type SupportedInputFormats = VecIntoIter<SupportedFormat>;
 pub type SupportedOutputFormats = VecIntoIter<SupportedFormat>;
 
+mod chmap;
 mod enumerate;
 
+lazy_static! {
+    // The formats supported by each device, keyed by the name of the device.
+    static ref INPUT_FORMATS: FormatCache = FormatCache::new();
+    static ref OUTPUT_FORMATS: FormatCache = FormatCache::new();
+}
+
+fn format_cache(stream_t: alsa::snd_pcm_stream_t) -> &'static FormatCache {
+    if stream_t == alsa::SND_PCM_STREAM_CAPTURE {
+        &INPUT_FORMATS
+    } else {
+        &OUTPUT_FORMATS
+    }
+}
+
+// Queries the formats of `devices` in the background, a few devices at once, so that they are
+// ready when listed.
+fn prefetch_formats(devices: &[Device], stream_t: alsa::snd_pcm_stream_t) {
+    let queries = devices
+        .iter()
+        .map(|device| {
+            let device = device.clone();
+            (device.0.clone(), move || unsafe { device.supported_formats(stream_t) })
+        })
+        .collect();
+    format_cache(stream_t).prefetch(queries);
+}
+
 /// The default linux, dragonfly and freebsd host type.
 #[derive(Debug)]
 pub struct Host;
@@ -52,9 +100,26 @@ impl HostTrait for Host {
     }
 
     fn devices(&self) -> Result<Self::Devices, DevicesError> {
+        // Enumerating the devices again refreshes their formats.
+        INPUT_FORMATS.clear();
+        OUTPUT_FORMATS.clear();
         Devices::new()
     }
 
+    // Listing the devices of a direction probes all of them, so their formats are queried
+    // concurrently up front. Only that direction is queried, as probing opens the devices.
+    fn input_devices(&self) -> Result<InputDevices<Self::Devices>, DevicesError> {
+        let devices = self.devices()?;
+        prefetch_formats(&Devices::new()?.collect::<Vec<_>>(), alsa::SND_PCM_STREAM_CAPTURE);
+        Ok(devices.filter(traits::supports_input::<Device>))
+    }
+
+    fn output_devices(&self) -> Result<OutputDevices<Self::Devices>, DevicesError> {
+        let devices = self.devices()?;
+        prefetch_formats(&Devices::new()?.collect::<Vec<_>>(), alsa::SND_PCM_STREAM_PLAYBACK);
+        Ok(devices.filter(traits::supports_output::<Device>))
+    }
+
     fn default_input_device(&self) -> Option<Self::Device> {
         default_input_device()
     }
@@ -89,12 +154,16 @@ impl DeviceTrait for Device {
         Device::default_output_format(self)
     }
 
-    fn build_input_stream<D, E>(&self, format: &Format, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
-        Ok(Stream::new(Arc::new(self.build_stream_inner(format, alsa::SND_PCM_STREAM_CAPTURE)?), data_callback, error_callback))
+    fn build_input_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
+        let stream_inner = self.build_stream_inner(format, options, alsa::SND_PCM_STREAM_CAPTURE)
+            .map_err(|err| self.invalidate_stale_formats(alsa::SND_PCM_STREAM_CAPTURE, err))?;
+        Stream::new(Arc::new(stream_inner), options, data_callback, error_callback)
     }
 
-    fn build_output_stream<D, E>(&self, format: &Format, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
-        Ok(Stream::new(Arc::new(self.build_stream_inner(format, alsa::SND_PCM_STREAM_PLAYBACK)?), data_callback, error_callback))
+    fn build_output_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
+        let stream_inner = self.build_stream_inner(format, options, alsa::SND_PCM_STREAM_PLAYBACK)
+            .map_err(|err| self.invalidate_stale_formats(alsa::SND_PCM_STREAM_PLAYBACK, err))?;
+        Stream::new(Arc::new(stream_inner), options, data_callback, error_callback)
     }
 }
 
@@ -104,10 +173,10 @@ struct TriggerSender(libc::c_int);
 struct TriggerReceiver(libc::c_int);
 
 impl TriggerSender {
+    // Only used when the stream is dropped, so a failure is ignored rather than panicking.
     fn wakeup(&self) {
         let buf = 1u64;
-        let ret = unsafe { libc::write(self.0, &buf as *const u64 as *const _, 8) };
-        assert!(ret == 8);
+        unsafe { libc::write(self.0, &buf as *const u64 as *const _, 8) };
     }
 }
 
@@ -143,11 +212,151 @@ impl Drop for TriggerReceiver {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Device(String);
+// The name of the device, and the channel map that its streams are opened with, if any.
+#[derive(Clone, Debug)]
+pub struct Device(String, Option<Vec<ChannelPosition>>);
+
+// A device is the same whatever the channel map of its streams, so that it still compares equal
+// to e.g. the default device once one is set.
+impl PartialEq for Device {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Device {}
+
+// Without a channel map, ALSA orders the channels of surround devices such as `surround51` front
+// left, front right, rear left, rear right, center, LFE, then side left and side right.
+impl SurroundLayout for Device {
+    fn surround_layout(&self, channels: ChannelCount) -> Vec<Speaker> {
+        let speaker = |position| match position {
+            ChannelPosition::FrontLeft => Speaker::FrontLeft,
+            ChannelPosition::FrontRight => Speaker::FrontRight,
+            ChannelPosition::FrontCenter => Speaker::Center,
+            ChannelPosition::LowFrequency => Speaker::LowFrequency,
+            ChannelPosition::RearLeft => Speaker::BackLeft,
+            ChannelPosition::RearRight => Speaker::BackRight,
+            ChannelPosition::SideLeft => Speaker::SideLeft,
+            ChannelPosition::SideRight => Speaker::SideRight,
+            _ => Speaker::Other,
+        };
+        let default = [
+            ChannelPosition::FrontLeft,
+            ChannelPosition::FrontRight,
+            ChannelPosition::RearLeft,
+            ChannelPosition::RearRight,
+            ChannelPosition::FrontCenter,
+            ChannelPosition::LowFrequency,
+            ChannelPosition::SideLeft,
+            ChannelPosition::SideRight,
+        ];
+        let positions = match self.channel_map() {
+            Some(positions) if positions.len() == channels as usize => positions,
+            _ => &default[..],
+        };
+        let mut layout: Vec<Speaker> = positions.iter().map(|&p| speaker(p)).collect();
+        layout.resize(channels as usize, Speaker::Other);
+        layout
+    }
+}
+
+/// How an ALSA device reaches the sound card, as given by the prefix of its name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessMode {
+    /// A `hw:` device, which talks to the driver directly. It only supports the formats of the
+    /// card, so that samples reach the card unchanged.
+    Hardware,
+    /// A `plughw:` device, which converts the format, channels and sample rate of a stream to
+    /// those of the card.
+    Plug,
+    /// The `default` device, which is usually routed through a sound server or a mixing plugin.
+    Default,
+    /// Any other device, e.g. `sysdefault:`, `front:` or `dmix:`.
+    Other,
+}
 
 impl Device {
-    fn build_stream_inner(&self, format: &Format, stream_type: alsa::snd_pcm_stream_t) -> Result<StreamInner, BuildStreamError> {
+    /// ALSA devices are only names, so there is no native handle before a stream is built.
+    pub fn raw_handle(&self) -> Option<RawDeviceHandle> {
+        None
+    }
+
+    /// How the device reaches the sound card.
+    pub fn access_mode(&self) -> AccessMode {
+        let prefix = self.0.split(':').next().unwrap_or("");
+        match prefix {
+            "hw" => AccessMode::Hardware,
+            "plughw" => AccessMode::Plug,
+            "default" => AccessMode::Default,
+            _ => AccessMode::Other,
+        }
+    }
+
+    /// The `hw:` device of the card and device that this device plays to or captures from.
+    ///
+    /// Streams on the `hw:` device are bit-exact: CPAL does not convert their samples either, so
+    /// building them fails with `BuildStreamError::SampleFormatNotSupported`,
+    /// `SampleRateNotSupported` or `InvalidChannelCount` if the card does not support the format
+    /// natively. Returns `None` for devices that do not name a card, such as `default`.
+    pub fn hardware_device(&self) -> Option<Device> {
+        let mut parts = self.0.splitn(2, ':');
+        let prefix = parts.next().unwrap_or("");
+        let args = parts.next()?;
+        match prefix {
+            "hw" => Some(self.clone()),
+            "plughw" => Some(Device(format!("hw:{}", args), None)),
+            _ => {
+                // Names such as `front:CARD=PCH,DEV=0` name the card and the device.
+                let mut card = None;
+                let mut device = "0";
+                for arg in args.split(',') {
+                    if let Some(name) = arg.strip_prefix("CARD=") {
+                        card = Some(name);
+                    } else if let Some(index) = arg.strip_prefix("DEV=") {
+                        device = index;
+                    }
+                }
+                card.map(|card| Device(format!("hw:CARD={},DEV={}", card, device), None))
+            },
+        }
+    }
+
+    /// The channel maps that input streams of the device support.
+    ///
+    /// The list is empty if the driver does not report channel maps.
+    pub fn supported_input_channel_maps(&self) -> Result<Vec<ChannelMap>, SupportedFormatsError> {
+        unsafe { self.channel_maps(alsa::SND_PCM_STREAM_CAPTURE) }
+    }
+
+    /// The channel maps that output streams of the device support.
+    ///
+    /// The list is empty if the driver does not report channel maps.
+    pub fn supported_output_channel_maps(&self) -> Result<Vec<ChannelMap>, SupportedFormatsError> {
+        unsafe { self.channel_maps(alsa::SND_PCM_STREAM_PLAYBACK) }
+    }
+
+    /// The same device, with its streams opened with the channel map `positions`.
+    ///
+    /// The map must have as many positions as the format of the stream has channels, and should be
+    /// one of the supported channel maps or a reordering of a variable or paired one. Otherwise
+    /// building the stream fails.
+    pub fn with_channel_map(&self, positions: &[ChannelPosition]) -> Device {
+        Device(self.0.clone(), Some(positions.to_vec()))
+    }
+
+    /// The channel map selected with `with_channel_map`, if any.
+    pub fn channel_map(&self) -> Option<&[ChannelPosition]> {
+        self.1.as_ref().map(|positions| &positions[..])
+    }
+
+    fn build_stream_inner(&self, format: &Format, options: &StreamOptions, stream_type: alsa::snd_pcm_stream_t) -> Result<StreamInner, BuildStreamError> {
+        self.check_format(format, stream_type)?;
+        if let Some(ref positions) = self.1 {
+            if positions.len() != format.channels as usize {
+                return Err(BuildStreamError::InvalidChannelCount);
+            }
+        }
         let name = ffi::CString::new(self.0.clone()).expect("unable to clone device");
 
         let handle = unsafe {
@@ -158,30 +367,47 @@ impl Device {
                 stream_type,
                 alsa::SND_PCM_NONBLOCK,
             ) {
-                -16 /* determined empirically */ => return Err(BuildStreamError::DeviceNotAvailable),
+                -16 /* determined empirically */ => return Err(BuildStreamError::DeviceInUse),
                 -22 => return Err(BuildStreamError::InvalidArgument),
-                e => if let Err(description) = check_errors(e) {
-                    let err = BackendSpecificError { description };
+                e if (e == -libc::EACCES || e == -libc::EPERM) && is_sandboxed() => {
+                    return Err(BuildStreamError::SandboxAccessDenied)
+                }
+                e if e == -libc::EACCES || e == -libc::EPERM => {
+                    return Err(BuildStreamError::AccessDenied)
+                }
+                e => if let Err(err) = check_errors(e) {
                     return Err(err.into());
                 }
             }
             handle
         };
-        let can_pause = unsafe {
+        let (hw_parameters, can_pause) = unsafe {
             let hw_params = HwParams::alloc();
-            set_hw_params_from_format(handle, &hw_params, format)
-                .map_err(|description| BackendSpecificError { description })?;
+            let hw_parameters = set_hw_params_from_format(
+                handle,
+                &hw_params,
+                format,
+                self.converts(),
+                options.power_saving,
+            )?;
 
-            alsa::snd_pcm_hw_params_can_pause(hw_params.0) == 1
+            (hw_parameters, alsa::snd_pcm_hw_params_can_pause(hw_params.0) == 1)
         };
+        if let Some(ref positions) = self.1 {
+            if let Err(desc) = unsafe { chmap::set(handle, positions) } {
+                unsafe { alsa::snd_pcm_close(handle) };
+                let description = format!("could not set channel map: {}", desc.description);
+                let err = BackendSpecificError { description, code: desc.code };
+                return Err(err.into());
+            }
+        }
         let (buffer_len, period_len) = unsafe {
-            set_sw_params_from_format(handle, format)
-                .map_err(|description| BackendSpecificError { description })?
+            set_sw_params_from_format(handle, format)?
         };
 
         if let Err(desc) = check_errors(unsafe { alsa::snd_pcm_prepare(handle) }) {
-            let description = format!("could not get handle: {}", desc);
-            let err = BackendSpecificError { description };
+            let description = format!("could not get handle: {}", desc.description);
+            let err = BackendSpecificError { description, code: desc.code };
             return Err(err.into());
         }
 
@@ -189,7 +415,7 @@ impl Device {
             let num_descriptors = unsafe { alsa::snd_pcm_poll_descriptors_count(handle) };
             if num_descriptors == 0 {
                 let description = "poll descriptor count for stream was 0".to_string();
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError { description, code: None };
                 return Err(err.into());
             }
             num_descriptors as usize
@@ -203,14 +429,10 @@ impl Device {
             buffer_len,
             period_len,
             can_pause,
+            hw_parameters,
+            meter: StreamMeter::new(format.sample_rate),
         };
 
-        if let Err(desc) = check_errors(unsafe { alsa::snd_pcm_start(handle) }) {
-            let description = format!("could not start stream: {}", desc);
-            let err = BackendSpecificError { description };
-            return Err(err.into());
-        }
-
         Ok(stream_inner)
     }
 
@@ -219,17 +441,18 @@ impl Device {
         Ok(self.0.clone())
     }
 
-    unsafe fn supported_formats(
+    // Opens the device to query its capabilities.
+    unsafe fn open_for_query(
         &self,
         stream_t: alsa::snd_pcm_stream_t,
-    ) -> Result<VecIntoIter<SupportedFormat>, SupportedFormatsError>
+    ) -> Result<*mut alsa::snd_pcm_t, SupportedFormatsError>
     {
         let mut handle = ptr::null_mut();
         let device_name = match ffi::CString::new(&self.0[..]) {
             Ok(name) => name,
             Err(err) => {
                 let description = format!("failed to retrieve device name: {}", err);
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError { description, code: None };
                 return Err(err.into());
             }
         };
@@ -243,18 +466,35 @@ impl Device {
             -2 |
             -16 /* determined empirically */ => return Err(SupportedFormatsError::DeviceNotAvailable),
             -22 => return Err(SupportedFormatsError::InvalidArgument),
-            e => if let Err(description) = check_errors(e) {
-                let err = BackendSpecificError { description };
+            e => if let Err(err) = check_errors(e) {
                 return Err(err.into())
             }
         }
 
+        Ok(handle)
+    }
+
+    unsafe fn channel_maps(
+        &self,
+        stream_t: alsa::snd_pcm_stream_t,
+    ) -> Result<Vec<ChannelMap>, SupportedFormatsError>
+    {
+        let handle = self.open_for_query(stream_t)?;
+        let maps = chmap::query(handle);
+        alsa::snd_pcm_close(handle);
+        Ok(maps)
+    }
+
+    unsafe fn supported_formats(
+        &self,
+        stream_t: alsa::snd_pcm_stream_t,
+    ) -> Result<Vec<SupportedFormat>, SupportedFormatsError>
+    {
+        let handle = self.open_for_query(stream_t)?;
+
         let hw_params = HwParams::alloc();
         match check_errors(alsa::snd_pcm_hw_params_any(handle, hw_params.0)) {
-            Err(description) => {
-                let err = BackendSpecificError { description };
-                return Err(err.into());
-            }
+            Err(err) => return Err(err.into()),
             Ok(_) => (),
         };
 
@@ -316,8 +556,8 @@ impl Device {
             &mut min_rate,
             ptr::null_mut(),
         )) {
-            let description = format!("unable to get minimum supported rate: {}", desc);
-            let err = BackendSpecificError { description };
+            let description = format!("unable to get minimum supported rate: {}", desc.description);
+            let err = BackendSpecificError { description, code: desc.code };
             return Err(err.into());
         }
 
@@ -327,8 +567,8 @@ impl Device {
             &mut max_rate,
             ptr::null_mut(),
         )) {
-            let description = format!("unable to get maximum supported rate: {}", desc);
-            let err = BackendSpecificError { description };
+            let description = format!("unable to get maximum supported rate: {}", desc.description);
+            let err = BackendSpecificError { description, code: desc.code };
             return Err(err.into());
         }
 
@@ -373,15 +613,15 @@ impl Device {
 
         let mut min_channels = 0;
         if let Err(desc) = check_errors(alsa::snd_pcm_hw_params_get_channels_min(hw_params.0, &mut min_channels)) {
-            let description = format!("unable to get minimum supported channel count: {}", desc);
-            let err = BackendSpecificError { description };
+            let description = format!("unable to get minimum supported channel count: {}", desc.description);
+            let err = BackendSpecificError { description, code: desc.code };
             return Err(err.into());
         }
 
         let mut max_channels = 0;
         if let Err(desc) = check_errors(alsa::snd_pcm_hw_params_get_channels_max(hw_params.0, &mut max_channels)) {
-            let description = format!("unable to get maximum supported channel count: {}", desc);
-            let err = BackendSpecificError { description };
+            let description = format!("unable to get maximum supported channel count: {}", desc.description);
+            let err = BackendSpecificError { description, code: desc.code };
             return Err(err.into());
         }
 
@@ -416,19 +656,84 @@ impl Device {
 
         // TODO: RAII
         alsa::snd_pcm_close(handle);
-        Ok(output.into_iter())
+        Ok(output)
     }
 
-    fn supported_input_formats(&self) -> Result<SupportedInputFormats, SupportedFormatsError> {
-        unsafe {
-            self.supported_formats(alsa::SND_PCM_STREAM_CAPTURE)
+    // The supported formats, queried once per enumeration of the devices.
+    fn cached_supported_formats(
+        &self,
+        stream_t: alsa::snd_pcm_stream_t,
+    ) -> Result<Vec<SupportedFormat>, SupportedFormatsError>
+    {
+        let device = self.clone();
+        format_cache(stream_t).get_or_query(&self.0, move || unsafe {
+            device.supported_formats(stream_t)
+        })
+    }
+
+    // Checks `format` against the supported formats, so that an unsupported format is reported
+    // with the field at fault rather than as a failure to set the hardware parameters. The check
+    // is skipped if the formats cannot be queried, e.g. because the device is busy.
+    fn check_format(
+        &self,
+        format: &Format,
+        stream_t: alsa::snd_pcm_stream_t,
+    ) -> Result<(), BuildStreamError>
+    {
+        let check = || match self.cached_supported_formats(stream_t) {
+            Ok(formats) => format_cache::check_format(format, &formats).or_else(|err| match err {
+                // The samples are converted from or to another sample format of the device.
+                BuildStreamError::SampleFormatNotSupported if self.converts() => DEVICE_FORMATS
+                    .iter()
+                    .map(|&data_type| Format { data_type, ..format.clone() })
+                    .map(|format| format_cache::check_format(&format, &formats))
+                    .find(Result::is_ok)
+                    .unwrap_or(Err(err)),
+                err => Err(err),
+            }),
+            Err(_) => Ok(()),
+        };
+        if check().is_ok() {
+            return Ok(());
         }
+        // The cached formats may be stale, e.g. if another device now has the same name.
+        format_cache(stream_t).invalidate(&self.0);
+        check()
     }
 
-    fn supported_output_formats(&self) -> Result<SupportedOutputFormats, SupportedFormatsError> {
-        unsafe {
-            self.supported_formats(alsa::SND_PCM_STREAM_PLAYBACK)
+    // Whether the samples of streams are converted if the device does not support their sample
+    // format. They are not on `hw:` devices, which are meant for bit-exact streams.
+    fn converts(&self) -> bool {
+        self.access_mode() != AccessMode::Hardware
+    }
+
+    // Forgets the cached formats of the device if failing to build a stream with `err` shows that
+    // they may be stale. ALSA reports most configuration failures as backend-specific errors,
+    // whereas the other failures to open a device do not depend on its formats.
+    fn invalidate_stale_formats(
+        &self,
+        stream_t: alsa::snd_pcm_stream_t,
+        err: BuildStreamError,
+    ) -> BuildStreamError
+    {
+        match err {
+            BuildStreamError::DeviceNotAvailable
+            | BuildStreamError::DeviceInUse
+            | BuildStreamError::AccessDenied
+            | BuildStreamError::SandboxAccessDenied => (),
+            _ => format_cache(stream_t).invalidate(&self.0),
         }
+        err
+    }
+
+    fn supported_input_formats(&self) -> Result<SupportedInputFormats, SupportedFormatsError> {
+        let formats = self.cached_supported_formats(alsa::SND_PCM_STREAM_CAPTURE)?;
+        Ok(formats.into_iter())
+    }
+
+    fn supported_output_formats(&self) -> Result<SupportedOutputFormats, SupportedFormatsError> {
+        let formats = self.cached_supported_formats(alsa::SND_PCM_STREAM_PLAYBACK)?;
+        Ok(formats.into_iter())
     }
 
     // ALSA does not offer default stream formats, so instead we compare all supported formats by
@@ -438,9 +743,11 @@ impl Device {
         stream_t: alsa::snd_pcm_stream_t,
     ) -> Result<Format, DefaultFormatError>
     {
-        let mut formats: Vec<_> = unsafe {
-            match self.supported_formats(stream_t) {
-                Err(SupportedFormatsError::DeviceNotAvailable) => {
+        let mut formats = {
+            match self.cached_supported_formats(stream_t) {
+                // A device that does not respond is as good as unavailable.
+                Err(SupportedFormatsError::DeviceNotAvailable)
+                | Err(SupportedFormatsError::Timeout) => {
                     return Err(DefaultFormatError::DeviceNotAvailable);
                 },
                 Err(SupportedFormatsError::InvalidArgument) => {
@@ -451,7 +758,7 @@ impl Device {
                 Err(SupportedFormatsError::BackendSpecific { err }) => {
                     return Err(err.into());
                 }
-                Ok(fmts) => fmts.collect(),
+                Ok(fmts) => fmts,
             }
         };
 
@@ -503,19 +810,43 @@ struct StreamInner {
 
     // Whether or not the hardware supports pausing the stream.
     can_pause: bool,
+
+    // The hardware parameters chosen by ALSA.
+    hw_parameters: HwParameters,
+
+    // Measurements of the data callback.
+    meter: StreamMeter,
 }
 
-// Assume that the ALSA library is built with thread safe option.
+// Assume that the ALSA library is built with thread safe option. The handle is used by the audio
+// thread, and by the stream to pause and resume it, which ALSA allows from another thread.
 unsafe impl Send for StreamInner {}
 
 unsafe impl Sync for StreamInner {}
 
 enum StreamType { Input, Output }
 
+/// The hardware parameters that ALSA chose for a stream, which may differ from the requested ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HwParameters {
+    /// The sample rate of the stream.
+    pub sample_rate: SampleRate,
+    /// The number of frames after which the device interrupts, i.e. the usual number of frames
+    /// per call to the data callback.
+    pub period_frames: usize,
+    /// The number of periods in the buffer.
+    pub periods: u32,
+    /// The number of frames in the buffer of the device, which bounds the latency of the stream.
+    pub buffer_frames: usize,
+    /// The sample format of the device. If the device does not support the sample format of the
+    /// stream, CPAL converts the samples between the two.
+    pub sample_format: SampleFormat,
+}
+
 pub struct Stream {
-    /// The high-priority audio processing thread calling callbacks.
-    /// Option used for moving out in destructor.
-    thread: Option<JoinHandle<()>>,
+    /// The high-priority audio processing thread calling callbacks, spawned on the first call to
+    /// `play`.
+    worker: Worker,
 
     /// Handle to the underlying stream for playback controls.
     inner: Arc<StreamInner>,
@@ -528,10 +859,39 @@ pub struct Stream {
 /// callback to avoid generating too much generic code.
 fn stream_worker(rx: TriggerReceiver,
                  stream: &StreamInner,
+                 options: &StreamOptions,
                  data_callback: &mut (dyn FnMut(StreamData) + Send + 'static),
-                 error_callback: &mut (dyn FnMut(StreamError) + Send + 'static)) {
-    let mut descriptors = Vec::new();
-    let mut buffer = Vec::new();
+                 error_callback: &mut dyn FnMut(StreamError)) {
+    // The samples read from or written to the device, and those passed to the data callback if
+    // they are converted from or to the sample format of the device.
+    let device_format = stream.hw_parameters.sample_format;
+    let converting = device_format != stream.sample_format;
+    // Allocate the largest buffers the loop may need up front, so that processing a buffer does
+    // not allocate.
+    let max_buffer_size = device_format.sample_size() * stream.buffer_len;
+    let mut descriptors = Vec::with_capacity(1 + stream.num_descriptors);
+    let mut buffer = Vec::with_capacity(max_buffer_size);
+    let mut converted = if converting {
+        Vec::with_capacity(stream.sample_format.sample_size() * stream.buffer_len)
+    } else {
+        Vec::new()
+    };
+    // The last buffer written to the device, used to conceal underruns.
+    let mut last_buffer = match options.slow_callback {
+        SlowCallbackPolicy::Repeat => Vec::with_capacity(max_buffer_size),
+        _ => Vec::new(),
+    };
+    // The device is only started once this thread is there to feed or drain it, so that a stream
+    // that was built some time before being played does not start with an xrun.
+    if let Err(err) = check_errors(unsafe { alsa::snd_pcm_start(stream.channel) }) {
+        let description = format!("could not start stream: {}", err.description);
+        error_callback(BackendSpecificError { description, code: err.code }.into());
+        return;
+    }
+    // Set when the stream went through an xrun, until the next buffer has been processed.
+    let mut recovering = false;
+    // The number of frames read from or written to the device since the stream was created.
+    let mut frames_transferred = 0u64;
     loop {
         descriptors.clear();
         // Add the self-pipe for signaling termination.
@@ -556,17 +916,18 @@ fn stream_worker(rx: TriggerReceiver,
             descriptors.set_len(len + stream.num_descriptors);
         }
 
-        let res = unsafe {
-            // Don't timeout, wait forever.
-            libc::poll(descriptors.as_mut_ptr(), descriptors.len() as libc::nfds_t, -1)
-        };
+        let wait_start = Instant::now();
+        let res = poll_descriptors(&mut descriptors, options.spin_wait);
+        stream.meter.record_wakeup(wait_start.elapsed());
         if res < 0 {
-            let description = format!("`libc::poll()` failed: {}", io::Error::last_os_error());
-            error_callback(BackendSpecificError { description }.into());
+            let err = io::Error::last_os_error();
+            let description = format!("`libc::poll()` failed: {}", err);
+            let code = err.raw_os_error();
+            error_callback(BackendSpecificError { description, code }.into());
             continue;
         } else if res == 0 {
             let description = String::from("`libc::poll()` spuriously returned");
-            error_callback(BackendSpecificError { description }.into());
+            error_callback(BackendSpecificError { description, code: None }.into());
             continue;
         }
 
@@ -589,10 +950,19 @@ fn stream_worker(rx: TriggerReceiver,
         };
         // Get the number of available samples for reading/writing.
         let available_samples = match get_available_samples(stream) {
-            Ok(n) => n,
+            Ok((n, xrun)) => {
+                recovering |= xrun;
+                n
+            },
             Err(err) => {
-                let description = format!("Failed to query the number of available samples: {}", err);
-                error_callback(BackendSpecificError { description }.into());
+                if is_device_lost(&err) {
+                    let reason = DeviceLossReason::Unplugged;
+                    error_callback(StreamError::DeviceNotAvailable { reason });
+                    return;
+                }
+                let description =
+                    format!("Failed to query the number of available samples: {}", err.description);
+                error_callback(BackendSpecificError { description, code: err.code }.into());
                 continue;
             }
         };
@@ -603,8 +973,10 @@ fn stream_worker(rx: TriggerReceiver,
         }
 
         // Prepare the data buffer.
-        let buffer_size = stream.sample_format.sample_size() * available_samples;
-        buffer.resize(buffer_size, 0u8);
+        buffer.resize(device_format.sample_size() * available_samples, 0u8);
+        if converting {
+            converted.resize(stream.sample_format.sample_size() * available_samples, 0u8);
+        }
         let available_frames = available_samples / stream.num_channels as usize;
 
         match stream_type {
@@ -616,75 +988,167 @@ fn stream_worker(rx: TriggerReceiver,
                         available_frames as alsa::snd_pcm_uframes_t,
                     )
                 };
-                if let Err(err) = check_errors(result as _) {
-                    let description = format!("`snd_pcm_readi` failed: {}", err);
-                    error_callback(BackendSpecificError { description }.into());
+                if is_xrun(result) {
+                    // buffer overrun, or the system was suspended
+                    if let Err(err) = recover(stream, result) {
+                        report_recovery_failure(err, error_callback);
+                        return;
+                    }
+                    // A prepared capture stream never becomes readable. A stream resumed from a
+                    // suspension is already running.
+                    let state = unsafe { alsa::snd_pcm_state(stream.channel) };
+                    if state == alsa::SND_PCM_STATE_PREPARED {
+                        let result = unsafe { alsa::snd_pcm_start(stream.channel) };
+                        if let Err(err) = check_errors(result) {
+                            report_recovery_failure(err, error_callback);
+                            return;
+                        }
+                    }
+                    stream.meter.record_overrun();
+                    recovering = true;
+                    let buffer_frames = stream.buffer_len / stream.num_channels as usize;
+                    error_callback(StreamError::Overrun { buffer_frames });
+                    continue;
+                } else if let Err(err) = check_errors(result as _) {
+                    if is_device_lost(&err) {
+                        let reason = DeviceLossReason::Unplugged;
+                        error_callback(StreamError::DeviceNotAvailable { reason });
+                        return;
+                    }
+                    let description = format!("`snd_pcm_readi` failed: {}", err.description);
+                    error_callback(BackendSpecificError { description, code: err.code }.into());
                     continue;
                 }
+                frames_transferred += result as u64;
+                record_position(stream, StreamType::Input, frames_transferred);
 
+                let samples: &[u8] = if converting {
+                    unsafe {
+                        convert_buffer(&buffer, device_format, &mut converted, stream.sample_format)
+                    };
+                    &converted
+                } else {
+                    &buffer
+                };
                 let input_buffer = match stream.sample_format {
-                    SampleFormat::I16 => UnknownTypeInputBuffer::I16(::InputBuffer {
-                        buffer: unsafe { cast_input_buffer(&mut buffer) },
-                    }),
-                    SampleFormat::U16 => UnknownTypeInputBuffer::U16(::InputBuffer {
-                        buffer: unsafe { cast_input_buffer(&mut buffer) },
-                    }),
-                    SampleFormat::F32 => UnknownTypeInputBuffer::F32(::InputBuffer {
-                        buffer: unsafe { cast_input_buffer(&mut buffer) },
-                    }),
+                    SampleFormat::I16 => UnknownTypeInputBuffer::I16(::InputBuffer::new(unsafe {
+                        cast_input_buffer(samples)
+                    })),
+                    SampleFormat::U16 => UnknownTypeInputBuffer::U16(::InputBuffer::new(unsafe {
+                        cast_input_buffer(samples)
+                    })),
+                    SampleFormat::F32 => UnknownTypeInputBuffer::F32(::InputBuffer::new(unsafe {
+                        cast_input_buffer(samples)
+                    })),
                 };
+                let flags = BufferFlags {
+                    discontinuity: recovering,
+                    ..Default::default()
+                };
+                let info = CallbackInfo::new(available_frames, recovering, flags);
                 let stream_data = StreamData::Input {
                     buffer: input_buffer,
+                    info,
                 };
-                data_callback(stream_data);
+                stream.meter.measure(&info, || data_callback(stream_data));
+                recovering = false;
             },
             StreamType::Output => {
                 {
                     // We're now sure that we're ready to write data.
-                    let output_buffer = match stream.sample_format {
-                        SampleFormat::I16 => UnknownTypeOutputBuffer::I16(::OutputBuffer {
-                            buffer: unsafe { cast_output_buffer(&mut buffer) },
-                        }),
-                        SampleFormat::U16 => UnknownTypeOutputBuffer::U16(::OutputBuffer {
-                            buffer: unsafe { cast_output_buffer(&mut buffer) },
-                        }),
-                        SampleFormat::F32 => UnknownTypeOutputBuffer::F32(::OutputBuffer {
-                            buffer: unsafe { cast_output_buffer(&mut buffer) },
-                        }),
+                    let samples: &mut [u8] = if converting { &mut converted } else { &mut buffer };
+                    let mut output_buffer = match stream.sample_format {
+                        SampleFormat::I16 => UnknownTypeOutputBuffer::I16(::OutputBuffer::new(unsafe {
+                            cast_output_buffer(samples)
+                        })),
+                        SampleFormat::U16 => UnknownTypeOutputBuffer::U16(::OutputBuffer::new(unsafe {
+                            cast_output_buffer(samples)
+                        })),
+                        SampleFormat::F32 => UnknownTypeOutputBuffer::F32(::OutputBuffer::new(unsafe {
+                            cast_output_buffer(samples)
+                        })),
                     };
+                    if options.zero_output_buffers {
+                        output_buffer.fill_silence();
+                    }
 
+                    let flags = BufferFlags {
+                        discontinuity: recovering,
+                        ..Default::default()
+                    };
+                    let info = CallbackInfo::new(available_frames, recovering, flags);
                     let stream_data = StreamData::Output {
                         buffer: output_buffer,
+                        info,
                     };
-                    data_callback(stream_data);
+                    stream.meter.measure(&info, || data_callback(stream_data));
+                    recovering = false;
                 }
+                if converting {
+                    unsafe {
+                        convert_buffer(&converted, stream.sample_format, &mut buffer, device_format)
+                    };
+                }
+                // Set when the previous buffer must be played again before this one, to conceal an
+                // underrun with `SlowCallbackPolicy::Repeat`.
+                let mut repeating = false;
+                let frame_size = device_format.sample_size() * stream.num_channels as usize;
                 loop {
+                    let (data, frames) = if repeating {
+                        (&last_buffer[..], last_buffer.len() / frame_size)
+                    } else {
+                        (&buffer[..], available_frames)
+                    };
                     let result = unsafe {
                         alsa::snd_pcm_writei(
                             stream.channel,
-                            buffer.as_ptr() as *const _,
-                            available_frames as alsa::snd_pcm_uframes_t,
+                            data.as_ptr() as *const _,
+                            frames as alsa::snd_pcm_uframes_t,
                         )
                     };
 
-                    if result == -libc::EPIPE as i64 {
-                        // buffer underrun
-                        // TODO: Notify the user of this.
-                        unsafe { alsa::snd_pcm_recover(stream.channel, result as i32, 0) };
+                    if is_xrun(result) {
+                        // buffer underrun, or the system was suspended
+                        if let Err(err) = recover(stream, result) {
+                            report_recovery_failure(err, error_callback);
+                            return;
+                        }
+                        stream.meter.record_underrun();
+                        recovering = true;
+                        // Every xrun is reported, the policy only decides what is played next.
+                        let buffer_frames = stream.buffer_len / stream.num_channels as usize;
+                        error_callback(StreamError::Underrun { buffer_frames });
+                        if options.slow_callback == SlowCallbackPolicy::Repeat {
+                            repeating = !last_buffer.is_empty();
+                        }
                     } else if let Err(err) = check_errors(result as _) {
-                        let description = format!("`snd_pcm_writei` failed: {}", err);
-                        error_callback(BackendSpecificError { description }.into());
+                        if is_device_lost(&err) {
+                            let reason = DeviceLossReason::Unplugged;
+                            error_callback(StreamError::DeviceNotAvailable { reason });
+                            return;
+                        }
+                        let description = format!("`snd_pcm_writei` failed: {}", err.description);
+                        error_callback(BackendSpecificError { description, code: err.code }.into());
                         continue;
-                    } else if result as usize != available_frames {
+                    } else if result as usize != frames {
                         let description = format!(
                             "unexpected number of frames written: expected {}, \
                                         result {} (this should never happen)",
-                            available_frames,
+                            frames,
                             result,
                         );
-                        error_callback(BackendSpecificError { description }.into());
+                        error_callback(BackendSpecificError { description, code: None }.into());
                         continue;
+                    } else if repeating {
+                        frames_transferred += result as u64;
+                        repeating = false;
                     } else {
+                        frames_transferred += result as u64;
+                        record_position(stream, StreamType::Output, frames_transferred);
+                        if options.slow_callback == SlowCallbackPolicy::Repeat {
+                            last_buffer.clear();
+                            last_buffer.extend_from_slice(&buffer);
+                        }
                         break;
                     }
                 }
@@ -694,31 +1158,55 @@ fn stream_worker(rx: TriggerReceiver,
 }
 
 impl Stream {
-    fn new<D, E>(inner: Arc<StreamInner>, mut data_callback: D, mut error_callback: E) -> Stream
+    fn new<D, E>(inner: Arc<StreamInner>, options: &StreamOptions, mut data_callback: D, mut error_callback: E) -> Result<Stream, BuildStreamError>
         where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
         let (tx, rx) = trigger();
         // Clone the handle for passing into worker thread.
         let stream = inner.clone();
-        let thread = thread::spawn(move || {
-            stream_worker(rx, &*stream, &mut data_callback, &mut error_callback);
+        let worker_options = options.clone();
+        let worker = Worker::new(options.thread_spawner.clone(), move || {
+            worker::catch_panics(&mut error_callback, |error_callback| {
+                stream_worker(rx, &stream, &worker_options, &mut data_callback, error_callback);
+            });
         });
-        Stream {
-            thread: Some(thread),
+        Ok(Stream {
+            worker,
             inner,
             trigger: tx,
-        }
+        })
+    }
+
+    pub fn raw_handle(&self) -> Option<RawStreamHandle> {
+        Some(RawStreamHandle::Alsa { pcm: self.inner.channel as *mut _ })
+    }
+
+    /// The hardware parameters that ALSA chose when the stream was built.
+    ///
+    /// ALSA rounds the buffer and period sizes that CPAL requests to what the device supports, so
+    /// applications that schedule their work around the periods should read them here.
+    pub fn hw_parameters(&self) -> HwParameters {
+        self.inner.hw_parameters
+    }
+
+    /// The position of each channel of the frames of the stream, or `None` if the driver does not
+    /// report it.
+    pub fn channel_map(&self) -> Option<Vec<ChannelPosition>> {
+        unsafe { chmap::get(self.inner.channel) }
     }
 }
 
 impl Drop for Stream {
     fn drop(&mut self) {
-        self.trigger.wakeup();
-        self.thread.take().unwrap().join().unwrap();
+        if self.worker.is_started() {
+            self.trigger.wakeup();
+        }
+        self.worker.join();
     }
 }
 
 impl StreamTrait for Stream {
     fn play(&self) -> Result<(), PlayStreamError> {
+        self.worker.start()?;
         unsafe {
             alsa::snd_pcm_pause(self.inner.channel, 0);
         }
@@ -732,6 +1220,18 @@ impl StreamTrait for Stream {
         // TODO: error handling
         Ok(())
     }
+    fn callback_load(&self) -> f32 {
+        self.inner.meter.callback_load()
+    }
+    fn stats(&self) -> StreamStats {
+        self.inner.meter.stats()
+    }
+    fn last_callback(&self) -> Option<Instant> {
+        self.inner.meter.last_callback()
+    }
+    fn position(&self) -> Option<StreamPosition> {
+        self.inner.meter.position()
+    }
 }
 
 // Check whether the event is `POLLOUT` or `POLLIN`.
@@ -758,7 +1258,7 @@ fn check_for_pollout_or_pollin(
     if let Err(desc) = check_errors(res) {
         let description =
             format!("`snd_pcm_poll_descriptors_revents` failed: {}",desc);
-        let err = BackendSpecificError { description };
+        let err = BackendSpecificError { description, code: desc.code };
         return Err(err);
     }
 
@@ -771,114 +1271,260 @@ fn check_for_pollout_or_pollin(
     }
 }
 
+// Wait for one of the descriptors to be ready, busy-waiting for up to `spin` before blocking.
+fn poll_descriptors(descriptors: &mut [libc::pollfd], spin: Duration) -> libc::c_int {
+    let len = descriptors.len() as libc::nfds_t;
+    if spin > Duration::from_secs(0) {
+        let deadline = Instant::now() + spin;
+        loop {
+            let res = unsafe { libc::poll(descriptors.as_mut_ptr(), len, 0) };
+            if res != 0 {
+                return res;
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            hint::spin_loop();
+        }
+    }
+    // Don't timeout, wait forever.
+    unsafe { libc::poll(descriptors.as_mut_ptr(), len, -1) }
+}
+
+// Record the frame that the device is currently playing or capturing, given the number of frames
+// that were transferred to or from it.
+fn record_position(stream: &StreamInner, stream_type: StreamType, frames_transferred: u64) {
+    let mut delay = 0;
+    let err = unsafe { alsa::snd_pcm_delay(stream.channel, &mut delay) };
+    if err < 0 {
+        return;
+    }
+    let delay = cmp::max(delay, 0) as u64;
+    let frame = match stream_type {
+        // Captured frames that have not been read yet are still in the buffer.
+        StreamType::Input => frames_transferred + delay,
+        StreamType::Output => frames_transferred.saturating_sub(delay),
+    };
+    stream.meter.record_position(frame, Instant::now());
+}
+
+// Whether `err` means that the device was disconnected, e.g. because a USB device was unplugged.
+// The stream cannot recover from it.
+fn is_device_lost(err: &BackendSpecificError) -> bool {
+    err.code == Some(libc::ENODEV)
+}
+
+// Whether the result of a read, write or `snd_pcm_avail_update` means that the stream went
+// through an xrun or was suspended, which `recover` can handle.
+fn is_xrun(result: alsa::snd_pcm_sframes_t) -> bool {
+    result == -(libc::EPIPE as alsa::snd_pcm_sframes_t)
+        || result == -(libc::ESTRPIPE as alsa::snd_pcm_sframes_t)
+}
+
+// Recovers the stream from the xrun or suspension reported by `err`, leaving it prepared. The next
+// write starts a playback stream again, while a capture stream must be started explicitly.
+fn recover(stream: &StreamInner, err: alsa::snd_pcm_sframes_t) -> Result<(), BackendSpecificError> {
+    trace_event!(warn, host = "ALSA", errno = -err, "recovering the stream from an xrun");
+    let result =
+        check_errors(unsafe { alsa::snd_pcm_recover(stream.channel, err as libc::c_int, 1) });
+    trace::trace_result("ALSA", "recovery from the xrun", &result);
+    result
+}
+
+// Reports a failure to recover from an xrun, after which the stream cannot go on.
+fn report_recovery_failure(err: BackendSpecificError, error_callback: &mut dyn FnMut(StreamError)) {
+    if is_device_lost(&err) {
+        let reason = DeviceLossReason::Unplugged;
+        error_callback(StreamError::DeviceNotAvailable { reason });
+    } else {
+        let description = format!("failed to recover from an xrun: {}", err.description);
+        error_callback(BackendSpecificError { description, code: err.code }.into());
+    }
+}
+
 // Determine the number of samples that are available to read/write.
-fn get_available_samples(stream: &StreamInner) -> Result<usize, BackendSpecificError> {
+//
+// The returned flag is `true` if the stream is in an xrun state, in which case the whole buffer is
+// reported as available.
+fn get_available_samples(stream: &StreamInner) -> Result<(usize, bool), BackendSpecificError> {
     let available = unsafe {
         alsa::snd_pcm_avail_update(stream.channel)
     };
-    if available == -32 {
-        // buffer underrun
-        // TODO: Notify the user some how.
-        Ok(stream.buffer_len)
+    if is_xrun(available) {
+        // The following read or write fails in the same way and recovers the stream.
+        Ok((stream.buffer_len, true))
     } else if let Err(desc) = check_errors(available as libc::c_int) {
-        let description = format!("failed to get available samples: {}", desc);
-        let err = BackendSpecificError { description };
+        let description = format!("failed to get available samples: {}", desc.description);
+        let err = BackendSpecificError { description, code: desc.code };
         Err(err)
     } else {
-        Ok((available * stream.num_channels as alsa::snd_pcm_sframes_t) as usize)
+        Ok(((available * stream.num_channels as alsa::snd_pcm_sframes_t) as usize, false))
+    }
+}
+
+// The sample formats of the device that streams fall back to, in order of preference, when the
+// device does not support the sample format of the stream. With the `fixed-point` feature, integer
+// formats come first, so that the samples are converted with integer arithmetic only.
+#[cfg(not(feature = "fixed-point"))]
+const DEVICE_FORMATS: [SampleFormat; 3] = [SampleFormat::F32, SampleFormat::I16, SampleFormat::U16];
+#[cfg(feature = "fixed-point")]
+const DEVICE_FORMATS: [SampleFormat; 3] = [SampleFormat::I16, SampleFormat::U16, SampleFormat::F32];
+
+// The ALSA format of the samples of `sample_format`, in native byte order.
+fn alsa_format(sample_format: SampleFormat) -> alsa::snd_pcm_format_t {
+    if cfg!(target_endian = "big") {
+        match sample_format {
+            SampleFormat::I16 => alsa::SND_PCM_FORMAT_S16_BE,
+            SampleFormat::U16 => alsa::SND_PCM_FORMAT_U16_BE,
+            SampleFormat::F32 => alsa::SND_PCM_FORMAT_FLOAT_BE,
+        }
+    } else {
+        match sample_format {
+            SampleFormat::I16 => alsa::SND_PCM_FORMAT_S16_LE,
+            SampleFormat::U16 => alsa::SND_PCM_FORMAT_U16_LE,
+            SampleFormat::F32 => alsa::SND_PCM_FORMAT_FLOAT_LE,
+        }
     }
 }
 
+// The requested duration of the device buffer in microseconds.
+const BUFFER_TIME: libc::c_uint = 100_000;
+// The requested duration of the device buffer in microseconds, in power-saving mode.
+const POWER_SAVING_BUFFER_TIME: libc::c_uint = 500_000;
+
 unsafe fn set_hw_params_from_format(
     pcm_handle: *mut alsa::snd_pcm_t,
     hw_params: &HwParams,
     format: &Format,
-) -> Result<(), String> {
+    convert: bool,
+    power_saving: bool,
+) -> Result<HwParameters, BuildStreamError> {
+    let backend_specific = |context: &str, err: BackendSpecificError| {
+        let description = format!("{}: {}", context, err.description);
+        BackendSpecificError { description, code: err.code }.into()
+    };
     if let Err(e) = check_errors(alsa::snd_pcm_hw_params_any(pcm_handle, hw_params.0)) {
-        return Err(format!("errors on pcm handle: {}", e));
+        return Err(backend_specific("errors on pcm handle", e));
     }
     if let Err(e) = check_errors(alsa::snd_pcm_hw_params_set_access(pcm_handle,
                                                     hw_params.0,
                                                     alsa::SND_PCM_ACCESS_RW_INTERLEAVED)) {
-        return Err(format!("handle not acessible: {}", e));
+        return Err(backend_specific("handle not acessible", e));
     }
 
-    let data_type = if cfg!(target_endian = "big") {
-        match format.data_type {
-            SampleFormat::I16 => alsa::SND_PCM_FORMAT_S16_BE,
-            SampleFormat::U16 => alsa::SND_PCM_FORMAT_U16_BE,
-            SampleFormat::F32 => alsa::SND_PCM_FORMAT_FLOAT_BE,
-        }
-    } else {
-        match format.data_type {
-            SampleFormat::I16 => alsa::SND_PCM_FORMAT_S16_LE,
-            SampleFormat::U16 => alsa::SND_PCM_FORMAT_U16_LE,
-            SampleFormat::F32 => alsa::SND_PCM_FORMAT_FLOAT_LE,
-        }
+    // Fall back to another sample format of the device, from or to which the samples of the
+    // stream are converted, if allowed.
+    let fallbacks: &[SampleFormat] = if convert { &DEVICE_FORMATS } else { &[] };
+    let sample_format = match Some(format.data_type)
+        .into_iter()
+        .chain(fallbacks.iter().cloned())
+        .find(|&sample_format| {
+            alsa::snd_pcm_hw_params_test_format(pcm_handle, hw_params.0, alsa_format(sample_format))
+                == 0
+        }) {
+        Some(sample_format) => sample_format,
+        None => return Err(BuildStreamError::SampleFormatNotSupported),
     };
-
-    if let Err(e) = check_errors(alsa::snd_pcm_hw_params_set_format(pcm_handle,
+    if check_errors(alsa::snd_pcm_hw_params_set_format(pcm_handle,
                                                     hw_params.0,
-                                                    data_type)) {
-        return Err(format!("format could not be set: {}", e));
+                                                    alsa_format(sample_format))).is_err() {
+        return Err(BuildStreamError::SampleFormatNotSupported);
     }
-    if let Err(e) = check_errors(alsa::snd_pcm_hw_params_set_rate(pcm_handle,
+    if check_errors(alsa::snd_pcm_hw_params_set_rate(pcm_handle,
                                                   hw_params.0,
                                                   format.sample_rate.0 as libc::c_uint,
-                                                  0)) {
-        return Err(format!("sample rate could not be set: {}", e));
+                                                  0)).is_err() {
+        return Err(BuildStreamError::SampleRateNotSupported);
     }
-    if let Err(e) = check_errors(alsa::snd_pcm_hw_params_set_channels(pcm_handle,
+    if check_errors(alsa::snd_pcm_hw_params_set_channels(pcm_handle,
                                                       hw_params.0,
                                                       format.channels as
-                                                                      libc::c_uint)) {
-        return Err(format!("channel count could not be set: {}", e));
+                                                                      libc::c_uint)).is_err() {
+        return Err(BuildStreamError::InvalidChannelCount);
     }
 
     // If this isn't set manually a overlarge buffer may be used causing audio delay
+    let mut buffer_time = if power_saving { POWER_SAVING_BUFFER_TIME } else { BUFFER_TIME };
     if let Err(e) = check_errors(alsa::snd_pcm_hw_params_set_buffer_time_near(
         pcm_handle,
         hw_params.0,
-        &mut 100_000,
+        &mut buffer_time,
         &mut 0,
     )) {
-        return Err(format!("buffer time could not be set: {}", e));
+        return Err(backend_specific("buffer time could not be set", e));
+    }
+    if power_saving {
+        // Wake up once per half buffer rather than for each of the default small periods.
+        if let Err(e) = check_errors(alsa::snd_pcm_hw_params_set_periods_near(
+            pcm_handle,
+            hw_params.0,
+            &mut 2,
+            &mut 0,
+        )) {
+            return Err(backend_specific("period count could not be set", e));
+        }
     }
 
     if let Err(e) = check_errors(alsa::snd_pcm_hw_params(pcm_handle, hw_params.0)) {
-        return Err(format!("hardware params could not be set: {}", e));
+        return Err(backend_specific("hardware params could not be set", e));
     }
 
-    Ok(())
+    let mut sample_rate = 0;
+    let mut period_frames = 0;
+    let mut periods = 0;
+    let mut buffer_frames = 0;
+    let results = [
+        alsa::snd_pcm_hw_params_get_rate(hw_params.0, &mut sample_rate, &mut 0),
+        alsa::snd_pcm_hw_params_get_period_size(hw_params.0, &mut period_frames, &mut 0),
+        alsa::snd_pcm_hw_params_get_periods(hw_params.0, &mut periods, &mut 0),
+        alsa::snd_pcm_hw_params_get_buffer_size(hw_params.0, &mut buffer_frames),
+    ];
+    for &result in results.iter() {
+        if let Err(e) = check_errors(result) {
+            return Err(backend_specific("hardware params could not be read", e));
+        }
+    }
+    Ok(HwParameters {
+        sample_rate: SampleRate(sample_rate),
+        period_frames: period_frames as usize,
+        periods,
+        buffer_frames: buffer_frames as usize,
+        sample_format,
+    })
 }
 
 unsafe fn set_sw_params_from_format(
     pcm_handle: *mut alsa::snd_pcm_t,
     format: &Format,
-) -> Result<(usize, usize), String>
+) -> Result<(usize, usize), BackendSpecificError>
 {
+    let with_context = |context: &str, err: BackendSpecificError| {
+        let description = format!("{}: {}", context, err.description);
+        BackendSpecificError { description, code: err.code }
+    };
     let mut sw_params = ptr::null_mut(); // TODO: RAII
     if let Err(e) = check_errors(alsa::snd_pcm_sw_params_malloc(&mut sw_params)) {
-        return Err(format!("snd_pcm_sw_params_malloc failed: {}", e));
+        return Err(with_context("snd_pcm_sw_params_malloc failed", e));
     }
     if let Err(e) = check_errors(alsa::snd_pcm_sw_params_current(pcm_handle, sw_params)) {
-        return Err(format!("snd_pcm_sw_params_current failed: {}", e));
+        return Err(with_context("snd_pcm_sw_params_current failed", e));
     }
     if let Err(e) = check_errors(alsa::snd_pcm_sw_params_set_start_threshold(pcm_handle, sw_params, 0)) {
-        return Err(format!("snd_pcm_sw_params_set_start_threshold failed: {}", e));
+        return Err(with_context("snd_pcm_sw_params_set_start_threshold failed", e));
     }
 
     let (buffer_len, period_len) = {
         let mut buffer = 0;
         let mut period = 0;
         if let Err(e) = check_errors(alsa::snd_pcm_get_params(pcm_handle, &mut buffer, &mut period)) {
-            return Err(format!("failed to initialize buffer: {}", e));
+            return Err(with_context("failed to initialize buffer", e));
         }
         if buffer == 0 {
-            return Err(format!("initialization resulted in a null buffer"));
+            let description = String::from("initialization resulted in a null buffer");
+            return Err(BackendSpecificError { description, code: None });
         }
         if let Err(e) = check_errors(alsa::snd_pcm_sw_params_set_avail_min(pcm_handle, sw_params, period)) {
-            return Err(format!("snd_pcm_sw_params_set_avail_min failed: {}", e));
+            return Err(with_context("snd_pcm_sw_params_set_avail_min failed", e));
         }
         let buffer = buffer as usize * format.channels as usize;
         let period = period as usize * format.channels as usize;
@@ -886,7 +1532,7 @@ unsafe fn set_sw_params_from_format(
     };
 
     if let Err(e) = check_errors(alsa::snd_pcm_sw_params(pcm_handle, sw_params)) {
-        return Err(format!("snd_pcm_sw_params failed: {}", e));
+        return Err(with_context("snd_pcm_sw_params failed", e));
     }
 
     alsa::snd_pcm_sw_params_free(sw_params);
@@ -925,20 +1571,26 @@ impl Drop for StreamInner {
 }
 
 #[inline]
-fn check_errors(err: libc::c_int) -> Result<(), String> {
+fn check_errors(err: libc::c_int) -> Result<(), BackendSpecificError> {
     if err < 0 {
         unsafe {
             let s = ffi::CStr::from_ptr(alsa::snd_strerror(err))
                 .to_bytes()
                 .to_vec();
-            let s = String::from_utf8(s).expect("Streaming error occured");
-            return Err(s);
+            let description = String::from_utf8(s).expect("Streaming error occured");
+            return Err(BackendSpecificError { description, code: Some(-err) });
         }
     }
 
     Ok(())
 }
 
+// Whether the process runs in a Flatpak or Snap sandbox, where access to the devices depends on
+// permissions that the user grants outside of ALSA.
+fn is_sandboxed() -> bool {
+    Path::new("/.flatpak-info").exists() || env::var_os("SNAP").is_some()
+}
+
 /// Cast a byte slice into a (immutable) slice of desired type.
 /// Safety: it's up to the caller to ensure that the input slice has valid bit representations.
 unsafe fn cast_input_buffer<T>(v: &[u8]) -> &[T] {
@@ -952,3 +1604,28 @@ unsafe fn cast_output_buffer<T>(v: &mut [u8]) -> &mut [T] {
     debug_assert!(v.len() % std::mem::size_of::<T>() == 0);
     std::slice::from_raw_parts_mut(v.as_mut_ptr() as *mut T, v.len() / std::mem::size_of::<T>())
 }
+
+/// Converts the samples of `src`, of `src_format`, to `dst_format` into `dst`.
+/// Safety: both slices must hold the same number of samples, aligned for their sample type.
+unsafe fn convert_buffer(
+    src: &[u8],
+    src_format: SampleFormat,
+    dst: &mut [u8],
+    dst_format: SampleFormat,
+) {
+    macro_rules! convert_from {
+        ($SampleType:ty) => {{
+            let src = cast_input_buffer::<$SampleType>(src);
+            match dst_format {
+                SampleFormat::I16 => convert_samples(src, cast_output_buffer::<i16>(dst)),
+                SampleFormat::U16 => convert_samples(src, cast_output_buffer::<u16>(dst)),
+                SampleFormat::F32 => convert_samples(src, cast_output_buffer::<f32>(dst)),
+            }
+        }};
+    }
+    match src_format {
+        SampleFormat::I16 => convert_from!(i16),
+        SampleFormat::U16 => convert_from!(u16),
+        SampleFormat::F32 => convert_from!(f32),
+    }
+}
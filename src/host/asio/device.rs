@@ -9,12 +9,14 @@ use DefaultFormatError;
 use DeviceNameError;
 use DevicesError;
 use Format;
+use RawDeviceHandle;
 use SampleFormat;
 use SampleRate;
 use SupportedFormat;
 use SupportedFormatsError;
 use super::sys;
 use super::parking_lot::Mutex;
+use super::stream::DriverMessages;
 
 /// A ASIO Device
 pub struct Device {
@@ -25,6 +27,29 @@ pub struct Device {
     // An driver can only have one of each.
     // They need to be created at the same time.
     pub asio_streams: Arc<Mutex<sys::AsioStreams>>,
+
+    // The channels of the driver that input and output streams use, if only some of them.
+    input_channels: Option<Vec<usize>>,
+    output_channels: Option<Vec<usize>>,
+
+    // Reports the messages of the driver to the streams, shared like `asio_streams`.
+    pub(super) messages: Arc<DriverMessages>,
+}
+
+/// The direct monitoring of an input, routed to an output by the hardware of a device, see
+/// `Device::set_direct_monitoring`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectMonitor {
+    /// The input channel, or `None` for all the inputs.
+    pub input: Option<usize>,
+    /// The output channel on which the input is monitored, the left one of a stereo pair.
+    pub output: usize,
+    /// Whether the input is monitored.
+    pub enabled: bool,
+    /// The linear gain, from 0.0 through 1.0 for unity gain to 4.0 for +12 dB.
+    pub gain: f32,
+    /// The pan, from -1.0 for left to 1.0 for right.
+    pub pan: f32,
 }
 
 /// All available devices.
@@ -48,10 +73,87 @@ impl Hash for Device {
 }
 
 impl Device {
+    pub fn raw_handle(&self) -> Option<RawDeviceHandle> {
+        None
+    }
+
     pub fn name(&self) -> Result<String, DeviceNameError> {
         Ok(self.driver.name().to_string())
     }
 
+    /// The same device, with its input streams using only the input channels `channels` of the
+    /// driver, in this order.
+    ///
+    /// The driver only creates buffers for these channels, which lowers the load of devices with
+    /// many channels. Streams must then have as many channels as `channels` lists, without
+    /// repeating any, and all the input streams of the driver must use the same channels.
+    pub fn with_input_channels(&self, channels: &[usize]) -> Device {
+        Device {
+            driver: self.driver.clone(),
+            asio_streams: self.asio_streams.clone(),
+            input_channels: Some(channels.to_vec()),
+            output_channels: self.output_channels.clone(),
+            messages: self.messages.clone(),
+        }
+    }
+
+    /// The same device, with its output streams using only the output channels `channels` of the
+    /// driver, in this order.
+    ///
+    /// The driver only creates buffers for these channels, which lowers the load of devices with
+    /// many channels. Streams must then have as many channels as `channels` lists, without
+    /// repeating any, and all the output streams of the driver must use the same channels.
+    pub fn with_output_channels(&self, channels: &[usize]) -> Device {
+        Device {
+            driver: self.driver.clone(),
+            asio_streams: self.asio_streams.clone(),
+            input_channels: self.input_channels.clone(),
+            output_channels: Some(channels.to_vec()),
+            messages: self.messages.clone(),
+        }
+    }
+
+    /// The input channels selected with `with_input_channels`, if any.
+    pub fn input_channels(&self) -> Option<&[usize]> {
+        self.input_channels.as_ref().map(|channels| &channels[..])
+    }
+
+    /// The output channels selected with `with_output_channels`, if any.
+    pub fn output_channels(&self) -> Option<&[usize]> {
+        self.output_channels.as_ref().map(|channels| &channels[..])
+    }
+
+    /// Whether the driver supports direct monitoring through `set_direct_monitoring`.
+    pub fn supports_direct_monitoring(&self) -> bool {
+        self.driver.can_input_monitor()
+    }
+
+    /// Routes an input to an output in the hardware of the device, or stops doing so, as
+    /// described by `monitor`.
+    ///
+    /// Direct monitoring bypasses the latency of the software, which matters when recording. The
+    /// gain and pan are suggestions, which drivers may ignore.
+    pub fn set_direct_monitoring(
+        &self,
+        monitor: &DirectMonitor,
+    ) -> Result<(), BackendSpecificError> {
+        // ASIO scales the gain so that 0 dB is a quarter of the range, and the pan to the range.
+        let gain = (monitor.gain.max(0.0) as f64 * 0x2000_0000 as f64).min(0x7fff_ffff as f64);
+        let pan = (monitor.pan.clamp(-1.0, 1.0) as f64 + 1.0) / 2.0 * 0x7fff_ffff as f64;
+        let monitor = sys::InputMonitor {
+            input: monitor.input,
+            output: monitor.output,
+            enabled: monitor.enabled,
+            gain: gain as _,
+            pan: pan as _,
+        };
+        self.driver.set_input_monitor(&monitor).map_err(|err| {
+            let description = format!("{}", err);
+            let code = super::asio_error_code(&err);
+            BackendSpecificError { description, code }
+        })
+    }
+
     /// Gets the supported input formats.
     /// TODO currently only supports the default.
     /// Need to find all possible formats.
@@ -159,7 +261,13 @@ impl Iterator for Devices {
                             input: None,
                             output: None,
                         }));
-                        return Some(Device { driver, asio_streams });
+                        return Some(Device {
+                            driver,
+                            asio_streams,
+                            input_channels: None,
+                            output_channels: None,
+                            messages: Arc::new(DriverMessages::new()),
+                        });
                     }
                     Err(_) => continue,
                 }
@@ -198,7 +306,8 @@ fn default_format_err(e: sys::AsioError) -> DefaultFormatError {
         sys::AsioError::NoRate => DefaultFormatError::StreamTypeNotSupported,
         err => {
             let description = format!("{}", err);
-            BackendSpecificError { description }.into()
+            let code = super::asio_error_code(&err);
+            BackendSpecificError { description, code }.into()
         }
     }
 }
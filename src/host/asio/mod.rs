@@ -12,20 +12,42 @@ use {
     SupportedFormatsError,
     StreamData,
     StreamError,
+    StreamOptions,
+    StreamPosition,
+    StreamStats,
 };
+use downmix::SurroundLayout;
 use traits::{
     DeviceTrait,
     HostTrait,
     StreamTrait,
 };
 
-pub use self::device::{Device, Devices, SupportedInputFormats, SupportedOutputFormats};
+pub use self::device::{
+    Device, Devices, DirectMonitor, SupportedInputFormats, SupportedOutputFormats,
+};
 pub use self::stream::Stream;
 use std::sync::Arc;
+use std::time::Instant;
 
 mod device;
 mod stream;
 
+// The `ASIOError` value corresponding to `err`, for `BackendSpecificError::code`.
+fn asio_error_code(err: &sys::AsioError) -> Option<i32> {
+    let code = match *err {
+        sys::AsioError::NoDrivers => sys::errors::AsioErrorWrapper::ASE_NotPresent,
+        sys::AsioError::HardwareMalfunction => sys::errors::AsioErrorWrapper::ASE_HWMalfunction,
+        sys::AsioError::InvalidInput => sys::errors::AsioErrorWrapper::ASE_InvalidParameter,
+        sys::AsioError::BadMode => sys::errors::AsioErrorWrapper::ASE_InvalidMode,
+        sys::AsioError::HardwareStuck => sys::errors::AsioErrorWrapper::ASE_SPNotAdvancing,
+        sys::AsioError::NoRate => sys::errors::AsioErrorWrapper::ASE_NoClock,
+        sys::AsioError::ASE_NoMemory => sys::errors::AsioErrorWrapper::ASE_NoMemory,
+        sys::AsioError::UnknownError => return None,
+    };
+    Some(code as i32)
+}
+
 /// The host for ASIO.
 #[derive(Debug)]
 pub struct Host {
@@ -45,8 +67,17 @@ impl HostTrait for Host {
     type Device = Device;
 
     fn is_available() -> bool {
-        true
-        //unimplemented!("check how to do this using asio-sys")
+        Self::availability().is_ok()
+    }
+
+    fn availability() -> Result<(), crate::HostUnavailable> {
+        // The host can only open devices through an installed ASIO driver.
+        if sys::Asio::new().driver_names().is_empty() {
+            return Err(crate::HostUnavailable::LibraryNotFound {
+                name: "an ASIO driver".to_string(),
+            });
+        }
+        Ok(())
     }
 
     fn devices(&self) -> Result<Self::Devices, DevicesError> {
@@ -64,6 +95,9 @@ impl HostTrait for Host {
     }
 }
 
+// Surround streams have the channels in the default order.
+impl SurroundLayout for Device {}
+
 impl DeviceTrait for Device {
     type SupportedInputFormats = SupportedInputFormats;
     type SupportedOutputFormats = SupportedOutputFormats;
@@ -89,18 +123,18 @@ impl DeviceTrait for Device {
         Device::default_output_format(self)
     }
 
-    fn build_input_stream<D, E>(&self, format: &Format, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+    fn build_input_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
     where
         D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static
     {
-        Device::build_input_stream(self, format, data_callback, error_callback)
+        Device::build_input_stream_dyn(self, format, options, data_callback, error_callback)
     }
 
-    fn build_output_stream<D, E>(&self, format: &Format, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+    fn build_output_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
     where
         D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static
     {
-        Device::build_output_stream(self, format, data_callback, error_callback)
+        Device::build_output_stream_dyn(self, format, options, data_callback, error_callback)
     }
 }
 
@@ -112,4 +146,21 @@ impl StreamTrait for Stream {
     fn pause(&self) -> Result<(), PauseStreamError> {
         Stream::pause(self)
     }
+
+    fn callback_load(&self) -> f32 {
+        Stream::callback_load(self)
+    }
+
+    fn stats(&self) -> StreamStats {
+        Stream::stats(self)
+    }
+
+    fn last_callback(&self) -> Option<Instant> {
+        Stream::last_callback(self)
+    }
+
+    fn position(&self) -> Option<StreamPosition> {
+        // The buffer switch callback of `asio-sys` does not provide the sample position yet.
+        None
+    }
 }
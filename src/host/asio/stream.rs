@@ -4,19 +4,28 @@ extern crate num_traits;
 use self::num_traits::PrimInt;
 use super::Device;
 use std;
+use std::cmp;
 use std::sync::atomic::{Ordering, AtomicBool};
 use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 use super::parking_lot::Mutex;
 use BackendSpecificError;
 use BuildStreamError;
+use CallbackInfo;
 use Format;
 use PauseStreamError;
 use PlayStreamError;
+use RawStreamHandle;
 use SampleFormat;
+use SampleRate;
 use StreamData;
+use stats::StreamMeter;
 use UnknownTypeInputBuffer;
 use UnknownTypeOutputBuffer;
 use StreamError;
+use StreamOptions;
+use StreamStats;
 
 /// Sample types whose constant silent value is known.
 trait Silence {
@@ -46,6 +55,29 @@ pub struct Stream {
     driver: Arc<sys::Driver>,
     asio_streams: Arc<Mutex<sys::AsioStreams>>,
     callback_id: sys::CallbackId,
+    // The error callback of the stream, among those that the messages of the driver are reported
+    // to.
+    messages: Arc<DriverMessages>,
+    error_callback_id: usize,
+    meter: Arc<StreamMeter>,
+}
+
+/// The messages of a driver, reported to the error callbacks of the streams of a device.
+///
+/// A single message callback is registered with the driver while the device has streams, so that
+/// a reset request of the driver rebuilds the buffers once, rather than once per stream.
+pub struct DriverMessages {
+    // The message callback registered with the driver, if any.
+    callback_id: Mutex<Option<sys::MessageCallbackId>>,
+    // Locked by the message callback, which the driver calls with its own lock held. It must not
+    // be held while registering or removing the message callback.
+    error_callbacks: Arc<Mutex<ErrorCallbacks>>,
+}
+
+// The error callbacks of the streams of a device, by an identifier unique to the device.
+struct ErrorCallbacks {
+    next_id: usize,
+    callbacks: Vec<(usize, Box<dyn FnMut(StreamError) + Send>)>,
 }
 
 impl Stream {
@@ -58,14 +90,32 @@ impl Stream {
         self.playing.store(false, Ordering::SeqCst);
         Ok(())
     }
+
+    pub fn callback_load(&self) -> f32 {
+        self.meter.callback_load()
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        self.meter.stats()
+    }
+
+    pub fn last_callback(&self) -> Option<Instant> {
+        self.meter.last_callback()
+    }
+
+    /// ASIO drivers are only reachable through the `asio-sys` driver, which is not exposed.
+    pub fn raw_handle(&self) -> Option<RawStreamHandle> {
+        None
+    }
 }
 
 impl Device {
-    pub fn build_input_stream<D, E>(
+    pub fn build_input_stream_dyn<D, E>(
         &self,
         format: &Format,
+        _options: &StreamOptions,
         mut data_callback: D,
-        _error_callback: E,
+        error_callback: E,
     ) -> Result<Stream, BuildStreamError>
     where
         D: FnMut(StreamData) + Send + 'static,
@@ -77,21 +127,27 @@ impl Device {
         let data_type = super::device::convert_data_type(&stream_type)
             .ok_or(BuildStreamError::FormatNotSupported)?;
         if format.data_type != data_type {
-            return Err(BuildStreamError::FormatNotSupported);
+            return Err(BuildStreamError::SampleFormatNotSupported);
         }
 
         let num_channels = format.channels.clone();
         let buffer_size = self.get_or_create_input_stream(format)?;
-        let cpal_num_samples = buffer_size * num_channels as usize;
-
-        // Create the buffer depending on the size of the data type.
-        let len_bytes = cpal_num_samples * data_type.sample_size();
-        let mut interleaved = vec![0u8; len_bytes];
+        let mut interleaved = self.interleaved_buffer(buffer_size, num_channels, data_type)?;
 
         let stream_playing = Arc::new(AtomicBool::new(false));
         let playing = Arc::clone(&stream_playing);
         let asio_streams = self.asio_streams.clone();
 
+        // Measure each invocation of the user's data callback.
+        let meter = Arc::new(StreamMeter::new(format.sample_rate));
+        let callback_meter = meter.clone();
+        let mut data_callback = move |data: StreamData| {
+            let info = match data {
+                StreamData::Input { info, .. } | StreamData::Output { info, .. } => info,
+            };
+            callback_meter.measure(&info, || data_callback(data))
+        };
+
         // Set the input callback.
         // This is most performance critical part of the ASIO bindings.
         let callback_id = self.driver.add_callback(move |buffer_index| unsafe {
@@ -106,6 +162,7 @@ impl Device {
                 Some(ref asio_stream) => asio_stream,
                 None => return,
             };
+            resize_interleaved(&mut interleaved, asio_stream, num_channels, data_type);
 
             /// 1. Write from the ASIO buffer to the interleaved CPAL buffer.
             /// 2. Deliver the CPAL buffer to the user callback.
@@ -135,8 +192,13 @@ impl Device {
                 }
 
                 // 2. Deliver the interleaved buffer to the callback.
+                let info = CallbackInfo::new(
+                    asio_stream.buffer_size as usize,
+                    false,
+                    Default::default(),
+                );
                 callback(
-                    StreamData::Input { buffer: B::unknown_type_input_buffer(interleaved) },
+                    StreamData::Input { buffer: B::unknown_type_input_buffer(interleaved), info },
                 );
             }
 
@@ -226,19 +288,24 @@ impl Device {
         // Immediately start the device?
         self.driver.start().map_err(build_stream_err)?;
 
+        let error_callback_id = self.messages.add(self, Box::new(error_callback));
         Ok(Stream {
             playing: stream_playing,
             driver,
             asio_streams,
             callback_id,
+            messages: self.messages.clone(),
+            error_callback_id,
+            meter,
         })
     }
 
-    pub fn build_output_stream<D, E>(
+    pub fn build_output_stream_dyn<D, E>(
         &self,
         format: &Format,
+        options: &StreamOptions,
         mut data_callback: D,
-        _error_callback: E,
+        error_callback: E,
     ) -> Result<Stream, BuildStreamError>
     where
         D: FnMut(StreamData) + Send + 'static,
@@ -250,22 +317,34 @@ impl Device {
         let data_type = super::device::convert_data_type(&stream_type)
             .ok_or(BuildStreamError::FormatNotSupported)?;
         if format.data_type != data_type {
-            return Err(BuildStreamError::FormatNotSupported);
+            return Err(BuildStreamError::SampleFormatNotSupported);
         }
 
         let num_channels = format.channels.clone();
         let buffer_size = self.get_or_create_output_stream(format)?;
-        let cpal_num_samples = buffer_size * num_channels as usize;
-
-        // Create buffers depending on data type.
-        let len_bytes = cpal_num_samples * data_type.sample_size();
-        let mut interleaved = vec![0u8; len_bytes];
+        let mut interleaved = self.interleaved_buffer(buffer_size, num_channels, data_type)?;
         let mut silence_asio_buffer = SilenceAsioBuffer::default();
 
         let stream_playing = Arc::new(AtomicBool::new(false));
         let playing = Arc::clone(&stream_playing);
         let asio_streams = self.asio_streams.clone();
 
+        // Measure each invocation of the user's data callback.
+        let meter = Arc::new(StreamMeter::new(format.sample_rate));
+        let callback_meter = meter.clone();
+        let zero_output_buffers = options.zero_output_buffers;
+        let mut data_callback = move |mut data: StreamData| {
+            let info = match data {
+                StreamData::Input { info, .. } | StreamData::Output { info, .. } => info,
+            };
+            if let StreamData::Output { ref mut buffer, .. } = data {
+                if zero_output_buffers {
+                    buffer.fill_silence();
+                }
+            }
+            callback_meter.measure(&info, || data_callback(data))
+        };
+
         let callback_id = self.driver.add_callback(move |buffer_index| unsafe {
             // If not playing, return early.
             if !playing.load(Ordering::SeqCst) {
@@ -278,6 +357,7 @@ impl Device {
                 Some(ref asio_stream) => asio_stream,
                 None => return,
             };
+            resize_interleaved(&mut interleaved, asio_stream, num_channels, data_type);
 
             // Silence the ASIO buffer that is about to be used.
             //
@@ -324,7 +404,12 @@ impl Device {
                 // 1. Render interleaved buffer from callback.
                 let interleaved: &mut [A] = cast_slice_mut(interleaved);
                 let buffer = A::unknown_type_output_buffer(interleaved);
-                callback(StreamData::Output { buffer });
+                let info = CallbackInfo::new(
+                    asio_stream.buffer_size as usize,
+                    false,
+                    Default::default(),
+                );
+                callback(StreamData::Output { buffer, info });
 
                 // 2. Silence ASIO channels if necessary.
                 let n_channels = interleaved.len() / asio_stream.buffer_size as usize;
@@ -438,14 +523,37 @@ impl Device {
         // Immediately start the device?
         self.driver.start().map_err(build_stream_err)?;
 
+        let error_callback_id = self.messages.add(self, Box::new(error_callback));
         Ok(Stream {
             playing: stream_playing,
             driver,
             asio_streams,
             callback_id,
+            messages: self.messages.clone(),
+            error_callback_id,
+            meter,
         })
     }
 
+    /// Creates the interleaved CPAL buffer of a stream whose ASIO buffers hold `buffer_size`
+    /// frames.
+    ///
+    /// The capacity covers the largest buffers of the driver, so that the callback does not
+    /// allocate when the buffers are rebuilt with another size after a reset request.
+    fn interleaved_buffer(
+        &self,
+        buffer_size: usize,
+        num_channels: u16,
+        data_type: SampleFormat,
+    ) -> Result<Vec<u8>, BuildStreamError> {
+        let max_buffer_size = self.driver.max_buffer_size().map_err(build_stream_err)?;
+        let max_buffer_size = cmp::max(max_buffer_size.max(0) as usize, buffer_size);
+        let frame_bytes = num_channels as usize * data_type.sample_size();
+        let mut interleaved = Vec::with_capacity(max_buffer_size * frame_bytes);
+        interleaved.resize(buffer_size * frame_bytes, 0);
+        Ok(interleaved)
+    }
+
     /// Create a new CPAL Input Stream.
     ///
     /// If there is no existing ASIO Input Stream it will be created.
@@ -455,23 +563,23 @@ impl Device {
         &self,
         format: &Format,
     ) -> Result<usize, BuildStreamError> {
-        match self.default_input_format() {
+        let channels = match self.default_input_format() {
             Ok(f) => {
                 let num_asio_channels = f.channels;
-                check_format(&self.driver, format, num_asio_channels)
+                check_format(&self.driver, format, num_asio_channels)?;
+                select_channels(self.input_channels(), format, num_asio_channels)
             },
             Err(_) => Err(BuildStreamError::FormatNotSupported),
         }?;
-        let num_channels = format.channels as usize;
         let ref mut streams = *self.asio_streams.lock();
         // Either create a stream if thers none or had back the
         // size of the current one.
         match streams.input {
-            Some(ref input) => Ok(input.buffer_size as usize),
+            Some(ref input) => existing_stream(input, &channels),
             None => {
                 let output = streams.output.take();
                 self.driver
-                    .prepare_input_stream(output, num_channels)
+                    .prepare_input_stream(output, &channels)
                     .map(|new_streams| {
                         let bs = match new_streams.input {
                             Some(ref inp) => inp.buffer_size as usize,
@@ -494,23 +602,23 @@ impl Device {
         &self,
         format: &Format,
     ) -> Result<usize, BuildStreamError> {
-        match self.default_output_format() {
+        let channels = match self.default_output_format() {
             Ok(f) => {
                 let num_asio_channels = f.channels;
-                check_format(&self.driver, format, num_asio_channels)
+                check_format(&self.driver, format, num_asio_channels)?;
+                select_channels(self.output_channels(), format, num_asio_channels)
             },
             Err(_) => Err(BuildStreamError::FormatNotSupported),
         }?;
-        let num_channels = format.channels as usize;
         let ref mut streams = *self.asio_streams.lock();
         // Either create a stream if thers none or had back the
         // size of the current one.
         match streams.output {
-            Some(ref output) => Ok(output.buffer_size as usize),
+            Some(ref output) => existing_stream(output, &channels),
             None => {
                 let output = streams.output.take();
                 self.driver
-                    .prepare_output_stream(output, num_channels)
+                    .prepare_output_stream(output, &channels)
                     .map(|new_streams| {
                         let bs = match new_streams.output {
                             Some(ref out) => out.buffer_size as usize,
@@ -530,6 +638,7 @@ impl Device {
 impl Drop for Stream {
     fn drop(&mut self) {
         self.driver.remove_callback(self.callback_id);
+        self.messages.remove(&self.driver, self.error_callback_id);
     }
 }
 
@@ -551,21 +660,21 @@ impl Silence for f64 {
 
 impl InterleavedSample for i16 {
     fn unknown_type_input_buffer(buffer: &[Self]) -> UnknownTypeInputBuffer {
-        UnknownTypeInputBuffer::I16(::InputBuffer { buffer })
+        UnknownTypeInputBuffer::I16(::InputBuffer::new(buffer))
     }
 
     fn unknown_type_output_buffer(buffer: &mut [Self]) -> UnknownTypeOutputBuffer {
-        UnknownTypeOutputBuffer::I16(::OutputBuffer { buffer })
+        UnknownTypeOutputBuffer::I16(::OutputBuffer::new(buffer))
     }
 }
 
 impl InterleavedSample for f32 {
     fn unknown_type_input_buffer(buffer: &[Self]) -> UnknownTypeInputBuffer {
-        UnknownTypeInputBuffer::F32(::InputBuffer { buffer })
+        UnknownTypeInputBuffer::F32(::InputBuffer::new(buffer))
     }
 
     fn unknown_type_output_buffer(buffer: &mut [Self]) -> UnknownTypeOutputBuffer {
-        UnknownTypeOutputBuffer::F32(::OutputBuffer { buffer })
+        UnknownTypeOutputBuffer::F32(::OutputBuffer::new(buffer))
     }
 }
 
@@ -598,20 +707,59 @@ fn check_format(
                 .set_sample_rate(sample_rate)
                 .map_err(build_stream_err)?;
         } else {
-            return Err(BuildStreamError::FormatNotSupported);
+            return Err(BuildStreamError::SampleRateNotSupported);
         }
     }
     // unsigned formats are not supported by asio
     match data_type {
         SampleFormat::I16 | SampleFormat::F32 => (),
-        SampleFormat::U16 => return Err(BuildStreamError::FormatNotSupported),
+        SampleFormat::U16 => return Err(BuildStreamError::SampleFormatNotSupported),
     }
     if *channels > num_asio_channels {
-        return Err(BuildStreamError::FormatNotSupported);
+        return Err(BuildStreamError::InvalidChannelCount);
     }
     Ok(())
 }
 
+/// The channels of the driver that a stream of `format` uses.
+///
+/// These are the `selected` channels if any, which must be as many as the stream has and exist on
+/// the driver, or the first channels of the driver otherwise.
+fn select_channels(
+    selected: Option<&[usize]>,
+    format: &Format,
+    num_asio_channels: u16,
+) -> Result<Vec<usize>, BuildStreamError> {
+    match selected {
+        Some(channels) => {
+            let exist = channels.iter().all(|&channel| channel < num_asio_channels as usize);
+            let distinct = channels
+                .iter()
+                .enumerate()
+                .all(|(i, channel)| !channels[..i].contains(channel));
+            if channels.len() != format.channels as usize || !exist || !distinct {
+                return Err(BuildStreamError::InvalidChannelCount);
+            }
+            Ok(channels.to_vec())
+        },
+        None => Ok((0..format.channels as usize).collect()),
+    }
+}
+
+/// Hands back the buffer size of the ASIO stream that a new CPAL stream shares.
+///
+/// The buffers of the ASIO stream cannot change while other CPAL streams use them, so the new
+/// stream must use the same channels of the driver.
+fn existing_stream(
+    stream: &sys::AsioStream,
+    channels: &[usize],
+) -> Result<usize, BuildStreamError> {
+    if stream.channels() != channels {
+        return Err(BuildStreamError::InvalidChannelCount);
+    }
+    Ok(stream.buffer_size as usize)
+}
+
 /// Cast a byte slice into a mutable slice of desired type.
 ///
 /// Safety: it's up to the caller to ensure that the input slice has valid bit representations.
@@ -640,6 +788,130 @@ fn from_be<T: PrimInt>(t: T) -> T {
     T::from_be(t)
 }
 
+impl DriverMessages {
+    pub fn new() -> Self {
+        let error_callbacks = ErrorCallbacks {
+            next_id: 0,
+            callbacks: Vec::new(),
+        };
+        DriverMessages {
+            callback_id: Mutex::new(None),
+            error_callbacks: Arc::new(Mutex::new(error_callbacks)),
+        }
+    }
+
+    // Adds the error callback of a stream of `device`, registering the message callback with the
+    // driver for the first one. Returns the identifier with which to remove it.
+    fn add(&self, device: &Device, error_callback: Box<dyn FnMut(StreamError) + Send>) -> usize {
+        let mut callback_id = self.callback_id.lock();
+        let id = {
+            let mut error_callbacks = self.error_callbacks.lock();
+            let id = error_callbacks.next_id;
+            error_callbacks.next_id += 1;
+            error_callbacks.callbacks.push((id, error_callback));
+            id
+        };
+        if callback_id.is_none() {
+            let callback = message_callback(device, self.error_callbacks.clone());
+            *callback_id = Some(device.driver.add_message_callback(callback));
+        }
+        id
+    }
+
+    // Removes the error callback `id`, and the message callback with the last one.
+    fn remove(&self, driver: &sys::Driver, id: usize) {
+        let mut callback_id = self.callback_id.lock();
+        let empty = {
+            let mut error_callbacks = self.error_callbacks.lock();
+            error_callbacks.callbacks.retain(|&(callback, _)| callback != id);
+            error_callbacks.callbacks.is_empty()
+        };
+        if empty {
+            if let Some(callback_id) = callback_id.take() {
+                driver.remove_message_callback(callback_id);
+            }
+        }
+    }
+}
+
+impl ErrorCallbacks {
+    fn report(&mut self, err: StreamError) {
+        for &mut (_, ref mut error_callback) in &mut self.callbacks {
+            error_callback(err.clone());
+        }
+    }
+}
+
+/// Forwards the messages of the driver of `device` that concern its streams to their error
+/// callbacks.
+///
+/// When the driver requests a reset, the buffers of the streams are rebuilt in place, so that
+/// the streams keep running with the new settings of the driver.
+fn message_callback(
+    device: &Device,
+    error_callbacks: Arc<Mutex<ErrorCallbacks>>,
+) -> impl FnMut(sys::AsioMessage) + Send + 'static {
+    let driver = Arc::downgrade(&device.driver);
+    let asio_streams = device.asio_streams.clone();
+    move |message| match message {
+        sys::AsioMessage::SampleRateChanged { rate } => {
+            let sample_rate = Some(SampleRate(rate as u32));
+            error_callbacks.lock().report(StreamError::DeviceReconfigured { sample_rate });
+        },
+        sys::AsioMessage::ResetRequest => {
+            trace_event!(info, host = "ASIO", "the driver requested a reset");
+            // The driver cannot be reset from within one of its callbacks.
+            let driver = driver.clone();
+            let asio_streams = asio_streams.clone();
+            let error_callbacks = error_callbacks.clone();
+            thread::spawn(move || {
+                let driver = match driver.upgrade() {
+                    Some(driver) => driver,
+                    None => return,
+                };
+                let err = match reset_driver(&driver, &asio_streams) {
+                    Ok(()) => StreamError::DeviceReconfigured { sample_rate: None },
+                    Err(err) => {
+                        let description = format!("failed to reset the driver: {}", err);
+                        let code = super::asio_error_code(&err);
+                        BackendSpecificError { description, code }.into()
+                    },
+                };
+                error_callbacks.lock().report(err);
+            });
+        },
+        sys::AsioMessage::ResyncRequest | sys::AsioMessage::LatenciesChanged => (),
+    }
+}
+
+/// Rebuilds the buffers of the streams of `driver` and starts it again.
+fn reset_driver(
+    driver: &sys::Driver,
+    asio_streams: &Mutex<sys::AsioStreams>,
+) -> Result<(), sys::AsioError> {
+    // Stopping the driver waits for the buffer callbacks, which lock the streams.
+    driver.stop()?;
+    driver.rebuild_buffers(&mut asio_streams.lock())?;
+    // Streams start the driver when they are built and pause by skipping their callbacks.
+    driver.start()
+}
+
+/// Resizes the interleaved CPAL buffer of a stream to the size of the ASIO buffers, which changes
+/// when the buffers are rebuilt after a reset request of the driver. The buffer only grows past
+/// its capacity if the driver exceeds the largest buffer size it reported.
+fn resize_interleaved(
+    interleaved: &mut Vec<u8>,
+    asio_stream: &sys::AsioStream,
+    num_channels: u16,
+    data_type: SampleFormat,
+) {
+    let num_samples = asio_stream.buffer_size as usize * num_channels as usize;
+    let len_bytes = num_samples * data_type.sample_size();
+    if interleaved.len() != len_bytes {
+        interleaved.resize(len_bytes, 0);
+    }
+}
+
 /// Shorthand for retrieving the asio buffer slice associated with a channel.
 ///
 /// Safety: it's up to the user to ensure that this function is not called multiple times for the
@@ -676,7 +948,8 @@ fn build_stream_err(e: sys::AsioError) -> BuildStreamError {
         sys::AsioError::BadMode => BuildStreamError::InvalidArgument,
         err => {
             let description = format!("{}", err);
-            BackendSpecificError { description }.into()
+            let code = super::asio_error_code(&err);
+            BackendSpecificError { description, code }.into()
         }
     }
 }
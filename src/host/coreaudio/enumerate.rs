@@ -71,7 +71,7 @@ impl Devices {
                 Ok(devices) => devices,
                 Err(os_status) => {
                     let description = format!("{}", os_status);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError { description, code: Some(os_status) };
                     return Err(err.into());
                 }
             }
@@ -80,15 +80,10 @@ impl Devices {
     }
 }
 
-unsafe impl Send for Devices {
-}
-unsafe impl Sync for Devices {
-}
-
 impl Iterator for Devices {
     type Item = Device;
     fn next(&mut self) -> Option<Device> {
-        self.0.next().map(|id| Device { audio_device_id: id })
+        self.0.next().map(Device::new)
     }
 }
 
@@ -115,10 +110,7 @@ pub fn default_input_device() -> Option<Device> {
         return None;
     }
 
-    let device = Device {
-        audio_device_id: audio_device_id,
-    };
-    Some(device)
+    Some(Device::new(audio_device_id))
 }
 
 pub fn default_output_device() -> Option<Device> {
@@ -144,10 +136,7 @@ pub fn default_output_device() -> Option<Device> {
         return None;
     }
 
-    let device = Device {
-        audio_device_id: audio_device_id,
-    };
-    Some(device)
+    Some(Device::new(audio_device_id))
 }
 
 pub type SupportedInputFormats = VecIntoIter<SupportedFormat>;
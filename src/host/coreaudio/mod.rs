@@ -4,29 +4,40 @@ extern crate core_foundation_sys;
 use ChannelCount;
 use BackendSpecificError;
 use BuildStreamError;
+use CallbackInfo;
 use DefaultFormatError;
 use DeviceNameError;
 use DevicesError;
 use Format;
 use PauseStreamError;
 use PlayStreamError;
+use RawDeviceHandle;
+use RawStreamHandle;
 use SupportedFormatsError;
 use SampleFormat;
 use SampleRate;
 use StreamData;
 use StreamError;
+use StreamOptions;
+use StreamPosition;
+use StreamStats;
 use SupportedFormat;
 use UnknownTypeInputBuffer;
 use UnknownTypeOutputBuffer;
+use clock;
+use downmix::SurroundLayout;
+use stats::StreamMeter;
 use traits::{DeviceTrait, HostTrait, StreamTrait};
 
 use std::ffi::CStr;
 use std::fmt;
 use std::mem;
 use std::cell::RefCell;
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
 use std::ptr::null;
 use std::slice;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use self::coreaudio::audio_unit::{AudioUnit, Scope, Element};
 use self::coreaudio::audio_unit::render_callback::{self, data};
@@ -43,6 +54,10 @@ use self::coreaudio::sys::{
     AudioObjectRemovePropertyListener,
     AudioObjectSetPropertyData,
     AudioStreamBasicDescription,
+    AudioTimeStamp,
+    AudioUnitInitialize,
+    AudioUnitSetProperty,
+    AudioUnitUninitialize,
     AudioValueRange,
     kAudioDevicePropertyAvailableNominalSampleRates,
     kAudioDevicePropertyDeviceNameCFString,
@@ -59,6 +74,8 @@ use self::coreaudio::sys::{
     kAudioObjectPropertyScopeOutput,
     kAudioOutputUnitProperty_CurrentDevice,
     kAudioOutputUnitProperty_EnableIO,
+    kAudioTimeStampHostTimeValid,
+    kAudioTimeStampSampleTimeValid,
     kAudioUnitProperty_StreamFormat,
     kCFStringEncodingUTF8,
     OSStatus,
@@ -69,8 +86,16 @@ use self::core_foundation_sys::string::{
 };
 
 mod enumerate;
+#[cfg(target_os = "ios")]
+mod session;
+#[cfg(target_os = "macos")]
+mod workgroup;
 
 pub use self::enumerate::{Devices, SupportedInputFormats, SupportedOutputFormats, default_input_device, default_output_device};
+#[cfg(target_os = "ios")]
+pub use self::session::{SessionCategory, SessionConfig, SessionMode, SessionOptions};
+#[cfg(target_os = "macos")]
+pub use self::workgroup::{Workgroup, WorkgroupMembership};
 
 /// Coreaudio host, the default host on macOS and iOS.
 #[derive(Debug)]
@@ -104,6 +129,9 @@ impl HostTrait for Host {
     }
 }
 
+// Surround streams have the channels in the default order.
+impl SurroundLayout for Device {}
+
 impl DeviceTrait for Device {
     type SupportedInputFormats = SupportedInputFormats;
     type SupportedOutputFormats = SupportedOutputFormats;
@@ -129,21 +157,86 @@ impl DeviceTrait for Device {
         Device::default_output_format(self)
     }
 
-    fn build_input_stream<D, E>(&self, format: &Format, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
-        Device::build_input_stream(self, format, data_callback, error_callback)
+    fn build_input_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
+        Device::build_input_stream_dyn(self, format, options, data_callback, error_callback)
     }
 
-    fn build_output_stream<D, E>(&self, format: &Format, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
-        Device::build_output_stream(self, format, data_callback, error_callback)
+    fn build_output_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
+        Device::build_output_stream_dyn(self, format, options, data_callback, error_callback)
     }
 }
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone)]
 pub struct Device {
     audio_device_id: AudioDeviceID,
+    // Set on the audio units of the streams of the device, see `with_audio_unit_property`.
+    audio_unit_properties: Vec<AudioUnitProperty>,
+}
+
+// A device is the same whatever the properties of its streams, so that it still compares equal
+// to e.g. the default device once they are set.
+impl PartialEq for Device {
+    fn eq(&self, other: &Self) -> bool {
+        self.audio_device_id == other.audio_device_id
+    }
+}
+
+impl Eq for Device {}
+
+// An AudioUnit property and the bytes of its value.
+#[derive(Clone)]
+struct AudioUnitProperty {
+    id: u32,
+    scope: u32,
+    element: u32,
+    data: Vec<u8>,
 }
 
 impl Device {
+    fn new(audio_device_id: AudioDeviceID) -> Self {
+        Device { audio_device_id, audio_unit_properties: Vec::new() }
+    }
+
+    pub fn raw_handle(&self) -> Option<RawDeviceHandle> {
+        Some(RawDeviceHandle::CoreAudio { device_id: self.audio_device_id })
+    }
+
+    /// The workgroup of the thread on which CoreAudio runs the callbacks of the device, which
+    /// other threads producing its audio can join. `None` before macOS 11.
+    #[cfg(target_os = "macos")]
+    pub fn io_workgroup(&self) -> Option<Workgroup> {
+        Workgroup::of_device(self.audio_device_id)
+    }
+
+    /// The same device, with `value` set as the AudioUnit property `id` in `scope` and `element`
+    /// of the audio units of its streams, e.g. `kAudioUnitProperty_MaximumFramesPerSlice`.
+    ///
+    /// The properties are set in the order in which they were added, after CPAL selects the device
+    /// and before it sets the stream format, while the unit is uninitialized. Building a stream
+    /// fails if the unit rejects one of them.
+    ///
+    /// # Safety
+    ///
+    /// `value` must be of the type that the property expects. Values that hold pointers, such as
+    /// `CFStringRef`s, must stay valid until the streams are built.
+    pub unsafe fn with_audio_unit_property<T: Copy>(
+        &self,
+        id: u32,
+        scope: u32,
+        element: u32,
+        value: &T,
+    ) -> Device {
+        let data = slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>());
+        let mut device = self.clone();
+        device.audio_unit_properties.push(AudioUnitProperty {
+            id,
+            scope,
+            element,
+            data: data.to_vec(),
+        });
+        device
+    }
+
     fn name(&self) -> Result<String, DeviceNameError> {
         let property_address = AudioObjectPropertyAddress {
             mSelector: kAudioDevicePropertyDeviceNameCFString,
@@ -166,7 +259,7 @@ impl Device {
             let c_string: *const c_char = CFStringGetCStringPtr(device_name, kCFStringEncodingUTF8);
             if c_string == null() {
                 let description = "core foundation unexpectedly returned null string".to_string();
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError { description, code: None };
                 return Err(err.into());
             }
             CStr::from_ptr(c_string as *mut _)
@@ -307,7 +400,7 @@ impl Device {
                 }
                 err => {
                     let description = format!("{}", std::error::Error::description(&err));
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError { description, code: Some(status) };
                     Err(err.into())
                 }
             }
@@ -388,6 +481,10 @@ struct StreamInner {
     // We must do this so that we can avoid changing the device sample rate if there is already
     // a stream associated with the device.
     device_id: AudioDeviceID,
+    // Measurements of the data callback, shared with the audio unit's callback.
+    meter: Arc<StreamMeter>,
+    // Reports changes of the sample rate of the device while the stream is open.
+    _sample_rate_listener: SampleRateListener,
 }
 
 // TODO need stronger error identification
@@ -399,6 +496,8 @@ impl From<coreaudio::Error> for BuildStreamError {
             coreaudio::Error::AudioUnit(coreaudio::error::AudioUnitError::FormatNotSupported) |
             coreaudio::Error::AudioCodec(_) |
             coreaudio::Error::AudioFormat(_) => BuildStreamError::FormatNotSupported,
+            // `kAudioDevicePermissionsError`, returned while another process hogs the device.
+            coreaudio::Error::Unknown(0x2168_6F67) => BuildStreamError::DeviceInUse,
             _ => BuildStreamError::DeviceNotAvailable,
         }
     }
@@ -472,11 +571,32 @@ fn audio_unit_from_device(device: &Device, input: bool) -> Result<AudioUnit, cor
         Some(&device.audio_device_id),
     )?;
 
+    if !device.audio_unit_properties.is_empty() {
+        let unit: coreaudio::sys::AudioUnit = *audio_unit.as_ref();
+        unsafe {
+            // Some properties, such as the maximum number of frames per slice, can only be set
+            // while the unit is uninitialized.
+            coreaudio::Error::from_os_status(AudioUnitUninitialize(unit))?;
+            for property in &device.audio_unit_properties {
+                let status = AudioUnitSetProperty(
+                    unit,
+                    property.id,
+                    property.scope,
+                    property.element,
+                    property.data.as_ptr() as *const c_void,
+                    property.data.len() as u32,
+                );
+                coreaudio::Error::from_os_status(status)?;
+            }
+            coreaudio::Error::from_os_status(AudioUnitInitialize(unit))?;
+        }
+    }
+
     Ok(audio_unit)
 }
 
 impl Device {
-    fn build_input_stream<D, E>(&self, format: &Format, mut data_callback: D, _error_callback: E) -> Result<Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
+    fn build_input_stream_dyn<D, E>(&self, format: &Format, _options: &StreamOptions, mut data_callback: D, error_callback: E) -> Result<Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
         // The scope and element for working with a device's input stream.
         let scope = Scope::Output;
         let element = Element::Input;
@@ -598,7 +718,7 @@ impl Device {
                 while sample_rate != reported_rate {
                     if timer.elapsed() > Duration::from_secs(1) {
                         let description = "timeout waiting for sample rate update for device".into();
-                        let err = BackendSpecificError { description };
+                        let err = BackendSpecificError { description, code: None };
                         return Err(err.into());
                     }
                     thread::sleep(Duration::from_millis(5));
@@ -616,6 +736,7 @@ impl Device {
         }
 
         let mut audio_unit = audio_unit_from_device(self, true)?;
+        let error_callback = Arc::new(Mutex::new(error_callback));
 
         // Set the stream in interleaved mode.
         let asbd = asbd_from_format(format);
@@ -625,6 +746,11 @@ impl Device {
         // fed to the audio buffer.
         let sample_format = format.data_type;
         let bytes_per_channel = format.data_type.sample_size();
+        let channels = format.channels as usize;
+        let meter = Arc::new(StreamMeter::new(format.sample_rate));
+        let callback_meter = meter.clone();
+        let callback_error_callback = error_callback.clone();
+        let mut first_sample_time = None;
         type Args = render_callback::Args<data::Raw>;
         audio_unit.set_input_callback(move |args: Args| unsafe {
             let ptr = (*args.data.data).mBuffers.as_ptr() as *const AudioBuffer;
@@ -642,10 +768,15 @@ impl Device {
             macro_rules! try_callback {
                 ($SampleFormat:ident, $SampleType:ty) => {{
                     let data_len = (data_byte_size as usize / bytes_per_channel) as usize;
+                    if data_len % channels != 0 {
+                        report_error(&callback_error_callback, partial_frame_error(data_len, channels).into());
+                        return Ok(());
+                    }
                     let data_slice = slice::from_raw_parts(data as *const $SampleType, data_len);
-                    let unknown_type_buffer = UnknownTypeInputBuffer::$SampleFormat(::InputBuffer { buffer: data_slice });
-                    let stream_data = StreamData::Input { buffer: unknown_type_buffer };
-                    data_callback(stream_data);
+                    let unknown_type_buffer = UnknownTypeInputBuffer::$SampleFormat(::InputBuffer::new(data_slice));
+                    let info = CallbackInfo::new(args.num_frames, false, Default::default());
+                    let stream_data = StreamData::Input { buffer: unknown_type_buffer, info };
+                    callback_meter.measure(&info, || data_callback(stream_data));
                 }};
             }
 
@@ -654,21 +785,27 @@ impl Device {
                 SampleFormat::I16 => try_callback!(I16, i16),
                 SampleFormat::U16 => try_callback!(U16, u16),
             }
+            record_position(&callback_meter, &args.time_stamp, &mut first_sample_time);
 
             Ok(())
         })?;
 
+        let sample_rate_listener =
+            SampleRateListener::new(self.audio_device_id, format.sample_rate, error_callback)?;
         audio_unit.start()?;
 
         Ok(Stream::new(StreamInner {
             playing: true,
             audio_unit,
             device_id: self.audio_device_id,
+            meter,
+            _sample_rate_listener: sample_rate_listener,
         }))
     }
 
-    fn build_output_stream<D, E>(&self, format: &Format, mut data_callback: D, _error_callback: E) -> Result<Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
+    fn build_output_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, mut data_callback: D, error_callback: E) -> Result<Stream, BuildStreamError> where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
         let mut audio_unit = audio_unit_from_device(self, false)?;
+        let error_callback = Arc::new(Mutex::new(error_callback));
 
         // The scope and element for working with a device's output stream.
         let scope = Scope::Input;
@@ -682,6 +819,12 @@ impl Device {
         // fed to the audio buffer.
         let sample_format = format.data_type;
         let bytes_per_channel = format.data_type.sample_size();
+        let channels = format.channels as usize;
+        let zero_output_buffers = options.zero_output_buffers;
+        let meter = Arc::new(StreamMeter::new(format.sample_rate));
+        let callback_meter = meter.clone();
+        let callback_error_callback = error_callback.clone();
+        let mut first_sample_time = None;
         type Args = render_callback::Args<data::Raw>;
         audio_unit.set_render_callback(move |args: Args| unsafe {
             // If `run()` is currently running, then a callback will be available from this list.
@@ -698,9 +841,18 @@ impl Device {
                 ($SampleFormat:ident, $SampleType:ty, $equilibrium:expr) => {{
                     let data_len = (data_byte_size as usize / bytes_per_channel) as usize;
                     let data_slice = slice::from_raw_parts_mut(data as *mut $SampleType, data_len);
-                    let unknown_type_buffer = UnknownTypeOutputBuffer::$SampleFormat(::OutputBuffer { buffer: data_slice });
-                    let stream_data = StreamData::Output { buffer: unknown_type_buffer };
-                    data_callback(stream_data);
+                    let mut unknown_type_buffer = UnknownTypeOutputBuffer::$SampleFormat(::OutputBuffer::new(data_slice));
+                    if data_len % channels != 0 {
+                        unknown_type_buffer.fill_silence();
+                        report_error(&callback_error_callback, partial_frame_error(data_len, channels).into());
+                        return Ok(());
+                    }
+                    if zero_output_buffers {
+                        unknown_type_buffer.fill_silence();
+                    }
+                    let info = CallbackInfo::new(args.num_frames, false, Default::default());
+                    let stream_data = StreamData::Output { buffer: unknown_type_buffer, info };
+                    callback_meter.measure(&info, || data_callback(stream_data));
                 }};
             }
 
@@ -709,20 +861,139 @@ impl Device {
                 SampleFormat::I16 => try_callback!(I16, i16, 0),
                 SampleFormat::U16 => try_callback!(U16, u16, ::std::u16::MAX / 2),
             }
+            record_position(&callback_meter, &args.time_stamp, &mut first_sample_time);
 
             Ok(())
         })?;
 
+        let sample_rate_listener =
+            SampleRateListener::new(self.audio_device_id, format.sample_rate, error_callback)?;
         audio_unit.start()?;
 
         Ok(Stream::new(StreamInner {
             playing: true,
             audio_unit,
             device_id: self.audio_device_id,
+            meter,
+            _sample_rate_listener: sample_rate_listener,
         }))
     }
 }
 
+// The error reported instead of calling the data callback with a buffer of `len` samples that
+// does not hold a whole number of frames of `channels` samples.
+fn partial_frame_error(len: usize, channels: usize) -> BackendSpecificError {
+    let description = format!(
+        "the device provided a buffer of {} samples, which is not a whole number of frames of {} \
+         channels",
+        len,
+        channels,
+    );
+    BackendSpecificError { description, code: None }
+}
+
+// Calls the error callback of a stream, unless it panicked before.
+fn report_error<E>(error_callback: &Mutex<E>, err: StreamError)
+where
+    E: FnMut(StreamError),
+{
+    if let Ok(mut error_callback) = error_callback.lock() {
+        (&mut *error_callback)(err);
+    }
+}
+
+// Listens to the nominal sample rate of the device of a stream, which the user may change in
+// Audio MIDI Setup while the stream is open. The audio unit then keeps running at the new rate, so
+// the stream plays at the wrong pitch until it is built again. The change is reported to the error
+// callback as `StreamError::DeviceReconfigured`.
+struct SampleRateListener {
+    device_id: AudioDeviceID,
+    // Boxed twice so that the address registered with the listener stays valid and thin.
+    on_change: Box<Box<dyn FnMut(AudioDeviceID) + Send>>,
+}
+
+impl SampleRateListener {
+    fn new<E>(
+        device_id: AudioDeviceID,
+        sample_rate: SampleRate,
+        error_callback: Arc<Mutex<E>>,
+    ) -> Result<Self, coreaudio::Error>
+    where
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let mut reported_rate = sample_rate;
+        let on_change = move |device_id: AudioDeviceID| {
+            let rate = match unsafe { nominal_sample_rate(device_id) } {
+                Ok(rate) => SampleRate(rate as u32),
+                Err(_) => return,
+            };
+            // The listener may be notified without a change, or several times for one change.
+            if rate != reported_rate {
+                reported_rate = rate;
+                let sample_rate = if rate != sample_rate { Some(rate) } else { None };
+                report_error(&error_callback, StreamError::DeviceReconfigured { sample_rate });
+            }
+        };
+        let mut on_change: Box<Box<dyn FnMut(AudioDeviceID) + Send>> =
+            Box::new(Box::new(on_change));
+        let status = unsafe {
+            AudioObjectAddPropertyListener(
+                device_id,
+                &NOMINAL_SAMPLE_RATE as *const _,
+                Some(sample_rate_listener),
+                &mut *on_change as *mut Box<_> as *mut c_void,
+            )
+        };
+        coreaudio::Error::from_os_status(status)?;
+        Ok(SampleRateListener { device_id, on_change })
+    }
+}
+
+impl Drop for SampleRateListener {
+    fn drop(&mut self) {
+        unsafe {
+            AudioObjectRemovePropertyListener(
+                self.device_id,
+                &NOMINAL_SAMPLE_RATE as *const _,
+                Some(sample_rate_listener),
+                &mut *self.on_change as *mut Box<_> as *mut c_void,
+            );
+        }
+    }
+}
+
+const NOMINAL_SAMPLE_RATE: AudioObjectPropertyAddress = AudioObjectPropertyAddress {
+    mSelector: kAudioDevicePropertyNominalSampleRate,
+    mScope: kAudioObjectPropertyScopeGlobal,
+    mElement: kAudioObjectPropertyElementMaster,
+};
+
+unsafe extern "C" fn sample_rate_listener(
+    device_id: AudioObjectID,
+    _n_addresses: u32,
+    _properties: *const AudioObjectPropertyAddress,
+    on_change: *mut c_void,
+) -> OSStatus {
+    let on_change = &mut *(on_change as *mut Box<dyn FnMut(AudioDeviceID) + Send>);
+    on_change(device_id);
+    0
+}
+
+unsafe fn nominal_sample_rate(device_id: AudioDeviceID) -> Result<f64, coreaudio::Error> {
+    let mut sample_rate: f64 = 0.0;
+    let mut data_size = mem::size_of::<f64>() as u32;
+    let status = AudioObjectGetPropertyData(
+        device_id,
+        &NOMINAL_SAMPLE_RATE as *const _,
+        0,
+        null(),
+        &mut data_size as *mut _,
+        &mut sample_rate as *mut f64 as *mut _,
+    );
+    coreaudio::Error::from_os_status(status)?;
+    Ok(sample_rate)
+}
+
 pub struct Stream {
     inner: RefCell<StreamInner>,
 }
@@ -733,6 +1004,19 @@ impl Stream {
             inner: RefCell::new(inner),
         }
     }
+
+    pub fn raw_handle(&self) -> Option<RawStreamHandle> {
+        let stream = self.inner.borrow();
+        let audio_unit: &coreaudio::sys::AudioUnit = stream.audio_unit.as_ref();
+        Some(RawStreamHandle::CoreAudio { audio_unit: *audio_unit as *mut _ })
+    }
+
+    /// The workgroup of the thread that runs the callbacks of the stream, see
+    /// `Device::io_workgroup`.
+    #[cfg(target_os = "macos")]
+    pub fn io_workgroup(&self) -> Option<Workgroup> {
+        Workgroup::of_device(self.inner.borrow().device_id)
+    }
 }
 
 impl StreamTrait for Stream {
@@ -742,7 +1026,7 @@ impl StreamTrait for Stream {
         if !stream.playing {
             if let Err(e) = stream.audio_unit.start() {
                 let description = format!("{}", std::error::Error::description(&e));
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError { description, code: Some(e.to_os_status()) };
                 return Err(err.into());
             }
             stream.playing = true;
@@ -756,7 +1040,7 @@ impl StreamTrait for Stream {
         if stream.playing {
             if let Err(e) = stream.audio_unit.stop() {
                 let description = format!("{}", std::error::Error::description(&e));
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError { description, code: Some(e.to_os_status()) };
                 return Err(err.into());
             }
 
@@ -764,6 +1048,44 @@ impl StreamTrait for Stream {
         }
         Ok(())
     }
+
+    fn callback_load(&self) -> f32 {
+        self.inner.borrow().meter.callback_load()
+    }
+
+    fn stats(&self) -> StreamStats {
+        self.inner.borrow().meter.stats()
+    }
+
+    fn last_callback(&self) -> Option<Instant> {
+        self.inner.borrow().meter.last_callback()
+    }
+
+    fn position(&self) -> Option<StreamPosition> {
+        self.inner.borrow().meter.position()
+    }
+}
+
+// Records the position of the buffer described by `time_stamp`. Frames are counted from the
+// sample time of the first buffer of the stream.
+fn record_position(
+    meter: &StreamMeter,
+    time_stamp: &AudioTimeStamp,
+    first_sample_time: &mut Option<f64>,
+) {
+    let valid = kAudioTimeStampSampleTimeValid | kAudioTimeStampHostTimeValid;
+    if time_stamp.mFlags & valid != valid {
+        return;
+    }
+    let first_sample_time = *first_sample_time.get_or_insert(time_stamp.mSampleTime);
+    let frame = (time_stamp.mSampleTime - first_sample_time).max(0.0) as u64;
+    meter.record_position(frame, host_time_to_instant(time_stamp.mHostTime));
+}
+
+// Converts a host time, as found in `AudioTimeStamp`, to an `Instant`.
+fn host_time_to_instant(host_time: u64) -> Instant {
+    // The host time is a reading of `mach_absolute_time`.
+    clock::from_ticks(host_time).unwrap_or_else(Instant::now)
 }
 
 fn check_os_status(os_status: OSStatus) -> Result<(), BackendSpecificError> {
@@ -771,7 +1093,7 @@ fn check_os_status(os_status: OSStatus) -> Result<(), BackendSpecificError> {
         Ok(()) => Ok(()),
         Err(err) => {
             let description = std::error::Error::description(&err).to_string();
-            Err(BackendSpecificError { description })
+            Err(BackendSpecificError { description, code: Some(os_status) })
         }
     }
 }
@@ -0,0 +1,233 @@
+use BackendSpecificError;
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use super::Host;
+
+// The Objective-C runtime, through which `AVAudioSession` is messaged.
+type Id = *mut c_void;
+type Sel = *const c_void;
+type Bool = i8;
+
+#[link(name = "objc")]
+extern "C" {
+    fn objc_getClass(name: *const c_char) -> Id;
+    fn sel_registerName(name: *const c_char) -> Sel;
+    fn objc_msgSend();
+}
+
+#[link(name = "AVFoundation", kind = "framework")]
+extern "C" {
+    static AVAudioSessionCategoryAmbient: Id;
+    static AVAudioSessionCategorySoloAmbient: Id;
+    static AVAudioSessionCategoryPlayback: Id;
+    static AVAudioSessionCategoryRecord: Id;
+    static AVAudioSessionCategoryPlayAndRecord: Id;
+    static AVAudioSessionCategoryMultiRoute: Id;
+    static AVAudioSessionModeDefault: Id;
+    static AVAudioSessionModeVoiceChat: Id;
+    static AVAudioSessionModeVideoChat: Id;
+    static AVAudioSessionModeGameChat: Id;
+    static AVAudioSessionModeMeasurement: Id;
+    static AVAudioSessionModeMoviePlayback: Id;
+    static AVAudioSessionModeSpokenAudio: Id;
+    static AVAudioSessionModeVideoRecording: Id;
+}
+
+// `AVAudioSessionSetActiveOptionNotifyOthersOnDeactivation`.
+const NOTIFY_OTHERS_ON_DEACTIVATION: usize = 1;
+
+/// The category of the audio session of the application, which tells iOS how its audio relates to
+/// that of other applications and to the silent switch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SessionCategory {
+    /// Playback that mixes with other audio and is silenced by the silent switch.
+    Ambient,
+    /// Playback that interrupts other audio and is silenced by the silent switch. The default of
+    /// iOS.
+    SoloAmbient,
+    /// Playback that is central to the application, and continues with the silent switch on.
+    Playback,
+    /// Recording only.
+    Record,
+    /// Simultaneous recording and playback, e.g. for calls. Required by input streams that run
+    /// alongside output streams.
+    PlayAndRecord,
+    /// Playback and recording on several routes at once, e.g. headphones and USB.
+    MultiRoute,
+}
+
+/// The mode of the audio session, which tunes the signal processing of iOS for a use case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SessionMode {
+    Default,
+    VoiceChat,
+    VideoChat,
+    GameChat,
+    /// Disables the signal processing of the input, for measurements and music applications.
+    Measurement,
+    MoviePlayback,
+    SpokenAudio,
+    VideoRecording,
+}
+
+/// Options of the audio session category. They all default to `false`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SessionOptions {
+    /// Mix with the audio of other applications instead of interrupting it.
+    pub mix_with_others: bool,
+    /// Lower the volume of the audio of other applications while the session is active.
+    pub duck_others: bool,
+    /// Allow Bluetooth hands-free devices as inputs and outputs, with `PlayAndRecord` or
+    /// `Record`.
+    pub allow_bluetooth: bool,
+    /// Allow Bluetooth A2DP devices as outputs, with `PlayAndRecord`.
+    pub allow_bluetooth_a2dp: bool,
+    /// Allow AirPlay devices as outputs, with `PlayAndRecord`.
+    pub allow_air_play: bool,
+    /// Play through the speaker rather than the receiver when no other output is connected, with
+    /// `PlayAndRecord`.
+    pub default_to_speaker: bool,
+}
+
+/// The configuration of the audio session of the application, see
+/// `Host::configure_audio_session`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SessionConfig {
+    pub category: SessionCategory,
+    pub mode: SessionMode,
+    pub options: SessionOptions,
+}
+
+impl Host {
+    /// Sets the category, mode and options of the `AVAudioSession` of the application.
+    ///
+    /// Streams play and record according to the session, so it should be configured before
+    /// building them. In particular, input streams need the `Record` or `PlayAndRecord` category.
+    pub fn configure_audio_session(
+        &self,
+        config: &SessionConfig,
+    ) -> Result<(), BackendSpecificError>
+    {
+        unsafe {
+            let set_category: unsafe extern "C" fn(Id, Sel, Id, Id, usize, *mut Id) -> Bool =
+                mem::transmute(objc_msgSend as unsafe extern "C" fn());
+            let mut error = ptr::null_mut();
+            let ok = set_category(
+                shared_session(),
+                sel(b"setCategory:mode:options:error:\0"),
+                config.category.to_av(),
+                config.mode.to_av(),
+                config.options.to_av(),
+                &mut error,
+            );
+            check_ns_error(ok, error, "could not configure the audio session")
+        }
+    }
+
+    /// Activates or deactivates the `AVAudioSession` of the application.
+    ///
+    /// Building a stream activates the session, but applications should activate it themselves
+    /// after an interruption, and deactivate it once they stop playing, so that the other
+    /// applications that they interrupted or ducked resume.
+    pub fn set_audio_session_active(&self, active: bool) -> Result<(), BackendSpecificError> {
+        unsafe {
+            let set_active: unsafe extern "C" fn(Id, Sel, Bool, usize, *mut Id) -> Bool =
+                mem::transmute(objc_msgSend as unsafe extern "C" fn());
+            let options = if active { 0 } else { NOTIFY_OTHERS_ON_DEACTIVATION };
+            let mut error = ptr::null_mut();
+            let ok = set_active(
+                shared_session(),
+                sel(b"setActive:withOptions:error:\0"),
+                active as Bool,
+                options,
+                &mut error,
+            );
+            check_ns_error(ok, error, "could not change the activation of the audio session")
+        }
+    }
+}
+
+impl SessionCategory {
+    unsafe fn to_av(self) -> Id {
+        match self {
+            SessionCategory::Ambient => AVAudioSessionCategoryAmbient,
+            SessionCategory::SoloAmbient => AVAudioSessionCategorySoloAmbient,
+            SessionCategory::Playback => AVAudioSessionCategoryPlayback,
+            SessionCategory::Record => AVAudioSessionCategoryRecord,
+            SessionCategory::PlayAndRecord => AVAudioSessionCategoryPlayAndRecord,
+            SessionCategory::MultiRoute => AVAudioSessionCategoryMultiRoute,
+        }
+    }
+}
+
+impl SessionMode {
+    unsafe fn to_av(self) -> Id {
+        match self {
+            SessionMode::Default => AVAudioSessionModeDefault,
+            SessionMode::VoiceChat => AVAudioSessionModeVoiceChat,
+            SessionMode::VideoChat => AVAudioSessionModeVideoChat,
+            SessionMode::GameChat => AVAudioSessionModeGameChat,
+            SessionMode::Measurement => AVAudioSessionModeMeasurement,
+            SessionMode::MoviePlayback => AVAudioSessionModeMoviePlayback,
+            SessionMode::SpokenAudio => AVAudioSessionModeSpokenAudio,
+            SessionMode::VideoRecording => AVAudioSessionModeVideoRecording,
+        }
+    }
+}
+
+impl SessionOptions {
+    // The `AVAudioSessionCategoryOptions` bits.
+    fn to_av(self) -> usize {
+        let flags = [
+            (self.mix_with_others, 0x1),
+            (self.duck_others, 0x2),
+            (self.allow_bluetooth, 0x4),
+            (self.default_to_speaker, 0x8),
+            (self.allow_bluetooth_a2dp, 0x20),
+            (self.allow_air_play, 0x40),
+        ];
+        flags.iter().filter(|&&(set, _)| set).fold(0, |options, &(_, bit)| options | bit)
+    }
+}
+
+fn sel(name: &[u8]) -> Sel {
+    unsafe { sel_registerName(name.as_ptr() as *const _) }
+}
+
+// `[AVAudioSession sharedInstance]`.
+unsafe fn shared_session() -> Id {
+    let send: unsafe extern "C" fn(Id, Sel) -> Id =
+        mem::transmute(objc_msgSend as unsafe extern "C" fn());
+    let class = objc_getClass(b"AVAudioSession\0".as_ptr() as *const _);
+    send(class, sel(b"sharedInstance\0"))
+}
+
+// Turns the `NSError` of a failed call into a `BackendSpecificError`.
+unsafe fn check_ns_error(ok: Bool, error: Id, context: &str) -> Result<(), BackendSpecificError> {
+    if ok != 0 {
+        return Ok(());
+    }
+    if error.is_null() {
+        return Err(BackendSpecificError { description: context.to_string(), code: None });
+    }
+    let send_id: unsafe extern "C" fn(Id, Sel) -> Id =
+        mem::transmute(objc_msgSend as unsafe extern "C" fn());
+    let send_code: unsafe extern "C" fn(Id, Sel) -> isize =
+        mem::transmute(objc_msgSend as unsafe extern "C" fn());
+    let send_str: unsafe extern "C" fn(Id, Sel) -> *const c_char =
+        mem::transmute(objc_msgSend as unsafe extern "C" fn());
+    let message = send_id(error, sel(b"localizedDescription\0"));
+    let message = send_str(message, sel(b"UTF8String\0"));
+    let message = if message.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(message).to_string_lossy().into_owned()
+    };
+    let code = send_code(error, sel(b"code\0"));
+    Err(BackendSpecificError {
+        description: format!("{}: {}", context, message),
+        code: Some(code as i32),
+    })
+}
@@ -0,0 +1,127 @@
+use BackendSpecificError;
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr::{self, null};
+use super::coreaudio::sys::{
+    AudioDeviceID,
+    AudioObjectGetPropertyData,
+    AudioObjectPropertyAddress,
+    kAudioObjectPropertyElementMaster,
+    kAudioObjectPropertyScopeGlobal,
+};
+
+// `kAudioDevicePropertyIOThreadOSWorkgroup`, which the bindings predate.
+const IO_THREAD_OS_WORKGROUP: u32 = 0x6f73_7767; // 'oswg'
+
+// `RTLD_DEFAULT` on macOS.
+const RTLD_DEFAULT: *mut c_void = -2isize as *mut c_void;
+
+#[allow(non_camel_case_types)]
+type os_workgroup_t = *mut c_void;
+
+// `os_workgroup_join_token_s`, which is opaque.
+#[repr(C, align(4))]
+struct JoinToken {
+    _opaque: [u8; 40],
+}
+
+type JoinFn = unsafe extern "C" fn(os_workgroup_t, *mut JoinToken) -> c_int;
+type LeaveFn = unsafe extern "C" fn(os_workgroup_t, *mut JoinToken);
+
+extern "C" {
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn os_release(object: *mut c_void);
+}
+
+/// The workgroup of the real-time thread on which CoreAudio runs the callbacks of a device.
+///
+/// Threads that help produce the audio of the device, e.g. the worker threads of a parallel DSP
+/// graph, should join it so that the scheduler of macOS 11 and later treats them as real-time
+/// audio threads, and keeps them off the efficiency cores of Apple Silicon Macs. The data
+/// callbacks of CPAL already run on the thread of the workgroup.
+pub struct Workgroup {
+    workgroup: os_workgroup_t,
+    join: JoinFn,
+    leave: LeaveFn,
+}
+
+/// The membership of the current thread in a `Workgroup`, which the thread leaves on drop.
+pub struct WorkgroupMembership<'a> {
+    workgroup: &'a Workgroup,
+    // The token must be passed to `os_workgroup_leave` at the address it was joined with.
+    token: Box<JoinToken>,
+    // The thread must leave the workgroup itself.
+    _not_send: PhantomData<*const ()>,
+}
+
+// Workgroups can be joined from any thread.
+unsafe impl Send for Workgroup {}
+unsafe impl Sync for Workgroup {}
+
+impl Workgroup {
+    // The workgroup of the IO thread of `device_id`, or `None` before macOS 11.
+    pub(super) fn of_device(device_id: AudioDeviceID) -> Option<Workgroup> {
+        unsafe {
+            let join = dlsym(RTLD_DEFAULT, b"os_workgroup_join\0".as_ptr() as *const _);
+            let leave = dlsym(RTLD_DEFAULT, b"os_workgroup_leave\0".as_ptr() as *const _);
+            if join.is_null() || leave.is_null() {
+                return None;
+            }
+            let property_address = AudioObjectPropertyAddress {
+                mSelector: IO_THREAD_OS_WORKGROUP,
+                mScope: kAudioObjectPropertyScopeGlobal,
+                mElement: kAudioObjectPropertyElementMaster,
+            };
+            let mut workgroup: os_workgroup_t = ptr::null_mut();
+            let mut data_size = mem::size_of::<os_workgroup_t>() as u32;
+            let status = AudioObjectGetPropertyData(
+                device_id,
+                &property_address as *const _,
+                0,
+                null(),
+                &mut data_size as *mut _,
+                &mut workgroup as *mut _ as *mut _,
+            );
+            if status != 0 || workgroup.is_null() {
+                return None;
+            }
+            Some(Workgroup {
+                workgroup,
+                join: mem::transmute::<*mut c_void, JoinFn>(join),
+                leave: mem::transmute::<*mut c_void, LeaveFn>(leave),
+            })
+        }
+    }
+
+    /// Makes the current thread a member of the workgroup until the membership is dropped.
+    ///
+    /// Fails if the workgroup was cancelled, e.g. because the device stopped, or if the thread is
+    /// already a member of a workgroup.
+    pub fn join(&self) -> Result<WorkgroupMembership<'_>, BackendSpecificError> {
+        let mut token = Box::new(JoinToken { _opaque: [0; 40] });
+        let code = unsafe { (self.join)(self.workgroup, &mut *token) };
+        if code != 0 {
+            let description = format!("could not join the workgroup of the device: errno {}", code);
+            return Err(BackendSpecificError { description, code: Some(code) });
+        }
+        Ok(WorkgroupMembership {
+            workgroup: self,
+            token,
+            _not_send: PhantomData,
+        })
+    }
+}
+
+impl Drop for Workgroup {
+    fn drop(&mut self) {
+        // The property returns a retained workgroup.
+        unsafe { os_release(self.workgroup) }
+    }
+}
+
+impl<'a> Drop for WorkgroupMembership<'a> {
+    fn drop(&mut self) {
+        unsafe { (self.workgroup.leave)(self.workgroup.workgroup, &mut *self.token) }
+    }
+}
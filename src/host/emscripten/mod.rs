@@ -1,6 +1,8 @@
 use std::mem;
 use std::os::raw::c_void;
 use std::slice::from_raw_parts;
+use std::sync::Arc;
+use std::time::Instant;
 use stdweb;
 use stdweb::Reference;
 use stdweb::unstable::TryInto;
@@ -8,17 +10,26 @@ use stdweb::web::TypedArray;
 use stdweb::web::set_timeout;
 
 use BuildStreamError;
+use CallbackInfo;
 use DefaultFormatError;
 use DeviceNameError;
 use DevicesError;
 use Format;
 use PauseStreamError;
 use PlayStreamError;
+use RawDeviceHandle;
+use RawStreamHandle;
+use SampleRate;
 use SupportedFormatsError;
 use StreamData;
 use StreamError;
+use StreamOptions;
+use StreamPosition;
+use StreamStats;
 use SupportedFormat;
 use UnknownTypeOutputBuffer;
+use downmix::SurroundLayout;
+use stats::StreamMeter;
 use traits::{DeviceTrait, HostTrait, StreamTrait};
 
 // The emscripten backend currently works by instantiating an `AudioContext` object per `Stream`.
@@ -38,6 +49,21 @@ pub struct Device;
 pub struct Stream {
     // A reference to an `AudioContext` object.
     audio_ctxt_ref: Reference,
+    // Measurements of the data callback.
+    meter: Arc<StreamMeter>,
+    // The number of channels of the format that the stream was built with.
+    channels: u16,
+}
+
+/// The state of the `AudioContext` of a stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContextState {
+    /// The context produces no audio, either because the stream is paused or because the browser
+    /// has not allowed it to start yet, see `Stream::resume_on_gesture`.
+    Suspended,
+    Running,
+    /// The context was closed and can not be resumed.
+    Closed,
 }
 
 // Index within the `streams` array of the events loop.
@@ -61,6 +87,10 @@ impl Devices {
 }
 
 impl Device {
+    pub fn raw_handle(&self) -> Option<RawDeviceHandle> {
+        None
+    }
+
     #[inline]
     fn name(&self) -> Result<String, DeviceNameError> {
         Ok("Default Device".to_owned())
@@ -131,6 +161,9 @@ impl HostTrait for Host {
     }
 }
 
+// Surround streams have the channels in the default order.
+impl SurroundLayout for Device {}
+
 impl DeviceTrait for Device {
     type SupportedInputFormats = SupportedInputFormats;
     type SupportedOutputFormats = SupportedOutputFormats;
@@ -156,9 +189,10 @@ impl DeviceTrait for Device {
         Device::default_output_format(self)
     }
 
-    fn build_input_stream<D, E>(
+    fn build_input_stream_dyn<D, E>(
         &self,
         _format: &Format,
+        _options: &StreamOptions,
         _data_callback: D,
         _error_callback: E,
     ) -> Result<Self::Stream, BuildStreamError>
@@ -169,9 +203,10 @@ impl DeviceTrait for Device {
         unimplemented!()
     }
 
-    fn build_output_stream<D, E>(
+    fn build_output_stream_dyn<D, E>(
         &self,
-        _format: &Format,
+        format: &Format,
+        _options: &StreamOptions,
         data_callback: D,
         error_callback: E,
     ) -> Result<Self::Stream, BuildStreamError>
@@ -181,11 +216,21 @@ impl DeviceTrait for Device {
     {
         // Create the stream.
         let audio_ctxt_ref = js!(return new AudioContext()).into_reference().unwrap();
-        let stream = Stream { audio_ctxt_ref };
+        // TODO: use the format's sample rate once `audio_callback_fn` respects it.
+        let meter = Arc::new(StreamMeter::new(SampleRate(44100)));
+        let channels = format.channels;
+        let stream = Stream {
+            audio_ctxt_ref: audio_ctxt_ref.clone(),
+            meter: meter.clone(),
+            channels,
+        };
 
-        // Specify the callback.
-        let mut user_data = (self, data_callback, error_callback);
-        let user_data_ptr = &mut user_data as *mut (_, _, _);
+        // Specify the callback. The callback loop owns its own handle to the stream, which it
+        // never frees as the loop never ends, and the buffer that it passes to the data callback.
+        let handle = Stream { audio_ctxt_ref, meter, channels };
+        let buffer = vec![0.0f32; 44100 / 3 * channels as usize];
+        let user_data = Box::new((handle, data_callback, error_callback, buffer));
+        let user_data_ptr = Box::into_raw(user_data);
 
         // Use `set_timeout` to invoke a Rust callback repeatedly.
         //
@@ -199,40 +244,138 @@ impl DeviceTrait for Device {
     }
 }
 
+impl Stream {
+    /// The `AudioContext` of the stream is a JavaScript object, not a native handle.
+    pub fn raw_handle(&self) -> Option<RawStreamHandle> {
+        None
+    }
+
+    /// The state of the `AudioContext` of the stream.
+    pub fn context_state(&self) -> ContextState {
+        let audio_ctxt = &self.audio_ctxt_ref;
+        let state: String = js!(return @{audio_ctxt}.state;).try_into().unwrap();
+        match state.as_str() {
+            "running" => ContextState::Running,
+            "closed" => ContextState::Closed,
+            // Includes the "interrupted" state of Safari.
+            _ => ContextState::Suspended,
+        }
+    }
+
+    /// Resumes the `AudioContext` of the stream on the next click, key press or touch of the page.
+    ///
+    /// The autoplay policies of browsers keep a context that was not created or resumed by a user
+    /// gesture suspended, so `play` has no effect until the page was interacted with. Calling this
+    /// after `play` makes the stream start as soon as it is allowed to. Has no effect if the
+    /// context is already running, if the stream was paused or if the stream already waits for a
+    /// gesture. Pausing the stream stops waiting for a gesture.
+    pub fn resume_on_gesture(&self) {
+        let audio_ctxt = &self.audio_ctxt_ref;
+        js!(
+            var context = @{audio_ctxt};
+            if (context.state === "running" || context.state === "closed") {
+                return;
+            }
+            if (context.__cpal_paused || context.__cpal_stop_resume_on_gesture) {
+                return;
+            }
+            var events = ["click", "keydown", "touchend", "mousedown"];
+            var remove = function() {
+                events.forEach(function(event) {
+                    document.removeEventListener(event, resume, true);
+                });
+                delete context.__cpal_stop_resume_on_gesture;
+            };
+            var resume = function() {
+                if (context.state === "closed") {
+                    remove();
+                    return;
+                }
+                // Listeners stay registered until a gesture that the browser accepted.
+                Promise.resolve(context.resume()).then(function() {
+                    if (context.state === "running") {
+                        remove();
+                    }
+                });
+            };
+            events.forEach(function(event) {
+                document.addEventListener(event, resume, true);
+            });
+            // Shared with `pause`, which must not let a later gesture resume the stream.
+            context.__cpal_stop_resume_on_gesture = remove;
+        );
+    }
+}
+
 impl StreamTrait for Stream {
     fn play(&self) -> Result<(), PlayStreamError> {
         let audio_ctxt = &self.audio_ctxt_ref;
-        js!(@{audio_ctxt}.resume());
+        js!(
+            var context = @{audio_ctxt};
+            context.__cpal_paused = false;
+            context.resume();
+        );
         Ok(())
     }
 
     fn pause(&self) -> Result<(), PauseStreamError> {
         let audio_ctxt = &self.audio_ctxt_ref;
-        js!(@{audio_ctxt}.suspend());
+        js!(
+            var context = @{audio_ctxt};
+            context.__cpal_paused = true;
+            if (context.__cpal_stop_resume_on_gesture) {
+                context.__cpal_stop_resume_on_gesture();
+            }
+            context.suspend();
+        );
         Ok(())
     }
+
+    fn callback_load(&self) -> f32 {
+        self.meter.callback_load()
+    }
+
+    fn stats(&self) -> StreamStats {
+        self.meter.stats()
+    }
+
+    fn last_callback(&self) -> Option<Instant> {
+        self.meter.last_callback()
+    }
+
+    fn position(&self) -> Option<StreamPosition> {
+        // TODO: Use the `currentTime` of the audio context.
+        None
+    }
 }
 
-// The first argument of the callback function (a `void*`) is a casted pointer to `self`
-// and to the `callback` parameter that was passed to `run`.
+// The first argument of the callback function (a `void*`) is a casted pointer to a handle to the
+// stream, to the callbacks that were passed to `build_output_stream` and to the buffer passed to
+// the data callback.
 fn audio_callback_fn<D, E>(user_data_ptr: *mut c_void)
 where
     D: FnMut(StreamData) + Send + 'static,
     E: FnMut(StreamError) + Send + 'static,
 {
     unsafe {
-        let user_data_ptr2 = user_data_ptr as *mut (&Stream, D, E);
+        let user_data_ptr2 = user_data_ptr as *mut (Stream, D, E, Vec<f32>);
         let user_data = &mut *user_data_ptr2;
-        let (ref stream, ref mut data_cb, ref mut _err_cb) = user_data;
+        let (ref stream, ref mut data_cb, ref mut _err_cb, ref mut temporary_buffer) = user_data;
         let audio_ctxt = &stream.audio_ctxt_ref;
 
-        // TODO: We should be re-using a buffer.
-        let mut temporary_buffer = vec![0.0; 44100 * 2 / 3];
+        let num_channels = stream.channels as u32;
+        // The data callback expects a buffer of silence, as with the other hosts.
+        for sample in temporary_buffer.iter_mut() {
+            *sample = 0.0;
+        }
 
         {
-            let buffer = UnknownTypeOutputBuffer::F32(::OutputBuffer { buffer: &mut temporary_buffer });
-            let data = StreamData::Output { buffer: buffer };
-            data_cb(data);
+            let frames = temporary_buffer.len() / num_channels as usize;
+            let info = CallbackInfo::new(frames, false, Default::default());
+            let buffer = ::OutputBuffer::new(&mut temporary_buffer[..]);
+            let buffer = UnknownTypeOutputBuffer::F32(buffer);
+            let data = StreamData::Output { buffer: buffer, info };
+            stream.meter.measure(&info, || data_cb(data));
         }
 
         // TODO: directly use a TypedArray<f32> once this is supported by stdweb
@@ -246,7 +389,6 @@ where
             typed_array
         };
 
-        let num_channels = 2u32; // TODO: correct value
         debug_assert_eq!(temporary_buffer.len() % num_channels as usize, 0);
 
         js!(
@@ -1,11 +1,11 @@
-#[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"))]
+#[cfg(all(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"), feature = "alsa"))]
 pub(crate) mod alsa;
 #[cfg(all(windows, feature = "asio"))]
 pub(crate) mod asio;
-#[cfg(any(target_os = "macos", target_os = "ios"))]
+#[cfg(all(any(target_os = "macos", target_os = "ios"), feature = "coreaudio"))]
 pub(crate) mod coreaudio;
-#[cfg(target_os = "emscripten")]
+#[cfg(all(target_os = "emscripten", feature = "emscripten"))]
 pub(crate) mod emscripten;
 pub(crate) mod null;
-#[cfg(windows)]
+#[cfg(all(windows, feature = "wasapi"))]
 pub(crate) mod wasapi;
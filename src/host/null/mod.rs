@@ -5,10 +5,17 @@ use DeviceNameError;
 use Format;
 use PauseStreamError;
 use PlayStreamError;
+use RawDeviceHandle;
+use RawStreamHandle;
 use StreamData;
 use StreamError;
+use StreamOptions;
+use StreamPosition;
+use StreamStats;
 use SupportedFormatsError;
 use SupportedFormat;
+use std::time::Instant;
+use downmix::SurroundLayout;
 use traits::{DeviceTrait, HostTrait, StreamTrait};
 
 #[derive(Default)]
@@ -32,12 +39,29 @@ impl Host {
     }
 }
 
+impl Device {
+    #[allow(dead_code)]
+    pub fn raw_handle(&self) -> Option<RawDeviceHandle> {
+        None
+    }
+}
+
+impl Stream {
+    #[allow(dead_code)]
+    pub fn raw_handle(&self) -> Option<RawStreamHandle> {
+        None
+    }
+}
+
 impl Devices {
     pub fn new() -> Result<Self, DevicesError> {
         Ok(Devices)
     }
 }
 
+// Surround streams have the channels in the default order.
+impl SurroundLayout for Device {}
+
 impl DeviceTrait for Device {
     type SupportedInputFormats = SupportedInputFormats;
     type SupportedOutputFormats = SupportedOutputFormats;
@@ -68,13 +92,13 @@ impl DeviceTrait for Device {
         unimplemented!()
     }
 
-    fn build_input_stream<D, E>(&self, _format: &Format, _data_callback: D, _error_callback: E) -> Result<Self::Stream, BuildStreamError>
+    fn build_input_stream_dyn<D, E>(&self, _format: &Format, _options: &StreamOptions, _data_callback: D, _error_callback: E) -> Result<Self::Stream, BuildStreamError>
         where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
         unimplemented!()
     }
 
     /// Create an output stream.
-    fn build_output_stream<D, E>(&self, _format: &Format, _data_callback: D, _error_callback: E) -> Result<Self::Stream, BuildStreamError>
+    fn build_output_stream_dyn<D, E>(&self, _format: &Format, _options: &StreamOptions, _data_callback: D, _error_callback: E) -> Result<Self::Stream, BuildStreamError>
         where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static{
         unimplemented!()
     }
@@ -109,6 +133,22 @@ impl StreamTrait for Stream {
     fn pause(&self) -> Result<(), PauseStreamError> {
         unimplemented!()
     }
+
+    fn callback_load(&self) -> f32 {
+        0.0
+    }
+
+    fn stats(&self) -> StreamStats {
+        StreamStats::default()
+    }
+
+    fn last_callback(&self) -> Option<Instant> {
+        None
+    }
+
+    fn position(&self) -> Option<StreamPosition> {
+        None
+    }
 }
 
 impl Iterator for Devices {
@@ -1,30 +1,84 @@
 //! Handles COM initialization and cleanup.
+//!
+//! COM is initialized once per thread, the first time the thread calls into WASAPI, and
+//! uninitialized when the thread exits. The threads of the application are initialized in the
+//! apartment chosen with `set_com_apartment`, and applications that manage COM themselves can opt
+//! out with `ComApartment::Application`. The threads that CPAL spawns itself have no message
+//! loop, and are always initialized in the multithreaded apartment.
 
 use super::check_result;
 use std::ptr;
+use std::sync::atomic::{AtomicU8, Ordering};
 
+use super::winapi::shared::winerror::RPC_E_CHANGED_MODE;
 use super::winapi::um::combaseapi::{CoInitializeEx, CoUninitialize};
-use super::winapi::um::objbase::COINIT_MULTITHREADED;
+use super::winapi::um::objbase::{COINIT, COINIT_APARTMENTTHREADED, COINIT_MULTITHREADED};
+
+/// The COM apartment model in which CPAL initializes the threads of the application calling into
+/// WASAPI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComApartment {
+    /// The multithreaded apartment. This is the default.
+    MultiThreaded,
+    /// A single-threaded apartment, e.g. for applications whose UI thread must be in one.
+    SingleThreaded,
+    /// CPAL does not initialize COM. The application must initialize it on every thread calling
+    /// into WASAPI before doing so, e.g. when CPAL is embedded in a plugin whose host owns COM.
+    Application,
+}
+
+// The `ComApartment` in which threads are initialized, stored as its discriminant.
+static APARTMENT: AtomicU8 = AtomicU8::new(ComApartment::MultiThreaded as u8);
+
+/// Sets the apartment model in which CPAL initializes COM on the threads of the application
+/// calling into WASAPI.
+///
+/// This only affects threads that did not call into WASAPI yet. Threads on which the application
+/// already initialized COM keep the apartment model chosen by the application. The threads that
+/// CPAL spawns, e.g. the audio threads of streams, always use the multithreaded apartment.
+pub fn set_com_apartment(apartment: ComApartment) {
+    APARTMENT.store(apartment as u8, Ordering::Relaxed);
+}
 
 thread_local!(static COM_INITIALIZED: ComInitialized = {
-    unsafe {
-        // this call can fail if another library initialized COM in single-threaded mode
-        // handling this situation properly would make the API more annoying, so we just don't care
-        check_result(CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED)).unwrap();
-        ComInitialized(ptr::null_mut())
+    match APARTMENT.load(Ordering::Relaxed) {
+        x if x == ComApartment::SingleThreaded as u8 => initialize(COINIT_APARTMENTTHREADED),
+        x if x == ComApartment::Application as u8 => ComInitialized(None),
+        _ => initialize(COINIT_MULTITHREADED),
     }
 });
 
+// Initialized first on the threads that CPAL spawns, so that the later calls of
+// `com_initialized` on them find COM already initialized in the multithreaded apartment.
+thread_local!(static OWN_THREAD_COM_INITIALIZED: ComInitialized = {
+    initialize(COINIT_MULTITHREADED)
+});
+
+fn initialize(apartment: COINIT) -> ComInitialized {
+    unsafe {
+        let result = CoInitializeEx(ptr::null_mut(), apartment);
+        // COM was already initialized on this thread with another apartment model, which WASAPI
+        // works with too. That initialization must not be undone by us.
+        if result == RPC_E_CHANGED_MODE {
+            return ComInitialized(None);
+        }
+        check_result(result).unwrap();
+        ComInitialized(Some(ptr::null_mut()))
+    }
+}
+
 /// RAII object that guards the fact that COM is initialized.
 ///
 // We store a raw pointer because it's the only way at the moment to remove `Send`/`Sync` from the
-// object.
-struct ComInitialized(*mut ());
+// object. It is `None` if COM does not need to be uninitialized.
+struct ComInitialized(Option<*mut ()>);
 
 impl Drop for ComInitialized {
     #[inline]
     fn drop(&mut self) {
-        unsafe { CoUninitialize() };
+        if self.0.is_some() {
+            unsafe { CoUninitialize() };
+        }
     }
 }
 
@@ -33,3 +87,10 @@ impl Drop for ComInitialized {
 pub fn com_initialized() {
     COM_INITIALIZED.with(|_| {});
 }
+
+/// Ensures that COM is initialized in the multithreaded apartment in this thread, which CPAL
+/// spawned itself. Must be called before anything else on the thread calls into COM.
+#[inline]
+pub fn own_thread_com_initialized() {
+    OWN_THREAD_COM_INITIALIZED.with(|_| {});
+}
@@ -7,22 +7,31 @@ use std::ops::{Deref, DerefMut};
 use std::os::windows::ffi::OsStringExt;
 use std::ptr;
 use std::slice;
-use std::sync::{Arc, Mutex, MutexGuard, atomic::Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, atomic::Ordering};
+use std::time::Duration;
 
 use BackendSpecificError;
 use DefaultFormatError;
 use DeviceNameError;
 use DevicesError;
 use Format;
+use RawDeviceHandle;
 use SampleFormat;
 use SampleRate;
 use SupportedFormat;
+use StreamOptions;
 use SupportedFormatsError;
 use COMMON_SAMPLE_RATES;
+use downmix::SurroundLayout;
+use format_cache::{self, FormatCache};
+use stats::StreamMeter;
 
 use super::check_result;
 use super::check_result_backend_specific;
 use super::com;
+use super::properties::{self, ClientProperties};
+use super::session::SessionNotifications;
+use super::spatial::{self, SpatialAudio, SpatialBuffers, SpatialStream, SpatialStreamOptions};
 use super::winapi::ctypes::c_void;
 use super::winapi::shared::devpkey;
 use super::winapi::shared::guiddef::GUID;
@@ -37,7 +46,7 @@ use super::winapi::um::audioclient::{
     self, IAudioClient, IID_IAudioClient, AUDCLNT_E_DEVICE_INVALIDATED,
 };
 use super::winapi::um::audiosessiontypes::{
-    AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+    AUDCLNT_SHAREMODE, AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
 };
 use super::winapi::um::combaseapi::{
     CoCreateInstance, CoTaskMemFree, PropVariantClear, CLSCTX_ALL,
@@ -51,7 +60,7 @@ use super::winapi::um::winnt::LPWSTR;
 use super::winapi::um::winnt::WCHAR;
 
 use super::{
-    stream::{AudioClientFlow, Stream, StreamInner},
+    stream::{AudioClientFlow, AudioClock, Stream, StreamInner},
     winapi::um::synchapi,
 };
 use crate::{traits::DeviceTrait, BuildStreamError, StreamData, StreamError};
@@ -68,11 +77,17 @@ unsafe impl Sync for IAudioClientWrapper {}
 /// An opaque type that identifies an end point.
 pub struct Device {
     device: *mut IMMDevice,
+    // Set on the audio clients of the streams before they are initialized.
+    client_properties: Option<ClientProperties>,
+    wake_mode: WakeMode,
     /// We cache an uninitialized `IAudioClient` so that we can call functions from it without
     /// having to create/destroy audio clients all the time.
     future_audio_client: Arc<Mutex<Option<IAudioClientWrapper>>>, // TODO: add NonZero around the ptr
 }
 
+// Surround streams have the channels in the default order.
+impl SurroundLayout for Device {}
+
 impl DeviceTrait for Device {
     type SupportedInputFormats = SupportedInputFormats;
     type SupportedOutputFormats = SupportedOutputFormats;
@@ -102,9 +117,10 @@ impl DeviceTrait for Device {
         Device::default_output_format(self)
     }
 
-    fn build_input_stream<D, E>(
+    fn build_input_stream_dyn<D, E>(
         &self,
         format: &Format,
+        options: &StreamOptions,
         data_callback: D,
         error_callback: E,
     ) -> Result<Self::Stream, BuildStreamError>
@@ -112,16 +128,21 @@ impl DeviceTrait for Device {
         D: FnMut(StreamData) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
-        Ok(Stream::new(
-            self.build_input_stream_inner(format)?,
+        let stream_inner = self
+            .build_input_stream_inner(format, options)
+            .map_err(|err| self.invalidate_stale_formats(err))?;
+        Stream::new(
+            stream_inner,
+            options,
             data_callback,
             error_callback,
-        ))
+        )
     }
 
-    fn build_output_stream<D, E>(
+    fn build_output_stream_dyn<D, E>(
         &self,
         format: &Format,
+        options: &StreamOptions,
         data_callback: D,
         error_callback: E,
     ) -> Result<Self::Stream, BuildStreamError>
@@ -129,11 +150,78 @@ impl DeviceTrait for Device {
         D: FnMut(StreamData) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
-        Ok(Stream::new(
-            self.build_output_stream_inner(format)?,
+        let stream_inner = self
+            .build_output_stream_inner(format, options)
+            .map_err(|err| self.invalidate_stale_formats(err))?;
+        Stream::new(
+            stream_inner,
+            options,
             data_callback,
             error_callback,
-        ))
+        )
+    }
+}
+
+// The duration of the buffer requested in power-saving mode, in units of 100 nanoseconds.
+const POWER_SAVING_BUFFER_DURATION: i64 = 2_000_000;
+
+// The duration of the buffer requested for polled streams, in units of 100 nanoseconds.
+const POLLING_BUFFER_DURATION: i64 = 400_000;
+
+/// How the audio thread of a stream learns that the device needs or has data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WakeMode {
+    /// WASAPI signals an event once per engine period (`AUDCLNT_STREAMFLAGS_EVENTCALLBACK`).
+    Event,
+    /// The audio thread polls the device on a timer, four times per buffer. This works with
+    /// drivers that never signal the event, at the cost of latency and wakeups.
+    Polling,
+    /// Events, falling back to polling if the driver rejects event-driven streams, and waking up
+    /// the audio thread whenever an event is a buffer late, as happens with some virtual devices
+    /// and in remote desktop sessions. The default.
+    Auto,
+}
+
+impl Default for WakeMode {
+    #[inline]
+    fn default() -> Self {
+        WakeMode::Auto
+    }
+}
+
+// The stream flags and the buffer duration with which to initialize an audio client.
+//
+// In shared mode, WASAPI signals the event of a stream once per engine period, which is about 10
+// milliseconds regardless of the size of the buffer. In power-saving mode, a large buffer is
+// requested without an event, and the audio thread only wakes up when half of it has been played.
+fn initialize_params(options: &StreamOptions, polling: bool) -> (DWORD, i64) {
+    if options.power_saving {
+        (0, POWER_SAVING_BUFFER_DURATION)
+    } else if polling {
+        (0, POLLING_BUFFER_DURATION)
+    } else {
+        (AUDCLNT_STREAMFLAGS_EVENTCALLBACK, 0)
+    }
+}
+
+// The interval at which the audio thread polls an audio client, or at which it gives up waiting
+// for the event of a client that has one.
+fn poll_interval(
+    options: &StreamOptions,
+    wake_mode: WakeMode,
+    polling: bool,
+    max_frames_in_buffer: u32,
+    sample_rate: SampleRate,
+) -> Option<Duration> {
+    let buffer = max_frames_in_buffer as f64 / sample_rate.0 as f64;
+    if options.power_saving {
+        Some(Duration::from_secs_f64(buffer / 2.0))
+    } else if polling {
+        Some(Duration::from_secs_f64(buffer / 4.0))
+    } else if wake_mode == WakeMode::Auto {
+        Some(Duration::from_secs_f64(buffer))
+    } else {
+        None
     }
 }
 
@@ -200,7 +288,7 @@ impl DerefMut for WaveFormat {
 }
 
 unsafe fn immendpoint_from_immdevice(device: *const IMMDevice) -> *mut IMMEndpoint {
-    let mut endpoint: *mut IMMEndpoint = mem::uninitialized();
+    let mut endpoint: *mut IMMEndpoint = ptr::null_mut();
     check_result(
         (*device).QueryInterface(&IMMEndpoint::uuidof(), &mut endpoint as *mut _ as *mut _),
     )
@@ -209,12 +297,27 @@ unsafe fn immendpoint_from_immdevice(device: *const IMMDevice) -> *mut IMMEndpoi
 }
 
 unsafe fn data_flow_from_immendpoint(endpoint: *const IMMEndpoint) -> EDataFlow {
-    let mut data_flow = mem::uninitialized();
+    let mut data_flow = 0;
     check_result((*endpoint).GetDataFlow(&mut data_flow))
         .expect("could not get endpoint data_flow");
     data_flow
 }
 
+// Converts an error returned while activating or initializing an audio client.
+pub(super) fn build_stream_error(err: IoError) -> BuildStreamError {
+    match err.raw_os_error() {
+        Some(AUDCLNT_E_DEVICE_INVALIDATED) => BuildStreamError::DeviceNotAvailable,
+        Some(audioclient::AUDCLNT_E_DEVICE_IN_USE) => BuildStreamError::DeviceInUse,
+        Some(audioclient::AUDCLNT_E_UNSUPPORTED_FORMAT) => BuildStreamError::FormatNotSupported,
+        // Returned when access to the microphone is disabled in the privacy settings.
+        Some(winerror::E_ACCESSDENIED) => BuildStreamError::AccessDenied,
+        _ => {
+            let description = format!("{}", err);
+            BackendSpecificError { description, code: err.raw_os_error() }.into()
+        }
+    }
+}
+
 // Given the audio client and format, returns whether or not the format is supported.
 pub unsafe fn is_format_supported(
     client: *const IAudioClient,
@@ -223,7 +326,7 @@ pub unsafe fn is_format_supported(
     /*
     // `IsFormatSupported` checks whether the format is supported and fills
     // a `WAVEFORMATEX`
-    let mut dummy_fmt_ptr: *mut mmreg::WAVEFORMATEX = mem::uninitialized();
+    let mut dummy_fmt_ptr: *mut mmreg::WAVEFORMATEX = ptr::null_mut();
     let hresult =
         (*audio_client)
             .IsFormatSupported(share_mode, &format_attempt.Format, &mut dummy_fmt_ptr);
@@ -294,7 +397,7 @@ pub unsafe fn is_format_supported(
 }
 
 // Get a cpal Format from a WAVEFORMATEX.
-unsafe fn format_from_waveformatex_ptr(
+pub(super) unsafe fn format_from_waveformatex_ptr(
     waveformatex_ptr: *const mmreg::WAVEFORMATEX,
 ) -> Option<Format> {
     fn cmp_guid(a: &GUID, b: &GUID) -> bool {
@@ -328,10 +431,82 @@ unsafe fn format_from_waveformatex_ptr(
     Some(format)
 }
 
+// `IMMDevice` is free-threaded, and the audio client is only accessed through its mutex.
 unsafe impl Send for Device {}
 unsafe impl Sync for Device {}
 
 impl Device {
+    pub fn raw_handle(&self) -> Option<RawDeviceHandle> {
+        Some(RawDeviceHandle::Wasapi { device: self.device as *mut _ })
+    }
+
+    /// The same device, with its streams initialized with the `AudioClientProperties`
+    /// `properties`.
+    ///
+    /// Building a stream fails with `BuildStreamError::FormatNotSupported` before Windows 8, and
+    /// with an error of the endpoint if it does not support the properties.
+    pub fn with_client_properties(&self, properties: ClientProperties) -> Device {
+        let mut device = self.clone();
+        device.client_properties = Some(properties);
+        device
+    }
+
+    /// The properties selected with `with_client_properties`, if any.
+    pub fn client_properties(&self) -> Option<ClientProperties> {
+        self.client_properties
+    }
+
+    /// The same device, with the audio threads of its streams woken up according to `wake_mode`.
+    ///
+    /// Power-saving streams are always polled, see `StreamOptions::power_saving`.
+    pub fn with_wake_mode(&self, wake_mode: WakeMode) -> Device {
+        let mut device = self.clone();
+        device.wake_mode = wake_mode;
+        device
+    }
+
+    /// The wake mode selected with `with_wake_mode`, `WakeMode::Auto` by default.
+    pub fn wake_mode(&self) -> WakeMode {
+        self.wake_mode
+    }
+
+    /// The spatial audio format engaged on the endpoint, or `None` if spatial audio is off or the
+    /// device is not an output device.
+    ///
+    /// Windows Sonic, Dolby Atmos and DTS:X are enabled per endpoint in the sound settings, and
+    /// may be turned on or off while streams run.
+    pub fn spatial_audio(&self) -> Result<Option<SpatialAudio>, BackendSpecificError> {
+        let format = match self.default_output_format() {
+            Ok(format) => format,
+            Err(DefaultFormatError::BackendSpecific { err }) => return Err(err),
+            Err(_) => return Ok(None),
+        };
+        spatial::spatial_audio(self.device, format.channels).map_err(|err| match err {
+            StreamError::BackendSpecific { err } => err,
+            err => BackendSpecificError { description: err.to_string(), code: None },
+        })
+    }
+
+    /// Builds an output stream of audio objects through `ISpatialAudioClient`.
+    ///
+    /// The stream renders the channels of the bed of the endpoint as static objects, and up to
+    /// `options.max_dynamic_objects` dynamic objects positioned by the data callback. It is only
+    /// available while a spatial audio format such as Windows Sonic or Dolby Atmos is active on the
+    /// endpoint; otherwise this fails with `BuildStreamError::FormatNotSupported` and applications
+    /// should fall back to a channel-based stream from `build_output_stream`.
+    pub fn build_spatial_output_stream<D, E>(
+        &self,
+        options: &SpatialStreamOptions,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<SpatialStream, BuildStreamError>
+    where
+        D: FnMut(&mut SpatialBuffers) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        SpatialStream::new(self.device, options, data_callback, error_callback)
+    }
+
     pub fn name(&self) -> Result<String, DeviceNameError> {
         unsafe {
             // Open the device's property store.
@@ -345,7 +520,7 @@ impl Device {
                 &mut property_value,
             )) {
                 let description = format!("failed to retrieve name from property store: {}", err);
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError { description, code: err.raw_os_error() };
                 return Err(err.into());
             }
 
@@ -355,7 +530,7 @@ impl Device {
                     "property store produced invalid data: {:?}",
                     property_value.vt
                 );
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError { description, code: None };
                 return Err(err.into());
             }
             let ptr_utf16 = *(&property_value.data as *const _ as *const (*const u16));
@@ -381,10 +556,28 @@ impl Device {
         }
     }
 
+    // The identifier of the endpoint, which stays the same across enumerations.
+    fn id(&self) -> Result<String, IoError> {
+        unsafe {
+            let mut id: LPWSTR = ptr::null_mut();
+            check_result((*self.device).GetId(&mut id))?;
+            let mut len = 0;
+            while *id.offset(len) != 0 {
+                len += 1;
+            }
+            let id_slice = slice::from_raw_parts(id, len as usize);
+            let id_string = OsString::from_wide(id_slice).to_string_lossy().into_owned();
+            CoTaskMemFree(id as *mut _);
+            Ok(id_string)
+        }
+    }
+
     #[inline]
     fn from_immdevice(device: *mut IMMDevice) -> Self {
         Device {
             device,
+            client_properties: None,
+            wake_mode: WakeMode::default(),
             future_audio_client: Arc::new(Mutex::new(None)),
         }
     }
@@ -399,7 +592,7 @@ impl Device {
         }
 
         let audio_client: *mut IAudioClient = unsafe {
-            let mut audio_client = mem::uninitialized();
+            let mut audio_client = ptr::null_mut();
             let hresult = (*self.device).Activate(
                 &IID_IAudioClient,
                 CLSCTX_ALL,
@@ -418,6 +611,71 @@ impl Device {
         Ok(lock)
     }
 
+    // Returns an uninitialized `IAudioClient` with the client properties of the device set.
+    unsafe fn build_configured_audioclient(&self) -> Result<*mut IAudioClient, BuildStreamError> {
+        let audio_client = self.build_audioclient().map_err(build_stream_error)?;
+        if let Some(ref properties) = self.client_properties {
+            if let Err(err) = properties::set(audio_client, properties) {
+                (*audio_client).Release();
+                return Err(err);
+            }
+        }
+        Ok(audio_client)
+    }
+
+    // Initializes `*audio_client` with `format`, returning whether the stream is polled rather
+    // than driven by an event. The client is released on failure.
+    //
+    // In `WakeMode::Auto`, a client whose driver rejects event-driven streams is replaced with a
+    // new, polled one.
+    unsafe fn initialize_audioclient(
+        &self,
+        audio_client: &mut *mut IAudioClient,
+        share_mode: AUDCLNT_SHAREMODE,
+        format: &mmreg::WAVEFORMATEX,
+        options: &StreamOptions,
+    ) -> Result<bool, BuildStreamError> {
+        let initialize = |audio_client: *mut IAudioClient, polling| {
+            let (stream_flags, buffer_duration) = initialize_params(options, polling);
+            let hresult = (*audio_client).Initialize(
+                share_mode,
+                stream_flags,
+                buffer_duration,
+                0,
+                format,
+                ptr::null(),
+            );
+            check_result(hresult)
+        };
+        // Power-saving streams are polled too.
+        let polling = self.wake_mode == WakeMode::Polling || options.power_saving;
+        let err = match initialize(*audio_client, polling) {
+            Ok(()) => return Ok(polling),
+            Err(err) => err,
+        };
+        (**audio_client).Release();
+        // Only the errors of drivers that do not support event-driven streams are worth a retry.
+        let event_rejected = match err.raw_os_error() {
+            Some(audioclient::AUDCLNT_E_INVALID_STREAM_FLAG)
+            | Some(audioclient::AUDCLNT_E_EVENTHANDLE_NOT_EXPECTED)
+            | Some(winerror::E_NOTIMPL) => !polling,
+            _ => false,
+        };
+        if !event_rejected || self.wake_mode != WakeMode::Auto {
+            return Err(build_stream_error(err));
+        }
+        // The error of the event-driven stream is the one that explains the failure.
+        *audio_client = match self.build_configured_audioclient() {
+            Ok(audio_client) => audio_client,
+            Err(_) => return Err(build_stream_error(err)),
+        };
+        if initialize(*audio_client, true).is_err() {
+            (**audio_client).Release();
+            return Err(build_stream_error(err));
+        }
+        Ok(true)
+    }
+
     /// Returns an uninitialized `IAudioClient`.
     #[inline]
     pub(crate) fn build_audioclient(&self) -> Result<*mut IAudioClient, IoError> {
@@ -438,7 +696,7 @@ impl Device {
     // number of channels seems to be supported. Any more or less returns an invalid
     // parameter error. Thus we just assume that the default number of channels is the only
     // number supported.
-    fn supported_formats(&self) -> Result<SupportedInputFormats, SupportedFormatsError> {
+    fn supported_formats(&self) -> Result<Vec<SupportedFormat>, SupportedFormatsError> {
         // initializing COM because we call `CoTaskMemFree` to release the format.
         com::com_initialized();
 
@@ -450,7 +708,7 @@ impl Device {
             }
             Err(e) => {
                 let description = format!("{}", e);
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError { description, code: e.raw_os_error() };
                 return Err(err.into());
             }
         };
@@ -458,7 +716,8 @@ impl Device {
 
         unsafe {
             // Retrieve the pointer to the default WAVEFORMATEX.
-            let mut default_waveformatex_ptr = WaveFormatExPtr(mem::uninitialized());
+            // The pointer must be null until it is filled, as it is freed on drop.
+            let mut default_waveformatex_ptr = WaveFormatExPtr(ptr::null_mut());
             match check_result((*client).GetMixFormat(&mut default_waveformatex_ptr.0)) {
                 Ok(()) => (),
                 Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
@@ -466,7 +725,7 @@ impl Device {
                 }
                 Err(e) => {
                     let description = format!("{}", e);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError { description, code: e.raw_os_error() };
                     return Err(err.into());
                 }
             };
@@ -482,7 +741,7 @@ impl Device {
                 match WaveFormat::copy_from_waveformatex_ptr(default_waveformatex_ptr.0) {
                     Some(f) => f,
                     // If the format is neither EX or EXTENSIBLE we don't know how to work with it.
-                    None => return Ok(vec![].into_iter()),
+                    None => return Ok(vec![]),
                 }
             };
 
@@ -520,7 +779,7 @@ impl Device {
                 None => {
                     let description =
                         "could not create a `cpal::Format` from a `WAVEFORMATEX`".to_string();
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError { description, code: None };
                     return Err(err.into());
                 }
             };
@@ -529,13 +788,60 @@ impl Device {
                 format.sample_rate = SampleRate(rate as _);
                 supported_formats.push(SupportedFormat::from(format.clone()));
             }
-            Ok(supported_formats.into_iter())
+            Ok(supported_formats)
         }
     }
 
+    // The supported formats, queried once per enumeration of the devices.
+    fn cached_supported_formats(&self) -> Result<SupportedInputFormats, SupportedFormatsError> {
+        let formats = match self.id() {
+            Ok(id) => {
+                let device = self.clone();
+                // The query runs on a thread of the cache.
+                FORMATS.get_or_query(&id, move || {
+                    com::own_thread_com_initialized();
+                    device.supported_formats()
+                })?
+            },
+            Err(_) => self.supported_formats()?,
+        };
+        Ok(formats.into_iter())
+    }
+
+    // The error for a `format` rejected by `IsFormatSupported`, naming the field at fault if the
+    // supported formats rule it out.
+    fn unsupported_format_error(&self, format: &Format) -> BuildStreamError {
+        let formats: Vec<_> = match self.cached_supported_formats() {
+            Ok(formats) => formats.collect(),
+            Err(_) => return BuildStreamError::FormatNotSupported,
+        };
+        match format_cache::check_format(format, &formats) {
+            Ok(()) => BuildStreamError::FormatNotSupported,
+            Err(err) => err,
+        }
+    }
+
+    // Forgets the cached formats of the device if `err` shows that they may be stale, i.e. if the
+    // device is no longer available or its mix format changed.
+    fn invalidate_stale_formats(&self, err: BuildStreamError) -> BuildStreamError {
+        match err {
+            BuildStreamError::DeviceNotAvailable
+            | BuildStreamError::FormatNotSupported
+            | BuildStreamError::InvalidChannelCount
+            | BuildStreamError::SampleFormatNotSupported
+            | BuildStreamError::SampleRateNotSupported => {
+                if let Ok(id) = self.id() {
+                    FORMATS.invalidate(&id);
+                }
+            }
+            _ => (),
+        }
+        err
+    }
+
     pub fn supported_input_formats(&self) -> Result<SupportedInputFormats, SupportedFormatsError> {
         if self.data_flow() == eCapture {
-            self.supported_formats()
+            self.cached_supported_formats()
         // If it's an output device, assume no input formats.
         } else {
             Ok(vec![].into_iter())
@@ -546,7 +852,7 @@ impl Device {
         &self,
     ) -> Result<SupportedOutputFormats, SupportedFormatsError> {
         if self.data_flow() == eRender {
-            self.supported_formats()
+            self.cached_supported_formats()
         // If it's an input device, assume no output formats.
         } else {
             Ok(vec![].into_iter())
@@ -568,21 +874,21 @@ impl Device {
             }
             Err(e) => {
                 let description = format!("{}", e);
-                let err = BackendSpecificError { description };
+                let err = BackendSpecificError { description, code: e.raw_os_error() };
                 return Err(err.into());
             }
         };
         let client = lock.unwrap().0;
 
         unsafe {
-            let mut format_ptr = WaveFormatExPtr(mem::uninitialized());
+            let mut format_ptr = WaveFormatExPtr(ptr::null_mut());
             match check_result((*client).GetMixFormat(&mut format_ptr.0)) {
                 Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
                     return Err(DefaultFormatError::DeviceNotAvailable);
                 }
                 Err(e) => {
                     let description = format!("{}", e);
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError { description, code: e.raw_os_error() };
                     return Err(err.into());
                 }
                 Ok(()) => (),
@@ -618,6 +924,7 @@ impl Device {
     pub(crate) fn build_input_stream_inner(
         &self,
         format: &Format,
+        options: &StreamOptions,
     ) -> Result<StreamInner, BuildStreamError> {
         unsafe {
             // Making sure that COM is initialized.
@@ -625,19 +932,10 @@ impl Device {
             com::com_initialized();
 
             // Obtaining a `IAudioClient`.
-            let audio_client = match self.build_audioclient() {
-                Ok(client) => client,
-                Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
-                    return Err(BuildStreamError::DeviceNotAvailable)
-                }
-                Err(e) => {
-                    let description = format!("{}", e);
-                    let err = BackendSpecificError { description };
-                    return Err(err.into());
-                }
-            };
+            let mut audio_client = self.build_configured_audioclient()?;
 
             // Computing the format and initializing the device.
+            let polling;
             let waveformatex = {
                 let format_attempt = format_to_waveformatextensible(format)
                     .ok_or(BuildStreamError::FormatNotSupported)?;
@@ -645,124 +943,38 @@ impl Device {
 
                 // Ensure the format is supported.
                 match super::device::is_format_supported(audio_client, &format_attempt.Format) {
-                    Ok(false) => return Err(BuildStreamError::FormatNotSupported),
+                    Ok(false) => return Err(self.unsupported_format_error(format)),
                     Err(_) => return Err(BuildStreamError::DeviceNotAvailable),
                     _ => (),
                 }
 
                 // finally initializing the audio client
-                let hresult = (*audio_client).Initialize(
+                polling = self.initialize_audioclient(
+                    &mut audio_client,
                     share_mode,
-                    AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
-                    0,
-                    0,
                     &format_attempt.Format,
-                    ptr::null(),
-                );
-                match check_result(hresult) {
-                    Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
-                        (*audio_client).Release();
-                        return Err(BuildStreamError::DeviceNotAvailable);
-                    }
-                    Err(e) => {
-                        (*audio_client).Release();
-                        let description = format!("{}", e);
-                        let err = BackendSpecificError { description };
-                        return Err(err.into());
-                    }
-                    Ok(()) => (),
-                };
+                    options,
+                )?;
 
                 format_attempt.Format
             };
 
-            // obtaining the size of the samples buffer in number of frames
-            let max_frames_in_buffer = {
-                let mut max_frames_in_buffer = mem::uninitialized();
-                let hresult = (*audio_client).GetBufferSize(&mut max_frames_in_buffer);
-
-                match check_result(hresult) {
-                    Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
-                        (*audio_client).Release();
-                        return Err(BuildStreamError::DeviceNotAvailable);
-                    }
-                    Err(e) => {
-                        (*audio_client).Release();
-                        let description = format!("{}", e);
-                        let err = BackendSpecificError { description };
-                        return Err(err.into());
-                    }
-                    Ok(()) => (),
-                };
-
-                max_frames_in_buffer
-            };
-
-            // Creating the event that will be signalled whenever we need to submit some samples.
-            let event = {
-                let event = synchapi::CreateEventA(ptr::null_mut(), 0, 0, ptr::null());
-                if event.is_null() {
-                    (*audio_client).Release();
-                    let description = "failed to create event".to_string();
-                    let err = BackendSpecificError { description };
-                    return Err(err.into());
-                }
-
-                if let Err(e) = check_result((*audio_client).SetEventHandle(event)) {
-                    (*audio_client).Release();
-                    let description = format!("failed to call SetEventHandle: {}", e);
-                    let err = BackendSpecificError { description };
-                    return Err(err.into());
-                }
-
-                event
-            };
-
-            // Building a `IAudioCaptureClient` that will be used to read captured samples.
-            let capture_client = {
-                let mut capture_client: *mut audioclient::IAudioCaptureClient =
-                    mem::uninitialized();
-                let hresult = (*audio_client).GetService(
-                    &audioclient::IID_IAudioCaptureClient,
-                    &mut capture_client as *mut *mut audioclient::IAudioCaptureClient as *mut _,
-                );
-
-                match check_result(hresult) {
-                    Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
-                        (*audio_client).Release();
-                        return Err(BuildStreamError::DeviceNotAvailable);
-                    }
-                    Err(e) => {
-                        (*audio_client).Release();
-                        let description = format!("failed to build capture client: {}", e);
-                        let err = BackendSpecificError { description };
-                        return Err(err.into());
-                    }
-                    Ok(()) => (),
-                };
-
-                &mut *capture_client
-            };
-
-            // Once we built the `StreamInner`, we add a command that will be picked up by the
-            // `run()` method and added to the `RunContext`.
-            let client_flow = AudioClientFlow::Capture { capture_client };
-
-            Ok(StreamInner {
+            let wake_mode = self.wake_mode;
+            build_capture_stream_inner(
+                self.device,
                 audio_client,
-                client_flow,
-                event,
-                playing: false,
-                max_frames_in_buffer,
-                bytes_per_frame: waveformatex.nBlockAlign,
-                sample_format: format.data_type,
-            })
+                &waveformatex,
+                format,
+                |frames| poll_interval(options, wake_mode, polling, frames, format.sample_rate),
+                polling,
+            )
         }
     }
 
     pub(crate) fn build_output_stream_inner(
         &self,
         format: &Format,
+        options: &StreamOptions,
     ) -> Result<StreamInner, BuildStreamError> {
         unsafe {
             // Making sure that COM is initialized.
@@ -770,19 +982,10 @@ impl Device {
             com::com_initialized();
 
             // Obtaining a `IAudioClient`.
-            let audio_client = match self.build_audioclient() {
-                Ok(client) => client,
-                Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
-                    return Err(BuildStreamError::DeviceNotAvailable)
-                }
-                Err(e) => {
-                    let description = format!("{}", e);
-                    let err = BackendSpecificError { description };
-                    return Err(err.into());
-                }
-            };
+            let mut audio_client = self.build_configured_audioclient()?;
 
             // Computing the format and initializing the device.
+            let polling;
             let waveformatex = {
                 let format_attempt = format_to_waveformatextensible(format)
                     .ok_or(BuildStreamError::FormatNotSupported)?;
@@ -790,33 +993,18 @@ impl Device {
 
                 // Ensure the format is supported.
                 match super::device::is_format_supported(audio_client, &format_attempt.Format) {
-                    Ok(false) => return Err(BuildStreamError::FormatNotSupported),
+                    Ok(false) => return Err(self.unsupported_format_error(format)),
                     Err(_) => return Err(BuildStreamError::DeviceNotAvailable),
                     _ => (),
                 }
 
                 // finally initializing the audio client
-                let hresult = (*audio_client).Initialize(
+                polling = self.initialize_audioclient(
+                    &mut audio_client,
                     share_mode,
-                    AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
-                    0,
-                    0,
                     &format_attempt.Format,
-                    ptr::null(),
-                );
-                match check_result(hresult) {
-                    Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
-                        (*audio_client).Release();
-                        return Err(BuildStreamError::DeviceNotAvailable);
-                    }
-                    Err(e) => {
-                        (*audio_client).Release();
-                        let description = format!("{}", e);
-                        let err = BackendSpecificError { description };
-                        return Err(err.into());
-                    }
-                    Ok(()) => (),
-                };
+                    options,
+                )?;
 
                 format_attempt.Format
             };
@@ -827,23 +1015,26 @@ impl Device {
                 if event.is_null() {
                     (*audio_client).Release();
                     let description = "failed to create event".to_string();
-                    let err = BackendSpecificError { description };
+                    let err = BackendSpecificError { description, code: None };
                     return Err(err.into());
                 }
 
-                if let Err(e) = check_result((*audio_client).SetEventHandle(event)) {
-                    (*audio_client).Release();
-                    let description = format!("failed to call SetEventHandle: {}", e);
-                    let err = BackendSpecificError { description };
-                    return Err(err.into());
-                };
+                // A polled client has no event.
+                if !polling {
+                    if let Err(e) = check_result((*audio_client).SetEventHandle(event)) {
+                        (*audio_client).Release();
+                        let description = format!("failed to call SetEventHandle: {}", e);
+                        let err = BackendSpecificError { description, code: e.raw_os_error() };
+                        return Err(err.into());
+                    }
+                }
 
                 event
             };
 
             // obtaining the size of the samples buffer in number of frames
             let max_frames_in_buffer = {
-                let mut max_frames_in_buffer = mem::uninitialized();
+                let mut max_frames_in_buffer = 0;
                 let hresult = (*audio_client).GetBufferSize(&mut max_frames_in_buffer);
 
                 match check_result(hresult) {
@@ -854,7 +1045,7 @@ impl Device {
                     Err(e) => {
                         (*audio_client).Release();
                         let description = format!("failed to obtain buffer size: {}", e);
-                        let err = BackendSpecificError { description };
+                        let err = BackendSpecificError { description, code: e.raw_os_error() };
                         return Err(err.into());
                     }
                     Ok(()) => (),
@@ -865,7 +1056,7 @@ impl Device {
 
             // Building a `IAudioRenderClient` that will be used to fill the samples buffer.
             let render_client = {
-                let mut render_client: *mut audioclient::IAudioRenderClient = mem::uninitialized();
+                let mut render_client: *mut audioclient::IAudioRenderClient = ptr::null_mut();
                 let hresult = (*audio_client).GetService(
                     &audioclient::IID_IAudioRenderClient,
                     &mut render_client as *mut *mut audioclient::IAudioRenderClient as *mut _,
@@ -879,7 +1070,7 @@ impl Device {
                     Err(e) => {
                         (*audio_client).Release();
                         let description = format!("failed to build render client: {}", e);
-                        let err = BackendSpecificError { description };
+                        let err = BackendSpecificError { description, code: e.raw_os_error() };
                         return Err(err.into());
                     }
                     Ok(()) => (),
@@ -892,7 +1083,9 @@ impl Device {
             // `run()` method and added to the `RunContext`.
             let client_flow = AudioClientFlow::Render { render_client };
 
+            (*self.device).AddRef();
             Ok(StreamInner {
+                device: self.device,
                 audio_client,
                 client_flow,
                 event,
@@ -900,11 +1093,130 @@ impl Device {
                 max_frames_in_buffer,
                 bytes_per_frame: waveformatex.nBlockAlign,
                 sample_format: format.data_type,
+                meter: Arc::new(StreamMeter::new(format.sample_rate)),
+                clock: AudioClock::new(audio_client, format.sample_rate),
+                poll_interval: poll_interval(
+                    options,
+                    self.wake_mode,
+                    polling,
+                    max_frames_in_buffer,
+                    format.sample_rate,
+                ),
+                session: SessionNotifications::register(audio_client, event),
             })
         }
     }
 }
 
+// Builds a capture stream on `audio_client`, which was initialized with `waveformatex`. The client
+// is released on failure.
+//
+// `device` may be null for clients that belong to no endpoint, and `poll_interval` gives the poll
+// interval of the stream for the size of its buffer.
+pub(super) unsafe fn build_capture_stream_inner<P>(
+    device: *mut IMMDevice,
+    audio_client: *mut IAudioClient,
+    waveformatex: &mmreg::WAVEFORMATEX,
+    format: &Format,
+    poll_interval: P,
+    polling: bool,
+) -> Result<StreamInner, BuildStreamError>
+where
+    P: FnOnce(u32) -> Option<Duration>,
+{
+    // obtaining the size of the samples buffer in number of frames
+    let max_frames_in_buffer = {
+        let mut max_frames_in_buffer = 0;
+        let hresult = (*audio_client).GetBufferSize(&mut max_frames_in_buffer);
+
+        match check_result(hresult) {
+            Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
+                (*audio_client).Release();
+                return Err(BuildStreamError::DeviceNotAvailable);
+            }
+            Err(e) => {
+                (*audio_client).Release();
+                let description = format!("{}", e);
+                let err = BackendSpecificError { description, code: e.raw_os_error() };
+                return Err(err.into());
+            }
+            Ok(()) => (),
+        };
+
+        max_frames_in_buffer
+    };
+
+    // Creating the event that will be signalled whenever we need to submit some samples.
+    let event = {
+        let event = synchapi::CreateEventA(ptr::null_mut(), 0, 0, ptr::null());
+        if event.is_null() {
+            (*audio_client).Release();
+            let description = "failed to create event".to_string();
+            let err = BackendSpecificError { description, code: None };
+            return Err(err.into());
+        }
+
+        // A polled client has no event.
+        if !polling {
+            if let Err(e) = check_result((*audio_client).SetEventHandle(event)) {
+                (*audio_client).Release();
+                let description = format!("failed to call SetEventHandle: {}", e);
+                let err = BackendSpecificError { description, code: e.raw_os_error() };
+                return Err(err.into());
+            }
+        }
+
+        event
+    };
+
+    // Building a `IAudioCaptureClient` that will be used to read captured samples.
+    let capture_client = {
+        let mut capture_client: *mut audioclient::IAudioCaptureClient = ptr::null_mut();
+        let hresult = (*audio_client).GetService(
+            &audioclient::IID_IAudioCaptureClient,
+            &mut capture_client as *mut *mut audioclient::IAudioCaptureClient as *mut _,
+        );
+
+        match check_result(hresult) {
+            Err(ref e) if e.raw_os_error() == Some(AUDCLNT_E_DEVICE_INVALIDATED) => {
+                (*audio_client).Release();
+                return Err(BuildStreamError::DeviceNotAvailable);
+            }
+            Err(e) => {
+                (*audio_client).Release();
+                let description = format!("failed to build capture client: {}", e);
+                let err = BackendSpecificError { description, code: e.raw_os_error() };
+                return Err(err.into());
+            }
+            Ok(()) => (),
+        };
+
+        &mut *capture_client
+    };
+
+    // Once we built the `StreamInner`, we add a command that will be picked up by the
+    // `run()` method and added to the `RunContext`.
+    let client_flow = AudioClientFlow::Capture { capture_client };
+
+    if !device.is_null() {
+        (*device).AddRef();
+    }
+    Ok(StreamInner {
+        device,
+        audio_client,
+        client_flow,
+        event,
+        playing: false,
+        max_frames_in_buffer,
+        bytes_per_frame: waveformatex.nBlockAlign,
+        sample_format: format.data_type,
+        meter: Arc::new(StreamMeter::new(format.sample_rate)),
+        clock: AudioClock::new(audio_client, format.sample_rate),
+        poll_interval: poll_interval(max_frames_in_buffer),
+        session: SessionNotifications::register(audio_client, event),
+    })
+}
+
 impl PartialEq for Device {
     #[inline]
     fn eq(&self, other: &Device) -> bool {
@@ -965,6 +1277,8 @@ impl Clone for Device {
 
         Device {
             device: self.device,
+            client_properties: self.client_properties,
+            wake_mode: self.wake_mode,
             future_audio_client: self.future_audio_client.clone(),
         }
     }
@@ -982,11 +1296,15 @@ impl fmt::Debug for Device {
 impl Drop for Device {
     #[inline]
     fn drop(&mut self) {
+        // The device may be dropped on another thread than the one that enumerated it.
+        com::com_initialized();
         unsafe {
             (*self.device).Release();
         }
 
-        if let Some(client) = self.future_audio_client.lock().unwrap().take() {
+        let mut future_audio_client =
+            self.future_audio_client.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(client) = future_audio_client.take() {
             unsafe {
                 (*client.0).Release();
             }
@@ -996,6 +1314,7 @@ impl Drop for Device {
 
 impl Drop for Endpoint {
     fn drop(&mut self) {
+        com::com_initialized();
         unsafe {
             (*self.endpoint).Release();
         }
@@ -1018,6 +1337,9 @@ impl Endpoint {
 }
 
 lazy_static! {
+    // The formats supported by each endpoint, keyed by the id of the endpoint.
+    static ref FORMATS: FormatCache = FormatCache::new();
+
     static ref ENUMERATOR: Enumerator = {
         // COM initialization is thread local, but we only need to have COM initialized in the
         // thread we create the objects in
@@ -1025,7 +1347,7 @@ lazy_static! {
 
         // building the devices enumerator object
         unsafe {
-            let mut enumerator: *mut IMMDeviceEnumerator = mem::uninitialized();
+            let mut enumerator: *mut IMMDeviceEnumerator = ptr::null_mut();
 
             let hresult = CoCreateInstance(
                 &CLSID_MMDeviceEnumerator,
@@ -1044,6 +1366,7 @@ lazy_static! {
 /// RAII object around `IMMDeviceEnumerator`.
 struct Enumerator(*mut IMMDeviceEnumerator);
 
+// `IMMDeviceEnumerator` is free-threaded.
 unsafe impl Send for Enumerator {}
 unsafe impl Sync for Enumerator {}
 
@@ -1065,8 +1388,10 @@ pub struct Devices {
 
 impl Devices {
     pub fn new() -> Result<Self, DevicesError> {
+        // Enumerating the devices again refreshes their formats.
+        FORMATS.clear();
         unsafe {
-            let mut collection: *mut IMMDeviceCollection = mem::uninitialized();
+            let mut collection: *mut IMMDeviceCollection = ptr::null_mut();
             // can fail because of wrong parameters (should never happen) or out of memory
             check_result_backend_specific((*ENUMERATOR.0).EnumAudioEndpoints(
                 eAll,
@@ -1074,9 +1399,9 @@ impl Devices {
                 &mut collection,
             ))?;
 
-            let count = mem::uninitialized();
+            let mut count = 0;
             // can fail if the parameter is null, which should never happen
-            check_result_backend_specific((*collection).GetCount(&count))?;
+            check_result_backend_specific((*collection).GetCount(&mut count))?;
 
             Ok(Devices {
                 collection,
@@ -1087,18 +1412,43 @@ impl Devices {
     }
 }
 
+// `IMMDeviceCollection` is free-threaded, and the collection is only iterated through `&mut self`.
 unsafe impl Send for Devices {}
 unsafe impl Sync for Devices {}
 
 impl Drop for Devices {
     #[inline]
     fn drop(&mut self) {
+        com::com_initialized();
         unsafe {
             (*self.collection).Release();
         }
     }
 }
 
+impl Devices {
+    /// Queries the formats of the endpoints of `data_flow` that remain to be listed in the
+    /// background, a few endpoints at once, so that they are ready when listed.
+    pub(crate) fn prefetch_formats(&self, data_flow: EDataFlow) {
+        let queries = (self.next_item..self.total_count)
+            .filter_map(|i| unsafe {
+                let mut device = ptr::null_mut();
+                check_result((*self.collection).Item(i, &mut device)).ok()?;
+                let device = Device::from_immdevice(device);
+                if device.data_flow() != data_flow {
+                    return None;
+                }
+                let id = device.id().ok()?;
+                Some((id, move || {
+                    com::own_thread_com_initialized();
+                    device.supported_formats()
+                }))
+            })
+            .collect();
+        FORMATS.prefetch(queries);
+    }
+}
+
 impl Iterator for Devices {
     type Item = Device;
 
@@ -1108,7 +1458,7 @@ impl Iterator for Devices {
         }
 
         unsafe {
-            let mut device = mem::uninitialized();
+            let mut device = ptr::null_mut();
             // can fail if out of range, which we just checked above
             check_result((*self.collection).Item(self.next_item, &mut device)).unwrap();
 
@@ -1127,7 +1477,7 @@ impl Iterator for Devices {
 
 fn default_device(data_flow: EDataFlow) -> Option<Device> {
     unsafe {
-        let mut device = mem::uninitialized();
+        let mut device = ptr::null_mut();
         let hres = (*ENUMERATOR.0).GetDefaultAudioEndpoint(data_flow, eConsole, &mut device);
         if let Err(_err) = check_result(hres) {
             return None; // TODO: check specifically for `E_NOTFOUND`, and panic otherwise
@@ -1147,7 +1497,9 @@ pub fn default_output_device() -> Option<Device> {
 // Turns a `Format` into a `WAVEFORMATEXTENSIBLE`.
 //
 // Returns `None` if the WAVEFORMATEXTENSIBLE does not support the given format.
-fn format_to_waveformatextensible(format: &Format) -> Option<mmreg::WAVEFORMATEXTENSIBLE> {
+pub(super) fn format_to_waveformatextensible(
+    format: &Format,
+) -> Option<mmreg::WAVEFORMATEXTENSIBLE> {
     let format_tag = match format.data_type {
         SampleFormat::I16 => mmreg::WAVE_FORMAT_PCM,
         SampleFormat::F32 => mmreg::WAVE_FORMAT_EXTENSIBLE,
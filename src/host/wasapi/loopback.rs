@@ -0,0 +1,295 @@
+//! Capture of the audio of a single process, through `ActivateAudioInterfaceAsync`.
+//!
+//! Process loopback requires Windows 10 version 2004. `winapi` declares neither the activation
+//! function nor its interfaces, so the parts that are needed are declared here, and the function
+//! is looked up when a stream is built so that older versions of Windows only fail to build one.
+
+use super::com;
+use super::device::{build_capture_stream_inner, build_stream_error, format_to_waveformatextensible};
+use super::check_result;
+use super::spatial::BlobPropVariant;
+use super::stream::Stream;
+use super::winapi::ctypes::c_void;
+use super::winapi::shared::guiddef::{IsEqualGUID, GUID, REFIID};
+use super::winapi::shared::minwindef::{FARPROC, ULONG};
+use super::winapi::shared::winerror::{E_NOINTERFACE, S_OK};
+use super::winapi::um::audioclient::{IAudioClient, IID_IAudioClient};
+use super::winapi::um::audiosessiontypes::{
+    AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_EVENTCALLBACK, AUDCLNT_STREAMFLAGS_LOOPBACK,
+};
+use super::winapi::um::handleapi;
+use super::winapi::um::libloaderapi;
+use super::winapi::um::synchapi;
+use super::winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use super::winapi::um::winbase;
+use super::winapi::um::winnt::{HANDLE, HRESULT, LPCWSTR};
+use super::winapi::Interface;
+use super::Host;
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{self, AtomicU32, Ordering};
+
+use BackendSpecificError;
+use BuildStreamError;
+use Format;
+use StreamData;
+use StreamError;
+use StreamOptions;
+
+// {41D949AB-9862-444A-80F6-C261334DA5EB}
+const IID_IACTIVATE_AUDIO_INTERFACE_COMPLETION_HANDLER: GUID = GUID {
+    Data1: 0x41D9_49AB,
+    Data2: 0x9862,
+    Data3: 0x444A,
+    Data4: [0x80, 0xF6, 0xC2, 0x61, 0x33, 0x4D, 0xA5, 0xEB],
+};
+
+// {94EA2B94-E9CC-49E0-C0FF-EE64CA8F5B90}
+const IID_IAGILE_OBJECT: GUID = GUID {
+    Data1: 0x94EA_2B94,
+    Data2: 0xE9CC,
+    Data3: 0x49E0,
+    Data4: [0xC0, 0xFF, 0xEE, 0x64, 0xCA, 0x8F, 0x5B, 0x90],
+};
+
+// `AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK`.
+const ACTIVATION_TYPE_PROCESS_LOOPBACK: u32 = 1;
+
+// `AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM`, as the virtual device has no mix format to match.
+const AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM: u32 = 0x8000_0000;
+
+// The duration of the buffer of the stream, in units of 100 nanoseconds.
+const BUFFER_DURATION: i64 = 200_000;
+
+type ActivateAudioInterfaceAsync = unsafe extern "system" fn(
+    LPCWSTR,
+    REFIID,
+    *mut BlobPropVariant,
+    *mut CompletionHandler,
+    *mut *mut IActivateAudioInterfaceAsyncOperation,
+) -> HRESULT;
+
+#[repr(C)]
+struct IActivateAudioInterfaceAsyncOperation {
+    vtbl: *const IActivateAudioInterfaceAsyncOperationVtbl,
+}
+
+#[repr(C)]
+struct IActivateAudioInterfaceAsyncOperationVtbl {
+    parent: IUnknownVtbl,
+    get_activate_result: unsafe extern "system" fn(
+        *mut IActivateAudioInterfaceAsyncOperation,
+        *mut HRESULT,
+        *mut *mut IUnknown,
+    ) -> HRESULT,
+}
+
+#[repr(C)]
+struct IActivateAudioInterfaceCompletionHandlerVtbl {
+    query_interface:
+        unsafe extern "system" fn(*mut CompletionHandler, REFIID, *mut *mut c_void) -> HRESULT,
+    add_ref: unsafe extern "system" fn(*mut CompletionHandler) -> ULONG,
+    release: unsafe extern "system" fn(*mut CompletionHandler) -> ULONG,
+    activate_completed: unsafe extern "system" fn(
+        *mut CompletionHandler,
+        *mut IActivateAudioInterfaceAsyncOperation,
+    ) -> HRESULT,
+}
+
+static COMPLETION_HANDLER_VTBL: IActivateAudioInterfaceCompletionHandlerVtbl =
+    IActivateAudioInterfaceCompletionHandlerVtbl {
+        query_interface,
+        add_ref,
+        release,
+        activate_completed,
+    };
+
+// `AUDIOCLIENT_ACTIVATION_PARAMS` for process loopback.
+#[repr(C)]
+struct ActivationParams {
+    activation_type: u32,
+    target_process_id: u32,
+    process_loopback_mode: u32,
+}
+
+// The implementation of `IActivateAudioInterfaceCompletionHandler` passed to the activation.
+#[repr(C)]
+struct CompletionHandler {
+    // Must come first, as COM interface pointers point to the vtable pointer.
+    vtbl: *const IActivateAudioInterfaceCompletionHandlerVtbl,
+    refs: AtomicU32,
+    // Signalled once the activation completed. Owned by the handler, which may outlive the wait.
+    completed: HANDLE,
+}
+
+/// Which processes a process loopback stream captures, see `Host::build_process_loopback_stream`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProcessLoopbackMode {
+    /// The audio of the process and of its child processes.
+    IncludeProcessTree,
+    /// The audio of all the processes but the process and its child processes, e.g. to capture
+    /// everything but the application itself.
+    ExcludeProcessTree,
+}
+
+impl Host {
+    /// Builds an input stream capturing the audio that a process renders to any endpoint.
+    ///
+    /// `process_id` is the identifier of the target process, and `mode` chooses whether its audio
+    /// or that of all the other processes is captured. The stream is converted to `format`, which
+    /// may be any PCM or float format. Requires Windows 10 version 2004 or later.
+    pub fn build_process_loopback_stream<D, E>(
+        &self,
+        process_id: u32,
+        mode: ProcessLoopbackMode,
+        format: &Format,
+        options: &StreamOptions,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Stream, BuildStreamError>
+    where
+        D: FnMut(StreamData) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        com::com_initialized();
+        let waveformatex = format_to_waveformatextensible(format)
+            .ok_or(BuildStreamError::FormatNotSupported)?;
+        let stream_inner = unsafe {
+            let audio_client = activate(process_id, mode)?;
+            let hresult = (*audio_client).Initialize(
+                AUDCLNT_SHAREMODE_SHARED,
+                AUDCLNT_STREAMFLAGS_LOOPBACK
+                    | AUDCLNT_STREAMFLAGS_EVENTCALLBACK
+                    | AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM,
+                BUFFER_DURATION,
+                0,
+                &waveformatex.Format,
+                ptr::null(),
+            );
+            if let Err(err) = check_result(hresult) {
+                (*audio_client).Release();
+                return Err(build_stream_error(err));
+            }
+            build_capture_stream_inner(
+                ptr::null_mut(),
+                audio_client,
+                &waveformatex.Format,
+                format,
+                |_| None,
+                false,
+            )?
+        };
+        Stream::new(stream_inner, options, data_callback, error_callback)
+    }
+}
+
+// Activates an uninitialized audio client of the process loopback virtual device.
+unsafe fn activate(
+    process_id: u32,
+    mode: ProcessLoopbackMode,
+) -> Result<*mut IAudioClient, BuildStreamError> {
+    let activate_async = activate_audio_interface_async().ok_or_else(|| {
+        let description = "process loopback requires Windows 10 version 2004".to_string();
+        BackendSpecificError { description, code: None }
+    })?;
+    let mut params = ActivationParams {
+        activation_type: ACTIVATION_TYPE_PROCESS_LOOPBACK,
+        target_process_id: process_id,
+        process_loopback_mode: match mode {
+            ProcessLoopbackMode::IncludeProcessTree => 0,
+            ProcessLoopbackMode::ExcludeProcessTree => 1,
+        },
+    };
+    let mut variant = BlobPropVariant::new(&mut params);
+    let device: Vec<u16> = "VAD\\Process_Loopback\0".encode_utf16().collect();
+
+    let handler = Box::into_raw(Box::new(CompletionHandler {
+        vtbl: &COMPLETION_HANDLER_VTBL,
+        refs: AtomicU32::new(1),
+        completed: synchapi::CreateEventA(ptr::null_mut(), 0, 0, ptr::null()),
+    }));
+    let mut operation: *mut IActivateAudioInterfaceAsyncOperation = ptr::null_mut();
+    let hresult = activate_async(
+        device.as_ptr(),
+        &IID_IAudioClient,
+        &mut variant,
+        handler,
+        &mut operation,
+    );
+    if let Err(err) = check_result(hresult) {
+        release(handler);
+        return Err(build_stream_error(err));
+    }
+    synchapi::WaitForSingleObject((*handler).completed, winbase::INFINITE);
+    release(handler);
+
+    let mut activate_result = S_OK;
+    let mut audio_client: *mut IUnknown = ptr::null_mut();
+    let hresult = ((*(*operation).vtbl).get_activate_result)(
+        operation,
+        &mut activate_result,
+        &mut audio_client,
+    );
+    ((*(*operation).vtbl).parent.Release)(operation as *mut IUnknown);
+    check_result(hresult).map_err(build_stream_error)?;
+    check_result(activate_result).map_err(build_stream_error)?;
+    Ok(audio_client as *mut IAudioClient)
+}
+
+// `ActivateAudioInterfaceAsync`, or `None` before Windows 8.
+unsafe fn activate_audio_interface_async() -> Option<ActivateAudioInterfaceAsync> {
+    // The library stays loaded, as the audio clients that it creates may outlive any stream.
+    let module = libloaderapi::LoadLibraryA(b"Mmdevapi.dll\0".as_ptr() as *const _);
+    if module.is_null() {
+        return None;
+    }
+    let function =
+        libloaderapi::GetProcAddress(module, b"ActivateAudioInterfaceAsync\0".as_ptr() as *const _);
+    if function.is_null() {
+        return None;
+    }
+    Some(mem::transmute::<FARPROC, ActivateAudioInterfaceAsync>(function))
+}
+
+unsafe extern "system" fn query_interface(
+    this: *mut CompletionHandler,
+    riid: REFIID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    let riid = &*riid;
+    // The handler must be agile, as it is called on a thread of the activation.
+    if IsEqualGUID(riid, &IUnknown::uuidof())
+        || IsEqualGUID(riid, &IID_IACTIVATE_AUDIO_INTERFACE_COMPLETION_HANDLER)
+        || IsEqualGUID(riid, &IID_IAGILE_OBJECT)
+    {
+        add_ref(this);
+        *object = this as *mut c_void;
+        S_OK
+    } else {
+        *object = ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref(this: *mut CompletionHandler) -> ULONG {
+    (*this).refs.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+unsafe extern "system" fn release(this: *mut CompletionHandler) -> ULONG {
+    let refs = (*this).refs.fetch_sub(1, Ordering::Release) - 1;
+    if refs == 0 {
+        atomic::fence(Ordering::Acquire);
+        let handler = Box::from_raw(this);
+        handleapi::CloseHandle(handler.completed);
+    }
+    refs
+}
+
+unsafe extern "system" fn activate_completed(
+    this: *mut CompletionHandler,
+    _: *mut IActivateAudioInterfaceAsyncOperation,
+) -> HRESULT {
+    synchapi::SetEvent((*this).completed);
+    S_OK
+}
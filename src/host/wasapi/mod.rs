@@ -2,17 +2,30 @@ extern crate winapi;
 
 pub use self::device::{
     default_input_device, default_output_device, Device, Devices, SupportedInputFormats,
-    SupportedOutputFormats,
+    SupportedOutputFormats, WakeMode,
+};
+pub use self::com::{set_com_apartment, ComApartment};
+pub use self::loopback::ProcessLoopbackMode;
+pub use self::properties::{ClientProperties, StreamCategory};
+pub use self::spatial::{
+    SpatialAudio, SpatialBuffers, SpatialChannel, SpatialStream, SpatialStreamOptions,
 };
 pub use self::stream::Stream;
+use self::winapi::um::mmdeviceapi::{eCapture, eRender};
 use self::winapi::um::winnt::HRESULT;
 use std::io::Error as IoError;
-use traits::HostTrait;
+use traits::{self, HostTrait};
 use BackendSpecificError;
 use DevicesError;
+use InputDevices;
+use OutputDevices;
 
 mod com;
 mod device;
+mod loopback;
+mod properties;
+mod session;
+mod spatial;
 mod stream;
 
 /// The WASAPI host, the default windows host type.
@@ -42,6 +55,20 @@ impl HostTrait for Host {
         Devices::new()
     }
 
+    // Listing the devices of a direction probes all of them, so their formats are queried
+    // concurrently up front.
+    fn input_devices(&self) -> Result<InputDevices<Self::Devices>, DevicesError> {
+        let devices = self.devices()?;
+        devices.prefetch_formats(eCapture);
+        Ok(devices.filter(traits::supports_input::<Device>))
+    }
+
+    fn output_devices(&self) -> Result<OutputDevices<Self::Devices>, DevicesError> {
+        let devices = self.devices()?;
+        devices.prefetch_formats(eRender);
+        Ok(devices.filter(traits::supports_output::<Device>))
+    }
+
     fn default_input_device(&self) -> Option<Self::Device> {
         default_input_device()
     }
@@ -65,6 +92,7 @@ fn check_result_backend_specific(result: HRESULT) -> Result<(), BackendSpecificE
         Ok(()) => Ok(()),
         Err(err) => Err(BackendSpecificError {
             description: format!("{}", err),
+            code: Some(result),
         }),
     }
 }
@@ -0,0 +1,134 @@
+//! The `AudioClientProperties` of the streams of a device, set through `IAudioClient2`.
+//!
+//! `winapi` does not declare `IAudioClient2`, so the parts that are needed are declared here.
+
+use super::check_result;
+use super::device::build_stream_error;
+use super::winapi::shared::guiddef::GUID;
+use super::winapi::shared::minwindef::BOOL;
+use super::winapi::shared::winerror;
+use super::winapi::um::audioclient::{IAudioClient, IAudioClientVtbl};
+use super::winapi::um::unknwnbase::IUnknown;
+use super::winapi::um::winnt::HRESULT;
+
+use std::mem;
+use std::ptr;
+
+use BuildStreamError;
+
+// {726778CD-F60A-4EDA-82DE-E47610CD78AA}
+const IID_IAUDIO_CLIENT_2: GUID = GUID {
+    Data1: 0x7267_78CD,
+    Data2: 0xF60A,
+    Data3: 0x4EDA,
+    Data4: [0x82, 0xDE, 0xE4, 0x76, 0x10, 0xCD, 0x78, 0xAA],
+};
+
+// `AUDCLNT_STREAMOPTIONS_RAW`.
+const STREAM_OPTIONS_RAW: u32 = 0x1;
+
+#[repr(C)]
+struct IAudioClient2 {
+    vtbl: *const IAudioClient2Vtbl,
+}
+
+#[repr(C)]
+struct IAudioClient2Vtbl {
+    parent: IAudioClientVtbl,
+    // `IsOffloadCapable`, which is not used.
+    _unused: [usize; 1],
+    set_client_properties:
+        unsafe extern "system" fn(*mut IAudioClient2, *const AudioClientProperties) -> HRESULT,
+}
+
+#[repr(C)]
+struct AudioClientProperties {
+    size: u32,
+    is_offload: BOOL,
+    category: u32,
+    options: u32,
+}
+
+/// The category of the audio of a stream, which Windows uses to apply its policies, e.g. ducking
+/// music during a call, and to choose the signal processing of the endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StreamCategory {
+    Other,
+    Communications,
+    Alerts,
+    SoundEffects,
+    GameEffects,
+    GameMedia,
+    GameChat,
+    Speech,
+    Movie,
+    Media,
+}
+
+/// The `AudioClientProperties` that the streams of a device are initialized with, see
+/// `Device::with_client_properties`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ClientProperties {
+    /// Defaults to `StreamCategory::Other`.
+    pub category: StreamCategory,
+    /// Bypasses the signal processing of the endpoint, e.g. for measurements or speech
+    /// recognition. Requires Windows 8.1, and endpoints that support raw streams.
+    pub raw: bool,
+    /// Renders the stream through the hardware offload engine of the endpoint, which only some
+    /// endpoints have, and only for `Media` and `Movie` streams.
+    pub offload: bool,
+}
+
+impl Default for ClientProperties {
+    fn default() -> Self {
+        ClientProperties {
+            category: StreamCategory::Other,
+            raw: false,
+            offload: false,
+        }
+    }
+}
+
+impl StreamCategory {
+    // The `AUDIO_STREAM_CATEGORY` of the category.
+    fn to_wasapi(self) -> u32 {
+        match self {
+            StreamCategory::Other => 0,
+            StreamCategory::Communications => 3,
+            StreamCategory::Alerts => 4,
+            StreamCategory::SoundEffects => 5,
+            StreamCategory::GameEffects => 6,
+            StreamCategory::GameMedia => 7,
+            StreamCategory::GameChat => 8,
+            StreamCategory::Speech => 9,
+            StreamCategory::Movie => 10,
+            StreamCategory::Media => 11,
+        }
+    }
+}
+
+// Sets `properties` on `audio_client`, which must not be initialized yet.
+pub(super) unsafe fn set(
+    audio_client: *mut IAudioClient,
+    properties: &ClientProperties,
+) -> Result<(), BuildStreamError> {
+    let mut audio_client_2: *mut IAudioClient2 = ptr::null_mut();
+    let hresult = (*audio_client).QueryInterface(
+        &IID_IAUDIO_CLIENT_2,
+        &mut audio_client_2 as *mut *mut IAudioClient2 as *mut _,
+    );
+    // Windows 7 has no `IAudioClient2`, and therefore none of the properties.
+    if hresult == winerror::E_NOINTERFACE {
+        return Err(BuildStreamError::FormatNotSupported);
+    }
+    check_result(hresult).map_err(build_stream_error)?;
+    let properties = AudioClientProperties {
+        size: mem::size_of::<AudioClientProperties>() as u32,
+        is_offload: properties.offload as BOOL,
+        category: properties.category.to_wasapi(),
+        options: if properties.raw { STREAM_OPTIONS_RAW } else { 0 },
+    };
+    let hresult = ((*(*audio_client_2).vtbl).set_client_properties)(audio_client_2, &properties);
+    ((*(*audio_client_2).vtbl).parent.parent.Release)(audio_client_2 as *mut IUnknown);
+    check_result(hresult).map_err(build_stream_error)
+}
@@ -0,0 +1,265 @@
+//! Notifications of the audio session of a stream.
+//!
+//! WASAPI tells why the session of a stream was disconnected through `IAudioSessionEvents`, while
+//! the stream itself only fails with `AUDCLNT_E_DEVICE_INVALIDATED`. `winapi` does not declare the
+//! session interfaces, so the parts that are needed are declared here.
+
+use super::winapi::ctypes::c_void;
+use super::winapi::shared::guiddef::{IsEqualGUID, GUID, LPCGUID, REFIID};
+use super::winapi::shared::minwindef::{BOOL, DWORD, ULONG};
+use super::winapi::shared::winerror::{E_NOINTERFACE, S_OK};
+use super::winapi::um::audioclient::IAudioClient;
+use super::winapi::um::synchapi;
+use super::winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use super::winapi::um::winnt::{HANDLE, HRESULT, LPCWSTR};
+use super::winapi::Interface;
+
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicU32, Ordering};
+
+use DeviceLossReason;
+
+// {F4B1A599-7266-4319-A8CA-E70ACB11E8CD}
+const IID_IAUDIO_SESSION_CONTROL: GUID = GUID {
+    Data1: 0xF4B1_A599,
+    Data2: 0x7266,
+    Data3: 0x4319,
+    Data4: [0xA8, 0xCA, 0xE7, 0x0A, 0xCB, 0x11, 0xE8, 0xCD],
+};
+
+// {24918ACC-64B3-37C1-8CA9-74A66E9957A8}
+const IID_IAUDIO_SESSION_EVENTS: GUID = GUID {
+    Data1: 0x2491_8ACC,
+    Data2: 0x64B3,
+    Data3: 0x37C1,
+    Data4: [0x8C, 0xA9, 0x74, 0xA6, 0x6E, 0x99, 0x57, 0xA8],
+};
+
+// The values of `AudioSessionDisconnectReason`.
+const DISCONNECT_REASON_SERVER_SHUTDOWN: u32 = 1;
+const DISCONNECT_REASON_FORMAT_CHANGED: u32 = 2;
+const DISCONNECT_REASON_SESSION_LOGOFF: u32 = 3;
+const DISCONNECT_REASON_SESSION_DISCONNECTED: u32 = 4;
+const DISCONNECT_REASON_EXCLUSIVE_MODE_OVERRIDE: u32 = 5;
+
+// Stored while the session has not been disconnected.
+const NOT_DISCONNECTED: u32 = u32::MAX;
+
+#[repr(C)]
+struct IAudioSessionControl {
+    vtbl: *const IAudioSessionControlVtbl,
+}
+
+#[repr(C)]
+struct IAudioSessionControlVtbl {
+    parent: IUnknownVtbl,
+    // `GetState`, `GetDisplayName`, `SetDisplayName`, `GetIconPath`, `SetIconPath`,
+    // `GetGroupingParam` and `SetGroupingParam`, which are not used.
+    _unused: [usize; 7],
+    register_audio_session_notification:
+        unsafe extern "system" fn(*mut IAudioSessionControl, *mut SessionEvents) -> HRESULT,
+    unregister_audio_session_notification:
+        unsafe extern "system" fn(*mut IAudioSessionControl, *mut SessionEvents) -> HRESULT,
+}
+
+#[repr(C)]
+struct IAudioSessionEventsVtbl {
+    query_interface:
+        unsafe extern "system" fn(*mut SessionEvents, REFIID, *mut *mut c_void) -> HRESULT,
+    add_ref: unsafe extern "system" fn(*mut SessionEvents) -> ULONG,
+    release: unsafe extern "system" fn(*mut SessionEvents) -> ULONG,
+    on_display_name_changed:
+        unsafe extern "system" fn(*mut SessionEvents, LPCWSTR, LPCGUID) -> HRESULT,
+    on_icon_path_changed:
+        unsafe extern "system" fn(*mut SessionEvents, LPCWSTR, LPCGUID) -> HRESULT,
+    on_simple_volume_changed:
+        unsafe extern "system" fn(*mut SessionEvents, f32, BOOL, LPCGUID) -> HRESULT,
+    on_channel_volume_changed:
+        unsafe extern "system" fn(*mut SessionEvents, DWORD, *mut f32, DWORD, LPCGUID) -> HRESULT,
+    on_grouping_param_changed:
+        unsafe extern "system" fn(*mut SessionEvents, LPCGUID, LPCGUID) -> HRESULT,
+    on_state_changed: unsafe extern "system" fn(*mut SessionEvents, u32) -> HRESULT,
+    on_session_disconnected: unsafe extern "system" fn(*mut SessionEvents, u32) -> HRESULT,
+}
+
+static SESSION_EVENTS_VTBL: IAudioSessionEventsVtbl = IAudioSessionEventsVtbl {
+    query_interface,
+    add_ref,
+    release,
+    on_display_name_changed: ignore_name_changed,
+    on_icon_path_changed: ignore_name_changed,
+    on_simple_volume_changed,
+    on_channel_volume_changed,
+    on_grouping_param_changed,
+    on_state_changed,
+    on_session_disconnected,
+};
+
+// The implementation of `IAudioSessionEvents` registered for a stream.
+#[repr(C)]
+struct SessionEvents {
+    // Must come first, as COM interface pointers point to the vtable pointer.
+    vtbl: *const IAudioSessionEventsVtbl,
+    refs: AtomicU32,
+    disconnect: SessionDisconnect,
+    // Signalled when the session is disconnected, so that the audio thread notices right away.
+    wakeup: HANDLE,
+}
+
+/// Why the session of a stream was disconnected, shared with the audio thread.
+#[derive(Clone)]
+pub struct SessionDisconnect(Arc<AtomicU32>);
+
+/// The registration of a stream for the notifications of its audio session, undone on drop.
+pub struct SessionNotifications {
+    control: *mut IAudioSessionControl,
+    events: *mut SessionEvents,
+}
+
+impl SessionDisconnect {
+    /// The reason of the disconnection of the session, if it was disconnected.
+    ///
+    /// Returns `None` if the device of the session was removed, as the state of the device tells
+    /// whether it was unplugged, disabled or uninstalled.
+    pub fn reason(&self) -> Option<DeviceLossReason> {
+        match self.0.load(Ordering::Acquire) {
+            DISCONNECT_REASON_SERVER_SHUTDOWN => Some(DeviceLossReason::ServiceStopped),
+            DISCONNECT_REASON_FORMAT_CHANGED => Some(DeviceLossReason::Reconfigured),
+            DISCONNECT_REASON_SESSION_LOGOFF | DISCONNECT_REASON_SESSION_DISCONNECTED => {
+                Some(DeviceLossReason::SessionEnded)
+            }
+            DISCONNECT_REASON_EXCLUSIVE_MODE_OVERRIDE => Some(DeviceLossReason::Preempted),
+            // `DISCONNECT_REASON_DEVICE_REMOVAL` or `NOT_DISCONNECTED`.
+            _ => None,
+        }
+    }
+}
+
+impl SessionNotifications {
+    /// Registers for the notifications of the session of `audio_client`.
+    ///
+    /// `wakeup` is signalled when the session is disconnected and must remain valid until the
+    /// registration is dropped. Returns `None` if the session does not provide notifications,
+    /// which only means that disconnections are reported with less detail.
+    pub unsafe fn register(audio_client: *mut IAudioClient, wakeup: HANDLE) -> Option<Self> {
+        let mut control: *mut IAudioSessionControl = ptr::null_mut();
+        let hresult = (*audio_client).GetService(
+            &IID_IAUDIO_SESSION_CONTROL,
+            &mut control as *mut *mut IAudioSessionControl as *mut _,
+        );
+        if hresult < 0 || control.is_null() {
+            return None;
+        }
+
+        let events = Box::into_raw(Box::new(SessionEvents {
+            vtbl: &SESSION_EVENTS_VTBL,
+            refs: AtomicU32::new(1),
+            disconnect: SessionDisconnect(Arc::new(AtomicU32::new(NOT_DISCONNECTED))),
+            wakeup,
+        }));
+        let hresult = ((*(*control).vtbl).register_audio_session_notification)(control, events);
+        if hresult < 0 {
+            release_control(control);
+            release(events);
+            return None;
+        }
+        Some(SessionNotifications { control, events })
+    }
+
+    /// The reason of the disconnection of the session, as seen from any thread.
+    pub fn disconnect(&self) -> SessionDisconnect {
+        unsafe { (*self.events).disconnect.clone() }
+    }
+}
+
+impl Drop for SessionNotifications {
+    fn drop(&mut self) {
+        unsafe {
+            ((*(*self.control).vtbl).unregister_audio_session_notification)(
+                self.control,
+                self.events,
+            );
+            release_control(self.control);
+            release(self.events);
+        }
+    }
+}
+
+unsafe fn release_control(control: *mut IAudioSessionControl) {
+    ((*(*control).vtbl).parent.Release)(control as *mut IUnknown);
+}
+
+unsafe extern "system" fn query_interface(
+    this: *mut SessionEvents,
+    riid: REFIID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    let riid = &*riid;
+    if IsEqualGUID(riid, &IUnknown::uuidof()) || IsEqualGUID(riid, &IID_IAUDIO_SESSION_EVENTS) {
+        add_ref(this);
+        *object = this as *mut c_void;
+        S_OK
+    } else {
+        *object = ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref(this: *mut SessionEvents) -> ULONG {
+    (*this).refs.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+unsafe extern "system" fn release(this: *mut SessionEvents) -> ULONG {
+    let refs = (*this).refs.fetch_sub(1, Ordering::Release) - 1;
+    if refs == 0 {
+        atomic::fence(Ordering::Acquire);
+        drop(Box::from_raw(this));
+    }
+    refs
+}
+
+unsafe extern "system" fn ignore_name_changed(
+    _: *mut SessionEvents,
+    _: LPCWSTR,
+    _: LPCGUID,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn on_simple_volume_changed(
+    _: *mut SessionEvents,
+    _: f32,
+    _: BOOL,
+    _: LPCGUID,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn on_channel_volume_changed(
+    _: *mut SessionEvents,
+    _: DWORD,
+    _: *mut f32,
+    _: DWORD,
+    _: LPCGUID,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn on_grouping_param_changed(
+    _: *mut SessionEvents,
+    _: LPCGUID,
+    _: LPCGUID,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn on_state_changed(_: *mut SessionEvents, _: u32) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn on_session_disconnected(this: *mut SessionEvents, reason: u32) -> HRESULT {
+    (*this).disconnect.0.store(reason, Ordering::Release);
+    synchapi::SetEvent((*this).wakeup);
+    S_OK
+}
@@ -0,0 +1,765 @@
+//! Object-based output through `ISpatialAudioClient`.
+//!
+//! When a spatial audio format such as Windows Sonic or Dolby Atmos is active on an endpoint, a
+//! spatial stream renders static objects, the channels of a bed such as 7.1.4, and dynamic objects
+//! that are placed anywhere around the listener and may move with every buffer. `winapi` does not
+//! declare the spatial audio interfaces, so the parts that are needed are declared here.
+
+use super::com;
+use super::device::format_from_waveformatex_ptr;
+use super::stream::stream_error_from_hresult;
+use super::winapi::ctypes::c_void;
+use super::winapi::shared::guiddef::{GUID, REFIID};
+use super::winapi::shared::minwindef::{BOOL, BYTE, FALSE};
+use super::winapi::shared::mmreg::WAVEFORMATEX;
+use super::winapi::shared::winerror;
+use super::winapi::um::combaseapi::CLSCTX_ALL;
+use super::winapi::um::handleapi;
+use super::winapi::um::mmdeviceapi::IMMDevice;
+use super::winapi::um::synchapi;
+use super::winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use super::winapi::um::winbase;
+use super::winapi::um::winnt::{HANDLE, HRESULT};
+
+use std::mem;
+use std::ptr;
+use std::slice;
+
+use worker::{self, Worker};
+
+use BackendSpecificError;
+use BuildStreamError;
+use PauseStreamError;
+use PlayStreamError;
+use SampleFormat;
+use SampleRate;
+use StreamError;
+use ThreadSpawner;
+
+// {BBF8E066-AAAA-49BE-9A4D-FD2A858EA27F}
+const IID_ISPATIAL_AUDIO_CLIENT: GUID = GUID {
+    Data1: 0xBBF8_E066,
+    Data2: 0xAAAA,
+    Data3: 0x49BE,
+    Data4: [0x9A, 0x4D, 0xFD, 0x2A, 0x85, 0x8E, 0xA2, 0x7F],
+};
+
+// {BAB5F473-B423-477B-85F5-B5A332A04153}
+const IID_ISPATIAL_AUDIO_OBJECT_RENDER_STREAM: GUID = GUID {
+    Data1: 0xBAB5_F473,
+    Data2: 0xB423,
+    Data3: 0x477B,
+    Data4: [0x85, 0xF5, 0xB5, 0xA3, 0x32, 0xA0, 0x41, 0x53],
+};
+
+// `AudioObjectType_Dynamic`. The other values of `AudioObjectType` are those of `SpatialChannel`.
+const AUDIO_OBJECT_TYPE_DYNAMIC: u32 = 0x1;
+// `VT_BLOB`.
+const VT_BLOB: u16 = 65;
+
+// `SPTLAUDCLNT_E_STREAM_NOT_STOPPED`, returned when starting a stream that is already started.
+const SPTLAUDCLNT_E_STREAM_NOT_STOPPED: HRESULT = 0x8889_010A_u32 as HRESULT;
+
+#[repr(C)]
+struct ISpatialAudioClient {
+    vtbl: *const ISpatialAudioClientVtbl,
+}
+
+#[repr(C)]
+struct ISpatialAudioClientVtbl {
+    parent: IUnknownVtbl,
+    // `GetStaticObjectPosition`, which is not used.
+    _unused: [usize; 1],
+    get_native_static_object_type_mask:
+        unsafe extern "system" fn(*mut ISpatialAudioClient, *mut u32) -> HRESULT,
+    get_max_dynamic_object_count:
+        unsafe extern "system" fn(*mut ISpatialAudioClient, *mut u32) -> HRESULT,
+    get_supported_audio_object_format_enumerator:
+        unsafe extern "system" fn(*mut ISpatialAudioClient, *mut *mut IAudioFormatEnumerator)
+            -> HRESULT,
+    // `GetMaxFrameCount`, `IsAudioObjectFormatSupported` and `IsSpatialAudioStreamAvailable`.
+    _unused_2: [usize; 3],
+    activate_spatial_audio_stream: unsafe extern "system" fn(
+        *mut ISpatialAudioClient,
+        *const BlobPropVariant,
+        REFIID,
+        *mut *mut c_void,
+    ) -> HRESULT,
+}
+
+#[repr(C)]
+struct IAudioFormatEnumerator {
+    vtbl: *const IAudioFormatEnumeratorVtbl,
+}
+
+#[repr(C)]
+struct IAudioFormatEnumeratorVtbl {
+    parent: IUnknownVtbl,
+    get_count: unsafe extern "system" fn(*mut IAudioFormatEnumerator, *mut u32) -> HRESULT,
+    get_format: unsafe extern "system" fn(
+        *mut IAudioFormatEnumerator,
+        u32,
+        *mut *mut WAVEFORMATEX,
+    ) -> HRESULT,
+}
+
+#[repr(C)]
+struct ISpatialAudioObjectRenderStream {
+    vtbl: *const ISpatialAudioObjectRenderStreamVtbl,
+}
+
+#[repr(C)]
+struct ISpatialAudioObjectRenderStreamVtbl {
+    parent: IUnknownVtbl,
+    // `GetAvailableDynamicObjectCount` and `GetService`, which are not used.
+    _unused: [usize; 2],
+    start: unsafe extern "system" fn(*mut ISpatialAudioObjectRenderStream) -> HRESULT,
+    stop: unsafe extern "system" fn(*mut ISpatialAudioObjectRenderStream) -> HRESULT,
+    reset: unsafe extern "system" fn(*mut ISpatialAudioObjectRenderStream) -> HRESULT,
+    begin_updating_audio_objects:
+        unsafe extern "system" fn(*mut ISpatialAudioObjectRenderStream, *mut u32, *mut u32)
+            -> HRESULT,
+    end_updating_audio_objects:
+        unsafe extern "system" fn(*mut ISpatialAudioObjectRenderStream) -> HRESULT,
+    activate_spatial_audio_object: unsafe extern "system" fn(
+        *mut ISpatialAudioObjectRenderStream,
+        u32,
+        *mut *mut ISpatialAudioObject,
+    ) -> HRESULT,
+}
+
+#[repr(C)]
+struct ISpatialAudioObject {
+    vtbl: *const ISpatialAudioObjectVtbl,
+}
+
+#[repr(C)]
+struct ISpatialAudioObjectVtbl {
+    parent: IUnknownVtbl,
+    get_buffer:
+        unsafe extern "system" fn(*mut ISpatialAudioObject, *mut *mut BYTE, *mut u32) -> HRESULT,
+    set_end_of_stream: unsafe extern "system" fn(*mut ISpatialAudioObject, u32) -> HRESULT,
+    is_active: unsafe extern "system" fn(*mut ISpatialAudioObject, *mut BOOL) -> HRESULT,
+    // `GetAudioObjectType`, which is not used.
+    _unused: [usize; 1],
+    set_position: unsafe extern "system" fn(*mut ISpatialAudioObject, f32, f32, f32) -> HRESULT,
+    set_volume: unsafe extern "system" fn(*mut ISpatialAudioObject, f32) -> HRESULT,
+}
+
+// `SpatialAudioObjectRenderStreamActivationParams`, which is packed.
+#[repr(C, packed)]
+struct ActivationParams {
+    object_format: *const WAVEFORMATEX,
+    static_object_type_mask: u32,
+    min_dynamic_object_count: u32,
+    max_dynamic_object_count: u32,
+    category: u32,
+    event_handle: HANDLE,
+    notify_object: *mut c_void,
+}
+
+// A `PROPVARIANT` holding a `BLOB`.
+#[repr(C)]
+pub(super) struct BlobPropVariant {
+    vt: u16,
+    reserved: [u16; 3],
+    size: u32,
+    data: *mut BYTE,
+}
+
+impl BlobPropVariant {
+    // A blob of the bytes of `data`, which must outlive the variant.
+    pub(super) fn new<T>(data: &mut T) -> Self {
+        BlobPropVariant {
+            vt: VT_BLOB,
+            reserved: [0; 3],
+            size: mem::size_of::<T>() as u32,
+            data: data as *mut T as *mut BYTE,
+        }
+    }
+}
+
+/// A static object of a spatial stream, i.e. a channel of the bed of the endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpatialChannel {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    LowFrequency,
+    SideLeft,
+    SideRight,
+    BackLeft,
+    BackRight,
+    TopFrontLeft,
+    TopFrontRight,
+    TopBackLeft,
+    TopBackRight,
+    BottomFrontLeft,
+    BottomFrontRight,
+    BottomBackLeft,
+    BottomBackRight,
+    BackCenter,
+}
+
+// The channels in the order of their `AudioObjectType` bits, from `0x2` to `0x20000`.
+const SPATIAL_CHANNELS: [SpatialChannel; 17] = [
+    SpatialChannel::FrontLeft,
+    SpatialChannel::FrontRight,
+    SpatialChannel::FrontCenter,
+    SpatialChannel::LowFrequency,
+    SpatialChannel::SideLeft,
+    SpatialChannel::SideRight,
+    SpatialChannel::BackLeft,
+    SpatialChannel::BackRight,
+    SpatialChannel::TopFrontLeft,
+    SpatialChannel::TopFrontRight,
+    SpatialChannel::TopBackLeft,
+    SpatialChannel::TopBackRight,
+    SpatialChannel::BottomFrontLeft,
+    SpatialChannel::BottomFrontRight,
+    SpatialChannel::BottomBackLeft,
+    SpatialChannel::BottomBackRight,
+    SpatialChannel::BackCenter,
+];
+
+/// The spatial audio format engaged on an output endpoint, see `Device::spatial_audio`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpatialAudio {
+    /// The largest number of dynamic objects that the format renders at once.
+    pub max_dynamic_objects: u32,
+    /// The channels of the bed of the format, i.e. its static objects.
+    pub static_channels: Vec<SpatialChannel>,
+    /// Whether the format virtualizes its bed over fewer channels than it has, e.g. Windows Sonic
+    /// and Dolby Atmos for headphones, rather than rendering each channel to a speaker.
+    /// Applications should then not apply their own virtualization or downmix.
+    pub virtualized: bool,
+}
+
+/// Configures a spatial output stream, see `Device::build_spatial_output_stream`.
+#[derive(Clone, Debug)]
+pub struct SpatialStreamOptions {
+    /// The largest number of dynamic objects that the stream renders at once. It is lowered to
+    /// the number that the spatial audio format of the endpoint supports.
+    ///
+    /// Defaults to `u32::MAX`, i.e. as many as the format supports.
+    pub max_dynamic_objects: u32,
+    /// Spawns the thread running the data callback. Defaults to `std::thread::spawn`.
+    pub thread_spawner: ThreadSpawner,
+}
+
+/// An output stream of audio objects, built by `Device::build_spatial_output_stream`.
+///
+/// Like other WASAPI streams, it starts paused and its thread is spawned on the first call to
+/// `play`.
+pub struct SpatialStream {
+    worker: Worker,
+    render_stream: *mut ISpatialAudioObjectRenderStream,
+    // Signalled to stop the audio thread.
+    terminate: HANDLE,
+    sample_rate: SampleRate,
+    static_channels: Vec<SpatialChannel>,
+    max_dynamic_objects: u32,
+}
+
+/// The buffers of the objects of a spatial stream for one period, passed to its data callback.
+///
+/// All the buffers hold `frames()` mono `f32` samples at the sample rate of the stream. Objects
+/// whose buffer is not requested are silent for the period.
+pub struct SpatialBuffers<'a> {
+    frames: usize,
+    // The number of dynamic objects that can still be activated.
+    available_dynamic_objects: u32,
+    renderer: &'a mut Renderer,
+}
+
+// Releases the spatial audio client of an endpoint on drop.
+struct Client(*mut ISpatialAudioClient);
+
+// The state of the audio thread of a spatial stream.
+struct Renderer {
+    render_stream: *mut ISpatialAudioObjectRenderStream,
+    // Signalled by the render stream when it needs data.
+    event: HANDLE,
+    static_objects: Vec<(SpatialChannel, *mut ISpatialAudioObject)>,
+    // The dynamic objects that were rendered in the last period, by the identifier that the data
+    // callback gave them, and whether they were rendered in the current period.
+    dynamic_objects: Vec<(u64, *mut ISpatialAudioObject, bool)>,
+}
+
+// The renderer is moved to the audio thread once built and only used there. Its COM objects are
+// free-threaded, like those of the other WASAPI streams.
+unsafe impl Send for Renderer {}
+
+// The render stream is free-threaded and only started and stopped from the caller's thread.
+unsafe impl Send for SpatialStream {}
+unsafe impl Sync for SpatialStream {}
+
+impl Default for SpatialStreamOptions {
+    fn default() -> Self {
+        SpatialStreamOptions {
+            max_dynamic_objects: u32::MAX,
+            thread_spawner: ThreadSpawner::default(),
+        }
+    }
+}
+
+// The channels whose `AudioObjectType` bits are set in `mask`.
+fn channels_of_mask(mask: u32) -> Vec<SpatialChannel> {
+    SPATIAL_CHANNELS
+        .iter()
+        .cloned()
+        .filter(|channel| mask & channel.object_type() != 0)
+        .collect()
+}
+
+impl SpatialChannel {
+    // The `AudioObjectType` of the channel.
+    fn object_type(self) -> u32 {
+        let index = SPATIAL_CHANNELS.iter().position(|&channel| channel == self).unwrap();
+        0x2 << index
+    }
+}
+
+impl SpatialStream {
+    // Builds a stream of the native static objects and up to `options.max_dynamic_objects`
+    // dynamic objects on `device`.
+    pub(super) fn new<D, E>(
+        device: *mut IMMDevice,
+        options: &SpatialStreamOptions,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<SpatialStream, BuildStreamError>
+    where
+        D: FnMut(&mut SpatialBuffers) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        com::com_initialized();
+        unsafe {
+            let client = match Client::activate(device).map_err(build_error)? {
+                Some(client) => client,
+                None => return Err(BuildStreamError::FormatNotSupported),
+            };
+            SpatialStream::activate(&client, options, data_callback, error_callback)
+        }
+    }
+
+    unsafe fn activate<D, E>(
+        client: &Client,
+        options: &SpatialStreamOptions,
+        mut data_callback: D,
+        mut error_callback: E,
+    ) -> Result<SpatialStream, BuildStreamError>
+    where
+        D: FnMut(&mut SpatialBuffers) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let (client, vtbl) = (client.0, &*(*client.0).vtbl);
+        // The endpoint supports no dynamic objects unless a spatial audio format is active.
+        let max_dynamic_objects = Client::max_dynamic_objects(client).map_err(build_error)?;
+        if max_dynamic_objects == 0 {
+            return Err(BuildStreamError::FormatNotSupported);
+        }
+        let max_dynamic_objects = max_dynamic_objects.min(options.max_dynamic_objects);
+        let static_mask = Client::static_mask(client).map_err(build_error)?;
+        let static_channels = channels_of_mask(static_mask);
+
+        // The objects use the first format that the endpoint supports, which is mono `f32`.
+        let mut formats: *mut IAudioFormatEnumerator = ptr::null_mut();
+        let hresult = (vtbl.get_supported_audio_object_format_enumerator)(client, &mut formats);
+        stream_error_from_hresult(hresult).map_err(build_error)?;
+        let release_formats = || ((*(*formats).vtbl).parent.Release)(formats as *mut IUnknown);
+        let mut count = 0;
+        let mut object_format: *mut WAVEFORMATEX = ptr::null_mut();
+        let hresult = ((*(*formats).vtbl).get_count)(formats, &mut count);
+        let hresult = if hresult >= 0 && count > 0 {
+            ((*(*formats).vtbl).get_format)(formats, 0, &mut object_format)
+        } else {
+            hresult
+        };
+        let format = if hresult >= 0 && !object_format.is_null() {
+            format_from_waveformatex_ptr(object_format)
+        } else {
+            None
+        };
+        let format = match format {
+            Some(format) if format.channels == 1 && format.data_type == SampleFormat::F32 => format,
+            _ => {
+                release_formats();
+                return Err(BuildStreamError::FormatNotSupported);
+            },
+        };
+
+        let event = synchapi::CreateEventA(ptr::null_mut(), 0, 0, ptr::null());
+        if event.is_null() {
+            release_formats();
+            return Err(event_error());
+        }
+        let mut params = ActivationParams {
+            object_format,
+            static_object_type_mask: static_mask,
+            min_dynamic_object_count: 0,
+            max_dynamic_object_count: max_dynamic_objects,
+            // `AudioCategory_Other`.
+            category: 0,
+            event_handle: event,
+            notify_object: ptr::null_mut(),
+        };
+        let variant = BlobPropVariant::new(&mut params);
+        let mut render_stream: *mut ISpatialAudioObjectRenderStream = ptr::null_mut();
+        let hresult = (vtbl.activate_spatial_audio_stream)(
+            client,
+            &variant,
+            &IID_ISPATIAL_AUDIO_OBJECT_RENDER_STREAM,
+            &mut render_stream as *mut *mut ISpatialAudioObjectRenderStream as *mut _,
+        );
+        release_formats();
+        if let Err(err) = stream_error_from_hresult(hresult) {
+            handleapi::CloseHandle(event);
+            return Err(build_error(err));
+        }
+
+        // From here on, the renderer releases the render stream and closes the event on drop.
+        let mut renderer = Renderer {
+            render_stream,
+            event,
+            static_objects: Vec::with_capacity(static_channels.len()),
+            dynamic_objects: Vec::with_capacity(max_dynamic_objects as usize),
+        };
+        for &channel in &static_channels {
+            let mut object = ptr::null_mut();
+            let hresult = ((*(*render_stream).vtbl).activate_spatial_audio_object)(
+                render_stream,
+                channel.object_type(),
+                &mut object,
+            );
+            stream_error_from_hresult(hresult).map_err(build_error)?;
+            renderer.static_objects.push((channel, object));
+        }
+
+        let terminate = synchapi::CreateEventA(ptr::null_mut(), 0, 0, ptr::null());
+        if terminate.is_null() {
+            return Err(event_error());
+        }
+        ((*(*render_stream).vtbl).parent.AddRef)(render_stream as *mut IUnknown);
+        let terminate_handle = terminate as usize;
+        let worker = Worker::new(options.thread_spawner.clone(), move || {
+            com::own_thread_com_initialized();
+            worker::catch_panics(&mut error_callback, |error_callback| {
+                let terminate = terminate_handle as HANDLE;
+                renderer.run(terminate, &mut data_callback, error_callback)
+            });
+        });
+
+        Ok(SpatialStream {
+            worker,
+            render_stream,
+            terminate,
+            sample_rate: format.sample_rate,
+            static_channels,
+            max_dynamic_objects,
+        })
+    }
+
+    /// Starts the stream, spawning its thread on the first call.
+    pub fn play(&self) -> Result<(), PlayStreamError> {
+        let hresult = unsafe { ((*(*self.render_stream).vtbl).start)(self.render_stream) };
+        if hresult != SPTLAUDCLNT_E_STREAM_NOT_STOPPED {
+            stream_error_from_hresult(hresult).map_err(play_error)?;
+        }
+        self.worker.start()?;
+        Ok(())
+    }
+
+    /// Pauses the stream.
+    pub fn pause(&self) -> Result<(), PauseStreamError> {
+        unsafe {
+            let hresult = ((*(*self.render_stream).vtbl).stop)(self.render_stream);
+            stream_error_from_hresult(hresult).map_err(pause_error)
+        }
+    }
+
+    /// The sample rate of the buffers of the objects.
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    /// The static objects of the stream, i.e. the channels of the bed of the endpoint.
+    pub fn static_channels(&self) -> &[SpatialChannel] {
+        &self.static_channels
+    }
+
+    /// The largest number of dynamic objects that the stream renders at once.
+    pub fn max_dynamic_objects(&self) -> u32 {
+        self.max_dynamic_objects
+    }
+}
+
+impl Drop for SpatialStream {
+    fn drop(&mut self) {
+        unsafe {
+            ((*(*self.render_stream).vtbl).stop)(self.render_stream);
+            synchapi::SetEvent(self.terminate);
+        }
+        self.worker.join();
+        unsafe {
+            ((*(*self.render_stream).vtbl).parent.Release)(self.render_stream as *mut IUnknown);
+            handleapi::CloseHandle(self.terminate);
+        }
+    }
+}
+
+impl Client {
+    // The spatial audio client of `device`, or `None` before the Creators Update of Windows 10.
+    unsafe fn activate(device: *mut IMMDevice) -> Result<Option<Client>, StreamError> {
+        let mut client: *mut ISpatialAudioClient = ptr::null_mut();
+        let hresult = (*device).Activate(
+            &IID_ISPATIAL_AUDIO_CLIENT,
+            CLSCTX_ALL,
+            ptr::null_mut(),
+            &mut client as *mut *mut ISpatialAudioClient as *mut _,
+        );
+        if hresult == winerror::E_NOINTERFACE {
+            return Ok(None);
+        }
+        stream_error_from_hresult(hresult)?;
+        Ok(Some(Client(client)))
+    }
+
+    unsafe fn max_dynamic_objects(client: *mut ISpatialAudioClient) -> Result<u32, StreamError> {
+        let mut count = 0;
+        let hresult = ((*(*client).vtbl).get_max_dynamic_object_count)(client, &mut count);
+        stream_error_from_hresult(hresult)?;
+        Ok(count)
+    }
+
+    unsafe fn static_mask(client: *mut ISpatialAudioClient) -> Result<u32, StreamError> {
+        let mut mask = 0;
+        let hresult = ((*(*client).vtbl).get_native_static_object_type_mask)(client, &mut mask);
+        stream_error_from_hresult(hresult)?;
+        Ok(mask)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        unsafe {
+            ((*(*self.0).vtbl).parent.Release)(self.0 as *mut IUnknown);
+        }
+    }
+}
+
+// The spatial audio format engaged on `device`, whose mix format has `endpoint_channels`.
+pub(super) fn spatial_audio(
+    device: *mut IMMDevice,
+    endpoint_channels: u16,
+) -> Result<Option<SpatialAudio>, StreamError> {
+    com::com_initialized();
+    unsafe {
+        let client = match Client::activate(device)? {
+            Some(client) => client,
+            None => return Ok(None),
+        };
+        let max_dynamic_objects = Client::max_dynamic_objects(client.0)?;
+        if max_dynamic_objects == 0 {
+            return Ok(None);
+        }
+        let static_channels = channels_of_mask(Client::static_mask(client.0)?);
+        let virtualized = static_channels.len() > endpoint_channels as usize;
+        Ok(Some(SpatialAudio { max_dynamic_objects, static_channels, virtualized }))
+    }
+}
+
+impl<'a> SpatialBuffers<'a> {
+    /// The number of frames of the buffers.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// The buffer of the static object of `channel`, or `None` if the stream does not have it.
+    pub fn static_channel(&mut self, channel: SpatialChannel) -> Option<&mut [f32]> {
+        let object = self
+            .renderer
+            .static_objects
+            .iter()
+            .find(|&&(static_channel, _)| static_channel == channel)?
+            .1;
+        unsafe { buffer(object, self.frames) }
+    }
+
+    /// The buffer of the dynamic object identified by `id`, placed at `position` and played at
+    /// `volume` for this period.
+    ///
+    /// `position` is in meters from the listener: `x` to the right, `y` up and `z` to the back.
+    /// An object keeps playing as long as its buffer is requested in every period, and ends in
+    /// the first period without it. Returns `None` if the stream already renders as many dynamic
+    /// objects as it can.
+    pub fn dynamic_object(
+        &mut self,
+        id: u64,
+        position: [f32; 3],
+        volume: f32,
+    ) -> Option<&mut [f32]> {
+        let dynamic_objects = &mut self.renderer.dynamic_objects;
+        let index = match dynamic_objects.iter().position(|&(object_id, _, _)| object_id == id) {
+            Some(index) => index,
+            None => {
+                if self.available_dynamic_objects == 0 {
+                    return None;
+                }
+                let render_stream = self.renderer.render_stream;
+                let mut object = ptr::null_mut();
+                let hresult = unsafe {
+                    ((*(*render_stream).vtbl).activate_spatial_audio_object)(
+                        render_stream,
+                        AUDIO_OBJECT_TYPE_DYNAMIC,
+                        &mut object,
+                    )
+                };
+                if hresult < 0 {
+                    return None;
+                }
+                self.available_dynamic_objects -= 1;
+                dynamic_objects.push((id, object, false));
+                dynamic_objects.len() - 1
+            },
+        };
+        let (_, object, ref mut rendered) = dynamic_objects[index];
+        *rendered = true;
+        unsafe {
+            ((*(*object).vtbl).set_position)(object, position[0], position[1], position[2]);
+            ((*(*object).vtbl).set_volume)(object, volume);
+            buffer(object, self.frames)
+        }
+    }
+}
+
+impl Renderer {
+    unsafe fn run(
+        &mut self,
+        terminate: HANDLE,
+        data_callback: &mut dyn FnMut(&mut SpatialBuffers),
+        error_callback: &mut dyn FnMut(StreamError),
+    ) {
+        let render_stream = self.render_stream;
+        let vtbl = &*(*render_stream).vtbl;
+        let handles = [terminate, self.event];
+        loop {
+            let result = synchapi::WaitForMultipleObjectsEx(
+                handles.len() as u32,
+                handles.as_ptr(),
+                FALSE,
+                winbase::INFINITE,
+                FALSE,
+            );
+            if result != winbase::WAIT_OBJECT_0 + 1 {
+                // Terminated, or the wait failed because the stream is being dropped.
+                break;
+            }
+
+            let mut available_dynamic_objects = 0;
+            let mut frames = 0;
+            let hresult = (vtbl.begin_updating_audio_objects)(
+                render_stream,
+                &mut available_dynamic_objects,
+                &mut frames,
+            );
+            if let Err(err) = stream_error_from_hresult(hresult) {
+                error_callback(err);
+                break;
+            }
+            for object in self.dynamic_objects.iter_mut() {
+                object.2 = false;
+            }
+            data_callback(&mut SpatialBuffers {
+                frames: frames as usize,
+                available_dynamic_objects,
+                renderer: self,
+            });
+            // End the dynamic objects that were not rendered in this period.
+            let mut index = 0;
+            while index < self.dynamic_objects.len() {
+                let (_, object, rendered) = self.dynamic_objects[index];
+                if rendered && is_active(object) {
+                    index += 1;
+                    continue;
+                }
+                ((*(*object).vtbl).set_end_of_stream)(object, 0);
+                release(object);
+                self.dynamic_objects.swap_remove(index);
+            }
+            let hresult = (vtbl.end_updating_audio_objects)(render_stream);
+            if let Err(err) = stream_error_from_hresult(hresult) {
+                error_callback(err);
+                break;
+            }
+        }
+        (vtbl.reset)(render_stream);
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        unsafe {
+            for &(_, object) in &self.static_objects {
+                release(object);
+            }
+            for &(_, object, _) in &self.dynamic_objects {
+                release(object);
+            }
+            ((*(*self.render_stream).vtbl).parent.Release)(self.render_stream as *mut IUnknown);
+            handleapi::CloseHandle(self.event);
+        }
+    }
+}
+
+// The buffer of `object` for the current period, cleared to silence.
+unsafe fn buffer<'a>(object: *mut ISpatialAudioObject, frames: usize) -> Option<&'a mut [f32]> {
+    let mut data = ptr::null_mut();
+    let mut len = 0;
+    let hresult = ((*(*object).vtbl).get_buffer)(object, &mut data, &mut len);
+    if hresult < 0 || data.is_null() {
+        return None;
+    }
+    let samples = (len as usize / mem::size_of::<f32>()).min(frames);
+    let buffer = slice::from_raw_parts_mut(data as *mut f32, samples);
+    for sample in buffer.iter_mut() {
+        *sample = 0.0;
+    }
+    Some(buffer)
+}
+
+unsafe fn is_active(object: *mut ISpatialAudioObject) -> bool {
+    let mut active = FALSE;
+    let hresult = ((*(*object).vtbl).is_active)(object, &mut active);
+    hresult >= 0 && active != FALSE
+}
+
+unsafe fn release(object: *mut ISpatialAudioObject) {
+    ((*(*object).vtbl).parent.Release)(object as *mut IUnknown);
+}
+
+fn event_error() -> BuildStreamError {
+    let description = "failed to create event".to_string();
+    BackendSpecificError { description, code: None }.into()
+}
+
+fn build_error(err: StreamError) -> BuildStreamError {
+    match err {
+        StreamError::BackendSpecific { err } => err.into(),
+        _ => BuildStreamError::DeviceNotAvailable,
+    }
+}
+
+fn play_error(err: StreamError) -> PlayStreamError {
+    match err {
+        StreamError::BackendSpecific { err } => err.into(),
+        _ => PlayStreamError::DeviceNotAvailable,
+    }
+}
+
+fn pause_error(err: StreamError) -> PauseStreamError {
+    match err {
+        StreamError::BackendSpecific { err } => err.into(),
+        _ => PauseStreamError::DeviceNotAvailable,
+    }
+}
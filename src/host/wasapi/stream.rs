@@ -1,63 +1,113 @@
 use super::check_result;
+use super::com;
+use super::session::{SessionDisconnect, SessionNotifications};
 use super::winapi::shared::basetsd::UINT32;
 use super::winapi::shared::minwindef::{BYTE, FALSE, WORD};
+use super::winapi::shared::winerror;
 use super::winapi::um::audioclient::{self, AUDCLNT_E_DEVICE_INVALIDATED, AUDCLNT_S_BUFFER_EMPTY};
 use super::winapi::um::handleapi;
+use super::winapi::um::mmdeviceapi::{
+    IMMDevice, DEVICE_STATE_ACTIVE, DEVICE_STATE_DISABLED, DEVICE_STATE_NOTPRESENT,
+    DEVICE_STATE_UNPLUGGED,
+};
+use super::winapi::um::profileapi;
 use super::winapi::um::synchapi;
+use super::winapi::um::timeapi;
 use super::winapi::um::winbase;
 use super::winapi::um::winnt;
 
+use std::cmp;
+use std::hint;
 use std::mem;
 use std::ptr;
 use std::slice;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::sync::mpsc::{sync_channel, RecvTimeoutError, SyncSender};
 
 use crate::traits::StreamTrait;
-use std::thread::{self, JoinHandle};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use spsc;
+use worker::{self, Worker};
 
 use BackendSpecificError;
+use BufferFlags;
+use BuildStreamError;
+use CallbackInfo;
+use DeviceLossReason;
 use PauseStreamError;
 use PlayStreamError;
+use RawStreamHandle;
 use SampleFormat;
+use SampleRate;
+use SlowCallbackPolicy;
+use stats::StreamMeter;
 use StreamData;
 use StreamError;
+use StreamOptions;
+use StreamPosition;
+use StreamStats;
 use UnknownTypeInputBuffer;
 use UnknownTypeOutputBuffer;
 
 pub struct Stream {
-    /// The high-priority audio processing thread calling callbacks.
-    /// Option used for moving out in destructor.
+    /// The high-priority audio processing thread calling callbacks, spawned on the first call to
+    /// `play`.
     ///
     /// TODO: Actually set the thread priority.
-    thread: Option<JoinHandle<()>>,
+    worker: Worker,
 
     // Commands processed by the `run()` method that is currently running.
     // `pending_scheduled_event` must be signalled whenever a command is added here, so that it
-    // will get picked up.
-    commands: Sender<Command>,
+    // will get picked up. The queue holds at most `COMMAND_QUEUE_CAPACITY` commands and never
+    // allocates nor blocks.
+    commands: spsc::Producer<Command>,
 
     // This event is signalled after a new entry is added to `commands`, so that the `run()`
     // method can be notified.
     pending_scheduled_event: winnt::HANDLE,
+
+    // Measurements of the data callback, shared with the audio processing thread.
+    meter: Arc<StreamMeter>,
+
+    // A reference to the audio client used by the audio thread, held for `raw_handle`.
+    audio_client: *mut audioclient::IAudioClient,
 }
 
+// The maximum number of commands waiting to be processed by the audio thread. Once reached,
+// `play` and `pause` fail until the audio thread catches up.
+const COMMAND_QUEUE_CAPACITY: usize = 32;
+
 struct RunContext {
     // Streams that have been created in this event loop.
     stream: StreamInner,
 
     // Handles corresponding to the `event` field of each element of `voices`. Must always be in
     // sync with `voices`, except that the first element is always `pending_scheduled_event`.
-    handles: Vec<winnt::HANDLE>,
+    handles: Vec<EventHandle>,
+
+    commands: spsc::Consumer<Command>,
 
-    commands: Receiver<Command>,
+    // The options with which the stream was built.
+    options: StreamOptions,
 }
 
-// Once we start running the eventloop, the RunContext will not be moved.
-unsafe impl Send for RunContext {}
+// The handle of an event object, which the audio thread waits on.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct EventHandle(winnt::HANDLE);
+
+// Kernel object handles are not tied to the thread that created them, and events may be signalled
+// and waited on from any thread.
+unsafe impl Send for EventHandle {}
+
+// Receives the outcome of a command once it has been processed by the audio thread.
+type Ack = SyncSender<Result<(), StreamError>>;
 
 pub enum Command {
-    PlayStream,
-    PauseStream,
+    PlayStream(Option<Ack>),
+    PauseStream(Option<Ack>),
     Terminate,
 }
 
@@ -70,7 +120,21 @@ pub enum AudioClientFlow {
     },
 }
 
+// The clock of an audio client, used to report the position of the stream.
+pub struct AudioClock {
+    clock: *mut audioclient::IAudioClock,
+    // The number of clock units per second.
+    frequency: u64,
+    // The sample rate of the stream, used to convert clock units to frames.
+    sample_rate: SampleRate,
+    // The frequency of the performance counter, which is fixed at boot.
+    qpc_frequency: u128,
+}
+
 pub struct StreamInner {
+    // The device of the stream, holding a reference. Its state tells why the stream was
+    // invalidated. Null for process loopback streams, which belong to no endpoint.
+    pub device: *mut IMMDevice,
     pub audio_client: *mut audioclient::IAudioClient,
     pub client_flow: AudioClientFlow,
     // Event that is signalled by WASAPI whenever audio data must be written.
@@ -83,45 +147,116 @@ pub struct StreamInner {
     pub bytes_per_frame: WORD,
     // The sample format with which the stream was created.
     pub sample_format: SampleFormat,
+    // Measurements of the data callback.
+    pub meter: Arc<StreamMeter>,
+    // The device clock, if the audio client provides one.
+    pub clock: Option<AudioClock>,
+    // The interval at which the audio thread polls the audio client, if WASAPI does not signal
+    // `event`.
+    pub poll_interval: Option<Duration>,
+    // The notifications of the audio session, which tell why the stream was invalidated. Signals
+    // `event` when the session is disconnected.
+    pub session: Option<SessionNotifications>,
 }
 
+// The stream is moved to the audio thread once built and only used there. Its COM objects are
+// free-threaded, so they may be called from any thread whatever the apartment of the thread that
+// created them, and `event` is an event handle.
+unsafe impl Send for StreamInner {}
+
 impl Stream {
     pub(crate) fn new<D, E>(
         stream_inner: StreamInner,
+        options: &StreamOptions,
         mut data_callback: D,
         mut error_callback: E,
-    ) -> Stream
+    ) -> Result<Stream, BuildStreamError>
     where
         D: FnMut(StreamData) + Send + 'static,
         E: FnMut(StreamError) + Send + 'static,
     {
         let pending_scheduled_event =
             unsafe { synchapi::CreateEventA(ptr::null_mut(), 0, 0, ptr::null()) };
-        let (tx, rx) = channel();
+        let (tx, rx) = spsc::queue(COMMAND_QUEUE_CAPACITY);
+        let meter = stream_inner.meter.clone();
+        let audio_client = stream_inner.audio_client;
+        unsafe {
+            (*audio_client).AddRef();
+        }
 
         let run_context = RunContext {
-            handles: vec![pending_scheduled_event, stream_inner.event],
+            handles: vec![EventHandle(pending_scheduled_event), EventHandle(stream_inner.event)],
             stream: stream_inner,
             commands: rx,
+            options: options.clone(),
         };
 
-        let thread =
-            thread::spawn(move || run_inner(run_context, &mut data_callback, &mut error_callback));
-
-        Stream {
-            thread: Some(thread),
+        let worker = Worker::new(options.thread_spawner.clone(), move || {
+            // The audio thread uses and releases the COM objects of the stream, so it keeps COM
+            // initialized itself rather than relying on the thread that built the stream.
+            com::own_thread_com_initialized();
+            worker::catch_panics(&mut error_callback, |error_callback| {
+                run_inner(run_context, &mut data_callback, error_callback)
+            });
+        });
+
+        Ok(Stream {
+            worker,
             commands: tx,
             pending_scheduled_event,
+            meter,
+            audio_client,
+        })
+    }
+
+    pub fn raw_handle(&self) -> Option<RawStreamHandle> {
+        Some(RawStreamHandle::Wasapi { audio_client: self.audio_client as *mut _ })
+    }
+
+    #[inline]
+    fn push_command(&self, command: Command) -> Result<(), BackendSpecificError> {
+        if self.commands.push(command).is_err() {
+            let description = String::from(
+                "the command queue of the stream is full: the audio thread is not responding",
+            );
+            return Err(BackendSpecificError { description, code: None });
+        }
+        self.signal_commands();
+        Ok(())
+    }
+
+    // Pushes the command built by `command` and waits until the audio thread has processed it.
+    fn push_command_and_wait<F>(&self, command: F) -> Result<(), StreamError>
+    where
+        F: FnOnce(Option<Ack>) -> Command,
+    {
+        let (tx, rx) = sync_channel(1);
+        self.push_command(command(Some(tx)))?;
+        loop {
+            // The command stays in the queue if the audio thread stopped because of an error.
+            match rx.recv_timeout(Duration::from_millis(10)) {
+                Ok(result) => return result,
+                Err(RecvTimeoutError::Timeout) if !self.thread_finished() => continue,
+                Err(_) => {
+                    let description =
+                        String::from("the audio thread stopped before processing the command");
+                    return Err(BackendSpecificError { description, code: None }.into());
+                }
+            }
         }
     }
 
     #[inline]
-    fn push_command(&self, command: Command) {
-        // Safe to unwrap: sender outlives receiver.
-        self.commands.send(command).unwrap();
+    fn thread_finished(&self) -> bool {
+        self.worker.is_finished()
+    }
+
+    #[inline]
+    fn signal_commands(&self) {
+        // `SetEvent` only fails for an invalid handle. This is also called when the stream is
+        // dropped, which must not panic.
         unsafe {
-            let result = synchapi::SetEvent(self.pending_scheduled_event);
-            assert_ne!(result, 0);
+            synchapi::SetEvent(self.pending_scheduled_event);
         }
     }
 }
@@ -129,27 +264,80 @@ impl Stream {
 impl Drop for Stream {
     #[inline]
     fn drop(&mut self) {
-        self.push_command(Command::Terminate);
-        self.thread.take().unwrap().join().unwrap();
+        // Wait for room in the queue, the audio thread must be terminated before it is joined.
+        if self.worker.is_started() {
+            let mut command = Command::Terminate;
+            while let Err(rejected) = self.commands.push(command) {
+                if self.thread_finished() {
+                    break;
+                }
+                command = rejected;
+                thread::yield_now();
+            }
+            self.signal_commands();
+        }
+        self.worker.join();
         unsafe {
             handleapi::CloseHandle(self.pending_scheduled_event);
+            (*self.audio_client).Release();
         }
     }
 }
 
 impl StreamTrait for Stream {
     fn play(&self) -> Result<(), PlayStreamError> {
-        self.push_command(Command::PlayStream);
+        self.push_command(Command::PlayStream(None))?;
+        self.worker.start()?;
         Ok(())
     }
     fn pause(&self) -> Result<(), PauseStreamError> {
-        self.push_command(Command::PauseStream);
+        // A stream that was never played is already paused.
+        if !self.worker.is_started() {
+            return Ok(());
+        }
+        self.push_command(Command::PauseStream(None))?;
         Ok(())
     }
+    fn play_blocking(&self) -> Result<(), PlayStreamError> {
+        self.worker.start()?;
+        self.push_command_and_wait(Command::PlayStream)
+            .map_err(|err| match err {
+                StreamError::DeviceNotAvailable { .. } => PlayStreamError::DeviceNotAvailable,
+                StreamError::BackendSpecific { err } => err.into(),
+                // Commands do not report xruns.
+                err => BackendSpecificError { description: err.to_string(), code: None }.into(),
+            })
+    }
+    fn pause_blocking(&self) -> Result<(), PauseStreamError> {
+        if !self.worker.is_started() {
+            return Ok(());
+        }
+        self.push_command_and_wait(Command::PauseStream)
+            .map_err(|err| match err {
+                StreamError::DeviceNotAvailable { .. } => PauseStreamError::DeviceNotAvailable,
+                StreamError::BackendSpecific { err } => err.into(),
+                err => BackendSpecificError { description: err.to_string(), code: None }.into(),
+            })
+    }
+    fn callback_load(&self) -> f32 {
+        self.meter.callback_load()
+    }
+    fn stats(&self) -> StreamStats {
+        self.meter.stats()
+    }
+
+    fn last_callback(&self) -> Option<Instant> {
+        self.meter.last_callback()
+    }
+
+    fn position(&self) -> Option<StreamPosition> {
+        self.meter.position()
+    }
 }
 
 impl Drop for AudioClientFlow {
     fn drop(&mut self) {
+        // The client was obtained with `GetService`, which added the reference released here.
         unsafe {
             match *self {
                 AudioClientFlow::Capture { capture_client } => (*capture_client).Release(),
@@ -159,40 +347,155 @@ impl Drop for AudioClientFlow {
     }
 }
 
+impl AudioClock {
+    // Obtains the clock of the given audio client, or `None` if the client does not provide one.
+    pub unsafe fn new(
+        audio_client: *mut audioclient::IAudioClient,
+        sample_rate: SampleRate,
+    ) -> Option<AudioClock> {
+        let mut clock: *mut audioclient::IAudioClock = ptr::null_mut();
+        let hresult = (*audio_client).GetService(
+            &audioclient::IID_IAudioClock,
+            &mut clock as *mut *mut audioclient::IAudioClock as *mut _,
+        );
+        if check_result(hresult).is_err() {
+            return None;
+        }
+        let mut frequency = 0;
+        let hresult = (*clock).GetFrequency(&mut frequency);
+        if check_result(hresult).is_err() || frequency == 0 {
+            (*clock).Release();
+            return None;
+        }
+        let mut qpc_frequency: winnt::LARGE_INTEGER = mem::zeroed();
+        profileapi::QueryPerformanceFrequency(&mut qpc_frequency);
+        Some(AudioClock {
+            clock,
+            frequency,
+            sample_rate,
+            qpc_frequency: cmp::max(*qpc_frequency.QuadPart(), 1) as u128,
+        })
+    }
+}
+
+impl Drop for AudioClock {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe {
+            (*self.clock).Release();
+        }
+    }
+}
+
 impl Drop for StreamInner {
     #[inline]
     fn drop(&mut self) {
+        // The stream owns a reference to the audio client and the event handle.
+        com::com_initialized();
+        // Unregistered first, as the notifications signal `event`.
+        self.session = None;
         unsafe {
             (*self.audio_client).Release();
+            if !self.device.is_null() {
+                (*self.device).Release();
+            }
             handleapi::CloseHandle(self.event);
         }
     }
 }
 
+// A buffer of captured data whose storage is aligned for every sample format.
+struct Staging {
+    words: Vec<u32>,
+    // The number of bytes in use.
+    len: usize,
+}
+
+impl Staging {
+    fn with_capacity(bytes: usize) -> Self {
+        Staging {
+            words: Vec::with_capacity(bytes.div_ceil(4)),
+            len: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Appends `data` and returns the appended bytes.
+    fn push(&mut self, data: &[BYTE]) -> &mut [BYTE] {
+        let start = self.len;
+        self.len += data.len();
+        let words = self.len.div_ceil(4);
+        if self.words.len() < words {
+            self.words.resize(words, 0);
+        }
+        // The first `len` bytes of `words` are initialized, and any byte pattern is a valid `BYTE`.
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut BYTE, self.len)
+        };
+        bytes[start..].copy_from_slice(data);
+        &mut bytes[start..]
+    }
+
+    fn bytes(&self) -> &[BYTE] {
+        unsafe { slice::from_raw_parts(self.words.as_ptr() as *const BYTE, self.len) }
+    }
+
+    fn samples<T>(&self) -> &[T] {
+        let len = self.len / mem::size_of::<T>();
+        // `words` is aligned for every sample format, and the stream only stages samples of `T`.
+        unsafe { slice::from_raw_parts(self.words.as_ptr() as *const T, len) }
+    }
+}
+
+// Write silence in the given sample format to `bytes`.
+fn write_silence(bytes: &mut [BYTE], sample_format: SampleFormat) {
+    match sample_format {
+        SampleFormat::U16 => {
+            for sample in bytes.chunks_mut(2) {
+                sample.copy_from_slice(&32768u16.to_ne_bytes());
+            }
+        }
+        SampleFormat::I16 | SampleFormat::F32 => {
+            for byte in bytes.iter_mut() {
+                *byte = 0;
+            }
+        }
+    }
+}
+
 // Process any pending commands that are queued within the `RunContext`.
 // Returns `true` if the loop should continue running, `false` if it should terminate.
 fn process_commands(run_context: &mut RunContext) -> Result<bool, StreamError> {
     // Process the pending commands.
-    for command in run_context.commands.try_iter() {
+    while let Some(command) = run_context.commands.pop() {
+        run_context.stream.meter.record_command();
         match command {
-            Command::PlayStream => {
+            Command::PlayStream(ack) => {
+                let mut result = Ok(());
                 if !run_context.stream.playing {
                     let hresult = unsafe { (*run_context.stream.audio_client).Start() };
-
-                    if let Err(err) = stream_error_from_hresult(hresult) {
-                        return Err(err);
-                    }
-                    run_context.stream.playing = true;
+                    result = stream_error_from_hresult(hresult);
+                    run_context.stream.playing = result.is_ok();
                 }
+                acknowledge(ack, &result);
+                result?;
             }
-            Command::PauseStream => {
+            Command::PauseStream(ack) => {
+                let mut result = Ok(());
                 if run_context.stream.playing {
                     let hresult = unsafe { (*run_context.stream.audio_client).Stop() };
-                    if let Err(err) = stream_error_from_hresult(hresult) {
-                        return Err(err);
-                    }
-                    run_context.stream.playing = false;
+                    result = stream_error_from_hresult(hresult);
+                    run_context.stream.playing = result.is_err();
                 }
+                acknowledge(ack, &result);
+                result?;
             }
             Command::Terminate => {
                 return Ok(false);
@@ -202,6 +505,15 @@ fn process_commands(run_context: &mut RunContext) -> Result<bool, StreamError> {
 
     Ok(true)
 }
+
+// Report the outcome of a command to the thread waiting for it, if any.
+fn acknowledge(ack: Option<Ack>, result: &Result<(), StreamError>) {
+    if let Some(ack) = ack {
+        // The waiting thread may have given up.
+        let _ = ack.send(result.clone());
+    }
+}
+
 // Wait for any of the given handles to be signalled.
 //
 // Returns the index of the `handle` that was signalled, or an `Err` if
@@ -210,21 +522,50 @@ fn process_commands(run_context: &mut RunContext) -> Result<bool, StreamError> {
 // This is called when the `run` thread is ready to wait for the next event. The
 // next event might be some command submitted by the user (the first handle) or
 // might indicate that one of the streams is ready to deliver or receive audio.
-fn wait_for_handle_signal(handles: &[winnt::HANDLE]) -> Result<usize, BackendSpecificError> {
+//
+// The handles are polled for up to `spin` before blocking. If no handle is signalled within
+// `timeout`, the stream is assumed to need data and the index of the last handle is returned.
+fn wait_for_handle_signal(
+    handles: &[EventHandle],
+    spin: Duration,
+    timeout: Option<Duration>,
+) -> Result<usize, BackendSpecificError> {
     debug_assert!(handles.len() <= winnt::MAXIMUM_WAIT_OBJECTS as usize);
-    let result = unsafe {
+    let wait = |timeout| unsafe {
         synchapi::WaitForMultipleObjectsEx(
             handles.len() as u32,
-            handles.as_ptr(),
-            FALSE,             // Don't wait for all, just wait for the first
-            winbase::INFINITE, // TODO: allow setting a timeout
-            FALSE,             // irrelevant parameter here
+            // `EventHandle` is a transparent wrapper around a handle.
+            handles.as_ptr() as *const winnt::HANDLE,
+            FALSE,   // Don't wait for all, just wait for the first
+            timeout,
+            FALSE,   // irrelevant parameter here
         )
     };
+    let mut result = winerror::WAIT_TIMEOUT;
+    if spin > Duration::from_secs(0) {
+        let deadline = Instant::now() + spin;
+        loop {
+            result = wait(0);
+            if result != winerror::WAIT_TIMEOUT || Instant::now() >= deadline {
+                break;
+            }
+            hint::spin_loop();
+        }
+    }
+    if result == winerror::WAIT_TIMEOUT {
+        let timeout = match timeout {
+            Some(timeout) => timeout.saturating_sub(spin).as_millis() as u32,
+            None => winbase::INFINITE,
+        };
+        result = wait(timeout);
+        if result == winerror::WAIT_TIMEOUT {
+            return Ok(handles.len() - 1);
+        }
+    }
     if result == winbase::WAIT_FAILED {
         let err = unsafe { winapi::um::errhandlingapi::GetLastError() };
         let description = format!("`WaitForMultipleObjectsEx failed: {}", err);
-        let err = BackendSpecificError { description };
+        let err = BackendSpecificError { description, code: Some(err as i32) };
         return Err(err);
     }
     // Notifying the corresponding task handler.
@@ -234,46 +575,167 @@ fn wait_for_handle_signal(handles: &[winnt::HANDLE]) -> Result<usize, BackendSpe
 
 // Get the number of available frames that are available for writing/reading.
 fn get_available_frames(stream: &StreamInner) -> Result<u32, StreamError> {
+    // The audio client is valid for as long as `stream` exists.
     unsafe {
-        let mut padding = mem::uninitialized();
+        let mut padding = 0;
         let hresult = (*stream.audio_client).GetCurrentPadding(&mut padding);
         stream_error_from_hresult(hresult)?;
         Ok(stream.max_frames_in_buffer - padding)
     }
 }
 
+// Record the position of the device clock in the stream meter.
+fn record_position(stream: &StreamInner) {
+    let clock = match stream.clock {
+        Some(ref clock) => clock,
+        None => return,
+    };
+    unsafe {
+        let mut position = 0;
+        let mut qpc_position = 0;
+        let hresult = (*clock.clock).GetPosition(&mut position, &mut qpc_position);
+        if check_result(hresult).is_err() {
+            return;
+        }
+        let now = Instant::now();
+        let mut qpc_now: winnt::LARGE_INTEGER = mem::zeroed();
+        profileapi::QueryPerformanceCounter(&mut qpc_now);
+        // `qpc_position` is expressed in units of 100 nanoseconds.
+        let now_100ns = *qpc_now.QuadPart() as u128 * 10_000_000 / clock.qpc_frequency;
+        let qpc_position = qpc_position as u128;
+        let time = if qpc_position >= now_100ns {
+            now + Duration::from_nanos(((qpc_position - now_100ns) * 100) as u64)
+        } else {
+            now - Duration::from_nanos(((now_100ns - qpc_position) * 100) as u64)
+        };
+        let frame = position as u128 * clock.sample_rate.0 as u128 / clock.frequency as u128;
+        stream.meter.record_position(frame as u64, time);
+    }
+}
+
+// WASAPI only reports that the device of a stream was invalidated. The session tells why it was
+// disconnected, and otherwise the state of the device tells whether it is gone or was only
+// reconfigured.
+fn with_device_loss_reason(
+    device: *mut IMMDevice,
+    session: Option<&SessionDisconnect>,
+    err: StreamError,
+) -> StreamError {
+    match err {
+        StreamError::DeviceNotAvailable { reason: DeviceLossReason::Unknown } => {
+            if let Some(reason) = session.and_then(SessionDisconnect::reason) {
+                return StreamError::DeviceNotAvailable { reason };
+            }
+            if device.is_null() {
+                return StreamError::DeviceNotAvailable { reason: DeviceLossReason::Unknown };
+            }
+            let mut state = 0;
+            let hresult = unsafe { (*device).GetState(&mut state) };
+            let reason = match state {
+                _ if check_result(hresult).is_err() => DeviceLossReason::Unknown,
+                DEVICE_STATE_ACTIVE => DeviceLossReason::Reconfigured,
+                DEVICE_STATE_DISABLED => DeviceLossReason::Disabled,
+                DEVICE_STATE_NOTPRESENT => DeviceLossReason::Removed,
+                DEVICE_STATE_UNPLUGGED => DeviceLossReason::Unplugged,
+                _ => DeviceLossReason::Unknown,
+            };
+            StreamError::DeviceNotAvailable { reason }
+        }
+        err => err,
+    }
+}
+
 // Convert the given `HRESULT` into a `StreamError` if it does indicate an error.
-fn stream_error_from_hresult(hresult: winnt::HRESULT) -> Result<(), StreamError> {
+#[inline]
+pub(super) fn stream_error_from_hresult(hresult: winnt::HRESULT) -> Result<(), StreamError> {
+    // Success codes are the common case on the audio thread.
+    if hresult >= 0 {
+        return Ok(());
+    }
     if hresult == AUDCLNT_E_DEVICE_INVALIDATED {
-        return Err(StreamError::DeviceNotAvailable);
+        // Refined by `with_device_loss_reason` before being reported.
+        let reason = DeviceLossReason::Unknown;
+        return Err(StreamError::DeviceNotAvailable { reason });
     }
     if let Err(err) = check_result(hresult) {
         let description = format!("{}", err);
-        let err = BackendSpecificError { description };
+        let err = BackendSpecificError { description, code: Some(hresult) };
         return Err(err.into());
     }
     Ok(())
 }
 
+// Raises the resolution of the system timer to `period` milliseconds while it exists.
+struct TimerResolution {
+    period: u32,
+}
+
+impl TimerResolution {
+    fn new(period: u32) -> Self {
+        unsafe {
+            timeapi::timeBeginPeriod(period);
+        }
+        TimerResolution { period }
+    }
+}
+
+impl Drop for TimerResolution {
+    fn drop(&mut self) {
+        unsafe {
+            timeapi::timeEndPeriod(self.period);
+        }
+    }
+}
+
 fn run_inner(
     mut run_context: RunContext,
     data_callback: &mut dyn FnMut(StreamData),
     error_callback: &mut dyn FnMut(StreamError),
 ) {
+    let device = run_context.stream.device;
+    let session = run_context.stream.session.as_ref().map(SessionNotifications::disconnect);
+    let error_callback =
+        &mut |err| error_callback(with_device_loss_reason(device, session.as_ref(), err));
+    // Whether the render buffer has been filled at least once.
+    let mut primed = false;
+    let spin_wait = run_context.options.spin_wait;
+    // Spinning is pointless if the thread then oversleeps by a whole timer tick.
+    let _timer_resolution = if spin_wait > Duration::from_secs(0) {
+        Some(TimerResolution::new(1))
+    } else {
+        None
+    };
+    // These never change while the stream exists, so they are not derived again for each buffer.
+    let sample_size = run_context.stream.sample_format.sample_size();
+    let bytes_per_frame = run_context.stream.bytes_per_frame as usize;
+    let max_buffer_bytes = run_context.stream.max_frames_in_buffer as usize * bytes_per_frame;
+    // The last buffer written to the device, used to conceal underruns. Allocated up front so that
+    // processing a buffer does not allocate.
+    let mut last_buffer: Vec<BYTE> = match run_context.options.slow_callback {
+        SlowCallbackPolicy::Repeat => Vec::with_capacity(max_buffer_bytes),
+        _ => Vec::new(),
+    };
+
+    // Captured packets waiting to be passed to the data callback at once, when coalescing input.
+    let mut staging = if run_context.options.coalesce_input {
+        Staging::with_capacity(max_buffer_bytes)
+    } else {
+        Staging::with_capacity(0)
+    };
+
+    // The COM objects of `run_context.stream` remain valid until it is dropped with the context.
+    // The buffers obtained from `GetBuffer` are only accessed until the matching `ReleaseBuffer`.
     unsafe {
         'stream_loop: loop {
-            // Process queued commands.
-            match process_commands(&mut run_context) {
-                Ok(true) => (),
-                Ok(false) => break,
-                Err(err) => {
-                    error_callback(err);
-                    break 'stream_loop;
-                }
-            };
-
             // Wait for any of the handles to be signalled.
-            let handle_idx = match wait_for_handle_signal(&run_context.handles) {
+            let wait_start = Instant::now();
+            let handle_idx = wait_for_handle_signal(
+                &run_context.handles,
+                spin_wait,
+                run_context.stream.poll_interval,
+            );
+            run_context.stream.meter.record_wakeup(wait_start.elapsed());
+            let handle_idx = match handle_idx {
                 Ok(idx) => idx,
                 Err(err) => {
                     error_callback(err.into());
@@ -284,19 +746,37 @@ fn run_inner(
             // If `handle_idx` is 0, then it's `pending_scheduled_event` that was signalled in
             // order for us to pick up the pending commands. Otherwise, a stream needs data.
             if handle_idx == 0 {
+                match process_commands(&mut run_context) {
+                    Ok(true) => continue,
+                    Ok(false) => break,
+                    Err(err) => {
+                        error_callback(err);
+                        break 'stream_loop;
+                    }
+                }
+            }
+
+            // The wait times out in polling mode whether the stream plays or not, but the device
+            // only consumes or produces data while it plays.
+            if !run_context.stream.playing {
                 continue;
             }
 
             let stream = &mut run_context.stream;
-            let sample_size = stream.sample_format.sample_size();
 
             // Obtaining a pointer to the buffer.
             match stream.client_flow {
                 AudioClientFlow::Capture { capture_client } => {
                     let mut frames_available = 0;
                     // Get the available data in the shared buffer.
-                    let mut buffer: *mut BYTE = mem::uninitialized();
-                    let mut flags = mem::uninitialized();
+                    let mut buffer: *mut BYTE = ptr::null_mut();
+                    let mut flags = 0;
+                    // The flags of the packets copied to `staging`.
+                    let mut staged_flags = BufferFlags {
+                        silent: true,
+                        discontinuity: false,
+                    };
+                    staging.clear();
                     loop {
                         let hresult = (*capture_client).GetNextPacketSize(&mut frames_available);
                         if let Err(err) = stream_error_from_hresult(hresult) {
@@ -325,22 +805,55 @@ fn run_inner(
                         debug_assert!(!buffer.is_null());
 
                         let buffer_len = frames_available as usize
-                            * stream.bytes_per_frame as usize
+                            * bytes_per_frame
                             / sample_size;
 
+                        let flags = BufferFlags {
+                            silent: flags & audioclient::AUDCLNT_BUFFERFLAGS_SILENT != 0,
+                            discontinuity: flags
+                                & audioclient::AUDCLNT_BUFFERFLAGS_DATA_DISCONTINUITY
+                                != 0,
+                        };
+                        let info = CallbackInfo::new(
+                            frames_available as usize,
+                            flags.discontinuity,
+                            flags,
+                        );
+                        if flags.discontinuity {
+                            stream.meter.record_overrun();
+                            let buffer_frames = stream.max_frames_in_buffer as usize;
+                            error_callback(StreamError::Overrun { buffer_frames });
+                        }
+
+                        if run_context.options.coalesce_input {
+                            let len = frames_available as usize * bytes_per_frame;
+                            let staged = staging.push(slice::from_raw_parts(buffer, len));
+                            // The content of silent packets must be ignored.
+                            if flags.silent {
+                                write_silence(staged, stream.sample_format);
+                            }
+                            staged_flags.silent &= flags.silent;
+                            staged_flags.discontinuity |= flags.discontinuity;
+                            let hresult = (*capture_client).ReleaseBuffer(frames_available);
+                            if let Err(err) = stream_error_from_hresult(hresult) {
+                                error_callback(err);
+                                break 'stream_loop;
+                            }
+                            continue;
+                        }
+
                         // Simplify the capture callback sample format branches.
                         macro_rules! capture_callback {
                             ($T:ty, $Variant:ident) => {{
                                 let buffer_data = buffer as *mut _ as *const $T;
                                 let slice = slice::from_raw_parts(buffer_data, buffer_len);
                                 let unknown_buffer =
-                                    UnknownTypeInputBuffer::$Variant(::InputBuffer {
-                                        buffer: slice,
-                                    });
+                                    UnknownTypeInputBuffer::$Variant(::InputBuffer::new(slice));
                                 let data = StreamData::Input {
                                     buffer: unknown_buffer,
+                                    info,
                                 };
-                                data_callback(data);
+                                stream.meter.measure(&info, || data_callback(data));
                                 // Release the buffer.
                                 let hresult = (*capture_client).ReleaseBuffer(frames_available);
                                 if let Err(err) = stream_error_from_hresult(hresult) {
@@ -356,11 +869,37 @@ fn run_inner(
                             SampleFormat::U16 => capture_callback!(u16, U16),
                         }
                     }
+
+                    // Deliver the coalesced packets.
+                    if !staging.is_empty() {
+                        let frames = staging.bytes().len() / bytes_per_frame;
+                        let info = CallbackInfo::new(
+                            frames,
+                            staged_flags.discontinuity,
+                            staged_flags,
+                        );
+                        let unknown_buffer = match stream.sample_format {
+                            SampleFormat::F32 => UnknownTypeInputBuffer::F32(::InputBuffer::new(
+                                staging.samples(),
+                            )),
+                            SampleFormat::I16 => UnknownTypeInputBuffer::I16(::InputBuffer::new(
+                                staging.samples(),
+                            )),
+                            SampleFormat::U16 => UnknownTypeInputBuffer::U16(::InputBuffer::new(
+                                staging.samples(),
+                            )),
+                        };
+                        let data = StreamData::Input {
+                            buffer: unknown_buffer,
+                            info,
+                        };
+                        stream.meter.measure(&info, || data_callback(data));
+                    }
                 }
 
                 AudioClientFlow::Render { render_client } => {
                     // The number of frames available for writing.
-                    let frames_available = match get_available_frames(&stream) {
+                    let mut frames_available = match get_available_frames(&stream) {
                         Ok(0) => continue, // TODO: Can this happen?
                         Ok(n) => n,
                         Err(err) => {
@@ -369,7 +908,45 @@ fn run_inner(
                         }
                     };
 
-                    let mut buffer: *mut BYTE = mem::uninitialized();
+                    // The whole buffer is free when the client has not been fed yet or when it
+                    // ran dry since the last callback.
+                    let recovery = frames_available == stream.max_frames_in_buffer;
+                    if recovery && primed {
+                        stream.meter.record_underrun();
+                        match run_context.options.slow_callback {
+                            SlowCallbackPolicy::Silence => (),
+                            SlowCallbackPolicy::Repeat => {
+                                // Play the previous buffer again before the new one.
+                                let frames = (last_buffer.len() / bytes_per_frame)
+                                    .min(frames_available as usize);
+                                let mut buffer: *mut BYTE = ptr::null_mut();
+                                let hresult = (*render_client)
+                                    .GetBuffer(frames as u32, &mut buffer as *mut *mut _);
+                                if let Err(err) = stream_error_from_hresult(hresult) {
+                                    error_callback(err);
+                                    break 'stream_loop;
+                                }
+                                let len = frames * bytes_per_frame;
+                                ptr::copy_nonoverlapping(last_buffer.as_ptr(), buffer, len);
+                                let hresult = (*render_client).ReleaseBuffer(frames as u32, 0);
+                                if let Err(err) = stream_error_from_hresult(hresult) {
+                                    error_callback(err);
+                                    break 'stream_loop;
+                                }
+                                frames_available -= frames as u32;
+                                if frames_available == 0 {
+                                    continue;
+                                }
+                            }
+                            SlowCallbackPolicy::Error => {
+                                let buffer_frames = stream.max_frames_in_buffer as usize;
+                                error_callback(StreamError::Underrun { buffer_frames });
+                            }
+                        }
+                    }
+                    primed = true;
+
+                    let mut buffer: *mut BYTE = ptr::null_mut();
                     let hresult =
                         (*render_client).GetBuffer(frames_available, &mut buffer as *mut *mut _);
 
@@ -380,19 +957,30 @@ fn run_inner(
 
                     debug_assert!(!buffer.is_null());
                     let buffer_len =
-                        frames_available as usize * stream.bytes_per_frame as usize / sample_size;
+                        frames_available as usize * bytes_per_frame / sample_size;
+                    let info =
+                        CallbackInfo::new(frames_available as usize, recovery, Default::default());
 
                     // Simplify the render callback sample format branches.
                     macro_rules! render_callback {
                         ($T:ty, $Variant:ident) => {{
                             let buffer_data = buffer as *mut $T;
                             let slice = slice::from_raw_parts_mut(buffer_data, buffer_len);
-                            let unknown_buffer =
-                                UnknownTypeOutputBuffer::$Variant(::OutputBuffer { buffer: slice });
+                            let mut unknown_buffer =
+                                UnknownTypeOutputBuffer::$Variant(::OutputBuffer::new(slice));
+                            if run_context.options.zero_output_buffers {
+                                unknown_buffer.fill_silence();
+                            }
                             let data = StreamData::Output {
                                 buffer: unknown_buffer,
+                                info,
                             };
-                            data_callback(data);
+                            stream.meter.measure(&info, || data_callback(data));
+                            if run_context.options.slow_callback == SlowCallbackPolicy::Repeat {
+                                let len = buffer_len * sample_size;
+                                last_buffer.clear();
+                                last_buffer.extend_from_slice(slice::from_raw_parts(buffer, len));
+                            }
                             let hresult =
                                 (*render_client).ReleaseBuffer(frames_available as u32, 0);
                             if let Err(err) = stream_error_from_hresult(hresult) {
@@ -409,6 +997,8 @@ fn run_inner(
                     }
                 }
             }
+
+            record_position(stream);
         }
     }
 }
@@ -0,0 +1,204 @@
+//! Measuring the round-trip latency of an output and an input device.
+//!
+//! `measure_round_trip` plays a chirp on the output device while capturing the input device, finds
+//! the chirp in the captured audio and compares the times at which the two devices played and
+//! captured it. The devices must be connected, e.g. with a loopback cable or by placing the
+//! microphone next to the speakers.
+//!
+//! ```no_run
+//! use cpal::traits::HostTrait;
+//! use std::time::Duration;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let host = cpal::default_host();
+//! let input = host.default_input_device().expect("no input device available");
+//! let output = host.default_output_device().expect("no output device available");
+//! let latency = cpal::latency::measure_round_trip(&input, &output, Duration::from_secs(1))?;
+//! println!("round-trip latency: {:?}", latency);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use playback::{default_format_error, play_samples};
+use signal::Sweep;
+use traits::{DeviceTrait, StreamTrait};
+use Format;
+use MeasureLatencyError;
+use Sample;
+use SampleFormat;
+use SampleRate;
+use StreamPosition;
+
+// The silence played before the chirp, while the streams settle.
+const LEAD_IN: Duration = Duration::from_millis(200);
+const CHIRP_DURATION: Duration = Duration::from_millis(100);
+const CHIRP_FADE: Duration = Duration::from_millis(5);
+// How closely the captured audio must match the chirp, from `0.0` to `1.0`.
+const DETECTION_THRESHOLD: f32 = 0.5;
+
+/// Measures the time between the playback of a sound on `output` and its capture on `input`.
+///
+/// The streams use the default input format of `input`, and the same sample rate on `output`.
+/// The chirp is searched for in the audio captured up to `max_latency` after it was played. This
+/// blocks for the duration of the measurement, i.e. a few hundred milliseconds plus `max_latency`.
+///
+/// The result includes the latencies of both devices and of what connects them. It is only as
+/// accurate as the stream positions reported by the hosts.
+pub fn measure_round_trip<I, O>(
+    input: &I,
+    output: &O,
+    max_latency: Duration,
+) -> Result<Duration, MeasureLatencyError>
+where
+    I: DeviceTrait,
+    O: DeviceTrait,
+{
+    let format = input.default_input_format().map_err(default_format_error)?;
+    match format.data_type {
+        SampleFormat::I16 => measure::<_, _, i16>(input, output, &format, max_latency),
+        SampleFormat::U16 => measure::<_, _, u16>(input, output, &format, max_latency),
+        SampleFormat::F32 => measure::<_, _, f32>(input, output, &format, max_latency),
+    }
+}
+
+fn measure<I, O, T>(
+    input: &I,
+    output: &O,
+    format: &Format,
+    max_latency: Duration,
+) -> Result<Duration, MeasureLatencyError>
+where
+    I: DeviceTrait,
+    O: DeviceTrait,
+    T: Sample + Send + 'static,
+{
+    let sample_rate = format.sample_rate;
+    let chirp = chirp(sample_rate);
+    let lead_in = frames(LEAD_IN, sample_rate);
+    let output_channels = output
+        .default_output_format()
+        .map_err(default_format_error)?
+        .channels;
+    let frame_len = output_channels as usize;
+    let mut samples = vec![0.0; (lead_in + chirp.len()) * frame_len];
+    let frames_out = samples[lead_in * frame_len..].chunks_exact_mut(frame_len);
+    for (frame, &value) in frames_out.zip(chirp.iter()) {
+        for sample in frame.iter_mut() {
+            *sample = value;
+        }
+    }
+
+    // The captured frames are never popped during the measurement, so that their index in the
+    // ring buffer is their index in the stream. Only the last frames are dropped if the ring
+    // buffer fills up.
+    let channels = (format.channels as usize).max(1);
+    let capacity = 2 * (lead_in + chirp.len() + frames(max_latency, sample_rate)) * channels;
+    let capture = input.capture_to_ring::<T>(format, capacity)?;
+    let playback = play_samples(output, sample_rate, output_channels, samples)?;
+    playback.wait()?;
+    thread::sleep(max_latency);
+
+    let output_position = playback.stream().position();
+    let input_position = capture.stream().position();
+    if let Some(err) = capture.error() {
+        return Err(err.into());
+    }
+    let (output_position, input_position) = match (output_position, input_position) {
+        (Some(output_position), Some(input_position)) => (output_position, input_position),
+        _ => return Err(MeasureLatencyError::PositionNotSupported),
+    };
+    let mut captured = vec![T::from(&0.0f32); capture.len()];
+    let captured_len = capture.pop_slice(&mut captured);
+    let captured: Vec<f32> = captured[..captured_len]
+        .chunks_exact(channels)
+        .map(|frame| frame[0].to_f32())
+        .collect();
+    let detected = find_chirp(&captured, &chirp).ok_or(MeasureLatencyError::ChirpNotDetected)?;
+
+    let played = time_of(&output_position, lead_in as u64, sample_rate);
+    let captured = time_of(&input_position, detected as u64, sample_rate);
+    captured
+        .checked_duration_since(played)
+        .ok_or(MeasureLatencyError::ChirpNotDetected)
+}
+
+// A sweep over the frequencies that most devices play and capture well, faded in and out.
+fn chirp(sample_rate: SampleRate) -> Vec<f32> {
+    let end_frequency = (0.4 * sample_rate.0 as f32).min(8_000.0);
+    let sweep = Sweep::new(500.0, end_frequency, CHIRP_DURATION, sample_rate);
+    let len = frames(CHIRP_DURATION, sample_rate);
+    let fade = frames(CHIRP_FADE, sample_rate).clamp(1, len / 2 + 1);
+    sweep
+        .take(len)
+        .enumerate()
+        .map(|(frame, sample)| {
+            let fade = frame.min(len - 1 - frame).min(fade) as f32 / fade as f32;
+            0.5 * fade * sample
+        })
+        .collect()
+}
+
+// The frame of `captured` at which the chirp starts, by normalized cross-correlation.
+fn find_chirp(captured: &[f32], chirp: &[f32]) -> Option<usize> {
+    if captured.len() < chirp.len() {
+        return None;
+    }
+    let chirp_energy: f32 = chirp.iter().map(|sample| sample * sample).sum();
+    let mut window_energy: f32 = captured[..chirp.len()].iter().map(|sample| sample * sample).sum();
+    let mut best = None;
+    let mut best_score = DETECTION_THRESHOLD;
+    for start in 0..=captured.len() - chirp.len() {
+        if start > 0 {
+            let (removed, added) = (captured[start - 1], captured[start + chirp.len() - 1]);
+            window_energy = (window_energy - removed * removed + added * added).max(0.0);
+        }
+        // Ignore the windows that are silent rather than dividing by zero.
+        if window_energy <= 1e-6 * chirp_energy {
+            continue;
+        }
+        let window = &captured[start..start + chirp.len()];
+        let correlation: f32 = window.iter().zip(chirp).map(|(a, b)| a * b).sum();
+        let score = correlation / (chirp_energy * window_energy).sqrt();
+        if score > best_score {
+            best_score = score;
+            best = Some(start);
+        }
+    }
+    best
+}
+
+// The time at which the device played or captured `frame`.
+fn time_of(position: &StreamPosition, frame: u64, sample_rate: SampleRate) -> Instant {
+    let offset = |frames: u64| Duration::from_secs_f64(frames as f64 / sample_rate.0 as f64);
+    if frame >= position.frame {
+        position.time + offset(frame - position.frame)
+    } else {
+        position.time - offset(position.frame - frame)
+    }
+}
+
+fn frames(duration: Duration, sample_rate: SampleRate) -> usize {
+    (duration.as_secs_f64() * sample_rate.0 as f64) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::{chirp, find_chirp};
+    use signal::WhiteNoise;
+    use SampleRate;
+
+    #[test]
+    fn chirp_is_found_in_noise() {
+        let chirp = chirp(SampleRate(8_000));
+        let mut captured: Vec<f32> = WhiteNoise::new(1).take(4_000).map(|s| 0.01 * s).collect();
+        for (sample, chirp) in captured[1_234..].iter_mut().zip(chirp.iter()) {
+            *sample += 0.1 * chirp;
+        }
+        assert_eq!(find_chirp(&captured, &chirp), Some(1_234));
+        assert_eq!(find_chirp(&captured[..1_000], &chirp), None);
+        assert_eq!(find_chirp(&[0.0; 4_000], &chirp), None);
+    }
+}
@@ -12,6 +12,14 @@
 //!   **Device** will run your stream before you can create one. Often, a default device can be
 //!   retrieved via the **Host**.
 //!
+//! **Host**, **Device** and **Devices** are `Send` and `Sync` on every platform, so they can be
+//! shared between threads. A **Stream** is neither: it must be played, paused and dropped on the
+//! thread that built it.
+//!
+//! Hosts, devices and streams may be dropped in any order. A stream keeps the resources of its
+//! device alive until it is dropped, so dropping the **Host** or the **Device** it was built from
+//! does not affect it.
+//!
 //! The first step is to initialise the `Host`:
 //!
 //! ```
@@ -61,8 +69,8 @@
 //! # let format = device.default_output_format().unwrap();
 //! let stream = device.build_output_stream(
 //!     &format,
-//!     move |data| {
-//!         // react to stream events and read or write stream data here.
+//!     move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+//!         // react to stream events and write stream data here.
 //!     },
 //!     move |err| {
 //!         // react to errors here.
@@ -71,10 +79,13 @@
 //! ```
 //!
 //! While the stream is running, the selected audio device will periodically call the data callback
-//! that was passed to the function. The callback is passed an instance of type `StreamData` that
-//! represents the data that must be read from or written to. The inner `UnknownTypeOutputBuffer`
-//! can be one of `I16`, `U16` or `F32` depending on the format that was passed to
-//! `build_output_stream`.
+//! that was passed to the function. The callback is passed a slice of interleaved samples that must
+//! be written to, or read from for an input stream. The type of the samples is the one matching
+//! the `data_type` of the format that was passed to `build_output_stream`, or else the stream is
+//! not built. Alongside the buffer, the callback also receives an `OutputCallbackInfo`, or an
+//! `InputCallbackInfo`, describing the number of frames requested by the device and a set of
+//! `BufferFlags`. Applications that only know the sample format at runtime can use
+//! `build_output_stream_raw` instead, which passes the samples as bytes.
 //!
 //! > **Note**: Creating and running a stream will *not* block the thread. On modern platforms, the
 //! > given callback is called by a dedicated, high-priority thread responsible for delivering
@@ -85,40 +96,42 @@
 //! > please share your issue and use-case with the CPAL team on the github issue tracker for
 //! > consideration.*
 //!
-//! In this example, we simply fill the given output buffer with zeroes.
+//! > **Note**: Between acquiring a buffer from the device and releasing it, CPAL does not allocate
+//! > memory, format strings or use channels, except to report errors and on Emscripten, which
+//! > creates a JavaScript buffer for each callback. For glitch-free audio, the data callback should
+//! > follow the same rules. The `rt-debug` feature can be used to check that it does.
+//!
+//! > **Note**: With the `tracing` feature, CPAL emits [`tracing`](https://docs.rs/tracing) events
+//! > with the `cpal` target when a host is selected, when devices are enumerated, when streams are
+//! > built, played, paused and dropped, when a stream reports an error and when CPAL recovers from
+//! > one. The audio thread of a stream runs in a `cpal_stream` span, which is nested in the span
+//! > that was current when the stream was built.
+//!
+//! > **Note**: With the `serde` feature, `Format`, `SupportedFormat`, `SampleRate` and
+//! > `SampleFormat` implement `Serialize` and `Deserialize`, so that applications can store the
+//! > format of their streams in their settings.
+//!
+//! In this example, we simply fill the given output buffer with silence, whatever the sample
+//! format of the device.
 //!
 //! ```no_run
-//! use cpal::{StreamData, UnknownTypeOutputBuffer};
+//! use cpal::{Sample, SampleFormat};
 //! use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 //! # let host = cpal::default_host();
 //! # let device = host.default_output_device().unwrap();
 //! # let format = device.default_output_format().unwrap();
-//! let stream = device.build_output_stream(
-//!     &format,
-//!     move |data| {
-//!         match data {
-//!             StreamData::Output { buffer: UnknownTypeOutputBuffer::U16(mut buffer) } => {
-//!                 for elem in buffer.iter_mut() {
-//!                     *elem = u16::max_value() / 2;
-//!                 }
-//!             },
-//!             StreamData::Output { buffer: UnknownTypeOutputBuffer::I16(mut buffer) } => {
-//!                 for elem in buffer.iter_mut() {
-//!                     *elem = 0;
-//!                 }
-//!             },
-//!             StreamData::Output { buffer: UnknownTypeOutputBuffer::F32(mut buffer) } => {
-//!                 for elem in buffer.iter_mut() {
-//!                     *elem = 0.0;
-//!                 }
-//!             },
-//!             _ => (),
-//!         }
-//!     },
-//!     move |err| {
-//!         eprintln!("an error occurred on the output audio stream: {}", err);
-//!     },
-//! );
+//! let err_fn = |err| eprintln!("an error occurred on the output audio stream: {}", err);
+//! let stream = match format.data_type {
+//!     SampleFormat::F32 => device.build_output_stream(&format, write_silence::<f32>, err_fn),
+//!     SampleFormat::I16 => device.build_output_stream(&format, write_silence::<i16>, err_fn),
+//!     SampleFormat::U16 => device.build_output_stream(&format, write_silence::<u16>, err_fn),
+//! };
+//!
+//! fn write_silence<T: Sample>(data: &mut [T], _: &cpal::OutputCallbackInfo) {
+//!     for sample in data.iter_mut() {
+//!         *sample = Sample::from(&0.0);
+//!     }
+//! }
 //! ```
 //!
 //! Not all platforms automatically run the stream upon creation. To ensure the stream has started,
@@ -129,7 +142,8 @@
 //! # let host = cpal::default_host();
 //! # let device = host.default_output_device().unwrap();
 //! # let format = device.default_output_format().unwrap();
-//! # let stream = device.build_output_stream(&format, move |_data| {}, move |_err| {}).unwrap();
+//! # let data_fn = move |_: &mut [f32], _: &cpal::OutputCallbackInfo| {};
+//! # let stream = device.build_output_stream(&format, data_fn, move |_err| {}).unwrap();
 //! stream.play().unwrap();
 //! ```
 //!
@@ -141,33 +155,118 @@
 //! # let host = cpal::default_host();
 //! # let device = host.default_output_device().unwrap();
 //! # let format = device.default_output_format().unwrap();
-//! # let stream = device.build_output_stream(&format, move |_data| {}, move |_err| {}).unwrap();
+//! # let data_fn = move |_: &mut [f32], _: &cpal::OutputCallbackInfo| {};
+//! # let stream = device.build_output_stream(&format, data_fn, move |_err| {}).unwrap();
 //! stream.pause().unwrap();
 
 #![recursion_limit = "512"]
-
-#[cfg(target_os = "windows")]
+// Without any host, only the null host is built and the helpers shared by the hosts are unused.
+#![cfg_attr(
+    not(any(
+        all(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"), feature = "alsa"),
+        all(any(target_os = "macos", target_os = "ios"), feature = "coreaudio"),
+        all(target_os = "emscripten", feature = "emscripten"),
+        all(windows, feature = "wasapi")
+    )),
+    allow(dead_code)
+)]
+
+#[cfg(any(
+    all(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"), feature = "alsa"),
+    all(target_os = "windows", feature = "wasapi")
+))]
 #[macro_use]
 extern crate lazy_static;
 // Extern crate declarations with `#[macro_use]` must unfortunately be at crate root.
-#[cfg(target_os = "emscripten")]
+#[cfg(all(target_os = "emscripten", feature = "emscripten"))]
 #[macro_use]
 extern crate stdweb;
+extern crate cpal_core;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "rubato")]
+extern crate rubato;
 extern crate thiserror;
-
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "wav")]
+extern crate hound;
+
+pub use capture::CaptureRing;
+pub use cpal_core::{
+    ChannelCount, Format, InputBuffer, OutputBuffer, RawInputBuffer, RawOutputBuffer, Sample,
+    SampleFormat, SampleRate, SupportedFormat, UnknownTypeInputBuffer, UnknownTypeOutputBuffer,
+};
 pub use error::*;
+pub use limiter::LimiterOptions;
 pub use platform::{
-    ALL_HOSTS, available_hosts, default_host, Device, Devices, Host, host_from_id,
-    HostId, Stream, SupportedInputFormats, SupportedOutputFormats,
+    ALL_HOSTS, available_hosts, default_host, Device, Devices, Host, host_availability,
+    host_from_id, HostId, Stream, SupportedInputFormats, SupportedOutputFormats,
 };
-pub use samples_formats::{Sample, SampleFormat};
-use std::ops::{Deref, DerefMut};
-
+pub use playback::{play_samples, play_tone, PlaybackHandle};
+pub use stats::{StreamInstant, StreamPosition, StreamStats};
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use traits::DeviceTrait;
+
+// Declared first, so that `trace_event!` is available in the other modules.
+#[macro_use]
+mod trace;
+
+mod adapter;
+#[cfg(feature = "capi")]
+pub mod capi;
+mod capture;
+pub mod clock;
+pub mod convert;
+mod downmix;
 mod error;
+#[cfg(any(
+    all(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"), feature = "alsa"),
+    all(target_os = "windows", feature = "wasapi")
+))]
+mod format_cache;
+pub mod gapless;
 mod host;
+pub mod latency;
+mod limiter;
+#[cfg(feature = "meter")]
+pub mod meter;
+#[cfg(feature = "mixer")]
+pub mod mixer;
+mod playback;
 pub mod platform;
-mod samples_formats;
+mod registry;
+pub mod resample;
+pub mod ring_buffer;
+#[cfg(feature = "rt-debug")]
+pub mod rt_debug;
+pub mod signal;
+mod spsc;
+pub mod splitter;
+mod stats;
+#[cfg(feature = "async")]
+pub mod stream_futures;
+pub mod stream_io;
+#[cfg(feature = "tokio")]
+pub mod stream_tokio;
 pub mod traits;
+#[cfg(feature = "virtual-device")]
+pub mod virtual_device;
+#[cfg(feature = "wav")]
+mod wav;
+#[cfg(any(
+    all(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"), feature = "alsa"),
+    all(target_os = "windows", feature = "wasapi"),
+    feature = "virtual-device"
+))]
+mod worker;
 
 /// A host's device iterator yielding only *input* devices.
 pub type InputDevices<I> = std::iter::Filter<I, fn(&<I as Iterator>::Item) -> bool>;
@@ -175,31 +274,167 @@ pub type InputDevices<I> = std::iter::Filter<I, fn(&<I as Iterator>::Item) -> bo
 /// A host's device iterator yielding only *output* devices.
 pub type OutputDevices<I> = std::iter::Filter<I, fn(&<I as Iterator>::Item) -> bool>;
 
-/// Number of channels.
-pub type ChannelCount = u16;
+/// The direction of the streams that a device may be filtered by.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Direction {
+    Input,
+    Output,
+}
 
-/// The number of samples processed per second for a single channel of audio.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct SampleRate(pub u32);
+/// A host's device iterator yielding only the devices that match the filters given to
+/// `HostTrait::devices_filtered`.
+pub struct FilteredDevices<I> {
+    devices: I,
+    direction: Option<Direction>,
+    name_substring: Option<String>,
+}
 
-/// The format of an input or output audio stream.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Format {
-    pub channels: ChannelCount,
-    pub sample_rate: SampleRate,
-    pub data_type: SampleFormat,
+impl<I> FilteredDevices<I> {
+    pub(crate) fn new(
+        devices: I,
+        direction: Option<Direction>,
+        name_substring: Option<&str>,
+    ) -> Self {
+        FilteredDevices {
+            devices,
+            direction,
+            name_substring: name_substring.map(str::to_string),
+        }
+    }
 }
 
-/// Describes a range of supported stream formats.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct SupportedFormat {
-    pub channels: ChannelCount,
-    /// Minimum value for the samples rate of the supported formats.
-    pub min_sample_rate: SampleRate,
-    /// Maximum value for the samples rate of the supported formats.
-    pub max_sample_rate: SampleRate,
-    /// Type of data expected by the device.
-    pub data_type: SampleFormat,
+impl<I> Iterator for FilteredDevices<I>
+where
+    I: Iterator,
+    I::Item: DeviceTrait,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for device in self.devices.by_ref() {
+            // The name is checked first, so that the formats of the devices that do not match it
+            // are never probed.
+            if let Some(ref substring) = self.name_substring {
+                match device.name() {
+                    Ok(ref name) if name.contains(substring.as_str()) => (),
+                    _ => continue,
+                }
+            }
+            let supported = match self.direction {
+                None => true,
+                Some(Direction::Input) => device
+                    .supported_input_formats()
+                    .map(|mut formats| formats.next().is_some())
+                    .unwrap_or(false),
+                Some(Direction::Output) => device
+                    .supported_output_formats()
+                    .map(|mut formats| formats.next().is_some())
+                    .unwrap_or(false),
+            };
+            if supported {
+                return Some(device);
+            }
+        }
+        None
+    }
+}
+
+/// Options that configure the behaviour of a stream beyond its `Format`.
+///
+/// Options that are not supported by a host are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamOptions {
+    /// What an output stream does when the data callback does not provide data in time.
+    pub slow_callback: SlowCallbackPolicy,
+    /// Fill output buffers with silence before passing them to the data callback, so that a
+    /// callback that does not write the whole buffer produces silence rather than noise.
+    ///
+    /// Enabled by default in debug builds.
+    pub zero_output_buffers: bool,
+    /// Pass all the data captured since the last wakeup of the audio thread to the data callback
+    /// at once, instead of calling it once per packet delivered by the host.
+    ///
+    /// This reduces the overhead per callback for applications that favour throughput over
+    /// latency, at the cost of copying the data to an intermediate buffer. Only WASAPI delivers
+    /// captured data in packets.
+    pub coalesce_input: bool,
+    /// How long the audio thread busy-waits for the device before blocking, on hosts where CPAL
+    /// runs the audio thread itself (currently ALSA and WASAPI).
+    ///
+    /// With buffers of a few milliseconds, the time it takes to wake up a blocked thread adds
+    /// noticeable jitter. Spinning for about one buffer period avoids it, at the cost of keeping
+    /// a CPU core busy. On Windows, this also raises the resolution of the system timer while the
+    /// stream exists. Defaults to zero, i.e. never spin.
+    pub spin_wait: Duration,
+    /// Requests a large buffer so that the audio thread wakes up as rarely as possible, at the
+    /// cost of latency.
+    ///
+    /// This suits applications such as background music players, for which latency does not
+    /// matter but waking up the CPU hundreds of times per second drains the battery. On ALSA, the
+    /// buffer holds about half a second of audio split into two periods. On WASAPI, the buffer
+    /// holds 200 milliseconds of audio and the audio thread wakes up when half of it has been
+    /// played. Other hosts ignore this option. Defaults to `false`.
+    pub power_saving: bool,
+    /// Spawns the thread running the data callback, on hosts where CPAL creates that thread
+    /// itself (currently ALSA and WASAPI). The thread is spawned when the stream is played for the
+    /// first time.
+    ///
+    /// Defaults to `std::thread::spawn`.
+    pub thread_spawner: ThreadSpawner,
+    /// Passes the output of the data callback through a limiter, so that a bug or a loud sound
+    /// does not play at full scale.
+    ///
+    /// This is useful while developing synthesizers and experiments. It is applied by the
+    /// `Device` of the `platform` module, whatever its host. Defaults to `None`.
+    pub limiter: Option<LimiterOptions>,
+    /// Opens stereo streams with 6 (5.1) or 8 (7.1) channels on devices that do not support
+    /// stereo, such as many HDMI outputs, and converts between stereo and surround.
+    ///
+    /// The data callback still receives stereo buffers. Captured surround audio is downmixed to
+    /// stereo, and stereo output is played on the front left and right channels. Like `limiter`,
+    /// this is applied by the `Device` of the `platform` module. Defaults to `false`.
+    pub downmix_surround: bool,
+}
+
+/// Spawns the thread that runs the data callback of a stream.
+///
+/// This allows embedders to configure the audio thread, e.g. to register it with a runtime or to
+/// change its scheduling policy. The spawner receives the function running the stream, which it
+/// must run on a new thread and return the handle of that thread.
+///
+/// ```
+/// use cpal::ThreadSpawner;
+/// use std::thread;
+///
+/// let spawner = ThreadSpawner::new(|run| {
+///     thread::Builder::new()
+///         .name("audio".into())
+///         .spawn(move || {
+///             // Register the thread, then run the stream.
+///             run();
+///         })
+/// });
+/// ```
+#[derive(Clone)]
+pub struct ThreadSpawner(Arc<SpawnFn>);
+
+type SpawnFn = dyn Fn(Box<dyn FnOnce() + Send>) -> io::Result<JoinHandle<()>> + Send + Sync;
+
+/// What an output stream does when the data callback misses its deadline, i.e. when the device
+/// runs out of data before the callback returns.
+///
+/// This only has an effect on hosts that are able to detect underruns, currently ALSA and WASAPI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlowCallbackPolicy {
+    /// Resume playback with the next buffer, leaving silence in the gap. This is the default.
+    Silence,
+    /// Conceal the gap by playing the last buffer provided by the data callback again before
+    /// resuming playback.
+    Repeat,
+    /// Report a `StreamError::Underrun` to the error callback, then resume playback as with
+    /// `Silence`. ALSA reports every underrun whatever the policy, so this is the same as
+    /// `Silence` there.
+    Error,
 }
 
 /// Stream data passed to the `EventLoop::run` callback.
@@ -207,215 +442,196 @@ pub struct SupportedFormat {
 pub enum StreamData<'a> {
     Input {
         buffer: UnknownTypeInputBuffer<'a>,
+        info: CallbackInfo,
     },
     Output {
         buffer: UnknownTypeOutputBuffer<'a>,
+        info: CallbackInfo,
     },
 }
 
-/// Represents a buffer containing audio data that may be read.
+/// Information about a single invocation of the data callback.
 ///
-/// This struct implements the `Deref` trait targeting `[T]`. Therefore this buffer can be read the
-/// same way as reading from a `Vec` or any other kind of Rust array.
-// TODO: explain audio stuff in general
-// TODO: remove the wrapper and just use slices in next major version
-#[derive(Debug)]
-pub struct InputBuffer<'a, T: 'a>
-where
-    T: Sample,
-{
-    buffer: &'a [T],
+/// This is passed alongside the buffer in `StreamData` so that the callback can tell how much
+/// data the device is asking for and whether the call is part of the regular flow of the stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CallbackInfo {
+    frames: usize,
+    recovery: bool,
+    flags: BufferFlags,
 }
 
-/// Represents a buffer that must be filled with audio data. The buffer in unfilled state may
-/// contain garbage values.
+/// Information passed to the data callback of an input stream, alongside the captured samples.
 ///
-/// This struct implements the `Deref` and `DerefMut` traits to `[T]`. Therefore writing to this
-/// buffer is done in the same way as writing to a `Vec` or any other kind of Rust array.
-// TODO: explain audio stuff in general
-// TODO: remove the wrapper and just use slices
-#[must_use]
-#[derive(Debug)]
-pub struct OutputBuffer<'a, T: 'a>
-where
-    T: Sample,
-{
-    buffer: &'a mut [T],
+/// It dereferences to the `CallbackInfo` shared by both directions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputCallbackInfo {
+    info: CallbackInfo,
 }
 
-/// This is the struct that is provided to you by cpal when you want to read samples from a buffer.
+/// Information passed to the data callback of an output stream, alongside the samples to fill.
 ///
-/// Since the type of data is only known at runtime, you have to read the right buffer.
-#[derive(Debug)]
-pub enum UnknownTypeInputBuffer<'a> {
-    /// Samples whose format is `u16`.
-    U16(InputBuffer<'a, u16>),
-    /// Samples whose format is `i16`.
-    I16(InputBuffer<'a, i16>),
-    /// Samples whose format is `f32`.
-    F32(InputBuffer<'a, f32>),
+/// It dereferences to the `CallbackInfo` shared by both directions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutputCallbackInfo {
+    info: CallbackInfo,
 }
 
-/// This is the struct that is provided to you by cpal when you want to write samples to a buffer.
+/// Flags describing the contents of a buffer passed to the data callback.
 ///
-/// Since the type of data is only known at runtime, you have to fill the right buffer.
-#[derive(Debug)]
-pub enum UnknownTypeOutputBuffer<'a> {
-    /// Samples whose format is `u16`.
-    U16(OutputBuffer<'a, u16>),
-    /// Samples whose format is `i16`.
-    I16(OutputBuffer<'a, i16>),
-    /// Samples whose format is `f32`.
-    F32(OutputBuffer<'a, f32>),
+/// Not all backends are able to report all flags. Flags that are not supported are always
+/// `false`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferFlags {
+    /// The device reports that the captured buffer contains silence. The contents of the buffer
+    /// should be treated as silence regardless of the actual sample values.
+    pub silent: bool,
+    /// The buffer is not contiguous with the previous one, e.g. because some data was lost after
+    /// an overrun or an underrun.
+    pub discontinuity: bool,
 }
 
-impl SupportedFormat {
-    /// Turns this `SupportedFormat` into a `Format` corresponding to the maximum samples rate.
+/// The native handle of a stream, for calling the platform APIs that CPAL does not wrap.
+///
+/// Returned by `Stream::raw_handle`. The pointers are untyped so that CPAL does not expose the
+/// types of the system crates it uses; cast them to the type named by each variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawStreamHandle {
+    /// The `snd_pcm_t*` of an ALSA stream.
+    Alsa { pcm: *mut std::os::raw::c_void },
+    /// The `IAudioClient*` of a WASAPI stream.
+    Wasapi { audio_client: *mut std::os::raw::c_void },
+    /// The `AudioUnit` of a CoreAudio stream.
+    CoreAudio { audio_unit: *mut std::os::raw::c_void },
+}
+
+/// The native handle of a device, for calling the platform APIs that CPAL does not wrap.
+///
+/// Returned by `Device::raw_handle`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawDeviceHandle {
+    /// The `IMMDevice*` of a WASAPI device.
+    Wasapi { device: *mut std::os::raw::c_void },
+    /// The `AudioDeviceID` of a CoreAudio device.
+    CoreAudio { device_id: u32 },
+}
+
+impl CallbackInfo {
     #[inline]
-    pub fn with_max_sample_rate(self) -> Format {
-        Format {
-            channels: self.channels,
-            sample_rate: self.max_sample_rate,
-            data_type: self.data_type,
-        }
+    pub(crate) fn new(frames: usize, recovery: bool, flags: BufferFlags) -> Self {
+        CallbackInfo { frames, recovery, flags }
     }
 
-    /// A comparison function which compares two `SupportedFormat`s in terms of their priority of
-    /// use as a default stream format.
-    ///
-    /// Some backends do not provide a default stream format for their audio devices. In these
-    /// cases, CPAL attempts to decide on a reasonable default format for the user. To do this we
-    /// use the "greatest" of all supported stream formats when compared with this method.
-    ///
-    /// Formats are prioritised by the following heuristics:
-    ///
-    /// **Channels**:
-    ///
-    /// - Stereo
-    /// - Mono
-    /// - Max available channels
-    ///
-    /// **Sample format**:
-    /// - f32
-    /// - i16
-    /// - u16
-    ///
-    /// **Sample rate**:
-    ///
-    /// - 44100 (cd quality)
-    /// - Max sample rate
-    pub fn cmp_default_heuristics(&self, other: &Self) -> std::cmp::Ordering {
-        use std::cmp::Ordering::Equal;
-        use SampleFormat::{F32, I16, U16};
-
-        let cmp_stereo = (self.channels == 2).cmp(&(other.channels == 2));
-        if cmp_stereo != Equal {
-            return cmp_stereo;
-        }
-
-        let cmp_mono = (self.channels == 1).cmp(&(other.channels == 1));
-        if cmp_mono != Equal {
-            return cmp_mono;
-        }
+    /// The number of frames requested by the device for an output buffer, or the number of frames
+    /// delivered by the device for an input buffer.
+    #[inline]
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
 
-        let cmp_channels = self.channels.cmp(&other.channels);
-        if cmp_channels != Equal {
-            return cmp_channels;
-        }
+    /// Whether this call is made to prime the device buffer before playback, or to refill it
+    /// while the stream recovers from an underrun or an overrun.
+    #[inline]
+    pub fn is_recovery(&self) -> bool {
+        self.recovery
+    }
 
-        let cmp_f32 = (self.data_type == F32).cmp(&(other.data_type == F32));
-        if cmp_f32 != Equal {
-            return cmp_f32;
-        }
+    /// Flags describing the buffer passed alongside this info.
+    #[inline]
+    pub fn flags(&self) -> BufferFlags {
+        self.flags
+    }
+}
 
-        let cmp_i16 = (self.data_type == I16).cmp(&(other.data_type == I16));
-        if cmp_i16 != Equal {
-            return cmp_i16;
-        }
+impl InputCallbackInfo {
+    #[inline]
+    pub(crate) fn new(info: CallbackInfo) -> Self {
+        InputCallbackInfo { info }
+    }
+}
 
-        let cmp_u16 = (self.data_type == U16).cmp(&(other.data_type == U16));
-        if cmp_u16 != Equal {
-            return cmp_u16;
-        }
+impl Deref for InputCallbackInfo {
+    type Target = CallbackInfo;
 
-        const HZ_44100: SampleRate = SampleRate(44_100);
-        let r44100_in_self = self.min_sample_rate <= HZ_44100
-            && HZ_44100 <= self.max_sample_rate;
-        let r44100_in_other = other.min_sample_rate <= HZ_44100
-            && HZ_44100 <= other.max_sample_rate;
-        let cmp_r44100 = r44100_in_self.cmp(&r44100_in_other);
-        if cmp_r44100 != Equal {
-            return cmp_r44100;
-        }
+    #[inline]
+    fn deref(&self) -> &CallbackInfo {
+        &self.info
+    }
+}
 
-        self.max_sample_rate.cmp(&other.max_sample_rate)
+impl OutputCallbackInfo {
+    #[inline]
+    pub(crate) fn new(info: CallbackInfo) -> Self {
+        OutputCallbackInfo { info }
     }
 }
 
-impl<'a, T> Deref for InputBuffer<'a, T>
-    where T: Sample
-{
-    type Target = [T];
+impl Deref for OutputCallbackInfo {
+    type Target = CallbackInfo;
 
     #[inline]
-    fn deref(&self) -> &[T] {
-        self.buffer
+    fn deref(&self) -> &CallbackInfo {
+        &self.info
     }
 }
 
-impl<'a, T> Deref for OutputBuffer<'a, T>
-    where T: Sample
-{
-    type Target = [T];
+impl Default for StreamOptions {
+    fn default() -> Self {
+        StreamOptions {
+            slow_callback: SlowCallbackPolicy::default(),
+            zero_output_buffers: cfg!(debug_assertions),
+            coalesce_input: false,
+            spin_wait: Duration::from_secs(0),
+            power_saving: false,
+            thread_spawner: ThreadSpawner::default(),
+            limiter: None,
+            downmix_surround: false,
+        }
+    }
+}
 
-    #[inline]
-    fn deref(&self) -> &[T] {
-        self.buffer
+impl ThreadSpawner {
+    /// Creates a spawner from a function that runs its argument on a new thread.
+    pub fn new<F>(spawn: F) -> Self
+    where
+        F: Fn(Box<dyn FnOnce() + Send>) -> io::Result<JoinHandle<()>> + Send + Sync + 'static,
+    {
+        ThreadSpawner(Arc::new(spawn))
+    }
+
+    pub(crate) fn spawn<F>(&self, f: F) -> io::Result<JoinHandle<()>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        (self.0)(Box::new(f))
     }
 }
 
-impl<'a, T> DerefMut for OutputBuffer<'a, T>
-    where T: Sample
-{
-    #[inline]
-    fn deref_mut(&mut self) -> &mut [T] {
-        self.buffer
+impl Default for ThreadSpawner {
+    fn default() -> Self {
+        ThreadSpawner::new(|f| Ok(thread::spawn(f)))
     }
 }
 
-impl<'a> UnknownTypeInputBuffer<'a> {
-    /// Returns the length of the buffer in number of samples.
-    #[inline]
-    pub fn len(&self) -> usize {
-        match self {
-            &UnknownTypeInputBuffer::U16(ref buf) => buf.len(),
-            &UnknownTypeInputBuffer::I16(ref buf) => buf.len(),
-            &UnknownTypeInputBuffer::F32(ref buf) => buf.len(),
-        }
+impl fmt::Debug for ThreadSpawner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThreadSpawner").finish()
     }
 }
 
-impl<'a> UnknownTypeOutputBuffer<'a> {
-    /// Returns the length of the buffer in number of samples.
-    #[inline]
-    pub fn len(&self) -> usize {
-        match self {
-            &UnknownTypeOutputBuffer::U16(ref buf) => buf.len(),
-            &UnknownTypeOutputBuffer::I16(ref buf) => buf.len(),
-            &UnknownTypeOutputBuffer::F32(ref buf) => buf.len(),
-        }
+// Two spawners are equal if they share the same function.
+impl PartialEq for ThreadSpawner {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
     }
 }
 
-impl From<Format> for SupportedFormat {
+impl Eq for ThreadSpawner {}
+
+impl Default for SlowCallbackPolicy {
     #[inline]
-    fn from(format: Format) -> SupportedFormat {
-        SupportedFormat {
-            channels: format.channels,
-            min_sample_rate: format.sample_rate,
-            max_sample_rate: format.sample_rate,
-            data_type: format.data_type,
-        }
+    fn default() -> Self {
+        SlowCallbackPolicy::Silence
     }
 }
 
@@ -423,7 +639,7 @@ impl From<Format> for SupportedFormat {
 // of commonly used rates. This is always the case for wasapi and is sometimes the case for alsa.
 //
 // If a rate you desire is missing from this list, feel free to add it!
-#[cfg(target_os = "windows")]
+#[cfg(all(target_os = "windows", feature = "wasapi"))]
 const COMMON_SAMPLE_RATES: &'static [SampleRate] = &[
     SampleRate(5512),
     SampleRate(8000),
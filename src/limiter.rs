@@ -0,0 +1,181 @@
+//! The limiter of output streams, see `StreamOptions::limiter`.
+//!
+//! The limiter computes a single gain for all the channels of a frame, so that limiting does not
+//! move the stereo image. The gain drops as soon as a frame would exceed the threshold, or ramps
+//! down over the look-ahead duration while the frames are delayed by as much, and then recovers
+//! exponentially. Samples that still exceed the threshold are clipped to it.
+
+use std::mem;
+use std::time::Duration;
+
+use adapter;
+use Format;
+use Sample;
+use StreamData;
+use UnknownTypeOutputBuffer;
+
+/// Configures the limiter that protects ears and speakers from accidental full-scale output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimiterOptions {
+    /// The largest absolute value of an output sample, where `1.0` is the full scale.
+    ///
+    /// Defaults to `0.9`, about -1 dBFS.
+    pub threshold: f32,
+    /// How long the gain takes to recover about two thirds of the way back to unity after a peak.
+    ///
+    /// Defaults to 100 milliseconds.
+    pub release: Duration,
+    /// How long the output is delayed so that the gain can be lowered smoothly before a peak
+    /// instead of abruptly at the peak.
+    ///
+    /// This adds to the latency of the stream. Defaults to zero, i.e. no look-ahead.
+    pub look_ahead: Duration,
+}
+
+// The state of the limiter of a stream.
+struct Limiter {
+    channels: usize,
+    threshold: f32,
+    // The fraction of the remaining way to unity by which the gain recovers each frame.
+    release: f32,
+    // The delayed frames, interleaved, and the position of the oldest one.
+    delay: Vec<f32>,
+    delay_position: usize,
+    look_ahead_frames: usize,
+    gain: f32,
+    // The gain to reach before the lowest peak in the delay line is output, the change of the gain
+    // per frame to reach it in time, and the number of frames until that peak is output.
+    target: f32,
+    slope: f32,
+    hold: usize,
+}
+
+/// Wraps `data_callback` so that the buffers it produces go through a limiter, if any.
+pub(crate) fn limit<D>(
+    format: &Format,
+    options: Option<&LimiterOptions>,
+    mut data_callback: D,
+) -> impl FnMut(StreamData) + Send + 'static
+where
+    D: FnMut(StreamData) + Send + 'static,
+{
+    let mut limiter = options.map(|options| Limiter::new(format, options));
+    move |data: StreamData| {
+        let limiter = match limiter {
+            Some(ref mut limiter) => limiter,
+            None => return data_callback(data),
+        };
+        adapter::call_then(data, &mut data_callback, |data| {
+            if let StreamData::Output { buffer, .. } = data {
+                match buffer {
+                    UnknownTypeOutputBuffer::U16(mut buffer) => limiter.process(&mut buffer),
+                    UnknownTypeOutputBuffer::I16(mut buffer) => limiter.process(&mut buffer),
+                    UnknownTypeOutputBuffer::F32(mut buffer) => limiter.process(&mut buffer),
+                }
+            }
+        });
+    }
+}
+
+impl Default for LimiterOptions {
+    fn default() -> Self {
+        LimiterOptions {
+            threshold: 0.9,
+            release: Duration::from_millis(100),
+            look_ahead: Duration::from_secs(0),
+        }
+    }
+}
+
+impl Limiter {
+    fn new(format: &Format, options: &LimiterOptions) -> Self {
+        let channels = (format.channels as usize).max(1);
+        let sample_rate = format.sample_rate.0 as f32;
+        let release_frames = (options.release.as_secs_f32() * sample_rate).max(1.0);
+        let look_ahead_frames = (options.look_ahead.as_secs_f32() * sample_rate).round() as usize;
+        Limiter {
+            channels,
+            threshold: options.threshold.abs(),
+            release: 1.0 - (-1.0 / release_frames).exp(),
+            delay: vec![0.0; look_ahead_frames * channels],
+            delay_position: 0,
+            look_ahead_frames,
+            gain: 1.0,
+            target: 1.0,
+            slope: 0.0,
+            hold: 0,
+        }
+    }
+
+    fn process<T: Sample>(&mut self, samples: &mut [T]) {
+        let channels = self.channels;
+        for frame in samples.chunks_exact_mut(channels) {
+            let peak = frame.iter().fold(0.0f32, |peak, sample| peak.max(sample.to_f32().abs()));
+            let needed = if peak > self.threshold { self.threshold / peak } else { 1.0 };
+            if needed < self.target {
+                // Reach the new target when its frame is output, without reaching the previous
+                // target later than planned.
+                let slope = (needed - self.gain) / (self.look_ahead_frames + 1) as f32;
+                self.slope = self.slope.min(slope);
+                self.target = needed;
+                self.hold = self.look_ahead_frames;
+            }
+            if self.gain > self.target {
+                self.gain = (self.gain + self.slope).max(self.target);
+            } else if self.hold == 0 {
+                // The peak was output.
+                self.slope = 0.0;
+                self.target += (1.0 - self.target) * self.release;
+                self.gain = self.target;
+            }
+            self.hold = self.hold.saturating_sub(1);
+
+            for sample in frame.iter_mut() {
+                let mut value = sample.to_f32();
+                if self.look_ahead_frames > 0 {
+                    let delayed = &mut self.delay[self.delay_position];
+                    value = mem::replace(delayed, value);
+                    self.delay_position = (self.delay_position + 1) % self.delay.len();
+                }
+                let limited = (value * self.gain).max(-self.threshold).min(self.threshold);
+                *sample = T::from(&limited);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Limiter, LimiterOptions};
+    use std::time::Duration;
+    use {Format, SampleFormat, SampleRate};
+
+    #[test]
+    fn peaks_are_limited_with_look_ahead() {
+        let format = Format {
+            channels: 1,
+            sample_rate: SampleRate(1_000),
+            data_type: SampleFormat::F32,
+        };
+        let options = LimiterOptions {
+            threshold: 0.5,
+            release: Duration::from_millis(10),
+            look_ahead: Duration::from_millis(3),
+        };
+        let mut limiter = Limiter::new(&format, &options);
+        let mut samples = [0.25, 0.25, 0.25, 1.0, 0.25, 0.25, 0.25, 0.25];
+        limiter.process(&mut samples);
+        // The output is delayed by three frames, and the gain halves over the four frames leading
+        // to the peak.
+        assert_eq!(&samples[..4], &[0.0, 0.0, 0.0, 0.25 * 0.875]);
+        assert_eq!(&samples[4..7], &[0.25 * 0.75, 0.25 * 0.625, 0.5]);
+        assert!(samples[7] > 0.125 && samples[7] < 0.25);
+
+        let mut limiter = Limiter::new(&format, &LimiterOptions::default());
+        let mut samples = [0.5, -2.0, 0.5];
+        limiter.process(&mut samples);
+        assert_eq!(samples[0], 0.5);
+        assert_eq!(samples[1], -0.9);
+        assert!(samples[2] < 0.5 * 0.45 + 0.01);
+    }
+}
@@ -0,0 +1,193 @@
+//! Peak and RMS levels of the buffers of a stream, see `Stream::meter`.
+//!
+//! Once a stream has been asked for its `Meter`, the data callback measures each buffer after the
+//! user's callback has returned and stores the levels of every channel in atomics, so that a level
+//! meter can be drawn from any thread without instrumenting the callback.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use adapter;
+use Format;
+use Sample;
+use StreamData;
+use UnknownTypeInputBuffer;
+use UnknownTypeOutputBuffer;
+
+/// The levels of the channels of a stream, updated by its data callback.
+///
+/// A `Meter` can be cloned and sent to other threads. All the clones share the same levels.
+#[derive(Clone)]
+pub struct Meter {
+    levels: Arc<Levels>,
+}
+
+/// The level of one channel of a stream.
+///
+/// Both values are linear amplitudes, where `1.0` is the full scale.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelLevel {
+    /// The largest absolute value of a sample since the previous call to `Meter::levels`.
+    pub peak: f32,
+    /// The root mean square of the samples of the last buffer.
+    pub rms: f32,
+}
+
+// The state shared between the data callback and the meters.
+struct Levels {
+    // Whether the data callback measures its buffers, which it only does once a meter was asked
+    // for.
+    enabled: AtomicBool,
+    // The bits of the `f32` peak and RMS of each channel. The levels are never negative, so their
+    // bits compare like their values and `fetch_max` keeps the largest peak.
+    peaks: Vec<AtomicU32>,
+    rms: Vec<AtomicU32>,
+}
+
+/// Wraps `data_callback` so that its buffers are measured once the returned `Meter` is enabled.
+pub(crate) fn meter<D>(
+    format: &Format,
+    mut data_callback: D,
+) -> (impl FnMut(StreamData) + Send + 'static, Meter)
+where
+    D: FnMut(StreamData) + Send + 'static,
+{
+    let channels = (format.channels as usize).max(1);
+    let levels = Arc::new(Levels {
+        enabled: AtomicBool::new(false),
+        peaks: (0..channels).map(|_| AtomicU32::new(0)).collect(),
+        rms: (0..channels).map(|_| AtomicU32::new(0)).collect(),
+    });
+    let callback_levels = levels.clone();
+    // The peak and the sum of the squares of each channel, allocated here rather than in the
+    // callback.
+    let mut scratch = vec![(0.0, 0.0); channels];
+    let callback = move |data: StreamData| {
+        if !callback_levels.enabled.load(Ordering::Relaxed) {
+            data_callback(data);
+            return;
+        }
+        adapter::call_then(data, &mut data_callback, |data| match data {
+            StreamData::Input { buffer, .. } => match buffer {
+                UnknownTypeInputBuffer::U16(buffer) => {
+                    callback_levels.update(&buffer, &mut scratch)
+                },
+                UnknownTypeInputBuffer::I16(buffer) => {
+                    callback_levels.update(&buffer, &mut scratch)
+                },
+                UnknownTypeInputBuffer::F32(buffer) => {
+                    callback_levels.update(&buffer, &mut scratch)
+                },
+            },
+            StreamData::Output { buffer, .. } => match buffer {
+                UnknownTypeOutputBuffer::U16(buffer) => {
+                    callback_levels.update(&buffer, &mut scratch)
+                },
+                UnknownTypeOutputBuffer::I16(buffer) => {
+                    callback_levels.update(&buffer, &mut scratch)
+                },
+                UnknownTypeOutputBuffer::F32(buffer) => {
+                    callback_levels.update(&buffer, &mut scratch)
+                },
+            },
+        });
+    };
+    (callback, Meter { levels })
+}
+
+impl Meter {
+    // Starts measuring the buffers of the stream.
+    pub(crate) fn enable(&self) {
+        self.levels.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// The number of channels of the stream.
+    pub fn channels(&self) -> usize {
+        self.levels.peaks.len()
+    }
+
+    /// The level of each channel of the stream.
+    ///
+    /// The peaks are reset by this call, so that a meter polled from a UI does not miss the
+    /// peaks of the buffers played between two frames.
+    pub fn levels(&self) -> Vec<ChannelLevel> {
+        self.levels
+            .peaks
+            .iter()
+            .zip(self.levels.rms.iter())
+            .map(|(peak, rms)| ChannelLevel {
+                peak: f32::from_bits(peak.swap(0, Ordering::Relaxed)),
+                rms: f32::from_bits(rms.load(Ordering::Relaxed)),
+            })
+            .collect()
+    }
+}
+
+impl Levels {
+    fn update<T: Sample>(&self, samples: &[T], scratch: &mut [(f32, f32)]) {
+        let channels = scratch.len();
+        let frames = samples.len() / channels;
+        if frames == 0 {
+            return;
+        }
+        for level in scratch.iter_mut() {
+            *level = (0.0, 0.0);
+        }
+        for frame in samples.chunks_exact(channels) {
+            for (sample, level) in frame.iter().zip(scratch.iter_mut()) {
+                let value = sample.to_f32();
+                level.0 = level.0.max(value.abs());
+                level.1 += value * value;
+            }
+        }
+        for (c, &(peak, squares)) in scratch.iter().enumerate() {
+            self.peaks[c].fetch_max(peak.to_bits(), Ordering::Relaxed);
+            let rms = (squares / frames as f32).sqrt();
+            self.rms[c].store(rms.to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{meter, ChannelLevel};
+    use {CallbackInfo, Format, OutputBuffer, SampleFormat, SampleRate, StreamData};
+    use UnknownTypeOutputBuffer;
+
+    #[test]
+    fn output_levels_are_measured_once_enabled() {
+        let format = Format {
+            channels: 2,
+            sample_rate: SampleRate(48_000),
+            data_type: SampleFormat::F32,
+        };
+        let mut gain = 1.0;
+        let (mut callback, meter) = meter(&format, move |data| {
+            if let StreamData::Output { buffer, .. } = data {
+                if let UnknownTypeOutputBuffer::F32(mut buffer) = buffer {
+                    buffer.copy_from_slice(&[0.5 * gain, -gain, -0.5 * gain, 0.0]);
+                    gain *= 0.5;
+                }
+            }
+        });
+        fn call(callback: &mut dyn FnMut(StreamData)) {
+            let mut samples = [0.0f32; 4];
+            callback(StreamData::Output {
+                buffer: UnknownTypeOutputBuffer::F32(OutputBuffer::new(&mut samples)),
+                info: CallbackInfo::default(),
+            });
+        }
+        call(&mut callback);
+        assert_eq!(meter.levels(), vec![ChannelLevel::default(); 2]);
+
+        meter.enable();
+        call(&mut callback);
+        call(&mut callback);
+        let levels = meter.levels();
+        // The peaks of both buffers, and the RMS of the last one.
+        assert_eq!(levels[0], ChannelLevel { peak: 0.25, rms: 0.125 });
+        assert_eq!(levels[1].peak, 0.5);
+        assert!((levels[1].rms - 0.25 / 2.0f32.sqrt()).abs() < 1e-6);
+        assert_eq!(meter.levels()[0].peak, 0.0);
+    }
+}
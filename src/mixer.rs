@@ -0,0 +1,401 @@
+//! A software mixer playing several sources on a single output stream, enabled by the `mixer`
+//! feature.
+//!
+//! A `Mixer` owns an output stream and mixes its inputs in the data callback. Each `MixerInput` has
+//! its own sample type, number of channels and sample rate, as well as a gain and a pan that can be
+//! changed while it plays. This allows e.g. a game to play music and sound effects at the same
+//! time on devices whose host does not mix streams.
+//!
+//! ```no_run
+//! use cpal::mixer::Mixer;
+//! use cpal::traits::{DeviceTrait, HostTrait};
+//! use cpal::SampleRate;
+//! use std::time::Duration;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let host = cpal::default_host();
+//! let device = host.default_output_device().expect("no output device available");
+//! let format = device.default_output_format()?;
+//! let mut mixer = Mixer::new(&device, &format, 8, |err| eprintln!("{}", err))?;
+//! let music = mixer
+//!     .add_input::<i16>(2, SampleRate(44_100), Duration::from_millis(200))
+//!     .expect("too many inputs");
+//! let effect = mixer
+//!     .add_input::<f32>(1, SampleRate(22_050), Duration::from_millis(50))
+//!     .expect("too many inputs");
+//! music.set_gain(0.5);
+//! effect.set_pan(-1.0);
+//! effect.push(&[0.0, 0.5, 1.0, 0.5, 0.0]);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Inputs are resampled to the rate of the stream by linear interpolation, which is cheap but not
+//! transparent; sources that matter should be provided at the rate of the stream. The mix is not
+//! limited, so loud inputs clip once their sum leaves `-1.0..=1.0`.
+
+use std::f32::consts::FRAC_PI_4;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+use adapter::play;
+use convert::convert_samples;
+use registry::{registry, Items, Registry};
+use ring_buffer::{latency_capacity, ring_buffer, Consumer, Producer};
+use traits::{DeviceTrait, StreamTrait};
+use BuildStreamError;
+use ChannelCount;
+use Format;
+use Sample;
+use SampleFormat;
+use SampleRate;
+use StreamError;
+
+// The number of samples mixed, or converted by `MixerInput::push`, at once.
+const CHUNK_SAMPLES: usize = 1_024;
+
+/// An output stream playing the mix of its inputs.
+///
+/// Dropping the mixer stops the stream. The inputs that are still alive then only fill their ring
+/// buffer.
+pub struct Mixer<S> {
+    stream: S,
+    format: Format,
+    inputs: Registry<Input>,
+}
+
+/// A source played by a `Mixer`.
+///
+/// The samples pushed to the input are interleaved samples of type `T` with the number of
+/// channels and the sample rate given to `Mixer::add_input`. Once the input is dropped, the mixer
+/// plays the samples that are left and then removes it.
+pub struct MixerInput<T> {
+    producer: Producer<f32>,
+    controls: Arc<Controls>,
+    sample_type: PhantomData<fn(T)>,
+}
+
+// The settings of an input, shared between its `MixerInput` and the data callback.
+struct Controls {
+    // The gain and the pan, stored as the bits of an `f32`.
+    gain: AtomicU32,
+    pan: AtomicU32,
+    dropped: AtomicBool,
+}
+
+// An input, as seen by the data callback.
+struct Input {
+    consumer: Consumer<f32>,
+    controls: Arc<Controls>,
+    channels: usize,
+    // The number of input frames per output frame.
+    step: f64,
+    // The position of the next output frame between `previous` and `next`.
+    position: f64,
+    previous: Vec<f32>,
+    next: Vec<f32>,
+}
+
+// The state of the data callback.
+struct Mix {
+    channels: usize,
+    inputs: Items<Input>,
+    scratch: Vec<f32>,
+}
+
+impl<S: StreamTrait> Mixer<S> {
+    /// Builds an output stream of `format` on `device`, and starts playing the mix of the inputs
+    /// added later.
+    ///
+    /// At most `max_inputs` inputs can be played at the same time, so that the data callback never
+    /// allocates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_inputs` or the number of channels of `format` is zero.
+    pub fn new<D, E>(
+        device: &D,
+        format: &Format,
+        max_inputs: usize,
+        error_callback: E,
+    ) -> Result<Self, BuildStreamError>
+    where
+        D: DeviceTrait<Stream = S>,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        assert!(format.channels > 0, "a mixer needs at least one channel");
+        let channels = format.channels as usize;
+        let (inputs, callback_inputs) = registry(max_inputs);
+        let mut mix = Mix {
+            channels,
+            inputs: callback_inputs,
+            scratch: vec![0.0; (CHUNK_SAMPLES / channels).max(1) * channels],
+        };
+        let stream = device.build_output_stream_raw(
+            format,
+            move |mut buffer, _| match buffer.sample_format() {
+                SampleFormat::F32 => mix.process(buffer.as_slice_mut::<f32>().unwrap_or(&mut [])),
+                SampleFormat::I16 => mix.process(buffer.as_slice_mut::<i16>().unwrap_or(&mut [])),
+                SampleFormat::U16 => mix.process(buffer.as_slice_mut::<u16>().unwrap_or(&mut [])),
+            },
+            error_callback,
+        )?;
+        play(&stream)?;
+        Ok(Mixer {
+            stream,
+            format: format.clone(),
+            inputs,
+        })
+    }
+}
+
+impl<S> Mixer<S> {
+    /// Adds an input of `channels` channels at `sample_rate`, with a gain of `1.0` and a centered
+    /// pan.
+    ///
+    /// The ring buffer of the input holds `latency` worth of audio, which bounds the delay between
+    /// a push and the playback of its samples. Returns `None` if `max_inputs` inputs are already
+    /// playing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channels` or `sample_rate` is zero.
+    pub fn add_input<T: Sample>(
+        &mut self,
+        channels: ChannelCount,
+        sample_rate: SampleRate,
+        latency: Duration,
+    ) -> Option<MixerInput<T>> {
+        assert!(channels > 0 && sample_rate.0 > 0, "invalid input format");
+        let step = sample_rate.0 as f64 / self.format.sample_rate.0 as f64;
+        self.inputs.add(|| {
+            let channels = channels as usize;
+            let (producer, consumer) =
+                ring_buffer(latency_capacity(latency, sample_rate, channels));
+            let controls = Arc::new(Controls {
+                gain: AtomicU32::new(1.0f32.to_bits()),
+                pan: AtomicU32::new(0.0f32.to_bits()),
+                dropped: AtomicBool::new(false),
+            });
+            let input = Input {
+                consumer,
+                controls: controls.clone(),
+                channels,
+                step,
+                // Load the first two frames on the first call.
+                position: 2.0,
+                previous: vec![0.0; channels],
+                next: vec![0.0; channels],
+            };
+            let handle = MixerInput {
+                producer,
+                controls,
+                sample_type: PhantomData,
+            };
+            (input, handle)
+        })
+    }
+
+    /// The format of the output stream.
+    pub fn format(&self) -> &Format {
+        &self.format
+    }
+
+    /// The output stream.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<T: Sample> MixerInput<T> {
+    /// Appends samples to the ring buffer of the input, and returns the number of samples that
+    /// fit.
+    ///
+    /// Only complete frames are played, so a partial frame waits for the rest of its samples.
+    pub fn push(&self, samples: &[T]) -> usize {
+        let mut converted = [0.0; CHUNK_SAMPLES];
+        let mut pushed = 0;
+        for chunk in samples.chunks(CHUNK_SAMPLES) {
+            let converted = &mut converted[..chunk.len()];
+            convert_samples(chunk, converted);
+            let count = self.producer.push_slice(converted);
+            pushed += count;
+            if count < chunk.len() {
+                break;
+            }
+        }
+        pushed
+    }
+
+    /// The number of samples that can be pushed without waiting for the mixer.
+    pub fn free_len(&self) -> usize {
+        self.producer.free_len()
+    }
+}
+
+impl<T> MixerInput<T> {
+    /// Sets the factor by which the samples of the input are multiplied.
+    pub fn set_gain(&self, gain: f32) {
+        self.controls.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    /// The factor by which the samples of the input are multiplied.
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.controls.gain.load(Ordering::Relaxed))
+    }
+
+    /// Sets the position of the input between the first two channels of the stream, from `-1.0`
+    /// (left) to `1.0` (right).
+    ///
+    /// Mono inputs are panned with constant power. For inputs with several channels, the pan
+    /// attenuates the first or the second channel instead. The pan has no effect on mono streams.
+    pub fn set_pan(&self, pan: f32) {
+        let pan = pan.clamp(-1.0, 1.0);
+        self.controls.pan.store(pan.to_bits(), Ordering::Relaxed);
+    }
+
+    /// The position of the input between the first two channels of the stream.
+    pub fn pan(&self) -> f32 {
+        f32::from_bits(self.controls.pan.load(Ordering::Relaxed))
+    }
+}
+
+impl<T> Drop for MixerInput<T> {
+    fn drop(&mut self) {
+        self.controls.dropped.store(true, Ordering::Release);
+    }
+}
+
+impl Mix {
+    fn process<T: Sample>(&mut self, output: &mut [T]) {
+        self.inputs.receive();
+        for chunk in output.chunks_mut(self.scratch.len()) {
+            let mix = &mut self.scratch[..chunk.len()];
+            for sample in mix.iter_mut() {
+                *sample = 0.0;
+            }
+            for input in self.inputs.iter_mut() {
+                input.mix_into(mix, self.channels);
+            }
+            convert_samples(mix, chunk);
+        }
+        self.inputs.retire(Input::is_finished);
+    }
+}
+
+impl Input {
+    fn mix_into(&mut self, mix: &mut [f32], channels: usize) {
+        let gain = f32::from_bits(self.controls.gain.load(Ordering::Relaxed));
+        let pan = f32::from_bits(self.controls.pan.load(Ordering::Relaxed));
+        // The gains of the first two channels of the stream.
+        let (left, right) = if self.channels == 1 {
+            let angle = (pan + 1.0) * FRAC_PI_4;
+            (gain * angle.cos(), gain * angle.sin())
+        } else {
+            (gain * (1.0 - pan).min(1.0), gain * (1.0 + pan).min(1.0))
+        };
+        for frame in mix.chunks_mut(channels) {
+            while self.position >= 1.0 {
+                self.advance();
+                self.position -= 1.0;
+            }
+            let t = self.position as f32;
+            let previous = &self.previous;
+            let next = &self.next;
+            let sample = |c: usize| previous[c] + (next[c] - previous[c]) * t;
+            if channels == 1 {
+                let sum: f32 = (0..self.channels).map(sample).sum();
+                frame[0] += gain * sum / self.channels as f32;
+            } else if self.channels == 1 {
+                let value = sample(0);
+                frame[0] += left * value;
+                frame[1] += right * value;
+            } else {
+                frame[0] += left * sample(0);
+                frame[1] += right * sample(1);
+                for (c, out) in frame.iter_mut().enumerate().take(self.channels).skip(2) {
+                    *out += gain * sample(c);
+                }
+            }
+            self.position += self.step;
+        }
+    }
+
+    // Moves to the next frame of the ring buffer, or to silence if there is no complete frame.
+    fn advance(&mut self) {
+        mem::swap(&mut self.previous, &mut self.next);
+        if self.consumer.len() >= self.channels {
+            self.consumer.pop_slice(&mut self.next);
+        } else {
+            for sample in self.next.iter_mut() {
+                *sample = 0.0;
+            }
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.controls.dropped.load(Ordering::Acquire) && self.consumer.len() < self.channels
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Controls, Input, Mix};
+    use registry::registry;
+    use ring_buffer::{ring_buffer, Producer};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicU32};
+
+    fn input(channels: usize, step: f64, pan: f32) -> (Input, Producer<f32>) {
+        let (producer, consumer) = ring_buffer(64);
+        let controls = Arc::new(Controls {
+            gain: AtomicU32::new(1.0f32.to_bits()),
+            pan: AtomicU32::new(pan.to_bits()),
+            dropped: AtomicBool::new(true),
+        });
+        let input = Input {
+            consumer,
+            controls,
+            channels,
+            step,
+            position: 2.0,
+            previous: vec![0.0; channels],
+            next: vec![0.0; channels],
+        };
+        (input, producer)
+    }
+
+    #[test]
+    fn inputs_are_mixed_and_retired() {
+        let (mut inputs, callback_inputs) = registry(2);
+        let mut mix = Mix {
+            channels: 2,
+            inputs: callback_inputs,
+            scratch: vec![0.0; 4],
+        };
+        // A mono input panned to the left, and a stereo input at half the rate of the stream.
+        let (left, left_samples) = input(1, 1.0, -1.0);
+        left_samples.push_slice(&[0.5, 0.5, 0.5]);
+        let (stereo, stereo_samples) = input(2, 0.5, 0.0);
+        stereo_samples.push_slice(&[0.0, 0.0, 0.5, -0.5, 1.0, 1.0, 0.0, 0.0]);
+        assert!(inputs.add(|| (left, ())).is_some());
+        assert!(inputs.add(|| (stereo, ())).is_some());
+
+        let mut output = [0i16; 8];
+        mix.process(&mut output);
+        let expected = [16383, 0, 24575, -8192, 32767, -16384, 24575, 8191];
+        for (&sample, &expected) in output.iter().zip(expected.iter()) {
+            assert!((sample as i32 - expected).abs() <= 1, "{:?}", output);
+        }
+        // Both inputs were dropped and the mono one ran out of samples, which makes room for a
+        // single input.
+        assert_eq!(mix.inputs.iter_mut().count(), 1);
+        let (other, _) = input(1, 1.0, 0.0);
+        assert!(inputs.add(|| (other, ())).is_some());
+        let (other, _) = input(1, 1.0, 0.0);
+        assert!(inputs.add(|| (other, ())).is_none());
+    }
+}
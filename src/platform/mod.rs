@@ -48,16 +48,32 @@ macro_rules! impl_platform_host {
         ///
         /// This type may be constructed via the **host_from_id** function. **HostId**s may
         /// be acquired via the **ALL_HOSTS** const and the **available_hosts** function.
+        ///
+        /// A **Host** is `Send` and `Sync`, and may be used from several threads at once.
         pub struct Host(HostInner);
 
         /// The **Device** implementation associated with the platform's dynamically dispatched
         /// **Host** type.
+        ///
+        /// A **Device** is `Send` and `Sync`. Streams may be built on a device from several
+        /// threads at once.
         pub struct Device(DeviceInner);
 
         /// The **Devices** iterator associated with the platform's dynamically dispatched **Host**
         /// type.
+        ///
+        /// A **Devices** iterator is `Send` and `Sync`.
         pub struct Devices(DevicesInner);
 
+        // Fails to compile if a host breaks the thread-safety documented above.
+        #[allow(dead_code)]
+        fn assert_send_sync() {
+            fn send_sync<T: Send + Sync>() {}
+            send_sync::<Host>();
+            send_sync::<Device>();
+            send_sync::<Devices>();
+        }
+
         /// The **Stream** implementation associated with the platform's dynamically dispatched
         /// **Host** type.
         // Streams cannot be `Send` or `Sync` if we plan to support Android's AAudio API. This is
@@ -65,7 +81,14 @@ macro_rules! impl_platform_host {
         // functions within the callback.
         //
         // TODO: Confirm this and add more specific detail and references.
-        pub struct Stream(StreamInner, crate::platform::NotSendSyncAcrossAllPlatforms);
+        pub struct Stream(
+            StreamInner,
+            crate::platform::NotSendSyncAcrossAllPlatforms,
+            // The WAV file that the stream is teed to, see `tee_to_wav`.
+            crate::platform::StreamTee,
+            // The levels of the stream, see `meter`.
+            crate::platform::StreamMeter,
+        );
 
         /// The **SupportedInputFormats** iterator associated with the platform's dynamically
         /// dispatched **Host** type.
@@ -76,6 +99,15 @@ macro_rules! impl_platform_host {
         pub struct SupportedOutputFormats(SupportedOutputFormatsInner);
 
         /// Unique identifier for available hosts on the platform.
+        ///
+        /// A **HostId** is displayed as a stable lowercase key, such as `alsa` or `wasapi`, which
+        /// parses back into the same **HostId**. Parsing ignores case.
+        ///
+        /// ```
+        /// for &id in cpal::ALL_HOSTS {
+        ///     assert_eq!(id.to_string().parse::<cpal::HostId>().unwrap(), id);
+        /// }
+        /// ```
         #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
         pub enum HostId {
             $(
@@ -127,6 +159,33 @@ macro_rules! impl_platform_host {
                     )*
                 }
             }
+
+            /// The stable key of the host, as used by `Display` and `FromStr`.
+            fn key(&self) -> &'static str {
+                match self {
+                    $(
+                        HostId::$HostVariant => stringify!($host_mod),
+                    )*
+                }
+            }
+        }
+
+        impl std::fmt::Display for HostId {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str(self.key())
+            }
+        }
+
+        impl std::str::FromStr for HostId {
+            type Err = crate::UnknownHostId;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                ALL_HOSTS
+                    .iter()
+                    .cloned()
+                    .find(|id| id.key().eq_ignore_ascii_case(s))
+                    .ok_or_else(|| crate::UnknownHostId { name: s.to_string() })
+            }
         }
 
         impl Host {
@@ -140,6 +199,74 @@ macro_rules! impl_platform_host {
             }
         }
 
+        impl Device {
+            /// The native handle of the device, or `None` if the host has none to offer.
+            ///
+            /// # Safety
+            ///
+            /// The handle is owned by the device and is only valid while the device exists. It
+            /// must not be released, and the device must not be reconfigured through it in ways
+            /// that CPAL does not expect.
+            pub unsafe fn raw_handle(&self) -> Option<crate::RawDeviceHandle> {
+                match self.0 {
+                    $(
+                        DeviceInner::$HostVariant(ref d) => d.raw_handle(),
+                    )*
+                }
+            }
+        }
+
+        impl Stream {
+            /// The native handle of the stream, or `None` if the host has none to offer.
+            ///
+            /// # Safety
+            ///
+            /// The handle is owned by the stream and is only valid while the stream exists. It
+            /// must not be released, and the stream must not be started, stopped or reconfigured
+            /// through it, as CPAL tracks the state of the stream itself. The audio thread uses
+            /// the handle concurrently, so only calls that the platform documents as thread-safe
+            /// may be made.
+            pub unsafe fn raw_handle(&self) -> Option<crate::RawStreamHandle> {
+                match self.0 {
+                    $(
+                        StreamInner::$HostVariant(ref s) => s.raw_handle(),
+                    )*
+                }
+            }
+
+            /// Writes every buffer passed to the data callback to a new WAV file at `path`, once
+            /// the callback has returned.
+            ///
+            /// For output streams this is the audio that the callback produced, and for input
+            /// streams the audio that it consumed, which helps reproducing the glitches reported by
+            /// users. The file is written by a background thread, which leaves out the frames that
+            /// it cannot keep up with. Calling this again switches to another file, and the file is
+            /// complete once the stream is dropped.
+            ///
+            /// Fails if the file cannot be created, or if the stream was not built by a `Device`.
+            #[cfg(feature = "wav")]
+            pub fn tee_to_wav<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+                match self.2 {
+                    Some(ref tee) => tee.start(path.as_ref()),
+                    None => Err(std::io::Error::other("the stream was not built by a device")),
+                }
+            }
+
+            /// The peak and RMS levels of the channels of the stream.
+            ///
+            /// Metering starts with the first call, so that the streams that are not metered do
+            /// not pay for it. The buffers are measured once the data callback has returned, i.e.
+            /// output streams are measured after the callback produced the audio. Returns `None` if
+            /// the stream was not built by a `Device`.
+            #[cfg(feature = "meter")]
+            pub fn meter(&self) -> Option<crate::meter::Meter> {
+                self.3.as_ref().map(|meter| {
+                    meter.enable();
+                    meter.clone()
+                })
+            }
+        }
+
         impl Iterator for Devices {
             type Item = Device;
 
@@ -219,7 +346,9 @@ macro_rules! impl_platform_host {
                 match self.0 {
                     $(
                         DeviceInner::$HostVariant(ref d) => {
-                            d.supported_input_formats()
+                            let result = d.supported_input_formats();
+                            crate::trace::trace_result($host_name, "query of the input formats", &result);
+                            result
                                 .map(SupportedInputFormatsInner::$HostVariant)
                                 .map(SupportedInputFormats)
                         }
@@ -231,7 +360,9 @@ macro_rules! impl_platform_host {
                 match self.0 {
                     $(
                         DeviceInner::$HostVariant(ref d) => {
-                            d.supported_output_formats()
+                            let result = d.supported_output_formats();
+                            crate::trace::trace_result($host_name, "query of the output formats", &result);
+                            result
                                 .map(SupportedOutputFormatsInner::$HostVariant)
                                 .map(SupportedOutputFormats)
                         }
@@ -242,7 +373,11 @@ macro_rules! impl_platform_host {
             fn default_input_format(&self) -> Result<crate::Format, crate::DefaultFormatError> {
                 match self.0 {
                     $(
-                        DeviceInner::$HostVariant(ref d) => d.default_input_format(),
+                        DeviceInner::$HostVariant(ref d) => {
+                            let result = d.default_input_format();
+                            crate::trace::trace_result($host_name, "query of the default input format", &result);
+                            result
+                        }
                     )*
                 }
             }
@@ -250,29 +385,60 @@ macro_rules! impl_platform_host {
             fn default_output_format(&self) -> Result<crate::Format, crate::DefaultFormatError> {
                 match self.0 {
                     $(
-                        DeviceInner::$HostVariant(ref d) => d.default_output_format(),
+                        DeviceInner::$HostVariant(ref d) => {
+                            let result = d.default_output_format();
+                            crate::trace::trace_result($host_name, "query of the default output format", &result);
+                            result
+                        }
                     )*
                 }
             }
 
-            fn build_input_stream<D, E>(&self, format: &crate::Format, data_callback: D, error_callback: E) -> Result<Self::Stream, crate::BuildStreamError>
+            fn build_input_stream_dyn<D, E>(&self, format: &crate::Format, options: &crate::StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, crate::BuildStreamError>
                 where D: FnMut(crate::StreamData) + Send + 'static, E: FnMut(crate::StreamError) + Send + 'static {
                 match self.0 {
                     $(
-                        DeviceInner::$HostVariant(ref d) => d.build_input_stream(format, data_callback, error_callback)
-                            .map(StreamInner::$HostVariant)
-                            .map(Stream::from),
+                        DeviceInner::$HostVariant(ref d) => {
+                            let error_callback = crate::trace::observe_errors($host_name, error_callback);
+                            trace_event!(info, host = $host_name, ?format, ?options, "building input stream");
+                            let surround = if options.downmix_surround {
+                                crate::downmix::surround_format(d, format, true)?
+                            } else {
+                                None
+                            };
+                            let data_callback = crate::downmix::adapt(surround.as_ref(), data_callback);
+                            let format = surround.as_ref().map_or(format, |s| &s.format);
+                            let (data_callback, meter) = crate::platform::meter(format, data_callback);
+                            let (data_callback, tee) = crate::platform::tee(format, data_callback);
+                            let result = d.build_input_stream_dyn(format, options, data_callback, error_callback);
+                            crate::trace::trace_result($host_name, "build of the input stream", &result);
+                            result.map(|s| Stream(StreamInner::$HostVariant(s), Default::default(), tee, meter))
+                        }
                     )*
                 }
             }
 
-            fn build_output_stream<D, E>(&self, format: &crate::Format, data_callback: D, error_callback: E) -> Result<Self::Stream, crate::BuildStreamError>
+            fn build_output_stream_dyn<D, E>(&self, format: &crate::Format, options: &crate::StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, crate::BuildStreamError>
                 where D: FnMut(crate::StreamData) + Send + 'static, E: FnMut(crate::StreamError) + Send + 'static {
                 match self.0 {
                     $(
-                        DeviceInner::$HostVariant(ref d) => d.build_output_stream(format, data_callback, error_callback)
-                            .map(StreamInner::$HostVariant)
-                            .map(Stream::from),
+                        DeviceInner::$HostVariant(ref d) => {
+                            let error_callback = crate::trace::observe_errors($host_name, error_callback);
+                            trace_event!(info, host = $host_name, ?format, ?options, "building output stream");
+                            let surround = if options.downmix_surround {
+                                crate::downmix::surround_format(d, format, false)?
+                            } else {
+                                None
+                            };
+                            let data_callback = crate::downmix::adapt(surround.as_ref(), data_callback);
+                            let format = surround.as_ref().map_or(format, |s| &s.format);
+                            let data_callback = crate::limiter::limit(format, options.limiter.as_ref(), data_callback);
+                            let (data_callback, meter) = crate::platform::meter(format, data_callback);
+                            let (data_callback, tee) = crate::platform::tee(format, data_callback);
+                            let result = d.build_output_stream_dyn(format, options, data_callback, error_callback);
+                            crate::trace::trace_result($host_name, "build of the output stream", &result);
+                            result.map(|s| Stream(StreamInner::$HostVariant(s), Default::default(), tee, meter))
+                        }
                     )*
                 }
             }
@@ -286,11 +452,27 @@ macro_rules! impl_platform_host {
                 $( crate::host::$host_mod::Host::is_available() ||)* false
             }
 
+            fn availability() -> Result<(), crate::HostUnavailable> {
+                // Report the reason of the first host when none of them is available.
+                let mut first_err = None;
+                $(
+                    match crate::host::$host_mod::Host::availability() {
+                        Ok(()) => return Ok(()),
+                        Err(err) => {
+                            first_err.get_or_insert(err);
+                        },
+                    }
+                )*
+                Err(first_err.unwrap_or(crate::HostUnavailable::NotSupported))
+            }
+
             fn devices(&self) -> Result<Self::Devices, crate::DevicesError> {
                 match self.0 {
                     $(
                         HostInner::$HostVariant(ref h) => {
-                            h.devices().map(DevicesInner::$HostVariant).map(Devices::from)
+                            let result = h.devices();
+                            crate::trace::trace_result($host_name, "enumeration of the devices", &result);
+                            result.map(DevicesInner::$HostVariant).map(Devices::from)
                         }
                     )*
                 }
@@ -300,7 +482,9 @@ macro_rules! impl_platform_host {
                 match self.0 {
                     $(
                         HostInner::$HostVariant(ref h) => {
-                            h.default_input_device().map(DeviceInner::$HostVariant).map(Device::from)
+                            let device = h.default_input_device();
+                            trace_event!(debug, host = $host_name, found = device.is_some(), "default input device");
+                            device.map(DeviceInner::$HostVariant).map(Device::from)
                         }
                     )*
                 }
@@ -310,7 +494,9 @@ macro_rules! impl_platform_host {
                 match self.0 {
                     $(
                         HostInner::$HostVariant(ref h) => {
-                            h.default_output_device().map(DeviceInner::$HostVariant).map(Device::from)
+                            let device = h.default_output_device();
+                            trace_event!(debug, host = $host_name, found = device.is_some(), "default output device");
+                            device.map(DeviceInner::$HostVariant).map(Device::from)
                         }
                     )*
                 }
@@ -322,7 +508,9 @@ macro_rules! impl_platform_host {
                 match self.0 {
                     $(
                         StreamInner::$HostVariant(ref s) => {
-                            s.play()
+                            let result = s.play();
+                            crate::trace::trace_result($host_name, "play of the stream", &result);
+                            result
                         }
                     )*
                 }
@@ -332,7 +520,73 @@ macro_rules! impl_platform_host {
                 match self.0 {
                     $(
                         StreamInner::$HostVariant(ref s) => {
-                            s.pause()
+                            let result = s.pause();
+                            crate::trace::trace_result($host_name, "pause of the stream", &result);
+                            result
+                        }
+                    )*
+                }
+            }
+
+            fn play_blocking(&self) -> Result<(), crate::PlayStreamError> {
+                match self.0 {
+                    $(
+                        StreamInner::$HostVariant(ref s) => {
+                            let result = s.play_blocking();
+                            crate::trace::trace_result($host_name, "play of the stream", &result);
+                            result
+                        }
+                    )*
+                }
+            }
+
+            fn pause_blocking(&self) -> Result<(), crate::PauseStreamError> {
+                match self.0 {
+                    $(
+                        StreamInner::$HostVariant(ref s) => {
+                            let result = s.pause_blocking();
+                            crate::trace::trace_result($host_name, "pause of the stream", &result);
+                            result
+                        }
+                    )*
+                }
+            }
+
+            fn callback_load(&self) -> f32 {
+                match self.0 {
+                    $(
+                        StreamInner::$HostVariant(ref s) => {
+                            s.callback_load()
+                        }
+                    )*
+                }
+            }
+
+            fn stats(&self) -> crate::StreamStats {
+                match self.0 {
+                    $(
+                        StreamInner::$HostVariant(ref s) => {
+                            s.stats()
+                        }
+                    )*
+                }
+            }
+
+            fn last_callback(&self) -> Option<std::time::Instant> {
+                match self.0 {
+                    $(
+                        StreamInner::$HostVariant(ref s) => {
+                            s.last_callback()
+                        }
+                    )*
+                }
+            }
+
+            fn position(&self) -> Option<crate::StreamPosition> {
+                match self.0 {
+                    $(
+                        StreamInner::$HostVariant(ref s) => {
+                            s.position()
                         }
                     )*
                 }
@@ -353,13 +607,28 @@ macro_rules! impl_platform_host {
 
         impl From<HostInner> for Host {
             fn from(h: HostInner) -> Self {
-                Host(h)
+                let host = Host(h);
+                trace_event!(info, host = host.id().name(), "host selected");
+                host
             }
         }
 
         impl From<StreamInner> for Stream {
             fn from(s: StreamInner) -> Self {
-                Stream(s, Default::default())
+                Stream(s, Default::default(), Default::default(), Default::default())
+            }
+        }
+
+        // Only implemented with tracing, so that fields can be moved out of `Stream` otherwise.
+        #[cfg(feature = "tracing")]
+        impl Drop for Stream {
+            fn drop(&mut self) {
+                let host = match self.0 {
+                    $(
+                        StreamInner::$HostVariant(_) => $host_name,
+                    )*
+                };
+                trace_event!(debug, host, "stream dropped");
             }
         }
 
@@ -390,22 +659,40 @@ macro_rules! impl_platform_host {
         )*
 
         /// Produces a list of hosts that are currently available on the system.
+        ///
+        /// Use **host_availability** to find out why a host is missing from the list.
         pub fn available_hosts() -> Vec<HostId> {
-            let mut host_ids = vec![];
-            $(
-                if <crate::host::$host_mod::Host as crate::traits::HostTrait>::is_available() {
-                    host_ids.push(HostId::$HostVariant);
-                }
-            )*
+            let host_ids: Vec<HostId> = ALL_HOSTS
+                .iter()
+                .cloned()
+                .filter(|&id| host_availability(id).is_ok())
+                .collect();
+            trace_event!(debug, hosts = ?host_ids, "available hosts");
             host_ids
         }
 
+        /// Whether the host is available on the system, and why it is not if it isn't.
+        pub fn host_availability(id: HostId) -> Result<(), crate::HostUnavailable> {
+            match id {
+                $(
+                    HostId::$HostVariant => {
+                        <crate::host::$host_mod::Host as crate::traits::HostTrait>::availability()
+                    }
+                )*
+            }
+        }
+
         /// Given a unique host identifier, initialise and produce the host if it is available.
+        ///
+        /// The error says why the host is unavailable, as with **host_availability**.
         pub fn host_from_id(id: HostId) -> Result<Host, crate::HostUnavailable> {
             match id {
                 $(
                     HostId::$HostVariant => {
-                        crate::host::$host_mod::Host::new()
+                        let result = host_availability(id)
+                            .and_then(|()| crate::host::$host_mod::Host::new());
+                        crate::trace::trace_result($host_name, "initialization of the host", &result);
+                        result
                             .map(HostInner::$HostVariant)
                             .map(Host::from)
                     }
@@ -416,12 +703,17 @@ macro_rules! impl_platform_host {
 }
 
 // TODO: Add pulseaudio and jack here eventually.
-#[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"))]
+#[cfg(all(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"), feature = "alsa"))]
 mod platform_impl {
     pub use crate::host::alsa::{
+        AccessMode as AlsaAccessMode,
+        ChannelMap as AlsaChannelMap,
+        ChannelMapType as AlsaChannelMapType,
+        ChannelPosition as AlsaChannelPosition,
         Device as AlsaDevice,
         Devices as AlsaDevices,
         Host as AlsaHost,
+        HwParameters as AlsaHwParameters,
         Stream as AlsaStream,
         SupportedInputFormats as AlsaSupportedInputFormats,
         SupportedOutputFormats as AlsaSupportedOutputFormats,
@@ -437,8 +729,7 @@ mod platform_impl {
     }
 }
 
-
-#[cfg(any(target_os = "macos", target_os = "ios"))]
+#[cfg(all(any(target_os = "macos", target_os = "ios"), feature = "coreaudio"))]
 mod platform_impl {
     pub use crate::host::coreaudio::{
         Device as CoreAudioDevice,
@@ -448,6 +739,18 @@ mod platform_impl {
         SupportedInputFormats as CoreAudioSupportedInputFormats,
         SupportedOutputFormats as CoreAudioSupportedOutputFormats,
     };
+    #[cfg(target_os = "ios")]
+    pub use crate::host::coreaudio::{
+        SessionCategory as CoreAudioSessionCategory,
+        SessionConfig as CoreAudioSessionConfig,
+        SessionMode as CoreAudioSessionMode,
+        SessionOptions as CoreAudioSessionOptions,
+    };
+    #[cfg(target_os = "macos")]
+    pub use crate::host::coreaudio::{
+        Workgroup as CoreAudioWorkgroup,
+        WorkgroupMembership as CoreAudioWorkgroupMembership,
+    };
 
     impl_platform_host!(CoreAudio coreaudio "CoreAudio");
 
@@ -459,9 +762,10 @@ mod platform_impl {
     }
 }
 
-#[cfg(target_os = "emscripten")]
+#[cfg(all(target_os = "emscripten", feature = "emscripten"))]
 mod platform_impl {
     pub use crate::host::emscripten::{
+        ContextState as EmscriptenContextState,
         Device as EmscriptenDevice,
         Devices as EmscriptenDevices,
         Host as EmscriptenHost,
@@ -480,24 +784,36 @@ mod platform_impl {
     }
 }
 
-#[cfg(windows)]
+#[cfg(all(windows, feature = "wasapi"))]
 mod platform_impl {
     #[cfg(feature = "asio")]
     pub use crate::host::asio::{
         Device as AsioDevice,
         Devices as AsioDevices,
+        DirectMonitor as AsioDirectMonitor,
         Stream as AsioStream,
         Host as AsioHost,
         SupportedInputFormats as AsioSupportedInputFormats,
         SupportedOutputFormats as AsioSupportedOutputFormats,
     };
     pub use crate::host::wasapi::{
+        set_com_apartment,
+        ComApartment,
+        ClientProperties as WasapiClientProperties,
         Device as WasapiDevice,
         Devices as WasapiDevices,
         Stream as WasapiStream,
         Host as WasapiHost,
+        ProcessLoopbackMode as WasapiProcessLoopbackMode,
+        SpatialAudio as WasapiSpatialAudio,
+        SpatialBuffers as WasapiSpatialBuffers,
+        SpatialChannel as WasapiSpatialChannel,
+        SpatialStream as WasapiSpatialStream,
+        SpatialStreamOptions as WasapiSpatialStreamOptions,
+        StreamCategory as WasapiStreamCategory,
         SupportedInputFormats as WasapiSupportedInputFormats,
         SupportedOutputFormats as WasapiSupportedOutputFormats,
+        WakeMode as WasapiWakeMode,
     };
 
     #[cfg(feature = "asio")]
@@ -514,15 +830,20 @@ mod platform_impl {
     }
 }
 
-#[cfg(not(any(windows, target_os = "linux", target_os = "dragonfly", target_os = "freebsd", target_os = "macos",
-              target_os = "ios", target_os = "emscripten")))]
+// Used on unsupported platforms, and on supported ones when the features of their hosts are
+// disabled.
+#[cfg(not(any(
+    all(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"), feature = "alsa"),
+    all(any(target_os = "macos", target_os = "ios"), feature = "coreaudio"),
+    all(target_os = "emscripten", feature = "emscripten"),
+    all(windows, feature = "wasapi")
+)))]
 mod platform_impl {
     pub use crate::host::null::{
         Device as NullDevice,
         Devices as NullDevices,
-        EventLoop as NullEventLoop,
         Host as NullHost,
-        StreamId as NullStreamId,
+        Stream as NullStream,
         SupportedInputFormats as NullSupportedInputFormats,
         SupportedOutputFormats as NullSupportedOutputFormats,
     };
@@ -537,6 +858,56 @@ mod platform_impl {
     }
 }
 
+// The state of `Stream::tee_to_wav`, which only exists with the `wav` feature. Streams that were
+// not built by a `Device` have none.
+#[cfg(feature = "wav")]
+type StreamTee = Option<crate::wav::Tee>;
+#[cfg(not(feature = "wav"))]
+type StreamTee = ();
+
+// Wraps the data callback of a stream so that the stream can be teed to a WAV file.
+#[cfg(feature = "wav")]
+fn tee<D>(
+    format: &crate::Format,
+    data_callback: D,
+) -> (impl FnMut(crate::StreamData) + Send + 'static, StreamTee)
+where
+    D: FnMut(crate::StreamData) + Send + 'static,
+{
+    let (data_callback, tee) = crate::wav::tee(format, data_callback);
+    (data_callback, Some(tee))
+}
+
+#[cfg(not(feature = "wav"))]
+fn tee<D>(_: &crate::Format, data_callback: D) -> (D, StreamTee) {
+    (data_callback, ())
+}
+
+// The meter of `Stream::meter`, which only exists with the `meter` feature. Streams that were not
+// built by a `Device` have none.
+#[cfg(feature = "meter")]
+type StreamMeter = Option<crate::meter::Meter>;
+#[cfg(not(feature = "meter"))]
+type StreamMeter = ();
+
+// Wraps the data callback of a stream so that the stream can be metered.
+#[cfg(feature = "meter")]
+fn meter<D>(
+    format: &crate::Format,
+    data_callback: D,
+) -> (impl FnMut(crate::StreamData) + Send + 'static, StreamMeter)
+where
+    D: FnMut(crate::StreamData) + Send + 'static,
+{
+    let (data_callback, meter) = crate::meter::meter(format, data_callback);
+    (data_callback, Some(meter))
+}
+
+#[cfg(not(feature = "meter"))]
+fn meter<D>(_: &crate::Format, data_callback: D) -> (D, StreamMeter) {
+    (data_callback, ())
+}
+
 // The following zero-sized types are for applying Send/Sync restrictions to ensure
 // consistent behaviour across different platforms. These verbosely named types are used
 // (rather than using the markers directly) in the hope of making the compile errors
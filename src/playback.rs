@@ -0,0 +1,210 @@
+//! Playing a buffer of samples with a single call, for tools that only need to emit a sound.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use adapter::{play, Shared};
+use convert::convert_samples;
+use signal::Sine;
+use traits::DeviceTrait;
+use BackendSpecificError;
+use BuildStreamError;
+use ChannelCount;
+use DefaultFormatError;
+use Format;
+use Sample;
+use SampleFormat;
+use SampleRate;
+use StreamError;
+use SupportedFormatsError;
+
+// How often `PlaybackHandle::wait` checks whether the playback is over.
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The playback started by `play_samples` or `play_tone`.
+///
+/// Dropping the handle stops the playback.
+pub struct PlaybackHandle<S> {
+    stream: S,
+    finished: Arc<AtomicBool>,
+    shared: Arc<Shared>,
+}
+
+/// Plays `samples`, interleaved samples of `channels` channels at `sample_rate`, on `device`.
+///
+/// The stream uses the most precise sample format that the device supports with this number of
+/// channels and sample rate, and the samples are converted to it. Playback starts immediately and
+/// lasts until all the samples have been played or the returned handle is dropped.
+///
+/// ```no_run
+/// use cpal::traits::HostTrait;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let host = cpal::default_host();
+/// let device = host.default_output_device().expect("no output device available");
+/// // A click in the left channel.
+/// let samples = vec![0.0, 0.0, 1.0, 0.0, 0.0, 0.0];
+/// cpal::play_samples(&device, cpal::SampleRate(48_000), 2, samples)?.wait()?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn play_samples<D: DeviceTrait>(
+    device: &D,
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+    samples: Vec<f32>,
+) -> Result<PlaybackHandle<D::Stream>, BuildStreamError> {
+    let format = output_format(device, sample_rate, channels)?;
+    let finished = Arc::new(AtomicBool::new(false));
+    let shared = Arc::new(Shared::new());
+    let callback_finished = finished.clone();
+    let mut position = 0;
+    let stream = device.build_output_stream_raw(
+        &format,
+        move |mut buffer, _| {
+            buffer.fill_silence();
+            // The previous buffer held the last samples.
+            if position == samples.len() {
+                callback_finished.store(true, Ordering::Release);
+                return;
+            }
+            let remaining = &samples[position..];
+            position += match buffer.sample_format() {
+                SampleFormat::F32 => copy(remaining, buffer.as_slice_mut::<f32>()),
+                SampleFormat::I16 => copy(remaining, buffer.as_slice_mut::<i16>()),
+                SampleFormat::U16 => copy(remaining, buffer.as_slice_mut::<u16>()),
+            };
+        },
+        shared.error_callback(),
+    )?;
+    play(&stream)?;
+    Ok(PlaybackHandle {
+        stream,
+        finished,
+        shared,
+    })
+}
+
+/// Plays a sine wave of `frequency` hertz for `duration` on `device`, at half of the full scale
+/// in all channels.
+///
+/// The tone uses the default output format of the device, and fades in and out over a few
+/// milliseconds to avoid clicks. This can be used as a beep, or to test the speakers of a device.
+pub fn play_tone<D: DeviceTrait>(
+    device: &D,
+    frequency: f32,
+    duration: Duration,
+) -> Result<PlaybackHandle<D::Stream>, BuildStreamError> {
+    let format = device.default_output_format().map_err(default_format_error)?;
+    let sample_rate = format.sample_rate.0 as f32;
+    let channels = format.channels as usize;
+    let frames = (duration.as_secs_f32() * sample_rate) as usize;
+    let fade_frames = ((0.005 * sample_rate) as usize).min(frames / 2).max(1);
+    let mut samples = Vec::with_capacity(frames * channels);
+    for (frame, sine) in (0..frames).zip(Sine::new(frequency, format.sample_rate)) {
+        let fade = frame.min(frames - 1 - frame).min(fade_frames) as f32 / fade_frames as f32;
+        let value = 0.5 * fade * sine;
+        samples.extend((0..channels).map(|_| value));
+    }
+    play_samples(device, format.sample_rate, format.channels, samples)
+}
+
+impl<S> PlaybackHandle<S> {
+    /// Whether all the samples have been played.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    /// Blocks until all the samples have been played, or returns the error that stopped the
+    /// stream.
+    pub fn wait(&self) -> Result<(), StreamError> {
+        while !self.is_finished() {
+            if let Some(err) = self.shared.stopped() {
+                return Err(err);
+            }
+            thread::sleep(WAIT_INTERVAL);
+        }
+        Ok(())
+    }
+
+    /// The output stream.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+pub(crate) fn default_format_error(err: DefaultFormatError) -> BuildStreamError {
+    match err {
+        DefaultFormatError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+        DefaultFormatError::StreamTypeNotSupported => BuildStreamError::InvalidArgument,
+        DefaultFormatError::BackendSpecific { err } => BuildStreamError::BackendSpecific { err },
+    }
+}
+
+pub(crate) fn supported_formats_error(err: SupportedFormatsError) -> BuildStreamError {
+    match err {
+        SupportedFormatsError::DeviceNotAvailable => BuildStreamError::DeviceNotAvailable,
+        SupportedFormatsError::InvalidArgument => BuildStreamError::InvalidArgument,
+        SupportedFormatsError::BackendSpecific { err } => BuildStreamError::BackendSpecific { err },
+        err @ SupportedFormatsError::Timeout => BackendSpecificError {
+            description: err.to_string(),
+            code: None,
+        }
+        .into(),
+    }
+}
+
+// Converts as many samples as fit in `buffer`, and returns their number.
+fn copy<T: Sample>(samples: &[f32], buffer: Option<&mut [T]>) -> usize {
+    let buffer = match buffer {
+        Some(buffer) => buffer,
+        None => return 0,
+    };
+    let count = samples.len().min(buffer.len());
+    convert_samples(&samples[..count], &mut buffer[..count]);
+    count
+}
+
+// The format of the stream playing samples of `channels` channels at `sample_rate`.
+fn output_format<D: DeviceTrait>(
+    device: &D,
+    sample_rate: SampleRate,
+    channels: ChannelCount,
+) -> Result<Format, BuildStreamError> {
+    let formats = device.supported_output_formats().map_err(supported_formats_error)?;
+    formats
+        .filter(|format| {
+            format.channels == channels
+                && format.min_sample_rate <= sample_rate
+                && sample_rate <= format.max_sample_rate
+        })
+        .max_by(|a, b| a.cmp_quality(b))
+        .and_then(|format| format.with_sample_rate(sample_rate))
+        .ok_or(BuildStreamError::FormatNotSupported)
+}
+
+#[cfg(all(test, feature = "virtual-device"))]
+mod test {
+    use super::play_samples;
+    use virtual_device::VirtualDevice;
+    use {BuildStreamError, Format, SampleFormat, SampleRate};
+
+    #[test]
+    fn samples_are_played_in_a_supported_format() {
+        let format = Format {
+            channels: 2,
+            sample_rate: SampleRate(48_000),
+            data_type: SampleFormat::I16,
+        };
+        let device = VirtualDevice::new("virtual", format);
+        let handle = play_samples(&device, SampleRate(48_000), 2, vec![0.5; 2_000]).unwrap();
+        handle.wait().unwrap();
+        assert!(handle.is_finished());
+        match play_samples(&device, SampleRate(44_100), 2, vec![0.5; 2_000]) {
+            Err(BuildStreamError::FormatNotSupported) => (),
+            _ => panic!("the sample rate is not supported"),
+        }
+    }
+}
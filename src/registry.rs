@@ -0,0 +1,118 @@
+//! Handing items, e.g. the inputs of a `Mixer`, to the data callback of a stream and back.
+//!
+//! The owner of the stream adds items to a `Registry`, and the data callback receives them in its
+//! `Items`. Once the callback is done with an item, it retires it to the registry, which frees it
+//! on the next addition. The callback therefore neither allocates nor frees items.
+
+use std::slice;
+
+use ring_buffer::{ring_buffer, Consumer, Producer};
+
+// The side of the owner of the stream.
+pub(crate) struct Registry<T> {
+    max_len: usize,
+    // The number of items that were added and not collected from `retired` yet.
+    len: usize,
+    added: Producer<T>,
+    retired: Consumer<T>,
+}
+
+// The side of the data callback.
+pub(crate) struct Items<T> {
+    items: Vec<T>,
+    added: Consumer<T>,
+    retired: Producer<T>,
+}
+
+// Creates a registry holding at most `max_len` items at the same time.
+pub(crate) fn registry<T>(max_len: usize) -> (Registry<T>, Items<T>) {
+    let (added, added_consumer) = ring_buffer(max_len);
+    let (retired_producer, retired) = ring_buffer(max_len);
+    let registry = Registry {
+        max_len,
+        len: 0,
+        added,
+        retired,
+    };
+    let items = Items {
+        items: Vec::with_capacity(max_len),
+        added: added_consumer,
+        retired: retired_producer,
+    };
+    (registry, items)
+}
+
+impl<T> Registry<T> {
+    // Adds the item returned by `make`, and returns the handle returned with it. Returns `None`
+    // without calling `make` if `max_len` items are alive.
+    pub fn add<R, F>(&mut self, make: F) -> Option<R>
+    where
+        F: FnOnce() -> (T, R),
+    {
+        // Free the items retired by the data callback.
+        while self.retired.pop().is_some() {
+            self.len -= 1;
+        }
+        if self.len == self.max_len {
+            return None;
+        }
+        let (item, handle) = make();
+        // There is room for every item that was not retired.
+        if self.added.push(item).is_err() {
+            return None;
+        }
+        self.len += 1;
+        Some(handle)
+    }
+}
+
+impl<T> Items<T> {
+    // Takes the items added since the last call.
+    pub fn receive(&mut self) {
+        while let Some(item) = self.added.pop() {
+            self.items.push(item);
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> slice::IterMut<'_, T> {
+        self.items.iter_mut()
+    }
+
+    // Hands the items for which `finished` returns `true` back to the registry, so that they are
+    // not freed on the audio thread.
+    pub fn retire<F>(&mut self, mut finished: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut i = 0;
+        while i < self.items.len() {
+            if finished(&self.items[i]) {
+                let item = self.items.swap_remove(i);
+                // The ring buffer has room for every item.
+                let _ = self.retired.push(item);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::registry;
+
+    #[test]
+    fn retired_items_make_room() {
+        let (mut registry, mut items) = registry(2);
+        assert_eq!(registry.add(|| (1, 'a')), Some('a'));
+        assert_eq!(registry.add(|| (2, 'b')), Some('b'));
+        assert_eq!(registry.add(|| -> (i32, char) { panic!("the registry is full") }), None);
+
+        items.receive();
+        assert_eq!(items.iter_mut().count(), 2);
+        items.retire(|&item| item == 1);
+        assert_eq!(items.iter_mut().map(|item| *item).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(registry.add(|| (3, 'c')), Some('c'));
+        assert_eq!(registry.add(|| (4, 'd')), None);
+    }
+}
@@ -0,0 +1,364 @@
+//! Sample rate conversion of interleaved `f32` audio.
+//!
+//! The `Resampler` trait is implemented by the `LinearResampler` and the `CubicResampler`, which
+//! are cheap enough for sound effects and for compensating the drift between the clocks of two
+//! devices, and with the `rubato` feature by the `RubatoResampler`, a band-limited resampler for
+//! music.
+//!
+//! Resamplers process audio in pieces of any size and keep the frames they need between calls, so
+//! that a stream can be resampled buffer by buffer:
+//!
+//! ```
+//! use cpal::resample::{CubicResampler, Resampler};
+//! use cpal::SampleRate;
+//!
+//! let mut resampler = CubicResampler::new(2, SampleRate(44_100), SampleRate(48_000));
+//! let input = vec![0.0; 2 * 441];
+//! let mut output = vec![0.0; 2 * 480];
+//! let (consumed, _produced) = resampler.process(&input, &mut output);
+//! // The last frames are kept until they can be interpolated with the next buffer.
+//! assert_eq!(consumed, 441);
+//! ```
+
+use ChannelCount;
+use SampleRate;
+
+/// Converts interleaved `f32` audio from one sample rate to another.
+///
+/// Implementations must not allocate or block in `process` and `set_ratio`, so that they can be
+/// used in data callbacks.
+pub trait Resampler: Send {
+    /// The number of channels of the audio.
+    fn channels(&self) -> usize;
+
+    /// The number of output frames per input frame.
+    fn ratio(&self) -> f64;
+
+    /// Changes the number of output frames per input frame from now on, e.g. to follow the drift
+    /// between the clocks of two devices.
+    ///
+    /// Implementations may limit how far the ratio can move from the one they were created with.
+    fn set_ratio(&mut self, ratio: f64);
+
+    /// Resamples the frames of `input` into `output`, until either all of `input` has been
+    /// consumed or `output` is full.
+    ///
+    /// Returns the number of frames consumed from `input` and the number of frames written to the
+    /// beginning of `output`. Frames that were consumed but not output yet are kept for the next
+    /// call.
+    fn process(&mut self, input: &[f32], output: &mut [f32]) -> (usize, usize);
+
+    /// Forgets the frames kept from previous calls, e.g. after a discontinuity.
+    fn reset(&mut self);
+}
+
+/// Resamples by linear interpolation between consecutive frames.
+///
+/// This is the cheapest resampler, but it attenuates high frequencies and aliases when
+/// downsampling.
+pub struct LinearResampler {
+    inner: Interpolator,
+}
+
+/// Resamples by cubic (Catmull-Rom) interpolation between consecutive frames.
+///
+/// This is smoother than linear interpolation for about twice the cost, but it is not
+/// band-limited either.
+pub struct CubicResampler {
+    inner: Interpolator,
+}
+
+// The interpolation shared by the `LinearResampler` and the `CubicResampler`.
+struct Interpolator {
+    channels: usize,
+    ratio: f64,
+    // The position of the next output frame between `history[1]` and `history[2]`, in input
+    // frames. Values of `1.0` and more wait for more input.
+    position: f64,
+    // The last four input frames, interleaved.
+    history: Vec<f32>,
+    interpolate: fn(&[f32; 4], f32) -> f32,
+}
+
+impl LinearResampler {
+    /// Creates a resampler of `channels` channels from the rate `from` to the rate `to`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channels` or either rate is zero.
+    pub fn new(channels: ChannelCount, from: SampleRate, to: SampleRate) -> Self {
+        LinearResampler {
+            inner: Interpolator::new(channels, from, to, linear),
+        }
+    }
+}
+
+impl CubicResampler {
+    /// Creates a resampler of `channels` channels from the rate `from` to the rate `to`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `channels` or either rate is zero.
+    pub fn new(channels: ChannelCount, from: SampleRate, to: SampleRate) -> Self {
+        CubicResampler {
+            inner: Interpolator::new(channels, from, to, cubic),
+        }
+    }
+}
+
+impl Interpolator {
+    fn new(
+        channels: ChannelCount,
+        from: SampleRate,
+        to: SampleRate,
+        interpolate: fn(&[f32; 4], f32) -> f32,
+    ) -> Self {
+        assert!(channels > 0 && from.0 > 0 && to.0 > 0, "invalid resampler format");
+        let channels = channels as usize;
+        Interpolator {
+            channels,
+            ratio: to.0 as f64 / from.0 as f64,
+            // Wait for the first three frames, so that the first output frame is the first input
+            // frame.
+            position: 3.0,
+            history: vec![0.0; 4 * channels],
+            interpolate,
+        }
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut [f32]) -> (usize, usize) {
+        let channels = self.channels;
+        let input_frames = input.len() / channels;
+        let output_frames = output.len() / channels;
+        let step = 1.0 / self.ratio;
+        let (mut consumed, mut produced) = (0, 0);
+        while produced < output_frames {
+            while self.position >= 1.0 && consumed < input_frames {
+                let frame = &input[consumed * channels..(consumed + 1) * channels];
+                self.history.copy_within(channels.., 0);
+                self.history[3 * channels..].copy_from_slice(frame);
+                self.position -= 1.0;
+                consumed += 1;
+            }
+            if self.position >= 1.0 {
+                break;
+            }
+            let t = self.position as f32;
+            let out = &mut output[produced * channels..(produced + 1) * channels];
+            for (c, sample) in out.iter_mut().enumerate() {
+                let h = &self.history;
+                let points = [h[c], h[channels + c], h[2 * channels + c], h[3 * channels + c]];
+                *sample = (self.interpolate)(&points, t);
+            }
+            self.position += step;
+            produced += 1;
+        }
+        (consumed, produced)
+    }
+
+    fn reset(&mut self) {
+        self.position = 3.0;
+        for sample in self.history.iter_mut() {
+            *sample = 0.0;
+        }
+    }
+}
+
+fn linear(points: &[f32; 4], t: f32) -> f32 {
+    points[1] + (points[2] - points[1]) * t
+}
+
+fn cubic(points: &[f32; 4], t: f32) -> f32 {
+    let [y0, y1, y2, y3] = *points;
+    let c1 = 0.5 * (y2 - y0);
+    let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+    let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+    ((c3 * t + c2) * t + c1) * t + y1
+}
+
+macro_rules! impl_interpolating_resampler {
+    ($name:ident) => {
+        impl Resampler for $name {
+            fn channels(&self) -> usize {
+                self.inner.channels
+            }
+
+            fn ratio(&self) -> f64 {
+                self.inner.ratio
+            }
+
+            fn set_ratio(&mut self, ratio: f64) {
+                assert!(ratio > 0.0, "the ratio must be positive");
+                self.inner.ratio = ratio;
+            }
+
+            fn process(&mut self, input: &[f32], output: &mut [f32]) -> (usize, usize) {
+                self.inner.process(input, output)
+            }
+
+            fn reset(&mut self) {
+                self.inner.reset()
+            }
+        }
+    };
+}
+
+impl_interpolating_resampler!(LinearResampler);
+impl_interpolating_resampler!(CubicResampler);
+
+#[cfg(feature = "rubato")]
+pub use self::rubato_adapter::RubatoResampler;
+
+#[cfg(feature = "rubato")]
+mod rubato_adapter {
+    use rubato::{self, SincFixedIn, SincInterpolationParameters, SincInterpolationType};
+    use rubato::{Resampler as _, WindowFunction};
+
+    use super::Resampler;
+    use ChannelCount;
+    use SampleRate;
+
+    // The number of input frames resampled at once.
+    const CHUNK_FRAMES: usize = 256;
+    // How far `set_ratio` may move the ratio from the initial one, as a factor.
+    const MAX_RELATIVE_RATIO: f64 = 1.1;
+
+    /// A band-limited resampler using the windowed sinc interpolation of the `rubato` crate,
+    /// enabled by the `rubato` feature.
+    ///
+    /// It resamples chunks of 256 input frames, so it delays the audio by at least one chunk.
+    /// `set_ratio` is limited to 10% away from the initial ratio.
+    pub struct RubatoResampler {
+        resampler: SincFixedIn<f32>,
+        initial_ratio: f64,
+        ratio: f64,
+        // The input frames waiting for a complete chunk, one buffer per channel.
+        input: Vec<Vec<f32>>,
+        input_frames: usize,
+        // The frames of the last chunk that were not output yet, one buffer per channel.
+        output: Vec<Vec<f32>>,
+        output_frames: usize,
+        output_read: usize,
+    }
+
+    impl RubatoResampler {
+        /// Creates a resampler of `channels` channels from the rate `from` to the rate `to`.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `channels` or either rate is zero.
+        pub fn new(channels: ChannelCount, from: SampleRate, to: SampleRate) -> Self {
+            assert!(channels > 0 && from.0 > 0 && to.0 > 0, "invalid resampler format");
+            let ratio = to.0 as f64 / from.0 as f64;
+            let parameters = SincInterpolationParameters {
+                sinc_len: 128,
+                f_cutoff: 0.95,
+                oversampling_factor: 128,
+                interpolation: SincInterpolationType::Cubic,
+                window: WindowFunction::BlackmanHarris2,
+            };
+            let channels = channels as usize;
+            let resampler =
+                SincFixedIn::new(ratio, MAX_RELATIVE_RATIO, parameters, CHUNK_FRAMES, channels)
+                    .expect("the parameters of the resampler are valid");
+            RubatoResampler {
+                input: vec![vec![0.0; resampler.input_frames_max()]; channels],
+                input_frames: 0,
+                output: vec![vec![0.0; resampler.output_frames_max()]; channels],
+                output_frames: 0,
+                output_read: 0,
+                resampler,
+                initial_ratio: ratio,
+                ratio,
+            }
+        }
+    }
+
+    impl Resampler for RubatoResampler {
+        fn channels(&self) -> usize {
+            self.input.len()
+        }
+
+        fn ratio(&self) -> f64 {
+            self.ratio
+        }
+
+        fn set_ratio(&mut self, ratio: f64) {
+            let min = self.initial_ratio / MAX_RELATIVE_RATIO;
+            let max = self.initial_ratio * MAX_RELATIVE_RATIO;
+            self.ratio = ratio.clamp(min, max);
+            // The ratio is within the range that the resampler was created with.
+            let _ = self.resampler.set_resample_ratio(self.ratio, true);
+        }
+
+        fn process(&mut self, input: &[f32], output: &mut [f32]) -> (usize, usize) {
+            let channels = self.channels();
+            let input_frames = input.len() / channels;
+            let output_frames = output.len() / channels;
+            let (mut consumed, mut produced) = (0, 0);
+            loop {
+                while self.output_read < self.output_frames && produced < output_frames {
+                    for c in 0..channels {
+                        output[produced * channels + c] = self.output[c][self.output_read];
+                    }
+                    self.output_read += 1;
+                    produced += 1;
+                }
+                if produced == output_frames {
+                    break;
+                }
+                let needed = self.resampler.input_frames_next();
+                while self.input_frames < needed && consumed < input_frames {
+                    for c in 0..channels {
+                        self.input[c][self.input_frames] = input[consumed * channels + c];
+                    }
+                    self.input_frames += 1;
+                    consumed += 1;
+                }
+                if self.input_frames < needed {
+                    break;
+                }
+                let result =
+                    self.resampler.process_into_buffer(&self.input, &mut self.output, None);
+                self.input_frames = 0;
+                self.output_frames = result.map(|(_, frames)| frames).unwrap_or(0);
+                self.output_read = 0;
+            }
+            (consumed, produced)
+        }
+
+        fn reset(&mut self) {
+            rubato::Resampler::reset(&mut self.resampler);
+            self.input_frames = 0;
+            self.output_frames = 0;
+            self.output_read = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CubicResampler, LinearResampler, Resampler};
+    use SampleRate;
+
+    #[test]
+    fn interpolation_keeps_the_input_frames() {
+        let mut linear = LinearResampler::new(2, SampleRate(24_000), SampleRate(48_000));
+        let input = [0.0, 1.0, 0.5, -1.0, 1.0, 0.0];
+        let mut output = [0.0; 10];
+        // The input is split, and the output waits for the frame after the interpolated ones.
+        assert_eq!(linear.process(&input[..2], &mut output), (1, 0));
+        assert_eq!(linear.process(&input[2..], &mut output), (2, 2));
+        assert_eq!(&output[..4], &[0.0, 1.0, 0.25, 0.0]);
+        assert_eq!(linear.process(&[0.0, 0.0], &mut output), (1, 2));
+        assert_eq!(&output[..4], &[0.5, -1.0, 0.75, -0.5]);
+
+        let mut cubic = CubicResampler::new(1, SampleRate(48_000), SampleRate(48_000));
+        let input = [0.1, 0.2, 0.3, 0.4];
+        let mut output = [0.0; 4];
+        assert_eq!(cubic.process(&input, &mut output), (4, 2));
+        assert_eq!(&output[..2], &[0.1, 0.2]);
+        cubic.set_ratio(0.5);
+        assert_eq!(cubic.ratio(), 0.5);
+    }
+}
@@ -0,0 +1,224 @@
+//! A ring buffer to pass samples between threads in real time.
+//!
+//! The ring buffer has a single producer and a single consumer. Neither side ever blocks or
+//! allocates once the buffer has been created, so both sides may be used from data callbacks. A
+//! typical use is to pass the samples captured by an input stream to an output stream:
+//!
+//! ```
+//! use cpal::ring_buffer::ring_buffer;
+//!
+//! let (producer, consumer) = ring_buffer::<f32>(4_800);
+//! // In the data callback of the input stream.
+//! let pushed = producer.push_slice(&[0.25, 0.5]);
+//! assert_eq!(pushed, 2);
+//! // In the data callback of the output stream.
+//! let mut buffer = [0.0; 4];
+//! let popped = consumer.pop_slice(&mut buffer);
+//! assert_eq!(&buffer[..popped], &[0.25, 0.5]);
+//! ```
+
+use std::cmp;
+use std::time::Duration;
+
+use spsc;
+use SampleRate;
+
+/// The sending half of a ring buffer.
+///
+/// The producer may be sent to another thread, but it cannot be shared between threads.
+pub struct Producer<T> {
+    inner: spsc::Producer<T>,
+}
+
+/// The receiving half of a ring buffer.
+///
+/// The consumer may be sent to another thread, but it cannot be shared between threads.
+pub struct Consumer<T> {
+    inner: spsc::Consumer<T>,
+}
+
+/// Creates a ring buffer holding up to `capacity` values.
+///
+/// This is the only operation of the ring buffer that allocates.
+///
+/// # Panics
+///
+/// Panics if `capacity` is zero.
+pub fn ring_buffer<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let (producer, consumer) = spsc::queue(capacity);
+    (Producer { inner: producer }, Consumer { inner: consumer })
+}
+
+impl<T> Producer<T> {
+    /// Appends a value to the ring buffer, or returns it if the ring buffer is full.
+    #[inline]
+    pub fn push(&self, value: T) -> Result<(), T> {
+        self.inner.push(value)
+    }
+
+    /// The number of values that can be pushed before the ring buffer is full.
+    #[inline]
+    pub fn free_len(&self) -> usize {
+        self.inner.free_len()
+    }
+
+    /// The maximum number of values in the ring buffer.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+}
+
+impl<T: Copy> Producer<T> {
+    /// Appends as many values of `values` as fit in the ring buffer, in order, and returns how
+    /// many were pushed.
+    #[inline]
+    pub fn push_slice(&self, values: &[T]) -> usize {
+        self.inner.push_slice(values)
+    }
+
+    /// Appends as many whole frames of `frame_len` interleaved values of `values` as fit in the
+    /// ring buffer, and returns how many values were pushed.
+    ///
+    /// As long as only whole frames are pushed, the consumer never sees a partial frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frame_len` is zero.
+    #[inline]
+    pub fn push_frames(&self, values: &[T], frame_len: usize) -> usize {
+        let len = cmp::min(self.free_len(), values.len()) / frame_len * frame_len;
+        self.push_slice(&values[..len])
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Removes the oldest value from the ring buffer, or returns `None` if it is empty.
+    #[inline]
+    pub fn pop(&self) -> Option<T> {
+        self.inner.pop()
+    }
+
+    /// The number of values in the ring buffer.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Whether the ring buffer is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The maximum number of values in the ring buffer.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Removes the oldest values from the ring buffer into the beginning of `values` and returns
+    /// how many were popped.
+    #[inline]
+    pub fn pop_slice(&self, values: &mut [T]) -> usize {
+        self.inner.pop_slice(values)
+    }
+
+    /// Removes as many of the oldest whole frames of `frame_len` values as fit into the beginning
+    /// of `values`, and returns how many values were popped.
+    ///
+    /// A partial frame at the end of the ring buffer is left until the rest of it is pushed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frame_len` is zero.
+    #[inline]
+    pub fn pop_frames(&self, values: &mut [T], frame_len: usize) -> usize {
+        let len = cmp::min(self.len(), values.len()) / frame_len * frame_len;
+        self.pop_slice(&mut values[..len])
+    }
+}
+
+/// The capacity of a ring buffer holding `latency` worth of frames of `frame_len` values at
+/// `sample_rate`, and at least one frame.
+pub(crate) fn latency_capacity(
+    latency: Duration,
+    sample_rate: SampleRate,
+    frame_len: usize,
+) -> usize {
+    let frames = (latency.as_secs_f64() * sample_rate.0 as f64) as usize;
+    cmp::max(frames, 1) * cmp::max(frame_len, 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::ring_buffer;
+    use std::thread;
+
+    #[test]
+    fn slices_wrap_around() {
+        let (producer, consumer) = ring_buffer(5);
+        let mut out = [0; 5];
+        for round in 0..10 {
+            let values = [round, round + 1, round + 2];
+            assert_eq!(producer.push_slice(&values), 3);
+            assert_eq!(consumer.len(), 3);
+            assert_eq!(producer.free_len(), 2);
+            assert_eq!(consumer.pop_slice(&mut out), 3);
+            assert_eq!(&out[..3], &values);
+            assert!(consumer.is_empty());
+        }
+        assert_eq!(producer.push_slice(&[1, 2, 3, 4, 5, 6]), 5);
+        assert_eq!(producer.push(7), Err(7));
+        assert_eq!(consumer.pop_slice(&mut out[..2]), 2);
+        assert_eq!(consumer.pop(), Some(3));
+    }
+
+    #[test]
+    fn only_whole_frames_are_pushed_and_popped() {
+        let (producer, consumer) = ring_buffer(5);
+        assert_eq!(producer.push_frames(&[1, 2, 3, 4, 5, 6], 2), 4);
+        assert_eq!(producer.push_frames(&[7, 8], 2), 0);
+        assert_eq!(producer.push_slice(&[5]), 1);
+        let mut out = [0; 6];
+        assert_eq!(consumer.pop_frames(&mut out[..3], 2), 2);
+        assert_eq!(consumer.pop_frames(&mut out, 2), 2);
+        assert_eq!(&out[..2], &[3, 4]);
+        // The partial frame waits for the rest of its values.
+        assert_eq!(consumer.pop_frames(&mut out, 2), 0);
+        assert_eq!(producer.push_slice(&[6]), 1);
+        assert_eq!(consumer.pop_frames(&mut out, 2), 2);
+        assert_eq!(&out[..2], &[5, 6]);
+    }
+
+    #[test]
+    fn values_cross_threads_in_order() {
+        let (producer, consumer) = ring_buffer(64);
+        let count = 100_000u32;
+        let thread = thread::spawn(move || {
+            let mut next = 0;
+            while next < count {
+                let values: Vec<u32> = (next..count.min(next + 48)).collect();
+                match producer.push_slice(&values) {
+                    0 => thread::yield_now(),
+                    pushed => next += pushed as u32,
+                }
+            }
+        });
+        let mut expected = 0;
+        let mut out = [0; 32];
+        while expected < count {
+            let popped = consumer.pop_slice(&mut out);
+            if popped == 0 {
+                thread::yield_now();
+            }
+            for &value in &out[..popped] {
+                assert_eq!(value, expected);
+                expected += 1;
+            }
+        }
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+//! Checks for real-time safety violations in data callbacks.
+//!
+//! This module is only available with the `rt-debug` feature. It is meant to be used during
+//! development, to find out whether a data callback performs operations that may block the audio
+//! thread and cause glitches.
+//!
+//! While a data callback runs, CPAL records:
+//!
+//! - heap allocations and deallocations, if `RtCheckAllocator` is installed as the global
+//!   allocator,
+//! - voluntary context switches of the audio thread (Linux only), which happen when the callback
+//!   waits on a contended lock or performs a blocking system call.
+//!
+//! When the callback returns, every violation is passed to the handler registered with
+//! `set_violation_handler`. By default, violations are printed to stderr.
+//!
+//! ```no_run
+//! use cpal::rt_debug::{self, RtCheckAllocator};
+//! use std::alloc::System;
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: RtCheckAllocator = RtCheckAllocator(System);
+//!
+//! fn main() {
+//!     rt_debug::set_violation_handler(|violation| panic!("{}", violation));
+//!     // Build and play streams as usual.
+//! }
+//! ```
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fmt;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+thread_local! {
+    static IN_CALLBACK: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static DEALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+// The handler registered with `set_violation_handler`, or `0` for the default handler.
+static VIOLATION_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// A global allocator that records the allocations performed inside data callbacks.
+///
+/// All allocations are forwarded to the inner allocator.
+#[derive(Debug, Default)]
+pub struct RtCheckAllocator<A = System>(pub A);
+
+/// The real-time safety violations that occurred during one invocation of a data callback.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Violation {
+    /// Number of heap allocations and reallocations.
+    pub allocations: usize,
+    /// Number of heap deallocations.
+    pub deallocations: usize,
+    /// Number of times the audio thread blocked, e.g. on a contended lock or a system call.
+    pub blocking_waits: usize,
+}
+
+/// Registers the function called for each data callback that violated real-time safety.
+///
+/// The handler is called on the audio thread right after the data callback returns.
+pub fn set_violation_handler(handler: fn(&Violation)) {
+    VIOLATION_HANDLER.store(handler as usize, Ordering::SeqCst);
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for RtCheckAllocator<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record(&ALLOCATIONS);
+        self.0.alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record(&ALLOCATIONS);
+        self.0.alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record(&ALLOCATIONS);
+        self.0.realloc(ptr, layout, new_size)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        record(&DEALLOCATIONS);
+        self.0.dealloc(ptr, layout)
+    }
+}
+
+impl Violation {
+    fn is_empty(&self) -> bool {
+        *self == Violation::default()
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "real-time safety violation in data callback: {} allocation(s), {} deallocation(s), \
+             {} blocking wait(s)",
+            self.allocations,
+            self.deallocations,
+            self.blocking_waits,
+        )
+    }
+}
+
+/// Marks the current thread as running a data callback until `exit` is called.
+///
+/// Guards may be nested, in which case the violations occurring inside the inner guard are only
+/// reported by the inner guard.
+pub(crate) struct CallbackGuard {
+    context_switches: usize,
+    // The state of the enclosing guard, restored by `exit`.
+    outer_in_callback: bool,
+    outer_allocations: usize,
+    outer_deallocations: usize,
+}
+
+impl CallbackGuard {
+    #[inline]
+    pub fn enter() -> Self {
+        let outer_allocations = ALLOCATIONS.with(|c| c.replace(0));
+        let outer_deallocations = DEALLOCATIONS.with(|c| c.replace(0));
+        let context_switches = voluntary_context_switches();
+        let outer_in_callback = IN_CALLBACK.with(|c| c.replace(true));
+        CallbackGuard {
+            context_switches,
+            outer_in_callback,
+            outer_allocations,
+            outer_deallocations,
+        }
+    }
+
+    /// Marks the end of the data callback and reports any violation.
+    #[inline]
+    pub fn exit(self) {
+        IN_CALLBACK.with(|c| c.set(false));
+        let violation = Violation {
+            allocations: ALLOCATIONS.with(|c| c.replace(self.outer_allocations)),
+            deallocations: DEALLOCATIONS.with(|c| c.replace(self.outer_deallocations)),
+            blocking_waits: voluntary_context_switches().saturating_sub(self.context_switches),
+        };
+        if !violation.is_empty() {
+            report(&violation);
+        }
+        IN_CALLBACK.with(|c| c.set(self.outer_in_callback));
+    }
+}
+
+#[inline]
+fn record(counter: &'static ::std::thread::LocalKey<Cell<usize>>) {
+    // `try_with` fails while thread-local storage is being torn down.
+    let in_callback = IN_CALLBACK.try_with(|c| c.get()).unwrap_or(false);
+    if in_callback {
+        let _ = counter.try_with(|c| c.set(c.get() + 1));
+    }
+}
+
+fn report(violation: &Violation) {
+    let handler = VIOLATION_HANDLER.load(Ordering::SeqCst);
+    if handler == 0 {
+        eprintln!("{}", violation);
+    } else {
+        let handler: fn(&Violation) = unsafe { mem::transmute(handler) };
+        handler(violation);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn voluntary_context_switches() -> usize {
+    extern crate libc;
+    unsafe {
+        let mut usage: libc::rusage = mem::zeroed();
+        if libc::getrusage(libc::RUSAGE_THREAD, &mut usage) != 0 {
+            return 0;
+        }
+        usage.ru_nvcsw as usize
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn voluntary_context_switches() -> usize {
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::{set_violation_handler, CallbackGuard, RtCheckAllocator, Violation};
+    use std::alloc::System;
+    use std::cell::Cell;
+    use stats::StreamMeter;
+    use {CallbackInfo, SampleRate};
+
+    #[global_allocator]
+    static ALLOCATOR: RtCheckAllocator = RtCheckAllocator(System);
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn record_violation(violation: &Violation) {
+        ALLOCATIONS.with(|c| c.set(c.get() + violation.allocations));
+    }
+
+    // Runs `f` as if it was a data callback and returns the number of allocations it performed.
+    fn count_allocations<F: FnOnce()>(f: F) -> usize {
+        set_violation_handler(record_violation);
+        ALLOCATIONS.with(|c| c.set(0));
+        let guard = CallbackGuard::enter();
+        f();
+        guard.exit();
+        ALLOCATIONS.with(|c| c.get())
+    }
+
+    #[test]
+    fn allocations_are_reported() {
+        assert_eq!(count_allocations(|| drop(Vec::<u8>::with_capacity(16))), 1);
+    }
+
+    #[test]
+    fn nested_allocations_are_reported_once() {
+        let allocations = count_allocations(|| {
+            drop(Vec::<u8>::with_capacity(16));
+            let guard = CallbackGuard::enter();
+            drop(Vec::<u8>::with_capacity(16));
+            guard.exit();
+            drop(Vec::<u8>::with_capacity(16));
+        });
+        assert_eq!(allocations, 3);
+    }
+
+    #[test]
+    fn callback_path_does_not_allocate() {
+        let meter = StreamMeter::new(SampleRate(48_000));
+        let info = CallbackInfo::new(480, false, Default::default());
+        let (producer, consumer) = ::spsc::queue(4);
+        let allocations = count_allocations(|| {
+            meter.measure(&info, || drop(Vec::<u8>::with_capacity(16)));
+            meter.record_underrun();
+            let _ = meter.position();
+            producer.push(1u8).unwrap();
+            consumer.pop().unwrap();
+        });
+        // Only the allocation of the callback itself.
+        assert_eq!(allocations, 1);
+    }
+}
@@ -0,0 +1,251 @@
+//! Test signals, for measuring devices and for testing streams.
+//!
+//! Each generator is an endless iterator over mono `f32` samples between `-1.0` and `1.0`, so that
+//! signals can be scaled or combined with the methods of `Iterator`. `output_callback` turns a
+//! signal into the data callback of an output stream.
+//!
+//! ```no_run
+//! use cpal::signal::{self, Sine};
+//! use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let host = cpal::default_host();
+//! let device = host.default_output_device().expect("no output device available");
+//! let mut format = device.default_output_format()?;
+//! format.data_type = cpal::SampleFormat::F32;
+//! // A 1 kHz tone at -6 dBFS.
+//! let sine = Sine::new(1_000.0, format.sample_rate).map(|sample| 0.5 * sample);
+//! let stream = device.build_output_stream::<f32, _, _>(
+//!     &format,
+//!     signal::output_callback(sine, format.channels),
+//!     |err| eprintln!("an error occurred on the output stream: {}", err),
+//! )?;
+//! stream.play()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use ChannelCount;
+use OutputCallbackInfo;
+use Sample;
+use SampleRate;
+
+/// A sine wave.
+#[derive(Clone, Debug)]
+pub struct Sine {
+    // The phase of the next sample and its increment per sample, in turns.
+    phase: f64,
+    step: f64,
+}
+
+/// A sine wave whose frequency rises exponentially, as used to measure frequency responses.
+///
+/// The sweep starts over once it reaches its end frequency.
+#[derive(Clone, Debug)]
+pub struct Sweep {
+    phase: f64,
+    sample_rate: f64,
+    start_frequency: f64,
+    // The factor by which the frequency is multiplied each sample.
+    growth: f64,
+    frequency: f64,
+    frames: u64,
+    position: u64,
+}
+
+/// White noise, uniformly distributed.
+///
+/// The noise is pseudo-random, so that two generators with the same seed produce the same
+/// samples.
+#[derive(Clone, Debug)]
+pub struct WhiteNoise {
+    state: u64,
+}
+
+/// Single samples of `1.0` separated by silence, as used to measure latencies and impulse
+/// responses.
+#[derive(Clone, Debug)]
+pub struct Impulse {
+    interval: u64,
+    position: u64,
+}
+
+impl Sine {
+    /// Creates a sine wave of `frequency` hertz, starting at zero.
+    pub fn new(frequency: f32, sample_rate: SampleRate) -> Self {
+        Sine {
+            phase: 0.0,
+            step: frequency as f64 / sample_rate.0 as f64,
+        }
+    }
+}
+
+impl Iterator for Sine {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = (2.0 * PI * self.phase).sin();
+        self.phase = (self.phase + self.step).fract();
+        Some(sample as f32)
+    }
+}
+
+impl Sweep {
+    /// Creates a sweep from `start_frequency` to `end_frequency` hertz over `duration`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either frequency is not positive.
+    pub fn new(
+        start_frequency: f32,
+        end_frequency: f32,
+        duration: Duration,
+        sample_rate: SampleRate,
+    ) -> Self {
+        assert!(start_frequency > 0.0 && end_frequency > 0.0, "frequencies must be positive");
+        let sample_rate = sample_rate.0 as f64;
+        let frames = ((duration.as_secs_f64() * sample_rate) as u64).max(1);
+        let ratio = end_frequency as f64 / start_frequency as f64;
+        Sweep {
+            phase: 0.0,
+            sample_rate,
+            start_frequency: start_frequency as f64,
+            growth: ratio.powf(1.0 / frames as f64),
+            frequency: start_frequency as f64,
+            frames,
+            position: 0,
+        }
+    }
+}
+
+impl Iterator for Sweep {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position == self.frames {
+            self.phase = 0.0;
+            self.frequency = self.start_frequency;
+            self.position = 0;
+        }
+        let sample = (2.0 * PI * self.phase).sin();
+        self.phase = (self.phase + self.frequency / self.sample_rate).fract();
+        self.frequency *= self.growth;
+        self.position += 1;
+        Some(sample as f32)
+    }
+}
+
+impl WhiteNoise {
+    /// Creates white noise from `seed`.
+    pub fn new(seed: u64) -> Self {
+        // The generator must not start from zero, which it would never leave.
+        WhiteNoise {
+            state: if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed },
+        }
+    }
+}
+
+impl Iterator for WhiteNoise {
+    type Item = f32;
+
+    // An xorshift64* generator.
+    fn next(&mut self) -> Option<f32> {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let random = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        // The 24 high bits, which an `f32` represents exactly.
+        let unit = (random >> 40) as f32 / (1 << 24) as f32;
+        Some(2.0 * unit - 1.0)
+    }
+}
+
+impl Impulse {
+    /// Creates impulses every `interval`, starting with the first sample.
+    ///
+    /// An `interval` shorter than a sample produces a single impulse.
+    pub fn new(interval: Duration, sample_rate: SampleRate) -> Self {
+        let interval = (interval.as_secs_f64() * sample_rate.0 as f64).round() as u64;
+        Impulse {
+            interval,
+            position: 0,
+        }
+    }
+}
+
+impl Iterator for Impulse {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = if self.position == 0 { 1.0 } else { 0.0 };
+        self.position += 1;
+        if self.position == self.interval {
+            self.position = 0;
+        }
+        Some(sample)
+    }
+}
+
+/// Creates the data callback of an output stream of `channels` channels playing `signal` in all
+/// its channels.
+///
+/// The stream is silent once `signal` ends.
+pub fn output_callback<I, T>(
+    mut signal: I,
+    channels: ChannelCount,
+) -> impl FnMut(&mut [T], &OutputCallbackInfo) + Send + 'static
+where
+    I: Iterator<Item = f32> + Send + 'static,
+    T: Sample,
+{
+    let channels = (channels as usize).max(1);
+    move |data: &mut [T], _: &OutputCallbackInfo| {
+        for frame in data.chunks_mut(channels) {
+            let value = signal.next().unwrap_or(0.0);
+            for sample in frame.iter_mut() {
+                *sample = T::from(&value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{output_callback, Impulse, Sine, Sweep, WhiteNoise};
+    use std::time::Duration;
+    use {CallbackInfo, OutputCallbackInfo, SampleRate};
+
+    #[test]
+    fn generators() {
+        let sine: Vec<f32> = Sine::new(12_000.0, SampleRate(48_000)).take(5).collect();
+        let expected = [0.0, 1.0, 0.0, -1.0, 0.0];
+        assert!(sine.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-6));
+
+        let mut sweep = Sweep::new(100.0, 1_000.0, Duration::from_millis(10), SampleRate(48_000));
+        let first: Vec<f32> = sweep.by_ref().take(480).collect();
+        let second: Vec<f32> = sweep.take(480).collect();
+        assert_eq!(first, second);
+
+        let noise: Vec<f32> = WhiteNoise::new(1).take(1_000).collect();
+        assert!(noise.iter().all(|&sample| -1.0 <= sample && sample < 1.0));
+        assert_eq!(noise, WhiteNoise::new(1).take(1_000).collect::<Vec<_>>());
+        assert_ne!(noise, WhiteNoise::new(2).take(1_000).collect::<Vec<_>>());
+
+        let impulses: Vec<f32> = Impulse::new(Duration::from_millis(1), SampleRate(3_000))
+            .take(7)
+            .collect();
+        assert_eq!(impulses, [1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn callback_plays_the_signal_in_all_channels() {
+        let signal = vec![0.5, -0.5].into_iter();
+        let mut callback = output_callback(signal, 2);
+        let mut data = [1.0f32; 6];
+        callback(&mut data, &OutputCallbackInfo::new(CallbackInfo::default()));
+        assert_eq!(data, [0.5, 0.5, -0.5, -0.5, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,225 @@
+//! Sharing the samples captured by an input stream between several consumers.
+//!
+//! Many drivers refuse to open a capture device twice. An `InputSplitter` opens it once and copies
+//! every captured buffer to the ring buffer of each of its `SplitConsumer`s, e.g. a recorder and a
+//! level meter. Consumers can be added and dropped while the stream is running, and a consumer that
+//! falls behind only loses its own samples.
+//!
+//! ```no_run
+//! use cpal::splitter::InputSplitter;
+//! use cpal::traits::{DeviceTrait, HostTrait};
+//! use std::time::Duration;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let host = cpal::default_host();
+//! let device = host.default_input_device().expect("no input device available");
+//! let mut format = device.default_input_format()?;
+//! format.data_type = cpal::SampleFormat::F32;
+//! let error_callback = |err| eprintln!("an error occurred on the input stream: {}", err);
+//! let mut splitter = InputSplitter::<_, f32>::new(&device, &format, 4, error_callback)?;
+//! let recorder = splitter.add_consumer(Duration::from_millis(500)).expect("too many consumers");
+//! let meter = splitter.add_consumer(Duration::from_millis(50)).expect("too many consumers");
+//! # let mut buffer = [0.0; 1_024];
+//! # recorder.pop_slice(&mut buffer);
+//! # meter.pop_slice(&mut buffer);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use adapter::play;
+use registry::{registry, Items, Registry};
+use ring_buffer::{latency_capacity, ring_buffer, Consumer, Producer};
+use traits::{DeviceTrait, StreamTrait};
+use BuildStreamError;
+use Format;
+use Sample;
+use StreamError;
+
+/// An input stream copying the captured samples to each of its consumers.
+///
+/// Dropping the splitter stops the stream. The consumers that are still alive then only drain
+/// their ring buffer.
+pub struct InputSplitter<S, T> {
+    stream: S,
+    format: Format,
+    consumers: Registry<Output<T>>,
+}
+
+/// Reads the samples captured by the stream of an `InputSplitter`.
+///
+/// The samples are interleaved and the ring buffer only receives whole frames. If the samples are
+/// not read in time, the frames that do not fit in the ring buffer are dropped.
+pub struct SplitConsumer<T> {
+    consumer: Consumer<T>,
+    dropped: Arc<AtomicBool>,
+}
+
+// A consumer, as seen by the data callback.
+struct Output<T> {
+    producer: Producer<T>,
+    dropped: Arc<AtomicBool>,
+}
+
+// The state of the data callback.
+struct Split<T> {
+    frame_len: usize,
+    outputs: Items<Output<T>>,
+}
+
+impl<S, T> InputSplitter<S, T>
+where
+    S: StreamTrait,
+    T: Sample + Send + 'static,
+{
+    /// Builds an input stream of `format` on `device`, and starts capturing for the consumers
+    /// added later.
+    ///
+    /// At most `max_consumers` consumers can be alive at the same time, so that the data callback
+    /// never allocates. `T` must be the sample type of `format.data_type`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_consumers` is zero.
+    pub fn new<D, E>(
+        device: &D,
+        format: &Format,
+        max_consumers: usize,
+        error_callback: E,
+    ) -> Result<Self, BuildStreamError>
+    where
+        D: DeviceTrait<Stream = S>,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        let (consumers, outputs) = registry(max_consumers);
+        let mut split = Split {
+            frame_len: (format.channels as usize).max(1),
+            outputs,
+        };
+        let stream = device.build_input_stream(
+            format,
+            move |data: &[T], _: &_| split.process(data),
+            error_callback,
+        )?;
+        play(&stream)?;
+        Ok(InputSplitter {
+            stream,
+            format: format.clone(),
+            consumers,
+        })
+    }
+}
+
+impl<S, T> InputSplitter<S, T> {
+    /// Adds a consumer receiving the samples captured from now on.
+    ///
+    /// The ring buffer of the consumer holds `latency` worth of audio, which bounds how late
+    /// captured samples may be read before they are dropped. Returns `None` if `max_consumers`
+    /// consumers are already alive.
+    pub fn add_consumer(&mut self, latency: Duration) -> Option<SplitConsumer<T>> {
+        let format = &self.format;
+        self.consumers.add(|| {
+            let channels = format.channels as usize;
+            let (producer, consumer) =
+                ring_buffer(latency_capacity(latency, format.sample_rate, channels));
+            let dropped = Arc::new(AtomicBool::new(false));
+            let output = Output {
+                producer,
+                dropped: dropped.clone(),
+            };
+            (output, SplitConsumer { consumer, dropped })
+        })
+    }
+
+    /// The format of the input stream.
+    pub fn format(&self) -> &Format {
+        &self.format
+    }
+
+    /// The input stream.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<T: Copy> SplitConsumer<T> {
+    /// Removes samples from the ring buffer into `values`, and returns the number of samples
+    /// removed.
+    pub fn pop_slice(&self, values: &mut [T]) -> usize {
+        self.consumer.pop_slice(values)
+    }
+}
+
+impl<T> SplitConsumer<T> {
+    /// The number of samples in the ring buffer.
+    pub fn len(&self) -> usize {
+        self.consumer.len()
+    }
+
+    /// Whether the ring buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.consumer.is_empty()
+    }
+}
+
+impl<T> Drop for SplitConsumer<T> {
+    fn drop(&mut self) {
+        self.dropped.store(true, Ordering::Release);
+    }
+}
+
+impl<T: Copy> Split<T> {
+    fn process(&mut self, data: &[T]) {
+        self.outputs.receive();
+        self.outputs.retire(|output| output.dropped.load(Ordering::Acquire));
+        for output in self.outputs.iter_mut() {
+            output.producer.push_frames(data, self.frame_len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Output, Split};
+    use registry::registry;
+    use ring_buffer::ring_buffer;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn consumers_receive_whole_frames() {
+        let (mut consumers, outputs) = registry(2);
+        let mut split = Split {
+            frame_len: 2,
+            outputs,
+        };
+        let (small, small_consumer) = ring_buffer(5);
+        let (large, large_consumer) = ring_buffer(8);
+        let dropped = Arc::new(AtomicBool::new(false));
+        for producer in vec![small, large] {
+            let output = Output { producer, dropped: dropped.clone() };
+            assert!(consumers.add(|| (output, ())).is_some());
+        }
+
+        split.process(&[1i16, 2, 3, 4, 5, 6]);
+        let mut buffer = [0; 8];
+        assert_eq!(small_consumer.pop_slice(&mut buffer), 4);
+        assert_eq!(&buffer[..4], &[1, 2, 3, 4]);
+        assert_eq!(large_consumer.pop_slice(&mut buffer), 6);
+        assert_eq!(&buffer[..6], &[1, 2, 3, 4, 5, 6]);
+
+        dropped.store(true, Ordering::Release);
+        split.process(&[7, 8]);
+        assert!(large_consumer.is_empty());
+        assert_eq!(split.outputs.iter_mut().count(), 0);
+        // Both consumers were retired, which makes room for two more.
+        for _ in 0..2 {
+            let (producer, _) = ring_buffer(2);
+            let output = Output { producer, dropped: dropped.clone() };
+            assert!(consumers.add(|| (output, ())).is_some());
+        }
+    }
+}
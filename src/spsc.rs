@@ -0,0 +1,237 @@
+//! A fixed-capacity, lock-free queue with a single producer and a single consumer.
+//!
+//! Neither side ever blocks or allocates after the queue has been created, which allows it to be
+//! used to communicate with the audio thread.
+
+use std::cell::{Cell, UnsafeCell};
+use std::cmp;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The sending half of a queue.
+///
+/// Only one thread may push at a time, so the producer is `Send` but not `Sync`.
+pub(crate) struct Producer<T> {
+    shared: Arc<Shared<T>>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+/// The receiving half of a queue.
+pub(crate) struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+struct Shared<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // The number of values popped so far, modulo twice the capacity. Only written by the consumer.
+    head: AtomicUsize,
+    // The number of values pushed so far, modulo twice the capacity. Only written by the producer.
+    //
+    // Counting modulo twice the capacity tells a full queue apart from an empty one, and keeps the
+    // index of a slot consistent when the counters wrap around.
+    tail: AtomicUsize,
+}
+
+// The slots are only accessed by the side that owns them according to `head` and `tail`.
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+/// Creates a queue holding up to `capacity` values.
+pub(crate) fn queue<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "the capacity of a queue must not be zero");
+    assert!(capacity <= usize::MAX / 4, "the capacity of a queue is too large");
+    let slots = (0..capacity)
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect();
+    let shared = Arc::new(Shared {
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    let producer = Producer {
+        shared: shared.clone(),
+        _not_sync: PhantomData,
+    };
+    let consumer = Consumer {
+        shared,
+        _not_sync: PhantomData,
+    };
+    (producer, consumer)
+}
+
+impl<T> Producer<T> {
+    /// Appends a value to the queue, or returns it if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let head = shared.head.load(Ordering::Acquire);
+        if shared.len(head, tail) == shared.slots.len() {
+            return Err(value);
+        }
+        unsafe {
+            (*shared.slot(tail)).as_mut_ptr().write(value);
+        }
+        shared.tail.store(shared.advance(tail, 1), Ordering::Release);
+        Ok(())
+    }
+
+    /// The number of values that can be pushed before the queue is full.
+    pub fn free_len(&self) -> usize {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let head = shared.head.load(Ordering::Acquire);
+        shared.slots.len() - shared.len(head, tail)
+    }
+
+    /// The maximum number of values in the queue.
+    pub fn capacity(&self) -> usize {
+        self.shared.slots.len()
+    }
+}
+
+impl<T: Copy> Producer<T> {
+    /// Appends as many values of `values` as fit in the queue and returns how many were pushed.
+    pub fn push_slice(&self, values: &[T]) -> usize {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let head = shared.head.load(Ordering::Acquire);
+        let count = cmp::min(values.len(), shared.slots.len() - shared.len(head, tail));
+        unsafe {
+            let (first, second) = shared.ranges(tail, count);
+            let slots = shared.slots_ptr();
+            ptr::copy_nonoverlapping(values.as_ptr(), slots.add(first.0), first.1);
+            ptr::copy_nonoverlapping(values.as_ptr().add(first.1), slots, second);
+        }
+        shared.tail.store(shared.advance(tail, count), Ordering::Release);
+        count
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Removes the oldest value from the queue, or returns `None` if the queue is empty.
+    pub fn pop(&self) -> Option<T> {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let value = unsafe { ptr::read((*shared.slot(head)).as_ptr()) };
+        shared.head.store(shared.advance(head, 1), Ordering::Release);
+        Some(value)
+    }
+
+    /// The number of values in the queue.
+    pub fn len(&self) -> usize {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+        shared.len(head, tail)
+    }
+
+    /// The maximum number of values in the queue.
+    pub fn capacity(&self) -> usize {
+        self.shared.slots.len()
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Removes the oldest values from the queue into `values` and returns how many were popped.
+    pub fn pop_slice(&self, values: &mut [T]) -> usize {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+        let count = cmp::min(values.len(), shared.len(head, tail));
+        unsafe {
+            let (first, second) = shared.ranges(head, count);
+            let slots = shared.slots_ptr();
+            ptr::copy_nonoverlapping(slots.add(first.0), values.as_mut_ptr(), first.1);
+            ptr::copy_nonoverlapping(slots, values.as_mut_ptr().add(first.1), second);
+        }
+        shared.head.store(shared.advance(head, count), Ordering::Release);
+        count
+    }
+}
+
+impl<T> Shared<T> {
+    #[inline]
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.slots[index % self.slots.len()].get()
+    }
+
+    // The slots as a pointer to values, valid for reads and writes of the whole slice.
+    #[inline]
+    fn slots_ptr(&self) -> *mut T {
+        UnsafeCell::raw_get(self.slots.as_ptr()) as *mut T
+    }
+
+    // The number of values between the `head` and `tail` counters.
+    #[inline]
+    fn len(&self, head: usize, tail: usize) -> usize {
+        let period = 2 * self.slots.len();
+        (tail + period - head) % period
+    }
+
+    #[inline]
+    fn advance(&self, index: usize, count: usize) -> usize {
+        (index + count) % (2 * self.slots.len())
+    }
+
+    // Splits the `count` slots starting at counter `index` into a range `(start, len)` at the end
+    // of the slots and a length at their beginning, where the range wraps around.
+    #[inline]
+    fn ranges(&self, index: usize, count: usize) -> ((usize, usize), usize) {
+        let start = index % self.slots.len();
+        let first = cmp::min(count, self.slots.len() - start);
+        ((start, first), count - first)
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            unsafe {
+                ptr::drop_in_place((*self.slot(head)).as_mut_ptr());
+            }
+            head = self.advance(head, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::queue;
+    use std::rc::Rc;
+
+    #[test]
+    fn values_are_popped_in_order() {
+        let (producer, consumer) = queue(2);
+        assert_eq!(consumer.pop(), None);
+        for round in 0..3 {
+            assert_eq!(producer.push(round * 2), Ok(()));
+            assert_eq!(producer.push(round * 2 + 1), Ok(()));
+            assert_eq!(producer.push(100), Err(100));
+            assert_eq!(consumer.pop(), Some(round * 2));
+            assert_eq!(consumer.pop(), Some(round * 2 + 1));
+            assert_eq!(consumer.pop(), None);
+        }
+    }
+
+    #[test]
+    fn remaining_values_are_dropped() {
+        let value = Rc::new(());
+        {
+            let (producer, consumer) = queue(4);
+            producer.push(value.clone()).unwrap();
+            producer.push(value.clone()).unwrap();
+            drop(consumer.pop());
+            assert_eq!(Rc::strong_count(&value), 2);
+        }
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,496 @@
+//! Measurements taken around the invocation of a stream's data callback.
+//!
+//! Every host wraps the calls to the user's data callback with a `StreamMeter` that is shared
+//! with the `Stream` handle, so that the measurements can be queried from any thread.
+
+use std::hint;
+use std::ops::{Add, Sub};
+use std::sync::atomic::{self, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use CallbackInfo;
+use SampleRate;
+
+// Weight of the most recent measurement in the moving average of the callback load.
+const LOAD_SMOOTHING: f32 = 0.1;
+
+/// Counters describing the health of a stream since it was created.
+///
+/// Not all backends are able to detect all kinds of glitches. Counters that are not supported by
+/// a backend always remain at zero.
+///
+/// The run-loop counters (`wakeups`, `wait_time` and `commands`) are only maintained on hosts
+/// where CPAL runs the audio thread itself, currently ALSA and WASAPI.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// The number of times the device ran out of data to play because the data callback did not
+    /// provide it in time.
+    pub underruns: u64,
+    /// The number of times captured data was lost because the data callback did not consume it
+    /// in time.
+    pub overruns: u64,
+    /// The number of buffers passed to the data callback that were flagged as not contiguous
+    /// with the previous one.
+    pub discontinuities: u64,
+    /// The time elapsed since the stream was created.
+    pub elapsed: Duration,
+    /// The number of times the audio thread woke up, whether to process a buffer, a command or
+    /// nothing at all.
+    pub wakeups: u64,
+    /// The total time the audio thread spent waiting for the device or for commands.
+    pub wait_time: Duration,
+    /// The number of commands, such as play and pause, processed by the audio thread.
+    pub commands: u64,
+    /// The number of frames passed to the data callback.
+    pub frames: u64,
+}
+
+impl StreamStats {
+    /// The average number of times the audio thread woke up per second.
+    pub fn wakeups_per_second(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.wakeups as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// The average time the audio thread waited before each wakeup, or `None` if it never woke
+    /// up.
+    pub fn average_wait_time(&self) -> Option<Duration> {
+        match self.wakeups {
+            0 => None,
+            wakeups => Some(self.wait_time.div_f64(wakeups as f64)),
+        }
+    }
+}
+
+/// An instant on the timeline of a stream, measured from its first frame.
+///
+/// Unlike frame indices, instants do not depend on the sample rate, so that they can be compared
+/// and offset with `Duration`s directly. `from_frames` and `to_frames` convert between the two.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamInstant(Duration);
+
+/// The position of a stream on the device clock.
+///
+/// Obtained from `StreamTrait::position`, it relates a frame of the stream to the instant at which
+/// the device played or captured it, which allows scheduling events sample-accurately against the
+/// audio.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamPosition {
+    /// The index of the frame, counted from the first frame of the stream.
+    pub frame: u64,
+    /// The instant of `frame` on the timeline of the stream.
+    pub instant: StreamInstant,
+    /// The instant at which `frame` was played by the device, or captured by it for input streams.
+    pub time: Instant,
+}
+
+impl StreamInstant {
+    /// The instant `secs` seconds and `nanos` nanoseconds after the first frame of the stream.
+    pub fn new(secs: u64, nanos: u32) -> Self {
+        StreamInstant(Duration::new(secs, nanos))
+    }
+
+    /// The instant of the frame with index `frame`, for a stream running at `sample_rate`.
+    ///
+    /// A sample rate of zero gives the first instant of the stream.
+    pub fn from_frames(frame: u64, sample_rate: SampleRate) -> Self {
+        let rate = u64::from(sample_rate.0);
+        if rate == 0 {
+            return StreamInstant::default();
+        }
+        let nanos = (frame % rate) * 1_000_000_000 / rate;
+        StreamInstant(Duration::new(frame / rate, nanos as u32))
+    }
+
+    /// The index of the frame played or captured at this instant, for a stream running at
+    /// `sample_rate`, rounded to the nearest frame so that `from_frames` round-trips.
+    pub fn to_frames(&self, sample_rate: SampleRate) -> u64 {
+        let rate = u64::from(sample_rate.0);
+        let nanos = u64::from(self.0.subsec_nanos()) * rate;
+        self.0.as_secs() * rate + (nanos + 500_000_000) / 1_000_000_000
+    }
+
+    /// The time elapsed between the first frame of the stream and this instant.
+    pub fn since_start(&self) -> Duration {
+        self.0
+    }
+
+    /// The time elapsed from `earlier` to this instant, or `None` if `earlier` is later.
+    pub fn duration_since(&self, earlier: StreamInstant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    /// The instant `duration` after this one, or `None` on overflow.
+    pub fn checked_add(&self, duration: Duration) -> Option<StreamInstant> {
+        self.0.checked_add(duration).map(StreamInstant)
+    }
+
+    /// The instant `duration` before this one, or `None` if it is before the start of the stream.
+    pub fn checked_sub(&self, duration: Duration) -> Option<StreamInstant> {
+        self.0.checked_sub(duration).map(StreamInstant)
+    }
+}
+
+impl Add<Duration> for StreamInstant {
+    type Output = StreamInstant;
+
+    /// # Panics
+    ///
+    /// Panics on overflow. See `checked_add` for a version that does not panic.
+    fn add(self, duration: Duration) -> StreamInstant {
+        self.checked_add(duration).expect("overflow when adding duration to stream instant")
+    }
+}
+
+impl Sub<Duration> for StreamInstant {
+    type Output = StreamInstant;
+
+    /// # Panics
+    ///
+    /// Panics if the result is before the start of the stream. See `checked_sub` for a version
+    /// that does not panic.
+    fn sub(self, duration: Duration) -> StreamInstant {
+        self.checked_sub(duration).expect("stream instant before the start of the stream")
+    }
+}
+
+impl StreamPosition {
+    /// Estimates the frame played or captured at `time`, assuming that the device runs at
+    /// `sample_rate`.
+    ///
+    /// The estimation drifts away from the device clock as `time` gets further from `self.time`,
+    /// so the position should be queried again regularly.
+    pub fn frame_at(&self, time: Instant, sample_rate: SampleRate) -> u64 {
+        let frames = |d: Duration| (d.as_secs_f64() * sample_rate.0 as f64) as u64;
+        if time >= self.time {
+            self.frame + frames(time - self.time)
+        } else {
+            self.frame.saturating_sub(frames(self.time - time))
+        }
+    }
+
+    /// Estimates the instant on the timeline of the stream that is played or captured at `time`.
+    ///
+    /// As with `frame_at`, the position should be queried again regularly.
+    pub fn instant_at(&self, time: Instant) -> StreamInstant {
+        if time >= self.time {
+            self.instant + (time - self.time)
+        } else {
+            self.instant.checked_sub(self.time - time).unwrap_or_default()
+        }
+    }
+
+    /// Estimates the time at which `instant` on the timeline of the stream is played or captured.
+    ///
+    /// `clock::to_ticks` converts the result to the monotonic clock of the system.
+    pub fn time_at(&self, instant: StreamInstant) -> Instant {
+        if instant >= self.instant {
+            self.time + (instant.0 - self.instant.0)
+        } else {
+            self.time - (self.instant.0 - instant.0)
+        }
+    }
+}
+
+/// Measurements shared between a stream handle and the thread running its data callback.
+///
+/// Only the thread running the data callback writes to the meter.
+#[derive(Debug)]
+pub(crate) struct StreamMeter {
+    sample_rate: SampleRate,
+    created: Instant,
+    // Nanoseconds between `created` and the return of the last callback, or `0` if the callback
+    // has not been called yet.
+    last_callback: AtomicU64,
+    // Sequence lock protecting the position: odd while the position is being written, `0` if no
+    // position was recorded yet.
+    position_seq: AtomicUsize,
+    position_frame: AtomicU64,
+    // Nanoseconds between `created` and the instant of the position.
+    position_time: AtomicU64,
+    // Moving average of the callback load, stored as the bits of an `f32`.
+    load: AtomicU32,
+    underruns: AtomicUsize,
+    overruns: AtomicUsize,
+    discontinuities: AtomicUsize,
+    wakeups: AtomicU64,
+    wait_nanos: AtomicU64,
+    commands: AtomicU64,
+    frames: AtomicU64,
+}
+
+impl StreamMeter {
+    pub fn new(sample_rate: SampleRate) -> Self {
+        StreamMeter {
+            sample_rate,
+            created: Instant::now(),
+            last_callback: AtomicU64::new(0),
+            position_seq: AtomicUsize::new(0),
+            position_frame: AtomicU64::new(0),
+            position_time: AtomicU64::new(0),
+            load: AtomicU32::new(0),
+            underruns: AtomicUsize::new(0),
+            overruns: AtomicUsize::new(0),
+            discontinuities: AtomicUsize::new(0),
+            wakeups: AtomicU64::new(0),
+            wait_nanos: AtomicU64::new(0),
+            commands: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs the data callback for the buffer described by `info`, measuring how long it takes.
+    #[inline]
+    pub fn measure<F, R>(&self, info: &CallbackInfo, callback: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        if info.flags().discontinuity {
+            self.discontinuities.fetch_add(1, Ordering::Relaxed);
+        }
+        self.frames.fetch_add(info.frames() as u64, Ordering::Relaxed);
+        #[cfg(feature = "rt-debug")]
+        let guard = ::rt_debug::CallbackGuard::enter();
+        let start = Instant::now();
+        let result = callback();
+        let elapsed = start.elapsed();
+        #[cfg(feature = "rt-debug")]
+        guard.exit();
+        self.record_load(elapsed, info.frames());
+        self.record_heartbeat(start + elapsed);
+        result
+    }
+
+    #[inline]
+    pub fn record_underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn record_overrun(&self) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that the audio thread woke up after waiting for `wait`.
+    #[inline]
+    pub fn record_wakeup(&self, wait: Duration) {
+        self.wakeups.fetch_add(1, Ordering::Relaxed);
+        self.wait_nanos.fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    // Only the WASAPI audio thread processes commands.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    #[inline]
+    pub fn record_command(&self) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        StreamStats {
+            underruns: self.underruns.load(Ordering::Relaxed) as u64,
+            overruns: self.overruns.load(Ordering::Relaxed) as u64,
+            discontinuities: self.discontinuities.load(Ordering::Relaxed) as u64,
+            elapsed: self.created.elapsed(),
+            wakeups: self.wakeups.load(Ordering::Relaxed),
+            wait_time: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+            commands: self.commands.load(Ordering::Relaxed),
+            frames: self.frames.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The moving average of the time spent in the callback, as a fraction of the buffer period.
+    #[inline]
+    pub fn callback_load(&self) -> f32 {
+        f32::from_bits(self.load.load(Ordering::Relaxed))
+    }
+
+    /// Records that `frame` was played or captured by the device at `time`.
+    pub fn record_position(&self, frame: u64, time: Instant) {
+        let nanos = time.saturating_duration_since(self.created).as_nanos() as u64;
+        let seq = self.position_seq.load(Ordering::Relaxed);
+        self.position_seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+        self.position_frame.store(frame, Ordering::Relaxed);
+        self.position_time.store(nanos, Ordering::Relaxed);
+        // Skip `0` when wrapping around, as it means that no position was recorded.
+        let next = match seq.wrapping_add(2) {
+            0 => 2,
+            n => n,
+        };
+        self.position_seq.store(next, Ordering::Release);
+    }
+
+    /// The last position recorded by the thread running the data callback.
+    pub fn position(&self) -> Option<StreamPosition> {
+        loop {
+            let seq = self.position_seq.load(Ordering::Acquire);
+            if seq == 0 {
+                return None;
+            }
+            if seq % 2 == 1 {
+                hint::spin_loop();
+                continue;
+            }
+            let frame = self.position_frame.load(Ordering::Relaxed);
+            let nanos = self.position_time.load(Ordering::Relaxed);
+            atomic::fence(Ordering::Acquire);
+            if self.position_seq.load(Ordering::Relaxed) == seq {
+                let time = self.created + Duration::from_nanos(nanos);
+                let instant = StreamInstant::from_frames(frame, self.sample_rate);
+                return Some(StreamPosition { frame, instant, time });
+            }
+        }
+    }
+
+    /// The time at which the data callback last returned.
+    pub fn last_callback(&self) -> Option<Instant> {
+        match self.last_callback.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(self.created + Duration::from_nanos(nanos)),
+        }
+    }
+
+    fn record_heartbeat(&self, now: Instant) {
+        let nanos = now.duration_since(self.created).as_nanos() as u64;
+        self.last_callback.store(nanos.max(1), Ordering::Relaxed);
+    }
+
+    fn record_load(&self, elapsed: Duration, frames: usize) {
+        if frames == 0 || self.sample_rate.0 == 0 {
+            return;
+        }
+        let period = frames as f64 / self.sample_rate.0 as f64;
+        let load = (elapsed.as_secs_f64() / period) as f32;
+        let average = self.callback_load();
+        let average = average + LOAD_SMOOTHING * (load - average);
+        self.load.store(average.to_bits(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StreamInstant, StreamMeter, StreamPosition, StreamStats};
+    use std::time::{Duration, Instant};
+    use {BufferFlags, CallbackInfo, SampleRate};
+
+    #[test]
+    fn callback_load_converges() {
+        let meter = StreamMeter::new(SampleRate(48_000));
+        assert_eq!(meter.callback_load(), 0.0);
+        // 480 frames at 48kHz is a 10ms period, half of which is spent in the callback.
+        for _ in 0..200 {
+            meter.record_load(Duration::from_millis(5), 480);
+        }
+        assert!((meter.callback_load() - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn empty_buffers_are_ignored() {
+        let meter = StreamMeter::new(SampleRate(48_000));
+        meter.record_load(Duration::from_millis(5), 0);
+        assert_eq!(meter.callback_load(), 0.0);
+    }
+
+    #[test]
+    fn stats_count_glitches() {
+        let meter = StreamMeter::new(SampleRate(48_000));
+        let flags = BufferFlags {
+            discontinuity: true,
+            ..Default::default()
+        };
+        meter.measure(&CallbackInfo::new(480, true, flags), || ());
+        meter.measure(&CallbackInfo::new(480, false, Default::default()), || ());
+        meter.record_underrun();
+        meter.record_overrun();
+        meter.record_overrun();
+        let stats = meter.stats();
+        let expected = StreamStats {
+            underruns: 1,
+            overruns: 2,
+            discontinuities: 1,
+            elapsed: stats.elapsed,
+            frames: 960,
+            ..Default::default()
+        };
+        assert_eq!(stats, expected);
+    }
+
+    #[test]
+    fn run_loop_averages() {
+        let meter = StreamMeter::new(SampleRate(48_000));
+        assert_eq!(meter.stats().average_wait_time(), None);
+        meter.record_wakeup(Duration::from_millis(4));
+        meter.record_wakeup(Duration::from_millis(6));
+        meter.record_command();
+        let stats = StreamStats {
+            elapsed: Duration::from_millis(500),
+            ..meter.stats()
+        };
+        assert_eq!(stats.wakeups, 2);
+        assert_eq!(stats.commands, 1);
+        assert_eq!(stats.average_wait_time(), Some(Duration::from_millis(5)));
+        assert_eq!(stats.wakeups_per_second(), 4.0);
+    }
+
+    #[test]
+    fn position_round_trips() {
+        let meter = StreamMeter::new(SampleRate(48_000));
+        assert_eq!(meter.position(), None);
+        let time = Instant::now() + Duration::from_millis(20);
+        meter.record_position(4_800, time);
+        let instant = StreamInstant::new(0, 100_000_000);
+        let expected = StreamPosition { frame: 4_800, instant, time };
+        assert_eq!(meter.position(), Some(expected));
+    }
+
+    #[test]
+    fn position_extrapolation() {
+        let time = Instant::now();
+        let instant = StreamInstant::new(0, 100_000_000);
+        let position = StreamPosition { frame: 4_800, instant, time };
+        let rate = SampleRate(48_000);
+        assert_eq!(position.frame_at(time, rate), 4_800);
+        assert_eq!(position.frame_at(time + Duration::from_millis(10), rate), 5_280);
+        assert_eq!(position.frame_at(time - Duration::from_millis(10), rate), 4_320);
+        assert_eq!(position.frame_at(time - Duration::from_secs(1), rate), 0);
+        let later = position.instant_at(time + Duration::from_millis(10));
+        assert_eq!(later, StreamInstant::new(0, 110_000_000));
+        assert_eq!(position.instant_at(time - Duration::from_secs(1)), StreamInstant::default());
+        assert_eq!(position.time_at(later), time + Duration::from_millis(10));
+    }
+
+    #[test]
+    fn stream_instant_arithmetic() {
+        let rate = SampleRate(44_100);
+        let instant = StreamInstant::from_frames(66_150, rate);
+        assert_eq!(instant, StreamInstant::new(1, 500_000_000));
+        assert_eq!(instant.to_frames(rate), 66_150);
+        let later = instant + Duration::from_millis(250);
+        assert_eq!(later.duration_since(instant), Some(Duration::from_millis(250)));
+        assert_eq!(instant.duration_since(later), None);
+        assert_eq!(later - Duration::from_millis(250), instant);
+        assert_eq!(instant.checked_sub(Duration::from_secs(2)), None);
+    }
+
+    #[test]
+    fn stream_instant_frames_round_trip() {
+        let rate = SampleRate(44_100);
+        assert_eq!(StreamInstant::from_frames(1, rate), StreamInstant::new(0, 22_675));
+        for &frame in &[1, 2, 3, 44_099, 44_101, 1_234_567] {
+            assert_eq!(StreamInstant::from_frames(frame, rate).to_frames(rate), frame);
+        }
+        assert_eq!(StreamInstant::from_frames(10, SampleRate(0)), StreamInstant::default());
+    }
+
+    #[test]
+    fn heartbeat_follows_callbacks() {
+        let meter = StreamMeter::new(SampleRate(48_000));
+        assert_eq!(meter.last_callback(), None);
+        let before = Instant::now();
+        meter.measure(&CallbackInfo::new(480, false, Default::default()), || ());
+        let last = meter.last_callback().unwrap();
+        assert!(last >= before && last <= Instant::now());
+    }
+}
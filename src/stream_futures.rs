@@ -0,0 +1,400 @@
+//! `futures` adapters over streams, enabled by the `async` feature.
+//!
+//! An `InputStream` yields the samples captured by an input stream as a `futures::Stream`, and an
+//! `OutputSink` plays the samples sent to it as a `futures::Sink`. The samples pass through a ring
+//! buffer and are converted from and to the sample format of the device, so that the data callback
+//! never waits for the task. The data callback wakes the task after each buffer, from the audio
+//! thread.
+//!
+//! ```no_run,edition2018
+//! use cpal::stream_futures::InputStream;
+//! use futures::StreamExt;
+//! use std::time::Duration;
+//!
+//! async fn print_peaks(device: &cpal::Device, format: &cpal::Format)
+//!     -> Result<(), cpal::BuildStreamError>
+//! {
+//!     let mut input = InputStream::<f32, _>::new(device, format, Duration::from_millis(100))?;
+//!     while let Some(samples) = input.next().await {
+//!         let peak = samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+//!         println!("peak: {}", peak);
+//!     }
+//!     Ok(())
+//! }
+//! # fn main() {}
+//! ```
+
+use std::cmp;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::{Sink, Stream};
+
+use adapter::{play, Shared};
+use convert::convert_samples;
+use ring_buffer::{latency_capacity, ring_buffer, Consumer, Producer};
+use traits::{DeviceTrait, StreamTrait};
+use BuildStreamError;
+use Format;
+use Sample;
+use StreamData;
+use StreamError;
+use StreamOptions;
+use UnknownTypeInputBuffer;
+use UnknownTypeOutputBuffer;
+
+// The number of samples converted at once by the data callback.
+const CHUNK_SAMPLES: usize = 1_024;
+
+/// The samples captured by an input stream, as a `futures::Stream` of interleaved samples.
+///
+/// Each item holds the whole frames captured since the previous one. If they are not polled in
+/// time, the stream drops the frames that do not fit in the ring buffer. The stream ends once the
+/// device is lost or the stream stops otherwise.
+pub struct InputStream<T, S> {
+    stream: S,
+    consumer: Consumer<T>,
+    channels: usize,
+    shared: Arc<Shared>,
+}
+
+/// Plays interleaved samples on an output stream, as a `futures::Sink`.
+///
+/// Frames are only played once all of their samples have been sent. Flushing waits until the
+/// stream has taken every complete frame. Once the stream stops, the sink fails with the error
+/// that stopped it.
+pub struct OutputSink<T, S> {
+    stream: S,
+    producer: Producer<T>,
+    channels: usize,
+    // The samples of the last item that did not fit in the ring buffer yet.
+    pending: Vec<T>,
+    pending_start: usize,
+    shared: Arc<Shared>,
+}
+
+// The adapters are never pinned structurally.
+impl<T, S> Unpin for InputStream<T, S> {}
+impl<T, S> Unpin for OutputSink<T, S> {}
+
+impl<T, S> InputStream<T, S>
+where
+    T: Sample + Send + 'static,
+    S: StreamTrait,
+{
+    /// Builds an input stream of `format` on `device`, and starts capturing the samples yielded
+    /// by the stream.
+    ///
+    /// The ring buffer holds `latency` worth of audio, which bounds how late captured samples may
+    /// be polled before they are dropped.
+    pub fn new<D>(device: &D, format: &Format, latency: Duration) -> Result<Self, BuildStreamError>
+    where
+        D: DeviceTrait<Stream = S>,
+    {
+        let channels = format.channels as usize;
+        let (producer, consumer) =
+            ring_buffer(latency_capacity(latency, format.sample_rate, channels));
+        let mut scratch = chunk(channels);
+        let shared = Arc::new(Shared::new());
+        let callback_shared = shared.clone();
+        let stream = device.build_input_stream_dyn(
+            format,
+            &StreamOptions::default(),
+            move |data| {
+                if let StreamData::Input { buffer, .. } = data {
+                    push_buffer(&producer, &buffer, &mut scratch, channels);
+                    callback_shared.wake();
+                }
+            },
+            shared.error_callback(),
+        )?;
+        play(&stream)?;
+        Ok(InputStream {
+            stream,
+            consumer,
+            channels,
+            shared,
+        })
+    }
+
+    /// The input stream.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<T, S> InputStream<T, S>
+where
+    T: Sample,
+{
+    // Pops the whole frames captured so far. The data callback may be pushing the next frame.
+    fn pop(&self) -> Option<Vec<T>> {
+        if self.consumer.len() < self.channels {
+            return None;
+        }
+        let mut samples = vec![T::from(&0.0f32); self.consumer.len()];
+        let popped = self.consumer.pop_frames(&mut samples, self.channels);
+        samples.truncate(popped);
+        Some(samples)
+    }
+}
+
+impl<T, S> Stream for InputStream<T, S>
+where
+    T: Sample,
+{
+    type Item = Vec<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Vec<T>>> {
+        let this = self.get_mut();
+        if let Some(samples) = this.pop() {
+            return Poll::Ready(Some(samples));
+        }
+        this.shared.waker.register(cx.waker());
+        // Samples may have been captured before the waker was registered.
+        if let Some(samples) = this.pop() {
+            return Poll::Ready(Some(samples));
+        }
+        if this.shared.stopped().is_some() {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+impl<T, S> OutputSink<T, S>
+where
+    T: Sample + Send + 'static,
+    S: StreamTrait,
+{
+    /// Builds an output stream of `format` on `device`, and starts playing the samples sent to the
+    /// sink.
+    ///
+    /// The ring buffer holds `latency` worth of audio, which bounds the delay between sending
+    /// samples and their playback.
+    pub fn new<D>(device: &D, format: &Format, latency: Duration) -> Result<Self, BuildStreamError>
+    where
+        D: DeviceTrait<Stream = S>,
+    {
+        let channels = format.channels as usize;
+        let (producer, consumer) =
+            ring_buffer(latency_capacity(latency, format.sample_rate, channels));
+        let mut scratch = chunk(channels);
+        let shared = Arc::new(Shared::new());
+        let callback_shared = shared.clone();
+        let stream = device.build_output_stream_dyn(
+            format,
+            &StreamOptions::default(),
+            move |data| {
+                if let StreamData::Output { mut buffer, .. } = data {
+                    buffer.fill_silence();
+                    pop_buffer(&consumer, &mut buffer, &mut scratch, channels);
+                    callback_shared.wake();
+                }
+            },
+            shared.error_callback(),
+        )?;
+        play(&stream)?;
+        Ok(OutputSink {
+            stream,
+            producer,
+            channels,
+            pending: Vec::new(),
+            pending_start: 0,
+            shared,
+        })
+    }
+
+    /// The output stream.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<T, S> OutputSink<T, S>
+where
+    T: Sample,
+{
+    // Pushes as many pending samples as fit in the ring buffer, and returns whether none remain.
+    fn push_pending(&mut self) -> bool {
+        self.pending_start += self.producer.push_slice(&self.pending[self.pending_start..]);
+        if self.pending_start < self.pending.len() {
+            return false;
+        }
+        self.pending.clear();
+        self.pending_start = 0;
+        true
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context) -> Poll<Result<(), StreamError>> {
+        if let Some(err) = self.shared.stopped() {
+            return Poll::Ready(Err(err));
+        }
+        if self.push_pending() {
+            return Poll::Ready(Ok(()));
+        }
+        self.shared.waker.register(cx.waker());
+        // The data callback may have made room before the waker was registered.
+        if self.push_pending() {
+            return Poll::Ready(Ok(()));
+        }
+        Poll::Pending
+    }
+
+    // Whether the ring buffer still holds a complete frame.
+    fn has_queued_frame(&self) -> bool {
+        self.producer.capacity() - self.producer.free_len() >= self.channels
+    }
+}
+
+impl<T, S> Sink<Vec<T>> for OutputSink<T, S>
+where
+    T: Sample,
+{
+    type Error = StreamError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), StreamError>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, samples: Vec<T>) -> Result<(), StreamError> {
+        let this = self.get_mut();
+        if let Some(err) = this.shared.stopped() {
+            return Err(err);
+        }
+        // `poll_ready` only succeeds once the previous item has been pushed.
+        debug_assert!(this.pending.is_empty());
+        this.pending = samples;
+        this.push_pending();
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), StreamError>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => (),
+            poll => return poll,
+        }
+        // A trailing partial frame is never taken, so it is left in the ring buffer.
+        if !this.has_queued_frame() {
+            return Poll::Ready(Ok(()));
+        }
+        this.shared.waker.register(cx.waker());
+        if !this.has_queued_frame() {
+            return Poll::Ready(Ok(()));
+        }
+        match this.shared.stopped() {
+            Some(err) => Poll::Ready(Err(err)),
+            None => Poll::Pending,
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), StreamError>> {
+        self.poll_flush(cx)
+    }
+}
+
+// A buffer of whole frames in which the data callback converts samples.
+fn chunk<T: Sample>(channels: usize) -> Vec<T> {
+    vec![T::from(&0.0f32); cmp::max(CHUNK_SAMPLES / channels, 1) * channels]
+}
+
+fn push_buffer<T: Sample>(
+    producer: &Producer<T>,
+    buffer: &UnknownTypeInputBuffer,
+    scratch: &mut [T],
+    channels: usize,
+) {
+    match *buffer {
+        UnknownTypeInputBuffer::U16(ref b) => push_converted(producer, b, scratch, channels),
+        UnknownTypeInputBuffer::I16(ref b) => push_converted(producer, b, scratch, channels),
+        UnknownTypeInputBuffer::F32(ref b) => push_converted(producer, b, scratch, channels),
+    }
+}
+
+fn pop_buffer<T: Sample>(
+    consumer: &Consumer<T>,
+    buffer: &mut UnknownTypeOutputBuffer,
+    scratch: &mut [T],
+    channels: usize,
+) {
+    match *buffer {
+        UnknownTypeOutputBuffer::U16(ref mut b) => pop_converted(consumer, b, scratch, channels),
+        UnknownTypeOutputBuffer::I16(ref mut b) => pop_converted(consumer, b, scratch, channels),
+        UnknownTypeOutputBuffer::F32(ref mut b) => pop_converted(consumer, b, scratch, channels),
+    }
+}
+
+// Converts the whole frames of `samples` that fit in the ring buffer, and pushes them.
+fn push_converted<T, U>(producer: &Producer<T>, samples: &[U], scratch: &mut [T], channels: usize)
+where
+    T: Sample,
+    U: Sample,
+{
+    for chunk in samples.chunks(scratch.len()) {
+        let converted = &mut scratch[..chunk.len()];
+        convert_samples(chunk, converted);
+        if producer.push_frames(converted, channels) < chunk.len() {
+            break;
+        }
+    }
+}
+
+// Pops whole frames from the ring buffer, and converts them into the beginning of `samples`.
+fn pop_converted<T, U>(
+    consumer: &Consumer<T>,
+    samples: &mut [U],
+    scratch: &mut [T],
+    channels: usize,
+) where
+    T: Sample,
+    U: Sample,
+{
+    for chunk in samples.chunks_mut(scratch.len()) {
+        let popped = consumer.pop_frames(&mut scratch[..chunk.len()], channels);
+        convert_samples(&scratch[..popped], &mut chunk[..popped]);
+        if popped < chunk.len() {
+            break;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "virtual-device"))]
+mod test {
+    use super::InputStream;
+    use futures::task::noop_waker;
+    use futures::Stream;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::Duration;
+    use virtual_device::VirtualDevice;
+    use {Format, SampleFormat, SampleRate};
+
+    #[test]
+    fn input_yields_whole_frames() {
+        let format = Format {
+            channels: 2,
+            sample_rate: SampleRate(48_000),
+            data_type: SampleFormat::I16,
+        };
+        let device = VirtualDevice::new("virtual", format.clone());
+        let mut input = InputStream::<f32, _>::new(&device, &format, Duration::from_millis(20))
+            .unwrap();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match Pin::new(&mut input).poll_next(&mut cx) {
+                Poll::Ready(Some(samples)) => {
+                    assert!(!samples.is_empty());
+                    assert_eq!(samples.len() % 2, 0);
+                    break;
+                },
+                Poll::Ready(None) => panic!("the stream ended"),
+                Poll::Pending => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+}
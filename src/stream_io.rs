@@ -0,0 +1,204 @@
+//! `std::io` adapters over streams, for tools that handle raw PCM data.
+//!
+//! A `StreamWriter` plays the bytes written to it on an output stream, and a `StreamReader` reads
+//! the bytes captured by an input stream. The bytes are interleaved samples in the sample format of
+//! the stream and in native byte order, as in a raw PCM file. They pass through a ring buffer, so
+//! that the data callback never waits for the writer or the reader.
+//!
+//! ```no_run
+//! use cpal::stream_io::StreamWriter;
+//! use cpal::traits::{DeviceTrait, HostTrait};
+//! use std::io::{self, Write};
+//! use std::time::Duration;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let host = cpal::default_host();
+//! let device = host.default_output_device().expect("no output device available");
+//! let format = device.default_output_format()?;
+//! let mut writer = StreamWriter::new(&device, &format, Duration::from_millis(200))?;
+//! let mut pcm = std::fs::File::open("audio.raw")?;
+//! io::copy(&mut pcm, &mut writer)?;
+//! // Wait until everything has been played.
+//! writer.flush()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cmp;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use adapter::{frame_bytes, play, Shared};
+use ring_buffer::{latency_capacity, ring_buffer, Consumer, Producer};
+use traits::{DeviceTrait, StreamTrait};
+use BuildStreamError;
+use Format;
+
+/// Plays the bytes written to it on an output stream.
+///
+/// `write` blocks while the ring buffer is full, and `flush` blocks until the stream has taken
+/// every complete frame. A frame is only played once all of its bytes have been written.
+pub struct StreamWriter<S> {
+    stream: S,
+    producer: Producer<u8>,
+    frame_bytes: usize,
+    shared: Arc<Shared>,
+    poll_interval: Duration,
+}
+
+/// Reads the bytes captured by an input stream.
+///
+/// `read` blocks until captured bytes are available. If they are not read in time, the stream
+/// drops the frames that do not fit in the ring buffer.
+pub struct StreamReader<S> {
+    stream: S,
+    consumer: Consumer<u8>,
+    shared: Arc<Shared>,
+    poll_interval: Duration,
+}
+
+impl<S: StreamTrait> StreamWriter<S> {
+    /// Builds an output stream of `format` on `device`, and starts playing the bytes written to
+    /// the writer.
+    ///
+    /// The ring buffer holds `latency` worth of audio, which bounds the delay between a write and
+    /// the playback of its bytes.
+    pub fn new<D>(device: &D, format: &Format, latency: Duration) -> Result<Self, BuildStreamError>
+    where
+        D: DeviceTrait<Stream = S>,
+    {
+        let frame_bytes = frame_bytes(format);
+        let capacity = latency_capacity(latency, format.sample_rate, frame_bytes);
+        let (producer, consumer) = ring_buffer(capacity);
+        let shared = Arc::new(Shared::new());
+        let stream = device.build_output_stream_raw(
+            format,
+            move |mut buffer, _| {
+                buffer.fill_silence();
+                consumer.pop_frames(&mut buffer[..], frame_bytes);
+            },
+            shared.error_callback(),
+        )?;
+        play(&stream)?;
+        Ok(StreamWriter {
+            stream,
+            producer,
+            frame_bytes,
+            shared,
+            poll_interval: poll_interval(latency),
+        })
+    }
+
+    /// The output stream.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<S> io::Write for StreamWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            self.shared.check_stopped()?;
+            let pushed = self.producer.push_slice(buf);
+            if pushed > 0 {
+                return Ok(pushed);
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // A trailing partial frame is never taken, so it is left in the ring buffer.
+        while self.producer.capacity() - self.producer.free_len() >= self.frame_bytes {
+            self.shared.check_stopped()?;
+            thread::sleep(self.poll_interval);
+        }
+        Ok(())
+    }
+}
+
+impl<S: StreamTrait> StreamReader<S> {
+    /// Builds an input stream of `format` on `device`, and starts capturing the bytes read from
+    /// the reader.
+    ///
+    /// The ring buffer holds `latency` worth of audio, which bounds how late captured bytes may
+    /// be read before they are dropped.
+    pub fn new<D>(device: &D, format: &Format, latency: Duration) -> Result<Self, BuildStreamError>
+    where
+        D: DeviceTrait<Stream = S>,
+    {
+        let frame_bytes = frame_bytes(format);
+        let capacity = latency_capacity(latency, format.sample_rate, frame_bytes);
+        let (producer, consumer) = ring_buffer(capacity);
+        let shared = Arc::new(Shared::new());
+        let stream = device.build_input_stream_raw(
+            format,
+            move |buffer, _| {
+                producer.push_frames(&buffer[..], frame_bytes);
+            },
+            shared.error_callback(),
+        )?;
+        play(&stream)?;
+        Ok(StreamReader {
+            stream,
+            consumer,
+            shared,
+            poll_interval: poll_interval(latency),
+        })
+    }
+
+    /// The input stream.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<S> io::Read for StreamReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let popped = self.consumer.pop_slice(buf);
+            if popped > 0 {
+                return Ok(popped);
+            }
+            self.shared.check_stopped()?;
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+// How long the writer and the reader sleep while waiting for the stream.
+fn poll_interval(latency: Duration) -> Duration {
+    cmp::max(latency / 4, Duration::from_millis(1))
+}
+
+#[cfg(all(test, feature = "virtual-device"))]
+mod test {
+    use super::StreamWriter;
+    use std::io::Write;
+    use std::time::Duration;
+    use virtual_device::VirtualDevice;
+    use {Format, SampleFormat, SampleRate};
+
+    #[test]
+    fn flush_waits_for_the_stream() {
+        let format = Format {
+            channels: 2,
+            sample_rate: SampleRate(48_000),
+            data_type: SampleFormat::I16,
+        };
+        let device = VirtualDevice::new("virtual", format.clone());
+        let mut writer = StreamWriter::new(&device, &format, Duration::from_millis(20)).unwrap();
+        // 50 ms of audio, more than the ring buffer holds, and a partial frame.
+        writer.write_all(&[0; 9_601]).unwrap();
+        writer.flush().unwrap();
+        assert!(writer.producer.capacity() - writer.producer.free_len() < 4);
+    }
+}
@@ -0,0 +1,349 @@
+//! `tokio` adapters over streams, enabled by the `tokio` feature.
+//!
+//! An `AsyncStreamReader` implements `AsyncRead` over an input stream, and an `AsyncStreamWriter`
+//! implements `AsyncWrite` over an output stream. As with the `stream_io` adapters, the bytes are
+//! interleaved samples in the sample format of the stream and in native byte order. The data
+//! callback wakes the task after each buffer, from the audio thread, so that the task never polls.
+//!
+//! ```no_run,edition2018
+//! use cpal::stream_tokio::AsyncStreamWriter;
+//! use std::time::Duration;
+//! use tokio::io::AsyncWriteExt;
+//!
+//! async fn play_pcm(device: &cpal::Device, format: &cpal::Format, pcm: &[u8])
+//!     -> Result<(), Box<dyn std::error::Error>>
+//! {
+//!     let mut writer = AsyncStreamWriter::new(device, format, Duration::from_millis(100))?;
+//!     writer.write_all(pcm).await?;
+//!     // Wait until everything has been played.
+//!     writer.flush().await?;
+//!     Ok(())
+//! }
+//! # fn main() {}
+//! ```
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use adapter::{frame_bytes, play, Shared};
+use ring_buffer::{latency_capacity, ring_buffer, Consumer, Producer};
+use traits::{DeviceTrait, StreamTrait};
+use BuildStreamError;
+use Format;
+
+/// Reads the bytes captured by an input stream, as an `AsyncRead`.
+///
+/// If the bytes are not read in time, the stream drops the frames that do not fit in the ring
+/// buffer. Once the stream stops, reads fail with the error that stopped it.
+pub struct AsyncStreamReader<S> {
+    stream: S,
+    consumer: Consumer<u8>,
+    shared: Arc<Shared>,
+}
+
+/// Plays the bytes written to it on an output stream, as an `AsyncWrite`.
+///
+/// A frame is only played once all of its bytes have been written. Flushing waits until the stream
+/// has taken every complete frame. Once the stream stops, writes fail with the error that stopped
+/// it.
+pub struct AsyncStreamWriter<S> {
+    stream: S,
+    producer: Producer<u8>,
+    frame_bytes: usize,
+    shared: Arc<Shared>,
+}
+
+// The adapters are never pinned structurally.
+impl<S> Unpin for AsyncStreamReader<S> {}
+impl<S> Unpin for AsyncStreamWriter<S> {}
+
+impl<S: StreamTrait> AsyncStreamReader<S> {
+    /// Builds an input stream of `format` on `device`, and starts capturing the bytes read from
+    /// the reader.
+    ///
+    /// The ring buffer holds `latency` worth of audio, which bounds how late captured bytes may
+    /// be read before they are dropped.
+    pub fn new<D>(device: &D, format: &Format, latency: Duration) -> Result<Self, BuildStreamError>
+    where
+        D: DeviceTrait<Stream = S>,
+    {
+        let frame_bytes = frame_bytes(format);
+        let capacity = latency_capacity(latency, format.sample_rate, frame_bytes);
+        let (producer, consumer) = ring_buffer(capacity);
+        let shared = Arc::new(Shared::new());
+        let callback_shared = shared.clone();
+        let stream = device.build_input_stream_raw(
+            format,
+            move |buffer, _| {
+                producer.push_frames(&buffer[..], frame_bytes);
+                callback_shared.wake();
+            },
+            shared.error_callback(),
+        )?;
+        play(&stream)?;
+        Ok(AsyncStreamReader {
+            stream,
+            consumer,
+            shared,
+        })
+    }
+
+    /// The input stream.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<S> AsyncStreamReader<S> {
+    fn pop(&self, buf: &mut ReadBuf) -> bool {
+        let popped = self.consumer.pop_slice(buf.initialize_unfilled());
+        buf.advance(popped);
+        popped > 0
+    }
+}
+
+impl<S> AsyncRead for AsyncStreamReader<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 || this.pop(buf) {
+            return Poll::Ready(Ok(()));
+        }
+        this.shared.waker.register(cx.waker());
+        // Bytes may have been captured before the waker was registered.
+        if this.pop(buf) {
+            return Poll::Ready(Ok(()));
+        }
+        match this.shared.stopped() {
+            Some(err) => Poll::Ready(Err(io::Error::other(err))),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<S: StreamTrait> AsyncStreamWriter<S> {
+    /// Builds an output stream of `format` on `device`, and starts playing the bytes written to
+    /// the writer.
+    ///
+    /// The ring buffer holds `latency` worth of audio, which bounds the delay between a write and
+    /// the playback of its bytes.
+    pub fn new<D>(device: &D, format: &Format, latency: Duration) -> Result<Self, BuildStreamError>
+    where
+        D: DeviceTrait<Stream = S>,
+    {
+        let frame_bytes = frame_bytes(format);
+        let capacity = latency_capacity(latency, format.sample_rate, frame_bytes);
+        let (producer, consumer) = ring_buffer(capacity);
+        let shared = Arc::new(Shared::new());
+        let callback_shared = shared.clone();
+        let stream = device.build_output_stream_raw(
+            format,
+            move |mut buffer, _| {
+                buffer.fill_silence();
+                consumer.pop_frames(&mut buffer[..], frame_bytes);
+                callback_shared.wake();
+            },
+            shared.error_callback(),
+        )?;
+        play(&stream)?;
+        Ok(AsyncStreamWriter {
+            stream,
+            producer,
+            frame_bytes,
+            shared,
+        })
+    }
+
+    /// The output stream.
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<S> AsyncStreamWriter<S> {
+    // Whether the ring buffer still holds a complete frame. A trailing partial frame is never
+    // taken, so it is left in the ring buffer.
+    fn has_queued_frame(&self) -> bool {
+        self.producer.capacity() - self.producer.free_len() >= self.frame_bytes
+    }
+}
+
+impl<S> AsyncWrite for AsyncStreamWriter<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.shared.check_stopped()?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let pushed = this.producer.push_slice(buf);
+        if pushed > 0 {
+            return Poll::Ready(Ok(pushed));
+        }
+        this.shared.waker.register(cx.waker());
+        // The data callback may have made room before the waker was registered.
+        match this.producer.push_slice(buf) {
+            0 => Poll::Pending,
+            pushed => Poll::Ready(Ok(pushed)),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.shared.check_stopped()?;
+        if !this.has_queued_frame() {
+            return Poll::Ready(Ok(()));
+        }
+        this.shared.waker.register(cx.waker());
+        if !this.has_queued_frame() {
+            return Poll::Ready(Ok(()));
+        }
+        Poll::Pending
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(all(test, feature = "virtual-device"))]
+mod test {
+    use super::{AsyncStreamReader, AsyncStreamWriter};
+    use futures::task::{noop_waker, waker, ArcWake};
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use virtual_device::test::device;
+    use virtual_device::{Fault, FaultScript, VirtualDevice};
+    use {DeviceLossReason, StreamError};
+
+    // A waker recording whether it was woken.
+    struct Woken(AtomicBool);
+
+    impl ArcWake for Woken {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    impl Woken {
+        fn wait(&self) {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !self.0.swap(false, Ordering::SeqCst) {
+                assert!(Instant::now() < deadline, "the task was not woken");
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    // A device running in real time, with buffers long enough that nothing is processed between
+    // building a stream and polling its adapter.
+    fn slow_device() -> VirtualDevice {
+        let mut device = device(FaultScript::new());
+        device.real_time = true;
+        device.buffer_frames = 4_800;
+        device
+    }
+
+    fn is_device_lost(err: &io::Error) -> bool {
+        match err.get_ref().and_then(|err| err.downcast_ref::<StreamError>()) {
+            Some(&StreamError::DeviceNotAvailable { .. }) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn reads_are_pending_until_the_stream_wakes_the_task() {
+        let device = slow_device();
+        let mut reader =
+            AsyncStreamReader::new(&device, &device.format, Duration::from_millis(200)).unwrap();
+        let woken = Arc::new(Woken(AtomicBool::new(false)));
+        let waker = waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = [0; 1_000];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        assert!(Pin::new(&mut reader).poll_read(&mut cx, &mut read_buf).is_pending());
+        woken.wait();
+        let mut read_buf = ReadBuf::new(&mut buf);
+        let poll = Pin::new(&mut reader).poll_read(&mut cx, &mut read_buf);
+        assert!(matches!(poll, Poll::Ready(Ok(()))));
+        assert_eq!(read_buf.filled().len(), 1_000);
+    }
+
+    #[test]
+    fn writes_are_pending_until_the_stream_wakes_the_task() {
+        let device = slow_device();
+        let mut writer =
+            AsyncStreamWriter::new(&device, &device.format, Duration::from_millis(20)).unwrap();
+        let woken = Arc::new(Woken(AtomicBool::new(false)));
+        let waker = waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        let bytes = [0; 1_000];
+        // Fill the ring buffer.
+        while let Poll::Ready(result) = Pin::new(&mut writer).poll_write(&mut cx, &bytes) {
+            assert!(result.unwrap() > 0);
+        }
+        woken.wait();
+        let poll = Pin::new(&mut writer).poll_write(&mut cx, &bytes);
+        assert!(matches!(poll, Poll::Ready(Ok(count)) if count > 0));
+    }
+
+    #[test]
+    fn reads_return_the_captured_bytes_until_the_device_is_lost() {
+        let script = FaultScript::new().at(2, Fault::DeviceLost(DeviceLossReason::Unplugged));
+        let device = device(script);
+        let mut reader =
+            AsyncStreamReader::new(&device, &device.format, Duration::from_millis(100)).unwrap();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = [0; 1_000];
+        let mut read = 0;
+        loop {
+            let mut read_buf = ReadBuf::new(&mut buf);
+            match Pin::new(&mut reader).poll_read(&mut cx, &mut read_buf) {
+                Poll::Ready(Ok(())) => {
+                    assert!(!read_buf.filled().is_empty(), "unexpected end of stream");
+                    read += read_buf.filled().len();
+                },
+                Poll::Ready(Err(err)) => {
+                    assert!(is_device_lost(&err), "{}", err);
+                    break;
+                },
+                Poll::Pending => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+        // The two buffers of 512 frames before the device was lost.
+        assert_eq!(read, 2 * 512 * 4);
+    }
+
+    #[test]
+    fn writes_fail_once_the_device_is_lost() {
+        let script = FaultScript::new().at(0, Fault::DeviceLost(DeviceLossReason::Unplugged));
+        let device = device(script);
+        let mut writer =
+            AsyncStreamWriter::new(&device, &device.format, Duration::from_millis(20)).unwrap();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match Pin::new(&mut writer).poll_write(&mut cx, &[0; 256]) {
+                Poll::Ready(Ok(_)) | Poll::Pending => thread::sleep(Duration::from_millis(1)),
+                Poll::Ready(Err(err)) => {
+                    assert!(is_device_lost(&err), "{}", err);
+                    break;
+                },
+            }
+        }
+    }
+}
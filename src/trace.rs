@@ -0,0 +1,88 @@
+//! Instrumentation with the `tracing` crate, enabled by the `tracing` feature.
+//!
+//! Without the feature, `trace_event!` expands to nothing and the functions of this module do
+//! nothing, so instrumentation costs nothing. The arguments of `trace_event!` are not evaluated in
+//! that case, so they must not have side effects.
+
+use std::fmt;
+
+use StreamError;
+
+/// Emits a `tracing` event at `$level` with the `cpal` target.
+///
+/// Takes the same arguments as the `tracing` macro of the level, e.g.
+/// `trace_event!(warn, host = id.name(), "host unavailable")`.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        {
+            ::tracing::$level!(target: "cpal", $($arg)+);
+        }
+    }};
+}
+
+/// Traces the outcome of `action` on `host`, as a debug event if it succeeded and as a warning
+/// with the error otherwise.
+#[inline]
+pub(crate) fn trace_result<T, E>(host: &'static str, action: &'static str, result: &Result<T, E>)
+where
+    E: fmt::Display,
+{
+    #[cfg(feature = "tracing")]
+    match *result {
+        Ok(_) => ::tracing::debug!(target: "cpal", host, "{} succeeded", action),
+        Err(ref err) => ::tracing::warn!(target: "cpal", host, error = %err, "{} failed", action),
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = (host, action, result);
+}
+
+/// Wraps the error callback of a stream, so that each error is traced before being reported.
+#[cfg(feature = "tracing")]
+pub(crate) fn observe_errors<E>(
+    host: &'static str,
+    mut error_callback: E,
+) -> impl FnMut(StreamError) + Send + 'static
+where
+    E: FnMut(StreamError) + Send + 'static,
+{
+    move |err| {
+        ::tracing::warn!(target: "cpal", host, error = %err, "stream error");
+        error_callback(err)
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline]
+pub(crate) fn observe_errors<E>(_host: &'static str, error_callback: E) -> E
+where
+    E: FnMut(StreamError) + Send + 'static,
+{
+    error_callback
+}
+
+/// Runs `run` in a `cpal_stream` span, which is created on the current thread so that it nests
+/// under the span in which the stream was built.
+#[cfg(feature = "tracing")]
+pub(crate) fn in_stream_span<F>(run: F) -> impl FnOnce() + Send + 'static
+where
+    F: FnOnce() + Send + 'static,
+{
+    let span = ::tracing::info_span!(target: "cpal", "cpal_stream");
+    move || {
+        span.in_scope(|| {
+            ::tracing::debug!(target: "cpal", "audio thread started");
+            run();
+            ::tracing::debug!(target: "cpal", "audio thread stopped");
+        })
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline]
+pub(crate) fn in_stream_span<F>(run: F) -> F
+where
+    F: FnOnce() + Send + 'static,
+{
+    run
+}
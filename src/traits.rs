@@ -2,19 +2,32 @@
 
 use {
     BuildStreamError,
+    CaptureRing,
     DefaultFormatError,
     DeviceNameError,
     DevicesError,
+    Direction,
+    FilteredDevices,
     Format,
+    HostUnavailable,
+    InputCallbackInfo,
     InputDevices,
+    OutputCallbackInfo,
     OutputDevices,
     PauseStreamError,
     PlayStreamError,
+    RawInputBuffer,
+    RawOutputBuffer,
+    Sample,
     StreamData,
     StreamError,
+    StreamOptions,
+    StreamPosition,
+    StreamStats,
     SupportedFormat,
     SupportedFormatsError,
 };
+use std::time::Instant;
 
 /// A **Host** provides access to the available audio devices on the system.
 ///
@@ -44,6 +57,18 @@ pub trait HostTrait {
     /// Whether or not the host is available on the system.
     fn is_available() -> bool;
 
+    /// Why the host is unavailable on the system, if it is.
+    ///
+    /// By default, this only reports `HostUnavailable::NotSupported` when `is_available` returns
+    /// `false`. Hosts that can tell why they are unavailable override it.
+    fn availability() -> Result<(), HostUnavailable> {
+        if Self::is_available() {
+            Ok(())
+        } else {
+            Err(HostUnavailable::NotSupported)
+        }
+    }
+
     /// An iterator yielding all `Device`s currently available to the host on the system.
     ///
     /// Can be empty if the system does not support audio in general.
@@ -64,11 +89,6 @@ pub trait HostTrait {
     ///
     /// Can be empty if the system does not support audio input.
     fn input_devices(&self) -> Result<InputDevices<Self::Devices>, DevicesError> {
-        fn supports_input<D: DeviceTrait>(device: &D) -> bool {
-            device.supported_input_formats()
-                .map(|mut iter| iter.next().is_some())
-                .unwrap_or(false)
-        }
         Ok(self.devices()?.filter(supports_input::<Self::Device>))
     }
 
@@ -77,13 +97,35 @@ pub trait HostTrait {
     ///
     /// Can be empty if the system does not support audio output.
     fn output_devices(&self) -> Result<OutputDevices<Self::Devices>, DevicesError> {
-        fn supports_output<D: DeviceTrait>(device: &D) -> bool {
-            device.supported_output_formats()
-                .map(|mut iter| iter.next().is_some())
-                .unwrap_or(false)
-        }
         Ok(self.devices()?.filter(supports_output::<Self::Device>))
     }
+
+    /// An iterator yielding the `Device`s whose name contains `name_substring` and that support
+    /// one or more stream formats in `direction`. A filter that is `None` matches every device.
+    ///
+    /// The formats of a device are only probed if its name matches, which is much faster than
+    /// filtering `devices` when the formats of many devices are slow to probe.
+    fn devices_filtered(
+        &self,
+        direction: Option<Direction>,
+        name_substring: Option<&str>,
+    ) -> Result<FilteredDevices<Self::Devices>, DevicesError> {
+        Ok(FilteredDevices::new(self.devices()?, direction, name_substring))
+    }
+}
+
+/// Whether `device` supports one or more input stream formats, the filter of `input_devices`.
+pub(crate) fn supports_input<D: DeviceTrait>(device: &D) -> bool {
+    device.supported_input_formats()
+        .map(|mut iter| iter.next().is_some())
+        .unwrap_or(false)
+}
+
+/// Whether `device` supports one or more output stream formats, the filter of `output_devices`.
+pub(crate) fn supports_output<D: DeviceTrait>(device: &D) -> bool {
+    device.supported_output_formats()
+        .map(|mut iter| iter.next().is_some())
+        .unwrap_or(false)
 }
 
 /// A device that is capable of audio input and/or output.
@@ -117,12 +159,107 @@ pub trait DeviceTrait {
     /// The default output stream format for the device.
     fn default_output_format(&self) -> Result<Format, DefaultFormatError>;
 
-    /// Create an input stream.
-    fn build_input_stream<D, E>(&self, format: &Format, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+    /// Create an input stream whose data callback receives the captured samples as a slice.
+    ///
+    /// `T` must be the sample type of `format.data_type`, otherwise
+    /// `BuildStreamError::SampleFormatNotSupported` is returned.
+    fn build_input_stream<T, D, E>(&self, format: &Format, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+        where T: Sample + 'static, D: FnMut(&[T], &InputCallbackInfo) + Send + 'static, E: FnMut(StreamError) + Send + 'static
+    {
+        self.build_input_stream_with_options(format, &StreamOptions::default(), data_callback, error_callback)
+    }
+
+    /// Create an output stream whose data callback fills the samples to play, given as a slice.
+    ///
+    /// `T` must be the sample type of `format.data_type`, otherwise
+    /// `BuildStreamError::SampleFormatNotSupported` is returned.
+    fn build_output_stream<T, D, E>(&self, format: &Format, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+        where T: Sample + 'static, D: FnMut(&mut [T], &OutputCallbackInfo) + Send + 'static, E: FnMut(StreamError) + Send + 'static
+    {
+        self.build_output_stream_with_options(format, &StreamOptions::default(), data_callback, error_callback)
+    }
+
+    /// Create an input stream as with `build_input_stream`, configured with the given
+    /// `StreamOptions`.
+    fn build_input_stream_with_options<T, D, E>(&self, format: &Format, options: &StreamOptions, mut data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+        where T: Sample + 'static, D: FnMut(&[T], &InputCallbackInfo) + Send + 'static, E: FnMut(StreamError) + Send + 'static
+    {
+        if format.data_type != T::get_format() {
+            return Err(BuildStreamError::SampleFormatNotSupported);
+        }
+        self.build_input_stream_dyn(format, options, move |data| {
+            if let StreamData::Input { buffer, info } = data {
+                if let Some(samples) = RawInputBuffer::from(buffer).as_slice::<T>() {
+                    data_callback(samples, &InputCallbackInfo::new(info));
+                }
+            }
+        }, error_callback)
+    }
+
+    /// Create an output stream as with `build_output_stream`, configured with the given
+    /// `StreamOptions`.
+    fn build_output_stream_with_options<T, D, E>(&self, format: &Format, options: &StreamOptions, mut data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+        where T: Sample + 'static, D: FnMut(&mut [T], &OutputCallbackInfo) + Send + 'static, E: FnMut(StreamError) + Send + 'static
+    {
+        if format.data_type != T::get_format() {
+            return Err(BuildStreamError::SampleFormatNotSupported);
+        }
+        self.build_output_stream_dyn(format, options, move |data| {
+            if let StreamData::Output { buffer, info } = data {
+                if let Some(samples) = RawOutputBuffer::from(buffer).as_slice_mut::<T>() {
+                    data_callback(samples, &OutputCallbackInfo::new(info));
+                }
+            }
+        }, error_callback)
+    }
+
+    /// Create an input stream whose data callback receives each buffer as raw bytes, tagged with
+    /// the format of its samples, for callers that dispatch on the sample format themselves.
+    fn build_input_stream_raw<D, E>(&self, format: &Format, mut data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+        where D: FnMut(RawInputBuffer, &InputCallbackInfo) + Send + 'static, E: FnMut(StreamError) + Send + 'static
+    {
+        self.build_input_stream_dyn(format, &StreamOptions::default(), move |data| {
+            if let StreamData::Input { buffer, info } = data {
+                data_callback(buffer.into(), &InputCallbackInfo::new(info));
+            }
+        }, error_callback)
+    }
+
+    /// Create an output stream whose data callback receives each buffer as raw bytes, tagged with
+    /// the format of its samples, for callers that dispatch on the sample format themselves.
+    fn build_output_stream_raw<D, E>(&self, format: &Format, mut data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+        where D: FnMut(RawOutputBuffer, &OutputCallbackInfo) + Send + 'static, E: FnMut(StreamError) + Send + 'static
+    {
+        self.build_output_stream_dyn(format, &StreamOptions::default(), move |data| {
+            if let StreamData::Output { buffer, info } = data {
+                data_callback(buffer.into(), &OutputCallbackInfo::new(info));
+            }
+        }, error_callback)
+    }
+
+    /// Create an input stream that pushes the samples it captures to a ring buffer holding up to
+    /// `capacity` samples, and start it.
+    ///
+    /// `T` must be the sample type of `format.data_type`, otherwise
+    /// `BuildStreamError::SampleFormatNotSupported` is returned. The returned `CaptureRing` reads
+    /// the captured samples and stops the stream when dropped.
+    fn capture_to_ring<T>(&self, format: &Format, capacity: usize) -> Result<CaptureRing<Self::Stream, T>, BuildStreamError>
+        where T: Sample + Send + 'static
+    {
+        CaptureRing::new(self, format, capacity)
+    }
+
+    /// Create an input stream whose data callback receives a `StreamData`, for any sample format.
+    ///
+    /// This is the method that hosts implement, and that the other stream builders are built on.
+    fn build_input_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
         where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static;
 
-    /// Create an output stream.
-    fn build_output_stream<D, E>(&self, format: &Format, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+    /// Create an output stream whose data callback receives a `StreamData`, for any sample
+    /// format.
+    ///
+    /// This is the method that hosts implement, and that the other stream builders are built on.
+    fn build_output_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
         where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static;
 }
 
@@ -140,4 +277,50 @@ pub trait StreamTrait {
     /// Note: Not all devices support suspending the stream at the hardware level. This method may
     /// fail in these cases.
     fn pause(&self) -> Result<(), PauseStreamError>;
+
+    /// Like `play`, but waits until the host has actually started the stream and returns the
+    /// error that occurred while doing so, if any.
+    ///
+    /// On hosts where `play` takes effect immediately, this is the same as `play`.
+    fn play_blocking(&self) -> Result<(), PlayStreamError> {
+        self.play()
+    }
+
+    /// Like `pause`, but waits until the host has actually paused the stream and returns the
+    /// error that occurred while doing so, if any.
+    ///
+    /// On hosts where `pause` takes effect immediately, this is the same as `pause`.
+    fn pause_blocking(&self) -> Result<(), PauseStreamError> {
+        self.pause()
+    }
+
+    /// The time spent in the data callback as a fraction of the duration of the buffers it
+    /// processes, averaged over the most recent callbacks.
+    ///
+    /// This can be used to display a DSP load meter. A value approaching `1.0` means that the
+    /// callback barely keeps up with the device, while values above `1.0` result in glitches.
+    /// Returns `0.0` until the data callback has been called.
+    fn callback_load(&self) -> f32;
+
+    /// Counters of the underruns, overruns and discontinuities that occurred since the stream was
+    /// created.
+    ///
+    /// This allows long-running applications to monitor the health of a stream without having to
+    /// inspect every error and every `CallbackInfo`.
+    fn stats(&self) -> StreamStats;
+
+    /// The time at which the data callback last returned, or `None` if it has not been called
+    /// yet.
+    ///
+    /// This can be polled by a watchdog to detect a hung driver or audio thread: if a playing
+    /// stream has not called back for much longer than its buffer duration, the application may
+    /// drop the stream and build a new one.
+    fn last_callback(&self) -> Option<Instant>;
+
+    /// The frame that the device was playing (or capturing, for input streams) at a recent
+    /// instant, according to the device clock.
+    ///
+    /// Use `StreamPosition::frame_at` to estimate the frame being played at another instant.
+    /// Returns `None` if the position is not known yet or if the backend is unable to report it.
+    fn position(&self) -> Option<StreamPosition>;
 }
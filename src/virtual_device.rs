@@ -0,0 +1,550 @@
+//! A virtual device that injects scripted faults into its streams.
+//!
+//! This module is only available with the `virtual-device` feature. It is meant for soak tests of
+//! error handling, both CPAL's and the application's: a `VirtualDevice` runs streams without any
+//! audio hardware and reports the faults of its `FaultScript` at chosen buffers, so that a test
+//! can check deterministically how the application recovers from them.
+//!
+//! Streams call the data callback on their own thread, with silent input buffers. The faults
+//! mimic what hosts report, e.g. a `Fault::Error` carries the `HRESULT` or `errno` that WASAPI or
+//! ALSA would return.
+//!
+//! ```no_run
+//! use cpal::traits::{DeviceTrait, StreamTrait};
+//! use cpal::virtual_device::{Fault, FaultScript, VirtualDevice};
+//! use cpal::{DeviceLossReason, Format, SampleFormat, SampleRate};
+//!
+//! let format = Format {
+//!     channels: 2,
+//!     sample_rate: SampleRate(48_000),
+//!     data_type: SampleFormat::F32,
+//! };
+//! let mut device = VirtualDevice::new("soak", format.clone());
+//! device.real_time = false;
+//! device.script = FaultScript::new()
+//!     .at(1_000, Fault::Error { code: -32 })
+//!     .at(5_000, Fault::DeviceLost(DeviceLossReason::Unplugged));
+//! let stream = device
+//!     .build_output_stream(&format, |_: &mut [f32], _: &_| (), |err| eprintln!("{}", err))
+//!     .unwrap();
+//! stream.play().unwrap();
+//! ```
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::vec::IntoIter as VecIntoIter;
+
+use traits::{DeviceTrait, StreamTrait};
+use worker::{self, Worker};
+use BackendSpecificError;
+use BufferFlags;
+use BuildStreamError;
+use CallbackInfo;
+use DefaultFormatError;
+use DeviceLossReason;
+use DeviceNameError;
+use Format;
+use PauseStreamError;
+use PlayStreamError;
+use Sample;
+use SampleFormat;
+use SlowCallbackPolicy;
+use stats::StreamMeter;
+use StreamData;
+use StreamError;
+use StreamOptions;
+use StreamPosition;
+use StreamStats;
+use SupportedFormat;
+use SupportedFormatsError;
+use UnknownTypeInputBuffer;
+use UnknownTypeOutputBuffer;
+
+/// A device without hardware whose streams report the faults of a script.
+///
+/// The device supports a single format, for both input and output streams.
+#[derive(Clone, Debug)]
+pub struct VirtualDevice {
+    /// The name of the device.
+    pub name: String,
+    /// The only format supported by the device.
+    pub format: Format,
+    /// The number of frames of each buffer passed to the data callback. Defaults to 512.
+    pub buffer_frames: usize,
+    /// Whether buffers are processed at the pace of the sample rate, as with a real device.
+    /// Otherwise, they are processed as fast as possible, which shortens soak tests. Defaults to
+    /// `true`.
+    pub real_time: bool,
+    /// The faults injected into each stream built on the device.
+    pub script: FaultScript,
+}
+
+/// The faults injected into a stream, each at a chosen buffer.
+///
+/// Buffers are counted from zero, from the first buffer processed after the stream is played.
+/// Buffers are not counted while the stream is paused. The faults of a buffer are injected in the
+/// order in which they were added, before the buffer is passed to the data callback.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultScript {
+    faults: Vec<(u64, Fault)>,
+}
+
+/// A fault injected into a stream.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault {
+    /// Reports a `StreamError::BackendSpecific` with `code`, e.g. an `HRESULT` or a negative
+    /// `errno`. The stream keeps running.
+    Error {
+        code: i32,
+    },
+    /// Drops the buffer as if the data callback had missed its deadline. An output stream reports
+    /// a `StreamError::Underrun` if built with `SlowCallbackPolicy::Error`, and an input stream
+    /// reports a `StreamError::Overrun`. The next buffer is flagged as a discontinuity.
+    Xrun,
+    /// Blocks the audio thread for the given duration before the buffer is processed. In real
+    /// time, the following buffers are then processed without delay until the stream caught up.
+    Stall(Duration),
+    /// Reports a `StreamError::DeviceNotAvailable` for the given reason and stops the stream, as
+    /// when the device is unplugged.
+    DeviceLost(DeviceLossReason),
+    /// Panics in place of the data callback, which the stream reports as a
+    /// `StreamError::CallbackPanicked` before stopping.
+    Panic,
+}
+
+/// A stream of a `VirtualDevice`.
+pub struct Stream {
+    worker: Worker,
+    shared: Arc<Shared>,
+}
+
+// The state of a stream shared with its audio thread.
+struct Shared {
+    playing: AtomicBool,
+    stopped: AtomicBool,
+    meter: StreamMeter,
+}
+
+// The buffer passed to the data callback, in the sample format of the stream.
+enum Buffer {
+    I16(Vec<i16>),
+    U16(Vec<u16>),
+    F32(Vec<f32>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Direction {
+    Input,
+    Output,
+}
+
+impl VirtualDevice {
+    /// A device supporting `format`, whose streams run in real time without faults.
+    pub fn new(name: &str, format: Format) -> Self {
+        VirtualDevice {
+            name: name.to_owned(),
+            format,
+            buffer_frames: 512,
+            real_time: true,
+            script: FaultScript::new(),
+        }
+    }
+
+    fn supported_formats(&self) -> VecIntoIter<SupportedFormat> {
+        vec![SupportedFormat::from(self.format.clone())].into_iter()
+    }
+
+    fn build_stream<D, E>(
+        &self,
+        direction: Direction,
+        format: &Format,
+        options: &StreamOptions,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Stream, BuildStreamError>
+    where
+        D: FnMut(StreamData) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        if *format != self.format {
+            return Err(BuildStreamError::FormatNotSupported);
+        }
+        if self.buffer_frames == 0 {
+            let description = "the buffer of a virtual device must not be empty".to_owned();
+            return Err(BackendSpecificError { description, code: None }.into());
+        }
+        let shared = Arc::new(Shared {
+            playing: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            meter: StreamMeter::new(format.sample_rate),
+        });
+        let run = RunContext {
+            direction,
+            shared: shared.clone(),
+            buffer: Buffer::silent(format.data_type, self.buffer_frames * format.channels as usize),
+            frames: self.buffer_frames,
+            period: if self.real_time {
+                Some(buffer_period(self.buffer_frames, format))
+            } else {
+                None
+            },
+            faults: self.script.faults.clone(),
+            options: options.clone(),
+        };
+        let worker = Worker::new(options.thread_spawner.clone(), move || {
+            run.run(data_callback, error_callback)
+        });
+        Ok(Stream { worker, shared })
+    }
+}
+
+impl FaultScript {
+    /// A script without faults.
+    pub fn new() -> Self {
+        FaultScript { faults: Vec::new() }
+    }
+
+    /// Injects `fault` into buffer `buffer` of the stream.
+    pub fn at(mut self, buffer: u64, fault: Fault) -> Self {
+        // Faults of the same buffer stay in the order in which they were added.
+        let index = self.faults.partition_point(|&(b, _)| b <= buffer);
+        self.faults.insert(index, (buffer, fault));
+        self
+    }
+}
+
+impl DeviceTrait for VirtualDevice {
+    type SupportedInputFormats = VecIntoIter<SupportedFormat>;
+    type SupportedOutputFormats = VecIntoIter<SupportedFormat>;
+    type Stream = Stream;
+
+    fn name(&self) -> Result<String, DeviceNameError> {
+        Ok(self.name.clone())
+    }
+
+    fn supported_input_formats(&self) -> Result<Self::SupportedInputFormats, SupportedFormatsError> {
+        Ok(self.supported_formats())
+    }
+
+    fn supported_output_formats(&self) -> Result<Self::SupportedOutputFormats, SupportedFormatsError> {
+        Ok(self.supported_formats())
+    }
+
+    fn default_input_format(&self) -> Result<Format, DefaultFormatError> {
+        Ok(self.format.clone())
+    }
+
+    fn default_output_format(&self) -> Result<Format, DefaultFormatError> {
+        Ok(self.format.clone())
+    }
+
+    fn build_input_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+        where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
+        self.build_stream(Direction::Input, format, options, data_callback, error_callback)
+    }
+
+    fn build_output_stream_dyn<D, E>(&self, format: &Format, options: &StreamOptions, data_callback: D, error_callback: E) -> Result<Self::Stream, BuildStreamError>
+        where D: FnMut(StreamData) + Send + 'static, E: FnMut(StreamError) + Send + 'static {
+        self.build_stream(Direction::Output, format, options, data_callback, error_callback)
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Release);
+        self.worker.join();
+    }
+}
+
+impl StreamTrait for Stream {
+    fn play(&self) -> Result<(), PlayStreamError> {
+        self.worker.start()?;
+        self.shared.playing.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<(), PauseStreamError> {
+        self.shared.playing.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    fn callback_load(&self) -> f32 {
+        self.shared.meter.callback_load()
+    }
+
+    fn stats(&self) -> StreamStats {
+        self.shared.meter.stats()
+    }
+
+    fn last_callback(&self) -> Option<Instant> {
+        self.shared.meter.last_callback()
+    }
+
+    fn position(&self) -> Option<StreamPosition> {
+        self.shared.meter.position()
+    }
+}
+
+impl Buffer {
+    fn silent(sample_format: SampleFormat, len: usize) -> Self {
+        match sample_format {
+            SampleFormat::I16 => Buffer::I16(vec![Sample::from(&0.0f32); len]),
+            SampleFormat::U16 => Buffer::U16(vec![Sample::from(&0.0f32); len]),
+            SampleFormat::F32 => Buffer::F32(vec![0.0; len]),
+        }
+    }
+
+    fn input(&self) -> UnknownTypeInputBuffer<'_> {
+        match *self {
+            Buffer::I16(ref b) => UnknownTypeInputBuffer::I16(::InputBuffer::new(b)),
+            Buffer::U16(ref b) => UnknownTypeInputBuffer::U16(::InputBuffer::new(b)),
+            Buffer::F32(ref b) => UnknownTypeInputBuffer::F32(::InputBuffer::new(b)),
+        }
+    }
+
+    fn output(&mut self) -> UnknownTypeOutputBuffer<'_> {
+        match *self {
+            Buffer::I16(ref mut b) => UnknownTypeOutputBuffer::I16(::OutputBuffer::new(b)),
+            Buffer::U16(ref mut b) => UnknownTypeOutputBuffer::U16(::OutputBuffer::new(b)),
+            Buffer::F32(ref mut b) => UnknownTypeOutputBuffer::F32(::OutputBuffer::new(b)),
+        }
+    }
+}
+
+// Everything the audio thread of a stream needs.
+struct RunContext {
+    direction: Direction,
+    shared: Arc<Shared>,
+    buffer: Buffer,
+    frames: usize,
+    // The duration of a buffer, or `None` to process buffers as fast as possible.
+    period: Option<Duration>,
+    // Sorted by buffer.
+    faults: Vec<(u64, Fault)>,
+    options: StreamOptions,
+}
+
+// Whether the stream goes on after injecting a fault.
+enum Outcome {
+    Continue,
+    SkipBuffer,
+    Stop,
+}
+
+impl RunContext {
+    fn run<D, E>(mut self, mut data_callback: D, mut error_callback: E)
+    where
+        D: FnMut(StreamData),
+        E: FnMut(StreamError),
+    {
+        worker::catch_panics(&mut error_callback, |error_callback| {
+            self.run_inner(&mut data_callback, error_callback)
+        });
+    }
+
+    fn run_inner(
+        &mut self,
+        data_callback: &mut dyn FnMut(StreamData),
+        error_callback: &mut dyn FnMut(StreamError),
+    ) {
+        let mut buffer_index = 0;
+        let mut next_fault = 0;
+        let mut frames_processed = 0;
+        let mut discontinuity = false;
+        let mut deadline = Instant::now();
+        while !self.shared.stopped.load(Ordering::Acquire) {
+            self.wait(&mut deadline);
+            if !self.shared.playing.load(Ordering::Acquire) {
+                continue;
+            }
+
+            let mut skip = false;
+            while next_fault < self.faults.len() && self.faults[next_fault].0 == buffer_index {
+                let fault = self.faults[next_fault].1.clone();
+                next_fault += 1;
+                match self.inject(fault, error_callback) {
+                    Outcome::Continue => (),
+                    Outcome::SkipBuffer => skip = true,
+                    Outcome::Stop => return,
+                }
+            }
+            buffer_index += 1;
+            if skip {
+                discontinuity = true;
+                continue;
+            }
+
+            let flags = BufferFlags {
+                discontinuity,
+                ..Default::default()
+            };
+            let info = CallbackInfo::new(self.frames, discontinuity, flags);
+            let stream_data = match self.direction {
+                Direction::Input => StreamData::Input { buffer: self.buffer.input(), info },
+                Direction::Output => {
+                    let mut buffer = self.buffer.output();
+                    if self.options.zero_output_buffers {
+                        buffer.fill_silence();
+                    }
+                    StreamData::Output { buffer, info }
+                },
+            };
+            self.shared.meter.measure(&info, || data_callback(stream_data));
+            frames_processed += self.frames as u64;
+            self.shared.meter.record_position(frames_processed, Instant::now());
+            discontinuity = false;
+        }
+    }
+
+    // Waits for the next buffer to be due.
+    fn wait(&self, deadline: &mut Instant) {
+        let period = match self.period {
+            Some(period) => period,
+            // Don't spin while paused.
+            None if !self.shared.playing.load(Ordering::Acquire) => Duration::from_millis(1),
+            None => return,
+        };
+        *deadline += period;
+        let now = Instant::now();
+        if *deadline > now {
+            thread::sleep(*deadline - now);
+        } else if !self.shared.playing.load(Ordering::Acquire) {
+            // Don't catch up on the time spent paused.
+            *deadline = now;
+        }
+    }
+
+    fn inject(&self, fault: Fault, error_callback: &mut dyn FnMut(StreamError)) -> Outcome {
+        trace_event!(debug, fault = ?fault, "injecting a fault");
+        match fault {
+            Fault::Error { code } => {
+                let description = format!("injected error {} ({:#010x})", code, code);
+                error_callback(BackendSpecificError { description, code: Some(code) }.into());
+                Outcome::Continue
+            },
+            Fault::Xrun => {
+                let buffer_frames = self.frames;
+                match self.direction {
+                    Direction::Input => {
+                        self.shared.meter.record_overrun();
+                        error_callback(StreamError::Overrun { buffer_frames });
+                    },
+                    Direction::Output => {
+                        self.shared.meter.record_underrun();
+                        if self.options.slow_callback == SlowCallbackPolicy::Error {
+                            error_callback(StreamError::Underrun { buffer_frames });
+                        }
+                    },
+                }
+                Outcome::SkipBuffer
+            },
+            Fault::Stall(duration) => {
+                thread::sleep(duration);
+                Outcome::Continue
+            },
+            Fault::DeviceLost(reason) => {
+                error_callback(StreamError::DeviceNotAvailable { reason });
+                Outcome::Stop
+            },
+            Fault::Panic => panic!("injected panic"),
+        }
+    }
+}
+
+// The time it takes a device to play or capture a buffer of `frames` frames in `format`.
+fn buffer_period(frames: usize, format: &Format) -> Duration {
+    let sample_rate = format.sample_rate.0.max(1) as u64;
+    Duration::from_nanos(frames as u64 * 1_000_000_000 / sample_rate)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{Fault, FaultScript, VirtualDevice};
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use traits::{DeviceTrait, StreamTrait};
+    use {DeviceLossReason, Format, InputCallbackInfo, SampleFormat, SampleRate, StreamError};
+
+    // A stereo `i16` device processing buffers as fast as possible, shared with the tests of the
+    // adapters over streams.
+    pub(crate) fn device(script: FaultScript) -> VirtualDevice {
+        let format = Format {
+            channels: 2,
+            sample_rate: SampleRate(48_000),
+            data_type: SampleFormat::I16,
+        };
+        let mut device = VirtualDevice::new("test", format);
+        device.real_time = false;
+        device.script = script;
+        device
+    }
+
+    #[test]
+    fn faults_are_injected_at_their_buffer() {
+        let script = FaultScript::new()
+            .at(3, Fault::DeviceLost(DeviceLossReason::Unplugged))
+            .at(1, Fault::Error { code: -5 })
+            .at(2, Fault::Xrun);
+        let device = device(script);
+        let (tx, rx) = mpsc::channel();
+        let data_tx = tx.clone();
+        let stream = device
+            .build_input_stream(
+                &device.format,
+                move |_: &[i16], info: &InputCallbackInfo| {
+                    data_tx.send(Ok(info.flags().discontinuity)).unwrap()
+                },
+                move |err| tx.send(Err(err)).unwrap(),
+            )
+            .unwrap();
+        stream.play().unwrap();
+        let timeout = Duration::from_secs(10);
+        assert!(matches!(rx.recv_timeout(timeout), Ok(Ok(false))));
+        match rx.recv_timeout(timeout) {
+            Ok(Err(StreamError::BackendSpecific { err })) => assert_eq!(err.code, Some(-5)),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(matches!(rx.recv_timeout(timeout), Ok(Ok(false))));
+        match rx.recv_timeout(timeout) {
+            Ok(Err(StreamError::Overrun { buffer_frames: 512 })) => (),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        match rx.recv_timeout(timeout) {
+            Ok(Err(StreamError::DeviceNotAvailable { reason })) => {
+                assert_eq!(reason, DeviceLossReason::Unplugged)
+            },
+            other => panic!("unexpected event: {:?}", other),
+        }
+        // The stream stopped after losing the device.
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(stream.stats().overruns, 1);
+    }
+
+    #[test]
+    fn input_streams_keep_capturing_after_an_overrun() {
+        let device = device(FaultScript::new().at(1, Fault::Xrun));
+        let (tx, rx) = mpsc::channel();
+        let data_tx = tx.clone();
+        let stream = device
+            .build_input_stream(
+                &device.format,
+                move |_: &[i16], info: &InputCallbackInfo| {
+                    let _ = data_tx.send(Ok(info.flags().discontinuity));
+                },
+                move |err| {
+                    let _ = tx.send(Err(err));
+                },
+            )
+            .unwrap();
+        stream.play().unwrap();
+        let timeout = Duration::from_secs(10);
+        assert!(matches!(rx.recv_timeout(timeout), Ok(Ok(false))));
+        assert!(matches!(rx.recv_timeout(timeout), Ok(Err(StreamError::Overrun { .. }))));
+        // The buffer following the overrun is flagged, and the stream goes on capturing.
+        assert!(matches!(rx.recv_timeout(timeout), Ok(Ok(true))));
+        assert!(matches!(rx.recv_timeout(timeout), Ok(Ok(false))));
+        assert_eq!(stream.stats().overruns, 1);
+    }
+}
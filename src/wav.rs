@@ -0,0 +1,246 @@
+//! Writing the buffers of a stream to a WAV file, see `Stream::tee_to_wav`.
+//!
+//! The data callback copies each buffer to a ring buffer once the user's callback has returned,
+//! and a background thread writes the ring buffer to the file. If the thread falls behind, whole
+//! frames are left out of the file rather than delaying the callback.
+
+use std::cell::RefCell;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::slice;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use hound::{self, WavSpec, WavWriter};
+
+use adapter;
+use ring_buffer::{latency_capacity, ring_buffer, Consumer, Producer};
+use Format;
+use Sample;
+use SampleFormat;
+use StreamData;
+use UnknownTypeInputBuffer;
+use UnknownTypeOutputBuffer;
+
+// The number of files that can be started before the data callback takes them.
+const PENDING_FILES: usize = 4;
+// The duration of audio buffered between the data callback and the writing thread.
+const RING_BUFFER_DURATION: Duration = Duration::from_secs(1);
+// How often the writing thread checks the ring buffer.
+const WRITE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The side of the tee owned by the stream.
+pub(crate) struct Tee {
+    format: Format,
+    // Sends the ring buffer of each new file to the data callback.
+    started: Producer<Producer<u8>>,
+    // Returns the ring buffers that the data callback no longer uses, so that they are not freed
+    // on the audio thread.
+    retired: Consumer<Producer<u8>>,
+    writer: RefCell<Option<Writer>>,
+}
+
+// The thread writing a file.
+struct Writer {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+/// Wraps `data_callback` so that its buffers can be written to a file with the returned `Tee`.
+pub(crate) fn tee<D>(
+    format: &Format,
+    mut data_callback: D,
+) -> (impl FnMut(StreamData) + Send + 'static, Tee)
+where
+    D: FnMut(StreamData) + Send + 'static,
+{
+    let frame_bytes = (format.channels as usize * format.data_type.sample_size()).max(1);
+    let (started, started_consumer) = ring_buffer(PENDING_FILES);
+    // There is room for every ring buffer that was started and not collected yet.
+    let (retired_producer, retired) = ring_buffer(PENDING_FILES + 1);
+    let mut current: Option<Producer<u8>> = None;
+    let callback = move |data: StreamData| {
+        while let Some(producer) = started_consumer.pop() {
+            if let Some(previous) = current.replace(producer) {
+                let _ = retired_producer.push(previous);
+            }
+        }
+        adapter::call_then(data, &mut data_callback, |data| {
+            if let Some(ref producer) = current {
+                producer.push_frames(buffer_bytes(&data), frame_bytes);
+            }
+        });
+    };
+    let tee = Tee {
+        format: format.clone(),
+        started,
+        retired,
+        writer: RefCell::new(None),
+    };
+    (callback, tee)
+}
+
+impl Tee {
+    /// Starts writing the buffers of the stream to a new file at `path`, and stops writing to the
+    /// previous file.
+    pub(crate) fn start(&self, path: &Path) -> io::Result<()> {
+        let spec = WavSpec {
+            channels: self.format.channels,
+            sample_rate: self.format.sample_rate.0,
+            bits_per_sample: match self.format.data_type {
+                SampleFormat::I16 | SampleFormat::U16 => 16,
+                SampleFormat::F32 => 32,
+            },
+            sample_format: match self.format.data_type {
+                SampleFormat::I16 | SampleFormat::U16 => hound::SampleFormat::Int,
+                SampleFormat::F32 => hound::SampleFormat::Float,
+            },
+        };
+        let file = WavWriter::create(path, spec).map_err(io_error)?;
+        let frame_bytes = self.format.channels as usize * self.format.data_type.sample_size();
+        let capacity = latency_capacity(RING_BUFFER_DURATION, self.format.sample_rate, frame_bytes);
+        let (producer, consumer) = ring_buffer(capacity);
+        while self.retired.pop().is_some() {}
+        if self.started.push(producer).is_err() {
+            return Err(io::Error::other("the stream has not taken the previous files yet"));
+        }
+        self.stop();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let data_type = self.format.data_type;
+        let thread = thread::Builder::new()
+            .name("cpal_wav_tee".to_string())
+            .spawn(move || write(consumer, file, data_type, &thread_stop))?;
+        *self.writer.borrow_mut() = Some(Writer { stop, thread });
+        Ok(())
+    }
+
+    // Waits for the current file, if any, to be written and finalized.
+    fn stop(&self) {
+        if let Some(writer) = self.writer.borrow_mut().take() {
+            writer.stop.store(true, Ordering::Release);
+            let _ = writer.thread.join();
+        }
+    }
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// Writes the bytes of the ring buffer to `file` until `stop` is set and the ring buffer is empty.
+fn write<W>(
+    consumer: Consumer<u8>,
+    mut file: WavWriter<W>,
+    data_type: SampleFormat,
+    stop: &AtomicBool,
+) where
+    W: io::Write + io::Seek,
+{
+    // A multiple of the size of every sample format, so that samples are never split.
+    let mut bytes = vec![0; 4_096];
+    loop {
+        let stopping = stop.load(Ordering::Acquire);
+        let popped = consumer.pop_slice(&mut bytes);
+        if popped == 0 {
+            if stopping {
+                break;
+            }
+            thread::sleep(WRITE_INTERVAL);
+            continue;
+        }
+        let sample_size = data_type.sample_size();
+        for sample in bytes[..popped].chunks_exact(sample_size) {
+            let result = match data_type {
+                SampleFormat::I16 => file.write_sample(i16::from_ne_bytes([sample[0], sample[1]])),
+                SampleFormat::U16 => {
+                    file.write_sample(u16::from_ne_bytes([sample[0], sample[1]]).to_i16())
+                },
+                SampleFormat::F32 => {
+                    let bytes = [sample[0], sample[1], sample[2], sample[3]];
+                    file.write_sample(f32::from_ne_bytes(bytes))
+                },
+            };
+            // There is no one to report the error to, so writing stops.
+            if result.is_err() {
+                return;
+            }
+        }
+    }
+    let _ = file.finalize();
+}
+
+// The bytes of the buffer passed to the data callback.
+fn buffer_bytes<'a>(data: &'a StreamData) -> &'a [u8] {
+    fn bytes<T: Sample>(samples: &[T]) -> &[u8] {
+        unsafe { slice::from_raw_parts(samples.as_ptr() as *const u8, mem::size_of_val(samples)) }
+    }
+    match *data {
+        StreamData::Input { ref buffer, .. } => match *buffer {
+            UnknownTypeInputBuffer::U16(ref buffer) => bytes(buffer),
+            UnknownTypeInputBuffer::I16(ref buffer) => bytes(buffer),
+            UnknownTypeInputBuffer::F32(ref buffer) => bytes(buffer),
+        },
+        StreamData::Output { ref buffer, .. } => match *buffer {
+            UnknownTypeOutputBuffer::U16(ref buffer) => bytes(buffer),
+            UnknownTypeOutputBuffer::I16(ref buffer) => bytes(buffer),
+            UnknownTypeOutputBuffer::F32(ref buffer) => bytes(buffer),
+        },
+    }
+}
+
+fn io_error(err: hound::Error) -> io::Error {
+    match err {
+        hound::Error::IoError(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::tee;
+    use std::fs;
+    use {CallbackInfo, Format, OutputBuffer, SampleFormat, SampleRate, StreamData};
+    use UnknownTypeOutputBuffer;
+
+    #[test]
+    fn output_buffers_are_written_after_the_callback() {
+        let format = Format {
+            channels: 2,
+            sample_rate: SampleRate(48_000),
+            data_type: SampleFormat::I16,
+        };
+        // Unique per process, so that concurrent runs of the test do not share the file.
+        let name = format!("cpal_wav_tee_test_{}.wav", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let mut next = 0;
+        let (mut callback, tee) = tee(&format, move |data| {
+            if let StreamData::Output { buffer, .. } = data {
+                if let UnknownTypeOutputBuffer::I16(mut buffer) = buffer {
+                    for sample in buffer.iter_mut() {
+                        *sample = next;
+                        next += 1;
+                    }
+                }
+            }
+        });
+        tee.start(&path).unwrap();
+        for _ in 0..3 {
+            let mut samples = [0i16; 4];
+            callback(StreamData::Output {
+                buffer: UnknownTypeOutputBuffer::I16(OutputBuffer::new(&mut samples)),
+                info: CallbackInfo::default(),
+            });
+        }
+        drop(tee);
+        let reader = hound::WavReader::open(&path).unwrap();
+        let samples: Vec<i16> = reader.into_samples().map(Result::unwrap).collect();
+        assert_eq!(samples, (0..12).collect::<Vec<i16>>());
+        fs::remove_file(&path).unwrap();
+    }
+}
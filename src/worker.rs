@@ -0,0 +1,147 @@
+//! The audio thread of hosts on which CPAL runs the data callback itself.
+//!
+//! The thread is only spawned when the stream is played for the first time, so that streams
+//! which are built ahead of time and never played do not cost a thread.
+//!
+//! Stopping the thread never panics, as it happens when a stream is dropped. A panic of the data
+//! callback is reported to the error callback instead, see `catch_panics`.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+
+use BackendSpecificError;
+use StreamError;
+use ThreadSpawner;
+use trace;
+
+pub(crate) struct Worker {
+    spawner: ThreadSpawner,
+    state: Mutex<State>,
+}
+
+enum State {
+    // The thread has not been spawned yet.
+    Pending(Box<dyn FnOnce() + Send>),
+    Running(JoinHandle<()>),
+    // The thread has been joined, or could not be spawned.
+    Stopped,
+}
+
+impl Worker {
+    /// Prepares a thread running `run`, to be spawned with `spawner` by `start`.
+    ///
+    /// `run` runs in the `cpal_stream` span of the stream being built.
+    pub fn new<F>(spawner: ThreadSpawner, run: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        Worker {
+            spawner,
+            state: Mutex::new(State::Pending(Box::new(trace::in_stream_span(run)))),
+        }
+    }
+
+    /// Spawns the thread, unless it has already been spawned.
+    pub fn start(&self) -> Result<(), BackendSpecificError> {
+        let mut state = self.state();
+        let run = match std::mem::replace(&mut *state, State::Stopped) {
+            State::Pending(run) => run,
+            State::Running(thread) => {
+                *state = State::Running(thread);
+                return Ok(());
+            },
+            State::Stopped => {
+                let description = String::from("the audio thread is not running");
+                return Err(BackendSpecificError { description, code: None });
+            },
+        };
+        match self.spawner.spawn(run) {
+            Ok(thread) => {
+                *state = State::Running(thread);
+                Ok(())
+            },
+            Err(err) => {
+                let description = format!("failed to spawn the audio thread: {}", err);
+                let code = err.raw_os_error();
+                Err(BackendSpecificError { description, code })
+            },
+        }
+    }
+
+    /// Whether `start` has been called successfully.
+    pub fn is_started(&self) -> bool {
+        match *self.state() {
+            State::Pending(_) => false,
+            State::Running(_) | State::Stopped => true,
+        }
+    }
+
+    /// Whether the thread has been spawned and has finished running.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub fn is_finished(&self) -> bool {
+        match *self.state() {
+            State::Pending(_) => false,
+            State::Running(ref thread) => thread.is_finished(),
+            State::Stopped => true,
+        }
+    }
+
+    /// Waits for the thread to finish if it has been spawned, or drops the function it would
+    /// have run otherwise.
+    pub fn join(&self) {
+        let state = std::mem::replace(&mut *self.state(), State::Stopped);
+        if let State::Running(thread) = state {
+            // The thread only panics if the error callback itself panicked, which was already
+            // reported by the panic hook.
+            let _ = thread.join();
+        }
+    }
+
+    // The state is consistent even if a thread panicked while holding the lock.
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Runs the loop of an audio thread, reporting a panic to `error_callback` as
+/// `StreamError::CallbackPanicked` instead of unwinding.
+pub fn catch_panics<F>(error_callback: &mut dyn FnMut(StreamError), run: F)
+where
+    F: FnOnce(&mut dyn FnMut(StreamError)),
+{
+    let payload = match panic::catch_unwind(AssertUnwindSafe(|| run(&mut *error_callback))) {
+        Ok(()) => return,
+        Err(payload) => payload,
+    };
+    let message = panic_message(&*payload);
+    trace_event!(error, message = %message, "the data callback panicked");
+    error_callback(StreamError::CallbackPanicked { message });
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic payload")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::catch_panics;
+    use StreamError;
+
+    #[test]
+    fn panics_are_reported_to_the_error_callback() {
+        let mut errors = Vec::new();
+        catch_panics(&mut |err| errors.push(err), |_| panic!("callback failed"));
+        match errors.as_slice() {
+            [StreamError::CallbackPanicked { message }] => assert_eq!(message, "callback failed"),
+            _ => panic!("unexpected errors: {:?}", errors),
+        }
+    }
+}